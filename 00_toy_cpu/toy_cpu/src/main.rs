@@ -10,6 +10,15 @@ struct Sample {
     bsdf_multiplied_cos_divided_by_pdf: glam::Vec3,
 }
 
+// 誘電体のFresnelの式でs偏光とp偏光の反射率を求めて平均する
+fn fresnel_dielectric(eta_1: f32, eta_2: f32, cos_theta_i: f32, cos_theta_t: f32) -> f32 {
+    let rho_s =
+        (eta_1 * cos_theta_i - eta_2 * cos_theta_t) / (eta_1 * cos_theta_i + eta_2 * cos_theta_t);
+    let rho_p =
+        (eta_2 * cos_theta_i - eta_1 * cos_theta_t) / (eta_2 * cos_theta_i + eta_1 * cos_theta_t);
+    (rho_s.powi(2) + rho_p.powi(2)) / 2.0
+}
+
 #[derive(Debug, Clone, Copy)]
 enum Material {
     Lambert { color: glam::Vec3 },
@@ -45,20 +54,17 @@ impl Material {
                     (*inner_eta, *outer_eta)
                 };
 
+                // Snellの法則から屈折角のcosを解析的に求める。
+                // 全反射の場合はsin_theta_t^2が1を超えるので、sqrtの中身が負にならないようにclampする。
+                let cos_theta_i = view_dir_tangent.dot(glam::Vec3::Y);
+                let sin_theta_t_squared = (eta_1 / eta_2).powi(2) * (1.0 - cos_theta_i.powi(2));
+                let is_total_internal_reflection = sin_theta_t_squared >= 1.0;
+                let cos_theta_t = (1.0 - sin_theta_t_squared).max(0.0).sqrt();
+
                 let refract_dir = (eta_1 / eta_2)
-                    * (-view_dir_tangent + view_dir_tangent.dot(glam::Vec3::Y) * glam::Vec3::Y)
-                    - (1.0
-                        - (eta_1 / eta_2).powi(2)
-                            * (1.0 + view_dir_tangent.dot(glam::Vec3::Y).powi(2)))
-                    .sqrt()
-                        * glam::Vec3::Y;
-                let reflect_dir =
-                    -2.0 * -view_dir_tangent.dot(glam::Vec3::Y) * glam::Vec3::Y - view_dir_tangent;
-
-                let is_total_internal_reflection = (1.0
-                    - (eta_1 / eta_2).powi(2)
-                        * (1.0 + view_dir_tangent.dot(glam::Vec3::Y).powi(2)))
-                    < 0.0;
+                    * (-view_dir_tangent + cos_theta_i * glam::Vec3::Y)
+                    - cos_theta_t * glam::Vec3::Y;
+                let reflect_dir = 2.0 * cos_theta_i * glam::Vec3::Y - view_dir_tangent;
 
                 if is_total_internal_reflection {
                     if reflect_dir.dot(light_dir_tangent) > 0.99999 {
@@ -112,20 +118,17 @@ impl Material {
                     (*inner_eta, *outer_eta)
                 };
 
+                // Snellの法則から屈折角のcosを解析的に求める。
+                // 全反射の場合はsin_theta_t^2が1を超えるので、sqrtの中身が負にならないようにclampする。
+                let cos_theta_i = view_dir_tangent.dot(glam::Vec3::Y);
+                let sin_theta_t_squared = (eta_1 / eta_2).powi(2) * (1.0 - cos_theta_i.powi(2));
+                let is_total_internal_reflection = sin_theta_t_squared >= 1.0;
+                let cos_theta_t = (1.0 - sin_theta_t_squared).max(0.0).sqrt();
+
                 let refract_dir = (eta_1 / eta_2)
-                    * (-view_dir_tangent + view_dir_tangent.dot(glam::Vec3::Y) * glam::Vec3::Y)
-                    - (1.0
-                        - (eta_1 / eta_2).powi(2)
-                            * (1.0 + view_dir_tangent.dot(glam::Vec3::Y).powi(2)))
-                    .sqrt()
-                        * glam::Vec3::Y;
-                let reflect_dir =
-                    -2.0 * -view_dir_tangent.dot(glam::Vec3::Y) * glam::Vec3::Y - view_dir_tangent;
-
-                let is_total_internal_reflection = (1.0
-                    - (eta_1 / eta_2).powi(2)
-                        * (1.0 + (view_dir_tangent.dot(glam::Vec3::Y)).powi(2)))
-                    < 0.0;
+                    * (-view_dir_tangent + cos_theta_i * glam::Vec3::Y)
+                    - cos_theta_t * glam::Vec3::Y;
+                let reflect_dir = 2.0 * cos_theta_i * glam::Vec3::Y - view_dir_tangent;
 
                 if is_total_internal_reflection {
                     let sample = Sample {
@@ -135,13 +138,7 @@ impl Material {
                     return Some(sample);
                 }
 
-                let cos_theta_i = view_dir_tangent.dot(glam::Vec3::Y);
-                let cos_theta_o = refract_dir.dot(glam::Vec3::NEG_Y);
-                let rho_s = (eta_1 * cos_theta_i - eta_2 * cos_theta_o)
-                    / (eta_1 * cos_theta_i + eta_2 * cos_theta_o);
-                let rho_p = (eta_1 * cos_theta_o - eta_2 * cos_theta_i)
-                    / (eta_1 * cos_theta_o + eta_2 * cos_theta_i);
-                let fresnel = (rho_s.powi(2) + rho_p.powi(2)) / 2.0;
+                let fresnel = fresnel_dielectric(eta_1, eta_2, cos_theta_i, cos_theta_t);
 
                 let uniform = Uniform::new(0.0, 1.0);
                 if uniform.sample(&mut rng) < fresnel {
@@ -199,6 +196,28 @@ impl AABB {
         2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
     }
 
+    // 12本の辺の両端の点
+    fn edges(&self) -> [(glam::Vec3, glam::Vec3); 12] {
+        let corner = |i: usize| {
+            glam::vec3(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            )
+        };
+        let mut edges = [(glam::Vec3::ZERO, glam::Vec3::ZERO); 12];
+        let mut n = 0;
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    edges[n] = (corner(i), corner(i | axis));
+                    n += 1;
+                }
+            }
+        }
+        edges
+    }
+
     fn intersect(&self, ray: &Ray) -> AABBHit {
         let inv_dir = glam::Vec3::new(1.0, 1.0, 1.0) / ray.dir;
         let t1 = (self.min - ray.origin) * inv_dir;
@@ -222,6 +241,7 @@ enum TriangleHit {
         t: f32,
         position: glam::Vec3,
         normal: glam::Vec3,
+        geometric_normal: glam::Vec3,
         material: Material,
     },
 }
@@ -248,37 +268,65 @@ impl Triangle {
         (aabb.min + aabb.max) / 2.0
     }
 
-    fn intersect(&self, ray: &Ray, tmin: f32, tmax: f32) -> TriangleHit {
-        let o = ray.origin;
-        let q = ray.origin + ray.dir;
+    // detが辺の長さに対してこれより小さい場合はレイが三角形の面と平行とみなす
+    const PARALLEL_EPSILON: f32 = 1e-7;
+
+    // Möller–Trumboreのアルゴリズムでレイと三角形の交差を求め、
+    // レイの上の距離tと(pa, pb, pc)の重心座標を返す。
+    // tはray.dirの長さを単位とするので、ray.dirが正規化されていなくてもよい。
+    // 従来と同じく三角形の表側(pa, pb, pcが反時計回りに見える側)からのhitのみを返す。
+    fn intersect_barycentric(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<(f32, glam::Vec3)> {
+        let e1 = self.pb - self.pa;
+        let e2 = self.pc - self.pa;
+        let p = ray.dir.cross(e2);
+        let det = e1.dot(p);
+
+        // detはe1とpのなす角のcosに長さを掛けたものなので、長さで割った値で平行かどうかを判定する。
+        // 三角形の大きさやレイの方向の長さによらずに、レイが面とほぼ平行な場合を除外できる。
+        // 裏側からのhitはdetが負になるのでここで除外される。
+        if det <= Self::PARALLEL_EPSILON * e1.length() * p.length() {
+            return None;
+        }
+        let inv_det = 1.0 / det;
 
-        let vc = (q - o).dot((self.pb - o).cross(self.pa - o));
-        let vb = (q - o).dot((self.pa - o).cross(self.pc - o));
-        let va = (q - o).dot((self.pc - o).cross(self.pb - o));
+        let s = ray.origin - self.pa;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
 
-        if va <= 0.0 || vb <= 0.0 || vc <= 0.0 {
-            return TriangleHit::Miss;
+        let q = s.cross(e1);
+        let v = ray.dir.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
         }
 
-        let v = va + vb + vc;
-        let alpha = va / v;
-        let beta = vb / v;
-        let gamma = vc / v;
+        let t = e2.dot(q) * inv_det;
+        if t < tmin || t > tmax {
+            return None;
+        }
 
-        let position = alpha * self.pa + beta * self.pb + gamma * self.pc;
+        Some((t, glam::vec3(1.0 - u - v, u, v)))
+    }
 
-        let t = (position - ray.origin).dot(ray.dir);
-        if t < tmin || t > tmax {
+    fn intersect(&self, ray: &Ray, tmin: f32, tmax: f32) -> TriangleHit {
+        let Some((t, barycentric)) = self.intersect_barycentric(ray, tmin, tmax) else {
             return TriangleHit::Miss;
-        }
+        };
+        let (alpha, beta, gamma) = (barycentric.x, barycentric.y, barycentric.z);
+
+        // 位置はレイの上ではなく重心座標から求めて、三角形の面の上に乗るようにする
+        let position = alpha * self.pa + beta * self.pb + gamma * self.pc;
 
         let normal = alpha * self.na + beta * self.nb + gamma * self.nc;
         let normal = normal.normalize();
+        let geometric_normal = (self.pb - self.pa).cross(self.pc - self.pa).normalize();
 
         TriangleHit::Hit {
             t,
             position,
             normal,
+            geometric_normal,
             material: self.material,
         }
     }
@@ -374,8 +422,11 @@ impl<'a> Triangles<'a> {
         comparator: impl Fn(&Triangle, &Triangle) -> Ordering,
         parent_surface_area: f32,
     ) -> SplitResult<'a> {
+        // 同じ位置の三角形は三角形のindexの順に並べて、入力の順序によらず同じBVHになるようにする
         let mut sorted_indices = self.indices.clone();
-        sorted_indices.sort_by(|a, b| comparator(&self.triangle_list[*a], &self.triangle_list[*b]));
+        sorted_indices.sort_by(|a, b| {
+            comparator(&self.triangle_list[*a], &self.triangle_list[*b]).then(a.cmp(b))
+        });
 
         let mut min_cost = std::f32::MAX;
         let mut min_cost_index = 0;
@@ -418,29 +469,30 @@ impl<'a> Triangles<'a> {
 
     fn split_x(&self, parent_surface_area: f32) -> SplitResult<'a> {
         self.split(
-            |a, b| a.center().x.partial_cmp(&b.center().x).unwrap(),
+            |a, b| a.center().x.total_cmp(&b.center().x),
             parent_surface_area,
         )
     }
 
     fn split_y(&self, parent_surface_area: f32) -> SplitResult<'a> {
         self.split(
-            |a, b| a.center().y.partial_cmp(&b.center().y).unwrap(),
+            |a, b| a.center().y.total_cmp(&b.center().y),
             parent_surface_area,
         )
     }
 
     fn split_z(&self, parent_surface_area: f32) -> SplitResult<'a> {
         self.split(
-            |a, b| a.center().z.partial_cmp(&b.center().z).unwrap(),
+            |a, b| a.center().z.total_cmp(&b.center().z),
             parent_surface_area,
         )
     }
 
-    fn traverse(&self, ray: &Ray) -> TriangleHit {
+    fn traverse(&self, ray: &Ray, stats: &mut TraversalStats) -> TriangleHit {
         let mut min_hit = TriangleHit::Miss;
         for i in self.indices.iter() {
-            let hit = self.triangle_list[*i].intersect(ray, BVH::RAY_EPSILON, BVH::RAY_MAX_T);
+            stats.triangle_tests += 1;
+            let hit = self.triangle_list[*i].intersect(ray, BVH::RAY_MIN_T, BVH::RAY_MAX_T);
             if let TriangleHit::Hit { t, .. } = hit {
                 if let TriangleHit::Hit { t: min_t, .. } = min_hit {
                     if t < min_t {
@@ -455,6 +507,18 @@ impl<'a> Triangles<'a> {
     }
 }
 
+// 1本のレイのtraverseで行った交差判定の回数
+#[derive(Debug, Clone, Copy, Default)]
+struct TraversalStats {
+    aabb_tests: u32,
+    triangle_tests: u32,
+}
+impl TraversalStats {
+    fn total(&self) -> u32 {
+        self.aabb_tests + self.triangle_tests
+    }
+}
+
 enum BVHNode<'a> {
     Leaf {
         triangles: Triangles<'a>,
@@ -467,11 +531,30 @@ enum BVHNode<'a> {
     },
 }
 impl<'a> BVHNode<'a> {
-    fn traverse(&self, ray: &Ray) -> TriangleHit {
+    fn aabb(&self) -> &AABB {
+        match self {
+            BVHNode::Leaf { aabb, .. } => aabb,
+            BVHNode::Node { aabb, .. } => aabb,
+        }
+    }
+
+    fn collect_aabbs(&self, depth: u32, max_depth: u32, aabbs: &mut Vec<AABB>) {
+        aabbs.push(*self.aabb());
+        if depth >= max_depth {
+            return;
+        }
+        if let BVHNode::Node { left, right, .. } = self {
+            left.collect_aabbs(depth + 1, max_depth, aabbs);
+            right.collect_aabbs(depth + 1, max_depth, aabbs);
+        }
+    }
+
+    fn traverse(&self, ray: &Ray, stats: &mut TraversalStats) -> TriangleHit {
+        stats.aabb_tests += 1;
         match self {
             BVHNode::Leaf { triangles, aabb } => {
                 if let AABBHit::Hit { .. } = aabb.intersect(ray) {
-                    triangles.traverse(ray)
+                    triangles.traverse(ray, stats)
                 } else {
                     TriangleHit::Miss
                 }
@@ -481,8 +564,8 @@ impl<'a> BVHNode<'a> {
                     return TriangleHit::Miss;
                 }
 
-                let left_hit = left.traverse(ray);
-                let right_hit = right.traverse(ray);
+                let left_hit = left.traverse(ray, stats);
+                let right_hit = right.traverse(ray, stats);
                 match (left_hit, right_hit) {
                     (TriangleHit::Miss, TriangleHit::Miss) => TriangleHit::Miss,
                     (TriangleHit::Miss, hit) => hit,
@@ -507,7 +590,8 @@ impl<'a> BVH<'a> {
     const COST_LEAF: f32 = 1.0;
     const COST_T: f32 = 1.0;
 
-    const RAY_EPSILON: f32 = 0.001;
+    // 自己交差はoffset_ray_originでレイの始点をずらして避けるので、tminは0にする
+    const RAY_MIN_T: f32 = 0.0;
     const RAY_MAX_T: f32 = 1e12;
 
     fn build(triangle_list: &'a TriangleList) -> Self {
@@ -561,8 +645,23 @@ impl<'a> BVH<'a> {
         }
     }
 
+    // ルートからmax_depthの深さまでのノードのAABBを集める。ルートの深さは0。
+    // 分割の質の確認用に、wireframeとして描画するために使う。
+    fn collect_aabbs(&self, max_depth: u32) -> Vec<AABB> {
+        let mut aabbs = vec![];
+        self.root.collect_aabbs(0, max_depth, &mut aabbs);
+        aabbs
+    }
+
     fn traverse(&self, ray: &Ray) -> TriangleHit {
-        self.root.traverse(ray)
+        self.root.traverse(ray, &mut TraversalStats::default())
+    }
+
+    // traverseと同じ交差判定を行い、行ったAABBと三角形の交差判定の回数も返す。
+    fn traverse_with_stats(&self, ray: &Ray) -> (TriangleHit, TraversalStats) {
+        let mut stats = TraversalStats::default();
+        let hit = self.root.traverse(ray, &mut stats);
+        (hit, stats)
     }
 }
 
@@ -624,6 +723,121 @@ impl Camera {
             dir: dir.normalize(),
         }
     }
+
+    // world spaceの点をピクセル座標に投影する。カメラの後ろの点はNoneを返す。
+    fn project(&self, point: glam::Vec3, res_x: u32, res_y: u32) -> Option<glam::Vec2> {
+        let aspect_ratio = res_x as f32 / res_y as f32;
+        let tan_fov = (self.fov.to_radians() / 2.0).tan();
+
+        let front = -self.view_dir;
+        let right = self.up.cross(front).normalize();
+        let up = front.cross(right).normalize();
+
+        let d = point - self.position;
+        let depth = -d.dot(front);
+        if depth <= 1e-4 {
+            return None;
+        }
+        let x = d.dot(right) / depth / (aspect_ratio * tan_fov);
+        let y = d.dot(up) / depth / tan_fov;
+        Some(glam::vec2(
+            (x + 1.0) / 2.0 * res_x as f32 - 0.5,
+            (1.0 - y) / 2.0 * res_y as f32 - 0.5,
+        ))
+    }
+}
+
+// トーンマッピングした[0, 1]の値に伝達関数をかける。
+// gammaがNoneの場合はPNGの色空間に合わせてsRGBの区分的な伝達関数をかける
+fn display_transfer(value: f32, gamma: Option<f32>) -> f32 {
+    let value = value.max(0.0);
+    match gamma {
+        Some(gamma) => value.powf(1.0 / gamma),
+        None if value <= 0.0031308 => 12.92 * value,
+        None => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+// ピクセルごとのprimary rayの交差判定の回数をheatmapの画像にする。
+// 回数は全ピクセルの最大値で正規化し、青(少ない)から緑、赤(多い)の色で表す。
+fn traversal_heatmap(
+    bvh: &BVH,
+    camera: &Camera,
+    width: u32,
+    height: u32,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let costs = (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let mut rng = rand::thread_rng();
+            let ray = camera.get_ray(&mut rng, i % width, i / width, width, height);
+            let (_, stats) = bvh.traverse_with_stats(&ray);
+            stats.total()
+        })
+        .collect::<Vec<_>>();
+    let max_cost = costs.iter().copied().max().unwrap_or(0).max(1);
+    println!("Max traversal cost of primary rays: {max_cost}");
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let t = costs[(y * width + x) as usize] as f32 / max_cost as f32;
+        let rgb = if t < 0.5 {
+            glam::vec3(0.0, 0.0, 1.0).lerp(glam::vec3(0.0, 1.0, 0.0), t * 2.0)
+        } else {
+            glam::vec3(0.0, 1.0, 0.0).lerp(glam::vec3(1.0, 0.0, 0.0), t * 2.0 - 1.0)
+        };
+        Rgb([
+            (rgb.x * 255.0) as u8,
+            (rgb.y * 255.0) as u8,
+            (rgb.z * 255.0) as u8,
+        ])
+    })
+}
+
+// BVHのAABBの辺をimgに線で描画する。カメラの後ろにかかる辺は描画しない。
+fn draw_aabbs(img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, camera: &Camera, aabbs: &[AABB]) {
+    let (width, height) = img.dimensions();
+    for aabb in aabbs {
+        for (a, b) in aabb.edges() {
+            let (Some(a), Some(b)) = (
+                camera.project(a, width, height),
+                camera.project(b, width, height),
+            ) else {
+                continue;
+            };
+            let steps = (b - a).abs().max_element().ceil().min(4096.0) as u32;
+            for i in 0..=steps {
+                let p = a.lerp(b, i as f32 / steps.max(1) as f32).round();
+                if p.x >= 0.0 && p.y >= 0.0 && p.x < width as f32 && p.y < height as f32 {
+                    img.put_pixel(p.x as u32, p.y as u32, Rgb([0, 255, 0]));
+                }
+            }
+        }
+    }
+}
+
+// 面上の位置positionから法線normalの側にレイを飛ばすときの始点を、自己交差しないように少しずらす。
+// Ray Tracing Gemsの"A Fast and Robust Method for Avoiding Self-Intersection"の方法で、
+// 座標をその大きさに応じたulp単位でずらすので、sceneのスケールによらず
+// 浮動小数点の誤差の分だけ面から離れる。原点付近はulpが小さすぎるので固定の距離でずらす。
+fn offset_ray_origin(position: glam::Vec3, normal: glam::Vec3) -> glam::Vec3 {
+    const ORIGIN: f32 = 1.0 / 32.0;
+    const FLOAT_SCALE: f32 = 1.0 / 65536.0;
+    const INT_SCALE: f32 = 256.0;
+
+    let offset = |p: f32, n: f32| {
+        if p.abs() < ORIGIN {
+            p + FLOAT_SCALE * n
+        } else {
+            let offset_int = (INT_SCALE * n) as i32;
+            let offset_int = if p < 0.0 { -offset_int } else { offset_int };
+            f32::from_bits((p.to_bits() as i32 + offset_int) as u32)
+        }
+    };
+    glam::Vec3::new(
+        offset(position.x, normal.x),
+        offset(position.y, normal.y),
+        offset(position.z, normal.z),
+    )
 }
 
 fn path_trace(mut rng: &mut ThreadRng, ray: &Ray, bvh: &BVH, depth: u32) -> glam::Vec3 {
@@ -637,6 +851,7 @@ fn path_trace(mut rng: &mut ThreadRng, ray: &Ray, bvh: &BVH, depth: u32) -> glam
         TriangleHit::Hit {
             position,
             normal,
+            geometric_normal,
             material,
             ..
         } => {
@@ -679,8 +894,14 @@ fn path_trace(mut rng: &mut ThreadRng, ray: &Ray, bvh: &BVH, depth: u32) -> glam
                     .mul_vec3(sample.sample_dir_tangent)
                     .normalize();
 
+                // 反射なら入射側、屈折なら反対側に始点をずらす
+                let offset_normal = if geometric_normal.dot(sample_dir_world) < 0.0 {
+                    -geometric_normal
+                } else {
+                    geometric_normal
+                };
                 let ray = Ray {
-                    origin: position,
+                    origin: offset_ray_origin(position, offset_normal),
                     dir: sample_dir_world,
                 };
 
@@ -783,6 +1004,18 @@ fn main() {
     let width = 800;
     let height = 600;
     let l_white = 30.0_f32;
+    // DISPLAY_GAMMAを指定するとsRGBの伝達関数の代わりに1 / gammaのべき乗をかける
+    let display_gamma = std::env::var("DISPLAY_GAMMA")
+        .ok()
+        .and_then(|gamma| gamma.parse::<f32>().ok())
+        .filter(|gamma| *gamma > 0.0);
+
+    // TRAVERSAL_HEATMAPを指定するとprimary rayの交差判定の回数のheatmapも保存する
+    if std::env::var_os("TRAVERSAL_HEATMAP").is_some() {
+        traversal_heatmap(&bvh, &camera, width, height)
+            .save("traversal_heatmap.png")
+            .unwrap();
+    }
 
     let mut img = ImageBuffer::new(width, height);
 
@@ -807,10 +1040,10 @@ fn main() {
             let g = (rgb.y * (1.0 + rgb.y / l_white.powi(2))) / (1.0 + rgb.y);
             let b = (rgb.z * (1.0 + rgb.z / l_white.powi(2))) / (1.0 + rgb.z);
 
-            // gamma correction
-            let r = r.powf(1.0 / 2.2);
-            let g = g.powf(1.0 / 2.2);
-            let b = b.powf(1.0 / 2.2);
+            // 伝達関数
+            let r = display_transfer(r, display_gamma);
+            let g = display_transfer(g, display_gamma);
+            let b = display_transfer(b, display_gamma);
 
             pixel[0] = (r * 255.0).min(255.0) as u8;
             pixel[1] = (g * 255.0).min(255.0) as u8;
@@ -820,5 +1053,242 @@ fn main() {
     let end = start.elapsed();
     println!("Finished rendering in {}s", end.as_secs_f32());
 
+    // BVH_OVERLAY_DEPTHを指定するとその深さまでのBVHのAABBをwireframeで重ねて描画する
+    if let Some(max_depth) = std::env::var("BVH_OVERLAY_DEPTH")
+        .ok()
+        .and_then(|depth| depth.parse::<u32>().ok())
+    {
+        let aabbs = bvh.collect_aabbs(max_depth);
+        println!("Drawing {} BVH nodes up to depth {max_depth}", aabbs.len());
+        draw_aabbs(&mut img, &camera, &aabbs);
+    }
+
     img.save("output.png").unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 入射角を0度から90度の手前まで変えて、反射率と透過率の和が1になることを確かめる。
+    // 透過率はFresnelの透過係数から、屈折で変わる光束の断面積の比を掛けて求める。
+    fn assert_reflectance_plus_transmittance_is_one(eta_1: f32, eta_2: f32) {
+        const STEPS: u32 = 90;
+        for i in 0..STEPS {
+            let theta_i = i as f32 / STEPS as f32 * std::f32::consts::FRAC_PI_2;
+            let cos_theta_i = theta_i.cos();
+            let sin_theta_t_squared = (eta_1 / eta_2).powi(2) * (1.0 - cos_theta_i.powi(2));
+            if sin_theta_t_squared >= 1.0 {
+                // 全反射
+                continue;
+            }
+            let cos_theta_t = (1.0 - sin_theta_t_squared).sqrt();
+
+            let reflectance = fresnel_dielectric(eta_1, eta_2, cos_theta_i, cos_theta_t);
+
+            let tau_s = 2.0 * eta_1 * cos_theta_i / (eta_1 * cos_theta_i + eta_2 * cos_theta_t);
+            let tau_p = 2.0 * eta_1 * cos_theta_i / (eta_2 * cos_theta_i + eta_1 * cos_theta_t);
+            let transmittance = (eta_2 * cos_theta_t) / (eta_1 * cos_theta_i)
+                * (tau_s.powi(2) + tau_p.powi(2))
+                / 2.0;
+
+            assert!(
+                (reflectance + transmittance - 1.0).abs() < 1e-4,
+                "eta {eta_1} -> {eta_2}, theta_i {theta_i}: R {reflectance} + T {transmittance}"
+            );
+        }
+    }
+
+    #[test]
+    fn fresnel_energy_conservation_from_outside() {
+        assert_reflectance_plus_transmittance_is_one(1.0, 1.5);
+    }
+
+    #[test]
+    fn fresnel_energy_conservation_from_inside() {
+        assert_reflectance_plus_transmittance_is_one(1.5, 1.0);
+    }
+
+    #[test]
+    fn fresnel_normal_incidence() {
+        // 垂直入射では((eta_1 - eta_2) / (eta_1 + eta_2))^2になる
+        let reflectance = fresnel_dielectric(1.0, 1.5, 1.0, 1.0);
+        assert!((reflectance - 0.04).abs() < 1e-6);
+    }
+
+    fn triangle(pa: glam::Vec3, pb: glam::Vec3, pc: glam::Vec3) -> Triangle {
+        let normal = (pb - pa).cross(pc - pa).normalize();
+        Triangle {
+            pa,
+            pb,
+            pc,
+            na: normal,
+            nb: normal,
+            nc: normal,
+            material: Material::Lambert {
+                color: glam::Vec3::ONE,
+            },
+        }
+    }
+
+    // 同じ位置に重なった三角形を含む、分割の比較で同値になりやすい三角形のリスト
+    fn overlapping_triangles() -> TriangleList {
+        let mut triangle_list = TriangleList::new();
+        for i in 0..8 {
+            for j in 0..8 {
+                let offset = glam::Vec3::new((i / 2) as f32, 0.0, (j / 2) as f32);
+                triangle_list.push(triangle(
+                    offset,
+                    offset + glam::Vec3::Z,
+                    offset + glam::Vec3::X,
+                ));
+            }
+        }
+        triangle_list
+    }
+
+    // BVHを深さ優先の順にノードのAABBとleafの三角形のindexの配列にする
+    fn flatten(node: &BVHNode, nodes: &mut Vec<([f32; 3], [f32; 3], Vec<usize>)>) {
+        let aabb = node.aabb();
+        match node {
+            BVHNode::Leaf { triangles, .. } => {
+                nodes.push((
+                    aabb.min.to_array(),
+                    aabb.max.to_array(),
+                    triangles.indices.clone(),
+                ));
+            }
+            BVHNode::Node { left, right, .. } => {
+                nodes.push((aabb.min.to_array(), aabb.max.to_array(), vec![]));
+                flatten(left, nodes);
+                flatten(right, nodes);
+            }
+        }
+    }
+
+    #[test]
+    fn bvh_build_is_deterministic() {
+        let triangle_list = overlapping_triangles();
+
+        let mut first = vec![];
+        flatten(&BVH::build(&triangle_list).root, &mut first);
+        let mut second = vec![];
+        flatten(&BVH::build(&triangle_list).root, &mut second);
+
+        assert!(first.len() > 1);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn bvh_build_with_nan_vertex_does_not_panic() {
+        let mut triangle_list = overlapping_triangles();
+        triangle_list.push(triangle(
+            glam::Vec3::new(f32::NAN, 0.0, 0.0),
+            glam::Vec3::Z,
+            glam::Vec3::X,
+        ));
+        BVH::build(&triangle_list);
+    }
+
+    #[test]
+    fn sliver_triangle_intersection() {
+        // x方向に長さ100、z方向に幅0.001の細長い三角形。法線は+y
+        let sliver = triangle(
+            glam::Vec3::ZERO,
+            glam::Vec3::new(0.0, 0.0, 1e-3),
+            glam::Vec3::new(100.0, 0.0, 0.0),
+        );
+
+        // x = 50での三角形の幅は0.0005なので、その内側に上から当てる
+        let ray = Ray {
+            origin: glam::Vec3::new(50.0, 1.0, 0.25e-3),
+            dir: glam::Vec3::NEG_Y,
+        };
+        let TriangleHit::Hit { t, position, .. } =
+            sliver.intersect(&ray, BVH::RAY_MIN_T, BVH::RAY_MAX_T)
+        else {
+            panic!("ray inside the sliver must hit");
+        };
+        assert!((t - 1.0).abs() < 1e-5);
+        assert!((position - glam::Vec3::new(50.0, 0.0, 0.25e-3)).length() < 1e-5);
+
+        // tはray.dirの長さを単位とするので、正規化されていない方向でも正しい位置になる
+        let ray = Ray {
+            origin: ray.origin,
+            dir: glam::Vec3::new(0.0, -10.0, 0.0),
+        };
+        let (t, _) = sliver
+            .intersect_barycentric(&ray, BVH::RAY_MIN_T, BVH::RAY_MAX_T)
+            .unwrap();
+        assert!((t - 0.1).abs() < 1e-6);
+
+        // 幅の外側はhitしない
+        let ray = Ray {
+            origin: glam::Vec3::new(50.0, 1.0, 0.75e-3),
+            dir: glam::Vec3::NEG_Y,
+        };
+        assert!(matches!(
+            sliver.intersect(&ray, BVH::RAY_MIN_T, BVH::RAY_MAX_T),
+            TriangleHit::Miss
+        ));
+    }
+
+    #[test]
+    fn grazing_ray_intersection() {
+        let floor = triangle(
+            glam::Vec3::new(-10.0, 0.0, -10.0),
+            glam::Vec3::new(-10.0, 0.0, 10.0),
+            glam::Vec3::new(10.0, 0.0, -10.0),
+        );
+
+        // 面に対して約0.06度の浅い角度で当たるレイ
+        let ray = Ray {
+            origin: glam::Vec3::new(-9.0, 1e-3, -5.0),
+            dir: glam::Vec3::new(1.0, -1e-3, 0.0),
+        };
+        let TriangleHit::Hit {
+            t,
+            position,
+            normal,
+            ..
+        } = floor.intersect(&ray, BVH::RAY_MIN_T, BVH::RAY_MAX_T)
+        else {
+            panic!("grazing ray must hit");
+        };
+        assert!((t - 1.0).abs() < 1e-4);
+        assert!(position.abs_diff_eq(glam::Vec3::new(-8.0, 0.0, -5.0), 1e-4));
+        assert!(position.y == 0.0);
+        assert!(normal.abs_diff_eq(glam::Vec3::Y, 1e-6));
+
+        // 面と平行なレイはNaNにならずにmissになる
+        for origin_y in [1e-3, 0.0] {
+            let ray = Ray {
+                origin: glam::Vec3::new(-9.0, origin_y, -5.0),
+                dir: glam::Vec3::X,
+            };
+            assert!(matches!(
+                floor.intersect(&ray, BVH::RAY_MIN_T, BVH::RAY_MAX_T),
+                TriangleHit::Miss
+            ));
+        }
+    }
+
+    #[test]
+    fn glass_sample_near_critical_angle_is_finite() {
+        let glass = Material::Glass {
+            inner_eta: 1.5,
+            outer_eta: 1.0,
+        };
+        let mut rng = rand::thread_rng();
+        // 内側からの臨界角(約41.8度)の前後の入射角でサンプリングしてもNaNにならない
+        for i in 0..=200 {
+            let theta_i = (40.0 + i as f32 * 0.02).to_radians();
+            let view_dir = glam::Vec3::new(theta_i.sin(), theta_i.cos(), 0.0);
+            for _ in 0..16 {
+                let sample = glass.sample(view_dir, false, &mut rng).unwrap();
+                assert!(sample.sample_dir_tangent.is_finite(), "theta_i {theta_i}");
+                assert!(sample.bsdf_multiplied_cos_divided_by_pdf.is_finite());
+            }
+        }
+    }
+}
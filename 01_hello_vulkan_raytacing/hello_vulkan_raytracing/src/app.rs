@@ -78,6 +78,7 @@ pub struct App {
     in_flight_fences: Vec<vk::Fence>,
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
+    images_in_flight: Vec<vk::Fence>,
     current_frame: usize,
     dirty_swapchain: bool,
 }
@@ -92,6 +93,9 @@ impl App {
         AccelerationStructure::name(),
         DeferredHostOperations::name(),
     ];
+    // 同時に処理するフレームの数。
+    // swapchain imageの数とは独立に、この数だけcommand bufferとsync objectsを用意する。
+    const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
     unsafe extern "system" fn vulkan_debug_utils_callback(
         message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
@@ -1737,7 +1741,7 @@ impl App {
             let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
                 .command_pool(command_pool)
                 .level(vk::CommandBufferLevel::PRIMARY)
-                .command_buffer_count(swapchain_images.len() as u32);
+                .command_buffer_count(Self::MAX_FRAMES_IN_FLIGHT as u32);
             let command_buffers =
                 unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }?;
             command_buffers
@@ -1747,27 +1751,30 @@ impl App {
         let fence_create_info =
             vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
         let mut in_flight_fences = vec![];
-        for _ in 0..swapchain_images.len() {
+        for _ in 0..Self::MAX_FRAMES_IN_FLIGHT {
             let fence = unsafe { device.create_fence(&fence_create_info, None)? };
             in_flight_fences.push(fence);
         }
 
         // semaphoreの作成
         let mut image_available_semaphores = vec![];
-        for _ in 0..swapchain_images.len() {
+        for _ in 0..Self::MAX_FRAMES_IN_FLIGHT {
             let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
             let timeline_semaphore =
                 unsafe { device.create_semaphore(&semaphore_create_info, None)? };
             image_available_semaphores.push(timeline_semaphore);
         }
         let mut render_finished_semaphores = vec![];
-        for _ in 0..swapchain_images.len() {
+        for _ in 0..Self::MAX_FRAMES_IN_FLIGHT {
             let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
             let timeline_semaphore =
                 unsafe { device.create_semaphore(&semaphore_create_info, None)? };
             render_finished_semaphores.push(timeline_semaphore);
         }
 
+        // swapchain imageごとに、そのimageを使っているフレームのfence
+        let images_in_flight = vec![vk::Fence::null(); swapchain_images.len()];
+
         Ok(Self {
             width,
             height,
@@ -1823,6 +1830,7 @@ impl App {
             in_flight_fences,
             image_available_semaphores,
             render_finished_semaphores,
+            images_in_flight,
             current_frame: 0,
             dirty_swapchain: false,
         })
@@ -2140,7 +2148,7 @@ impl App {
         let fence_create_info =
             vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
         let mut in_flight_fences = vec![];
-        for _ in 0..self.swapchain_images.len() {
+        for _ in 0..Self::MAX_FRAMES_IN_FLIGHT {
             let fence = unsafe { self.device.create_fence(&fence_create_info, None)? };
             in_flight_fences.push(fence);
         }
@@ -2148,14 +2156,14 @@ impl App {
 
         // semaphoreの作成
         let mut image_available_semaphores = vec![];
-        for _ in 0..self.swapchain_images.len() {
+        for _ in 0..Self::MAX_FRAMES_IN_FLIGHT {
             let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
             let timeline_semaphore =
                 unsafe { self.device.create_semaphore(&semaphore_create_info, None)? };
             image_available_semaphores.push(timeline_semaphore);
         }
         let mut render_finished_semaphores = vec![];
-        for _ in 0..self.swapchain_images.len() {
+        for _ in 0..Self::MAX_FRAMES_IN_FLIGHT {
             let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
             let timeline_semaphore =
                 unsafe { self.device.create_semaphore(&semaphore_create_info, None)? };
@@ -2163,6 +2171,7 @@ impl App {
         }
         self.image_available_semaphores = image_available_semaphores;
         self.render_finished_semaphores = render_finished_semaphores;
+        self.images_in_flight = vec![vk::Fence::null(); self.swapchain_images.len()];
 
         // current frameをリセットする
         self.current_frame = 0;
@@ -2182,6 +2191,15 @@ impl App {
             self.recreate_swapchain(width, height)?;
         }
 
+        // このフレームの前回のコマンドの完了を待機
+        unsafe {
+            self.device.wait_for_fences(
+                std::slice::from_ref(&self.in_flight_fences[self.current_frame]),
+                true,
+                u64::MAX,
+            )
+        }?;
+
         // swapchainから次のimageを取得
         let result = unsafe {
             self.swapchain_loader.acquire_next_image(
@@ -2200,14 +2218,17 @@ impl App {
             Err(error) => return Err(anyhow::anyhow!(error)),
         };
 
-        // fenceを待機
-        unsafe {
-            self.device.wait_for_fences(
-                std::slice::from_ref(&self.in_flight_fences[self.current_frame]),
-                true,
-                u64::MAX,
-            )
-        }?;
+        // 取得したimageを別のフレームがまだ使っている場合はそのfenceを待機
+        if self.images_in_flight[index] != vk::Fence::null() {
+            unsafe {
+                self.device.wait_for_fences(
+                    std::slice::from_ref(&self.images_in_flight[index]),
+                    true,
+                    u64::MAX,
+                )
+            }?;
+        }
+        self.images_in_flight[index] = self.in_flight_fences[self.current_frame];
 
         // fenceをリセット
         unsafe {
@@ -2466,7 +2487,7 @@ impl App {
         self.dirty_swapchain = is_dirty_swapchain;
 
         // current_frameを更新
-        self.current_frame = (self.current_frame + 1) % self.in_flight_fences.len();
+        self.current_frame = (self.current_frame + 1) % Self::MAX_FRAMES_IN_FLIGHT;
 
         Ok(())
     }
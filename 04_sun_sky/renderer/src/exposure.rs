@@ -0,0 +1,18 @@
+//! 物理カメラのパラメータ(絞り、シャッタースピード、ISO感度)から
+//! 出力時に掛ける線形の露出倍率を計算するヘルパー関数を定義する。
+
+/// 絞り(F値)、シャッタースピード(秒)、ISO感度からEV100を計算する。
+///
+/// EV100 = log2(N^2 / t * 100 / ISO)
+pub fn ev100_from_physical(aperture: f32, shutter_speed: f32, iso: f32) -> f32 {
+    (aperture * aperture / shutter_speed * 100.0 / iso).log2()
+}
+
+/// EV100から放射輝度に掛ける線形の露出倍率を計算する。
+///
+/// 反射式露出計の式から平均輝度を L_avg = 1000 / 65 * 2^EV100 / 100 とし、
+/// それが18%グレーになるように 0.18 / L_avg を露出倍率とする。
+pub fn exposure_multiplier(ev100: f32) -> f32 {
+    let l_avg = 1000.0 / 65.0 * ev100.exp2() / 100.0;
+    0.18 / l_avg
+}
@@ -1,3 +1,4 @@
+pub mod exposure;
 mod renderer;
 pub use renderer::Renderer;
 mod scene;
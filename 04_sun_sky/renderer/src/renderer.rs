@@ -42,9 +42,7 @@ struct FinalPushConstants {
     input_index: u32,
     output_index: u32,
     l_white: f32,
-    aperture: f32,
-    shutter_speed: f32,
-    iso: f32,
+    exposure: f32,
 }
 
 pub struct Renderer {
@@ -643,9 +641,13 @@ impl Renderer {
                 input_index: self.accumulate_storage_image_index,
                 output_index: self.final_storage_image_indices[self.current_image_index],
                 l_white: self.params.l_white,
-                aperture: self.params.aperture,
-                shutter_speed: self.params.shutter_speed,
-                iso: self.params.iso,
+                exposure: crate::exposure::exposure_multiplier(
+                    crate::exposure::ev100_from_physical(
+                        self.params.aperture,
+                        self.params.shutter_speed,
+                        self.params.iso,
+                    ),
+                ),
             }],
         );
         command_buffer.cmd_dispatch((self.params.width + 7) / 8, (self.params.height + 7) / 8, 1);
@@ -0,0 +1,72 @@
+//! Handleのclone/dropのスループットを計測するベンチマーク。
+//!
+//! Handleのcloneは参照カウントのatomicなインクリメント、dropはデクリメントなので、
+//! シングルスレッドでの1回あたりのコストと、複数スレッドから同じHandleを
+//! clone/dropした場合の競合によるコストを計測する。
+//! Vulkanのデバイスが必要なので、GPUのある環境で`cargo bench -p ashtray`で実行する。
+
+use ashtray::{utils, DeviceHandle, InstanceHandle};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+const THREAD_COUNT: usize = 4;
+const CLONES_PER_THREAD: usize = 10_000;
+
+fn create_device() -> (InstanceHandle, DeviceHandle) {
+    let instance = InstanceHandle::new_headless();
+    let required_device_extensions = utils::get_required_headless_device_extensions(&[]);
+    let physical_device = utils::select_headless_physical_device(
+        &instance,
+        &required_device_extensions,
+        &utils::PhysicalDevicePreference::default(),
+    );
+    let queue_indices = utils::get_headless_queue_indices(&instance, physical_device);
+    let device = utils::create_device(
+        &instance,
+        physical_device,
+        &queue_indices,
+        &required_device_extensions,
+    );
+    (instance, device)
+}
+
+fn clone_drop(c: &mut Criterion) {
+    let (instance, device) = create_device();
+
+    c.bench_function("InstanceHandle clone/drop", |b| {
+        b.iter(|| drop(black_box(instance.clone())))
+    });
+    c.bench_function("DeviceHandle clone/drop", |b| {
+        b.iter(|| drop(black_box(device.clone())))
+    });
+    c.bench_function("DeviceHandle clone/drop borrowed", |b| {
+        // cloneせずに参照を渡す場合との比較用
+        b.iter(|| black_box(&device))
+    });
+
+    // 複数スレッドから同じHandleをclone/dropして、参照カウントの競合を計測する
+    c.bench_function(
+        &format!("DeviceHandle clone/drop contended ({THREAD_COUNT} threads)"),
+        |b| {
+            b.iter_batched(
+                || device.clone(),
+                |device| {
+                    std::thread::scope(|s| {
+                        for _ in 0..THREAD_COUNT {
+                            s.spawn(|| {
+                                for _ in 0..CLONES_PER_THREAD {
+                                    drop(black_box(device.clone()));
+                                }
+                            });
+                        }
+                    });
+                },
+                BatchSize::SmallInput,
+            )
+        },
+    );
+
+    device.wait_idle();
+}
+
+criterion_group!(benches, clone_drop);
+criterion_main!(benches);
@@ -4,6 +4,9 @@
 //! 参照カウントの実装には「詳解 Rustアトミック操作とロック ―並行処理実装のための低レイヤプログラミング」の
 //! Arcの実装を参考にしている。
 //! メモリのOrderingなどは、それに準拠している。
+//! cloneでは参照カウントをRelaxedでインクリメントし、
+//! dropではReleaseでデクリメントして最後の参照だった場合のみAcquireのfenceを置いてから破棄する。
+//! cloneはatomicな操作になるので、毎フレーム呼ばれる処理ではなるべく参照を渡すようにする。
 
 mod instance;
 pub use instance::InstanceHandle;
@@ -14,11 +17,11 @@ pub use device::DeviceHandle;
 mod command_pool;
 pub use command_pool::CommandPoolHandle;
 mod command_buffer;
-pub use command_buffer::CommandBufferHandle;
+pub use command_buffer::{CommandBufferHandle, DebugLabelScope};
 mod swapchain;
 pub use swapchain::SwapchainHandle;
 mod image;
-pub use image::ImageHandle;
+pub use image::{ExternalMemoryHandle, ImageHandle};
 mod image_view;
 pub use image_view::ImageViewHandle;
 mod sampler;
@@ -35,6 +38,8 @@ mod descriptor_set;
 pub use descriptor_set::DescriptorSetHandle;
 mod pipeline_layout;
 pub use pipeline_layout::PipelineLayoutHandle;
+mod pipeline_cache;
+pub use pipeline_cache::PipelineCacheHandle;
 mod compute_pipeline;
 pub use compute_pipeline::ComputePipelineHandle;
 mod semaphore;
@@ -45,8 +50,13 @@ mod acceleration_structure;
 pub use acceleration_structure::AccelerationStructureHandle;
 mod raytracing_pipeline;
 pub use raytracing_pipeline::RayTracingPipelineHandle;
+mod query_pool;
+pub use query_pool::QueryPoolHandle;
+
+mod raw_handle_guard;
+pub use raw_handle_guard::RawHandleGuard;
 
 mod allocator;
-pub use allocator::AllocatorHandle;
+pub use allocator::{AllocatorHandle, MemoryReport};
 mod allocation;
 pub use allocation::AllocationHandle;
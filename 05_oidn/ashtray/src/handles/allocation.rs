@@ -29,6 +29,7 @@ impl AllocationHandleData {
             .unwrap()
             .allocate(allocation_create_desc)
             .expect("Failed to allocate memory.");
+        allocator.track_allocation(&allocation, true);
 
         Ok(Self {
             device,
@@ -128,6 +129,7 @@ impl Drop for AllocationHandle {
                 let data = Box::from_raw(self.ptr.as_ptr());
 
                 // device_memoryの破棄
+                data.allocator.track_allocation(&data.allocation, false);
                 let mut allocator = data.allocator.lock().unwrap();
                 allocator
                     .free(data.allocation)
@@ -2,17 +2,31 @@
 //! AllocatorはDrop時に自動で破棄される。
 
 use anyhow::Result;
+use ash::vk;
 use gpu_allocator::vulkan::*;
 use std::{
     fmt::Debug,
     ops::Deref,
     ptr::NonNull,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
 };
 
+/// AllocatorHandle::reportで取得するdevice localメモリの使用状況
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// DEVICE_LOCALなmemory heapのサイズの合計(バイト)
+    pub device_local_total: u64,
+    /// このAllocatorHandleで確保中のDEVICE_LOCALなallocationのサイズの合計(バイト)
+    pub device_local_allocated: u64,
+}
+
 struct AllocatorHandleData {
     device: crate::DeviceHandle,
     allocator: Arc<Mutex<Allocator>>,
+    device_local_allocated: AtomicU64,
 }
 impl AllocatorHandleData {
     fn new(
@@ -23,7 +37,11 @@ impl AllocatorHandleData {
         let allocator = Allocator::new(allocator_create_desc)?;
         let allocator = Arc::new(Mutex::new(allocator));
 
-        Ok(Self { device, allocator })
+        Ok(Self {
+            device,
+            allocator,
+            device_local_allocated: AtomicU64::new(0),
+        })
     }
 }
 
@@ -55,6 +73,37 @@ impl AllocatorHandle {
         crate::AllocationHandle::new(self.device(), self.clone(), allocation_create_desc)
     }
 
+    /// device localメモリの使用状況を取得する
+    pub fn report(&self) -> MemoryReport {
+        let memory_properties = self.device().get_physical_device_memory_properties();
+        let device_local_total = memory_properties.memory_heaps
+            [..memory_properties.memory_heap_count as usize]
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum();
+        MemoryReport {
+            device_local_total,
+            device_local_allocated: self.data().device_local_allocated.load(Ordering::Relaxed),
+        }
+    }
+
+    // AllocationHandleの確保と破棄の際にdevice localメモリの使用量を記録する
+    pub(crate) fn track_allocation(&self, allocation: &Allocation, allocated: bool) {
+        if !allocation
+            .memory_properties()
+            .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+        {
+            return;
+        }
+        let counter = &self.data().device_local_allocated;
+        if allocated {
+            counter.fetch_add(allocation.size(), Ordering::Relaxed);
+        } else {
+            counter.fetch_sub(allocation.size(), Ordering::Relaxed);
+        }
+    }
+
     // raw
 
     /// DeviceHandleを取得する
@@ -13,6 +13,7 @@ use std::{
 struct BufferHandleData {
     device: crate::DeviceHandle,
     buffer: vk::Buffer,
+    size: vk::DeviceSize,
     ref_count: AtomicUsize,
 }
 impl BufferHandleData {
@@ -23,6 +24,7 @@ impl BufferHandleData {
         Ok(Self {
             device,
             buffer,
+            size: buffer_create_info.size,
             ref_count: AtomicUsize::new(1),
         })
     }
@@ -64,6 +66,11 @@ impl BufferHandle {
         }
     }
 
+    /// 作成時に指定したBufferのサイズを取得する
+    pub fn size(&self) -> vk::DeviceSize {
+        self.data().size
+    }
+
     // raw
 
     /// DeviceHandleを取得する
@@ -79,6 +86,13 @@ impl BufferHandle {
         self.data().buffer.clone()
     }
 
+    /// vk::BufferとそのBufferを破棄させないためのガードに分ける。
+    /// ## Safety
+    /// 取り出したvk::Bufferはガードがdropされると無効になるので、ガードより長く使ってはいけない。
+    pub unsafe fn into_raw(self) -> (vk::Buffer, crate::RawHandleGuard<Self>) {
+        (self.buffer_raw(), crate::RawHandleGuard::new(self))
+    }
+
     fn data(&self) -> &BufferHandleData {
         unsafe { self.ptr.as_ref() }
     }
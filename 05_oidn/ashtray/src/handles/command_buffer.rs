@@ -14,6 +14,8 @@ struct CommandBufferHandleData {
     device: crate::DeviceHandle,
     command_pool: crate::CommandPoolHandle,
     command_buffer: vk::CommandBuffer,
+    // 開いたままのGpuTimerScopeの数
+    open_timer_scopes: AtomicUsize,
     ref_count: AtomicUsize,
 }
 impl CommandBufferHandleData {
@@ -33,6 +35,7 @@ impl CommandBufferHandleData {
                 device: device.clone(),
                 command_pool: command_pool.clone(),
                 command_buffer,
+                open_timer_scopes: AtomicUsize::new(0),
                 ref_count: AtomicUsize::new(1),
             })
             .collect();
@@ -78,7 +81,14 @@ impl CommandBufferHandle {
     }
 
     /// CommandBufferを終了する
+    ///
+    /// GpuTimerScopeが開いたままの場合はdebugビルドでpanicする。
     pub fn end_command_buffer(&self) {
+        debug_assert_eq!(
+            self.data().open_timer_scopes.load(Ordering::Relaxed),
+            0,
+            "GpuTimerScope must be dropped before end_command_buffer."
+        );
         unsafe {
             self.data()
                 .device
@@ -133,6 +143,104 @@ impl CommandBufferHandle {
         }
     }
 
+    /// srcのsrc_offsetからsizeバイトをdstのdst_offsetにコピーするcopy bufferコマンドを積む
+    ///
+    /// 範囲がbufferのサイズを超える場合はdebugビルドでpanicする。
+    pub fn cmd_copy_buffer_region(
+        &self,
+        src_buffer: &crate::BufferHandle,
+        dst_buffer: &crate::BufferHandle,
+        src_offset: vk::DeviceSize,
+        dst_offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) {
+        debug_assert!(
+            src_offset
+                .checked_add(size)
+                .is_some_and(|end| end <= src_buffer.size()),
+            "Copy source range {src_offset}..+{size} exceeds buffer size {}.",
+            src_buffer.size()
+        );
+        debug_assert!(
+            dst_offset
+                .checked_add(size)
+                .is_some_and(|end| end <= dst_buffer.size()),
+            "Copy destination range {dst_offset}..+{size} exceeds buffer size {}.",
+            dst_buffer.size()
+        );
+        self.cmd_copy_buffer(
+            src_buffer,
+            dst_buffer,
+            &[vk::BufferCopy {
+                src_offset,
+                dst_offset,
+                size,
+            }],
+        );
+    }
+
+    /// fill bufferコマンドを積む
+    ///
+    /// offsetとsizeは4の倍数である必要がある。
+    /// sizeに`vk::WHOLE_SIZE`を指定するとoffset以降のbuffer全体を埋める。
+    /// 範囲がbufferのサイズを超える場合はdebugビルドでpanicする。
+    pub fn cmd_fill_buffer(
+        &self,
+        dst_buffer: &crate::BufferHandle,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+        data: u32,
+    ) {
+        debug_assert!(
+            if size == vk::WHOLE_SIZE {
+                offset <= dst_buffer.size()
+            } else {
+                offset
+                    .checked_add(size)
+                    .is_some_and(|end| end <= dst_buffer.size())
+            },
+            "Fill range {offset}..+{size} exceeds buffer size {}.",
+            dst_buffer.size()
+        );
+        unsafe {
+            self.data().device.cmd_fill_buffer(
+                self.command_buffer_raw(),
+                **dst_buffer,
+                offset,
+                size,
+                data,
+            )
+        }
+    }
+
+    /// update bufferコマンドを積む
+    ///
+    /// 小さなデータをコマンドバッファに埋め込んでアップロードするためのもの。
+    /// dataのサイズは65536バイト以下かつ4の倍数、offsetは4の倍数である必要がある。
+    pub fn cmd_update_buffer(
+        &self,
+        dst_buffer: &crate::BufferHandle,
+        offset: vk::DeviceSize,
+        data: &[u8],
+    ) {
+        assert!(
+            data.len() <= 65536,
+            "cmd_update_buffer data size must be less than or equal to 65536 bytes."
+        );
+        assert!(
+            data.len().is_multiple_of(4),
+            "cmd_update_buffer data size must be a multiple of 4."
+        );
+        unsafe {
+            self.data().device.cmd_update_buffer(
+                self.command_buffer_raw(),
+                **dst_buffer,
+                offset,
+                data,
+            )
+        }
+    }
+
     /// copy buffer to imageコマンドを積む
     pub fn cmd_copy_buffer_to_image(
         &self,
@@ -171,6 +279,29 @@ impl CommandBufferHandle {
         }
     }
 
+    /// blit imageコマンドを積む
+    pub fn cmd_blit_image(
+        &self,
+        src_image: &crate::ImageHandle,
+        src_image_layout: vk::ImageLayout,
+        dst_image: &crate::ImageHandle,
+        dst_image_layout: vk::ImageLayout,
+        regions: &[vk::ImageBlit],
+        filter: vk::Filter,
+    ) {
+        unsafe {
+            self.data().device.cmd_blit_image(
+                self.command_buffer_raw(),
+                **src_image,
+                src_image_layout,
+                **dst_image,
+                dst_image_layout,
+                regions,
+                filter,
+            )
+        }
+    }
+
     /// pipeline barrier2コマンドを積む
     pub fn cmd_pipeline_barrier2(&self, dependency_info: &vk::DependencyInfoKHR) {
         unsafe {
@@ -240,20 +371,30 @@ impl CommandBufferHandle {
     }
 
     /// PushConstantsを積むコマンドを積む
+    ///
+    /// valuesをバイト列にキャストして積む。
+    /// デバッグビルドではpipeline layoutのpush constant rangeと
+    /// stage_flags、offset、Tのサイズが食い違っていないかをチェックする。
     pub fn cmd_push_constants<T: bytemuck::Pod>(
         &self,
         pipeline_layout: &crate::PipelineLayoutHandle,
         stage_flags: vk::ShaderStageFlags,
         offset: u32,
-        values: &[T],
+        values: &T,
     ) {
+        debug_assert_push_constant_ranges(
+            pipeline_layout.push_constant_ranges(),
+            stage_flags,
+            offset,
+            std::mem::size_of::<T>() as u32,
+        );
         unsafe {
             self.data().device.cmd_push_constants(
                 self.command_buffer_raw(),
                 **pipeline_layout,
                 stage_flags,
                 offset,
-                bytemuck::cast_slice(values),
+                bytemuck::bytes_of(values),
             )
         }
     }
@@ -276,7 +417,78 @@ impl CommandBufferHandle {
         }
     }
 
+    /// acceleration structureのプロパティをqueryに書き込むコマンドを積む
+    pub fn cmd_write_acceleration_structures_properties(
+        &self,
+        acceleration_structures: &[crate::AccelerationStructureHandle],
+        query_type: vk::QueryType,
+        query_pool: &crate::QueryPoolHandle,
+        first_query: u32,
+    ) {
+        let acceleration_structures = acceleration_structures
+            .iter()
+            .map(|a| **a)
+            .collect::<Vec<_>>();
+        unsafe {
+            self.data()
+                .device
+                .acceleration_structure_loader_raw()
+                .cmd_write_acceleration_structures_properties(
+                    self.command_buffer_raw(),
+                    &acceleration_structures,
+                    query_type,
+                    **query_pool,
+                    first_query,
+                )
+        }
+    }
+
+    /// acceleration structureをコピーするコマンドを積む
+    pub fn cmd_copy_acceleration_structure(&self, info: &vk::CopyAccelerationStructureInfoKHR) {
+        unsafe {
+            self.data()
+                .device
+                .acceleration_structure_loader_raw()
+                .cmd_copy_acceleration_structure(self.command_buffer_raw(), info)
+        }
+    }
+
+    /// timestampをqueryに書き込むコマンドを積む
+    pub fn cmd_write_timestamp(
+        &self,
+        pipeline_stage: vk::PipelineStageFlags,
+        query_pool: &crate::QueryPoolHandle,
+        query: u32,
+    ) {
+        unsafe {
+            self.data().device.cmd_write_timestamp(
+                self.command_buffer_raw(),
+                pipeline_stage,
+                **query_pool,
+                query,
+            )
+        }
+    }
+
+    /// queryをリセットするコマンドを積む
+    pub fn cmd_reset_query_pool(
+        &self,
+        query_pool: &crate::QueryPoolHandle,
+        first_query: u32,
+        query_count: u32,
+    ) {
+        unsafe {
+            self.data().device.cmd_reset_query_pool(
+                self.command_buffer_raw(),
+                **query_pool,
+                first_query,
+                query_count,
+            )
+        }
+    }
+
     /// RayTracingを起動するコマンドを積む
+    #[allow(clippy::too_many_arguments)]
     pub fn cmd_trace_rays(
         &self,
         raygen_shader_binding_table_entry: &vk::StridedDeviceAddressRegionKHR,
@@ -304,6 +516,39 @@ impl CommandBufferHandle {
         }
     }
 
+    /// debug utilsのlabelの開始を積む
+    ///
+    /// RenderDocなどのキャプチャでcmd_end_labelまでのコマンドがnameの区間として表示される。
+    /// debug utilsが有効でない場合は何もしない。
+    pub fn cmd_begin_label(&self, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils_loader) = self.data().device.debug_utils_loader() {
+            let name = std::ffi::CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+            unsafe {
+                debug_utils_loader.cmd_begin_debug_utils_label(self.command_buffer_raw(), &label)
+            }
+        }
+    }
+
+    /// cmd_begin_labelで開始したlabelを終了する
+    ///
+    /// debug utilsが有効でない場合は何もしない。
+    pub fn cmd_end_label(&self) {
+        if let Some(debug_utils_loader) = self.data().device.debug_utils_loader() {
+            unsafe { debug_utils_loader.cmd_end_debug_utils_label(self.command_buffer_raw()) }
+        }
+    }
+
+    /// dropされるまでのコマンドをnameの区間にするDebugLabelScopeを作成する
+    pub fn debug_label(&self, name: &str, color: [f32; 4]) -> DebugLabelScope<'_> {
+        self.cmd_begin_label(name, color);
+        DebugLabelScope {
+            command_buffer: self,
+        }
+    }
+
     // raw
 
     /// DeviceHandleを取得する
@@ -319,11 +564,36 @@ impl CommandBufferHandle {
         self.data().command_buffer.clone()
     }
 
+    // GpuTimerScopeの開始と終了を記録する
+    pub(crate) fn open_timer_scope(&self) {
+        self.data()
+            .open_timer_scopes
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn close_timer_scope(&self) {
+        self.data()
+            .open_timer_scopes
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+
     fn data(&self) -> &CommandBufferHandleData {
         unsafe { self.ptr.as_ref() }
     }
 }
 
+/// dropされるまでのコマンドをdebug utilsのlabelの区間にするスコープ
+///
+/// 作成時にlabelの開始を、dropで終了をcommand bufferに積む。
+/// command bufferをendする前にdropする必要がある。
+pub struct DebugLabelScope<'a> {
+    command_buffer: &'a CommandBufferHandle,
+}
+impl Drop for DebugLabelScope<'_> {
+    fn drop(&mut self) {
+        self.command_buffer.cmd_end_label();
+    }
+}
+
 // Debugトレイト実装
 impl Debug for CommandBufferHandle {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -369,3 +639,110 @@ impl Drop for CommandBufferHandle {
         }
     }
 }
+
+// cmd_push_constantsのstage_flags、offset、sizeがpipeline layoutの
+// push constant rangeと矛盾しないかをデバッグビルドでチェックする。
+fn debug_assert_push_constant_ranges(
+    ranges: &[vk::PushConstantRange],
+    stage_flags: vk::ShaderStageFlags,
+    offset: u32,
+    size: u32,
+) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    for byte in offset..offset + size {
+        // VUID-vkCmdPushConstants-offset-01795:
+        // 積む各stageについて、そのbyteとstageを両方含むrangeが存在する
+        for bit in 0..u32::BITS {
+            let stage = vk::ShaderStageFlags::from_raw(1 << bit);
+            if !stage_flags.contains(stage) {
+                continue;
+            }
+            assert!(
+                ranges.iter().any(|range| range.stage_flags.contains(stage)
+                    && range.offset <= byte
+                    && byte < range.offset + range.size),
+                "Push constants (offset: {offset}, size: {size}, stages: {stage_flags:?}) \
+                 byte {byte} for stage {stage:?} is not in any pipeline layout push constant \
+                 range: {ranges:?}",
+            );
+        }
+        // VUID-vkCmdPushConstants-offset-01796:
+        // そのbyteを含むrangeのstageはすべて積むstageに含まれる
+        for range in ranges
+            .iter()
+            .filter(|range| range.offset <= byte && byte < range.offset + range.size)
+        {
+            assert!(
+                stage_flags.contains(range.stage_flags),
+                "Push constants (offset: {offset}, size: {size}, stages: {stage_flags:?}) \
+                 do not include all stages of the overlapping pipeline layout push constant \
+                 range: {range:?}",
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(stage_flags: vk::ShaderStageFlags, offset: u32, size: u32) -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags,
+            offset,
+            size,
+        }
+    }
+
+    #[test]
+    fn push_with_all_layout_stages_passes() {
+        let stages = vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::INTERSECTION_KHR;
+        debug_assert_push_constant_ranges(&[range(stages, 0, 64)], stages, 0, 64);
+    }
+
+    #[test]
+    fn push_into_disjoint_ranges_passes() {
+        let ranges = [
+            range(vk::ShaderStageFlags::VERTEX, 0, 16),
+            range(vk::ShaderStageFlags::FRAGMENT, 16, 16),
+        ];
+        debug_assert_push_constant_ranges(&ranges, vk::ShaderStageFlags::FRAGMENT, 16, 16);
+    }
+
+    #[test]
+    #[should_panic(expected = "do not include all stages")]
+    fn push_with_fewer_stages_than_layout_panics() {
+        let layout_stages =
+            vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::INTERSECTION_KHR;
+        debug_assert_push_constant_ranges(
+            &[range(layout_stages, 0, 64)],
+            vk::ShaderStageFlags::RAYGEN_KHR,
+            0,
+            64,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "is not in any pipeline layout push constant range")]
+    fn push_with_stage_missing_from_layout_panics() {
+        debug_assert_push_constant_ranges(
+            &[range(vk::ShaderStageFlags::RAYGEN_KHR, 0, 64)],
+            vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::MISS_KHR,
+            0,
+            64,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "is not in any pipeline layout push constant range")]
+    fn push_past_range_end_panics() {
+        debug_assert_push_constant_ranges(
+            &[range(vk::ShaderStageFlags::COMPUTE, 0, 16)],
+            vk::ShaderStageFlags::COMPUTE,
+            8,
+            16,
+        );
+    }
+}
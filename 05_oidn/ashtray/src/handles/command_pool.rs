@@ -49,6 +49,22 @@ impl CommandPoolHandle {
         Self { ptr }
     }
 
+    // CommandPoolの関数
+
+    /// CommandPoolをリセットする
+    ///
+    /// このCommandPoolから割り当てたすべてのCommandBufferは初期状態に戻るため、
+    /// リセット前に記録したコマンドは無効になる。
+    /// GPUで実行中のCommandBufferが存在しないことを呼び出し側で保証すること。
+    pub fn reset(&self) {
+        unsafe {
+            self.data()
+                .device
+                .reset_command_pool(self.data().command_pool, vk::CommandPoolResetFlags::empty())
+                .expect("Failed to reset command pool.");
+        }
+    }
+
     // raw
 
     /// DeviceHandleを取得する
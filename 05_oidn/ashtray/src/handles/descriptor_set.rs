@@ -1,7 +1,6 @@
 //! 参照カウンタで管理して、参照がすべて破棄された際に
 //! DescriptorSetの破棄の処理まで行うDescriptorSetHandleを定義する。
 
-use anyhow::Result;
 use ash::vk;
 use std::{
     fmt::Debug,
@@ -21,7 +20,7 @@ impl DescriptorSetHandleData {
         device: crate::DeviceHandle,
         descriptor_pool: &crate::DescriptorPoolHandle,
         descriptor_set_allocate_info: &vk::DescriptorSetAllocateInfo,
-    ) -> Result<Vec<Self>> {
+    ) -> Result<Vec<Self>, vk::Result> {
         // create descriptor_set
         let descriptor_sets = unsafe {
             ash::Device::allocate_descriptor_sets(&device, descriptor_set_allocate_info)?
@@ -66,6 +65,24 @@ impl DescriptorSetHandle {
         ptrs.into_iter().map(|ptr| Self { ptr }).collect()
     }
 
+    pub(crate) fn try_new(
+        device_handle: crate::DeviceHandle,
+        descriptor_pool: &crate::DescriptorPoolHandle,
+        descriptor_set_allocate_info: &vk::DescriptorSetAllocateInfo,
+    ) -> Result<Vec<Self>, vk::Result> {
+        let data = DescriptorSetHandleData::new(
+            device_handle,
+            descriptor_pool,
+            descriptor_set_allocate_info,
+        )?;
+        Ok(data
+            .into_iter()
+            .map(|data| Self {
+                ptr: unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(data))) },
+            })
+            .collect())
+    }
+
     // raw
 
     /// DeviceHandleを取得する
@@ -20,6 +20,8 @@ use std::{
 struct DeviceHandleData {
     instance: crate::InstanceHandle,
     physical_device: vk::PhysicalDevice,
+    enabled_features: vk::PhysicalDeviceFeatures,
+    enabled_extension_names: Vec<std::ffi::CString>,
     device: ash::Device,
     swapchain_loader: Swapchain,
     acceleration_structure_loader: AccelerationStructure,
@@ -41,6 +43,21 @@ impl DeviceHandleData {
             ash::Instance::create_device(&instance, physical_device, device_create_info, None)?
         };
 
+        // enabled features
+        let enabled_features = if device_create_info.p_enabled_features.is_null() {
+            vk::PhysicalDeviceFeatures::default()
+        } else {
+            unsafe { *device_create_info.p_enabled_features }
+        };
+
+        // enabled extension names
+        let enabled_extension_names = (0..device_create_info.enabled_extension_count as usize)
+            .map(|i| unsafe {
+                std::ffi::CStr::from_ptr(*device_create_info.pp_enabled_extension_names.add(i))
+                    .to_owned()
+            })
+            .collect();
+
         // swapchain loader
         let swapchain_loader = Swapchain::new(&instance, &device);
 
@@ -61,6 +78,8 @@ impl DeviceHandleData {
         Ok(Self {
             instance,
             physical_device,
+            enabled_features,
+            enabled_extension_names,
             device,
             swapchain_loader,
             acceleration_structure_loader,
@@ -197,6 +216,20 @@ impl DeviceHandle {
         )
     }
 
+    /// DescriptorSetHandleを割り当てる。
+    /// poolが足りない場合にpanicせずにERROR_OUT_OF_POOL_MEMORYなどのエラーを返す。
+    pub fn try_allocate_descriptor_sets(
+        &self,
+        descriptor_pool_handle: &crate::DescriptorPoolHandle,
+        descriptor_set_allocate_info: &vk::DescriptorSetAllocateInfo,
+    ) -> Result<Vec<crate::DescriptorSetHandle>, vk::Result> {
+        crate::DescriptorSetHandle::try_new(
+            self.clone(),
+            descriptor_pool_handle,
+            descriptor_set_allocate_info,
+        )
+    }
+
     /// PipelineLayoutHandleを作成する
     pub fn create_pipeline_layout(
         &self,
@@ -205,26 +238,36 @@ impl DeviceHandle {
         crate::PipelineLayoutHandle::new(self.clone(), pipeline_layout_create_info)
     }
 
+    /// PipelineCacheHandleを作成する。
+    /// initial_dataに以前get_dataで取得したバイト列を渡すと、その内容でcacheを初期化する。
+    pub fn create_pipeline_cache(&self, initial_data: Option<&[u8]>) -> crate::PipelineCacheHandle {
+        let mut create_info = vk::PipelineCacheCreateInfo::builder();
+        if let Some(initial_data) = initial_data {
+            create_info = create_info.initial_data(initial_data);
+        }
+        crate::PipelineCacheHandle::new(self.clone(), &create_info)
+    }
+
     /// ComputePipelineHandleを作成する
     pub fn create_compute_pipelines(
         &self,
-        pipeline_cache: vk::PipelineCache,
+        pipeline_cache: &crate::PipelineCacheHandle,
         create_infos: &[vk::ComputePipelineCreateInfo],
     ) -> Vec<crate::ComputePipelineHandle> {
-        crate::ComputePipelineHandle::new(self.clone(), pipeline_cache, create_infos)
+        crate::ComputePipelineHandle::new(self.clone(), **pipeline_cache, create_infos)
     }
 
     /// RayTracingPipelineHandleを作成する
     pub fn create_ray_tracing_pipelines(
         &self,
         deferred_operation: vk::DeferredOperationKHR,
-        pipeline_cache: vk::PipelineCache,
+        pipeline_cache: &crate::PipelineCacheHandle,
         create_infos: &[vk::RayTracingPipelineCreateInfoKHR],
     ) -> Vec<crate::RayTracingPipelineHandle> {
         crate::RayTracingPipelineHandle::new(
             self.clone(),
             deferred_operation,
-            pipeline_cache,
+            **pipeline_cache,
             create_infos,
         )
     }
@@ -250,6 +293,14 @@ impl DeviceHandle {
         crate::AccelerationStructureHandle::new(self.clone(), acceleration_structure_create_info)
     }
 
+    /// QueryPoolHandleを作成する
+    pub fn create_query_pool(
+        &self,
+        query_pool_create_info: &vk::QueryPoolCreateInfo,
+    ) -> crate::QueryPoolHandle {
+        crate::QueryPoolHandle::new(self.clone(), query_pool_create_info)
+    }
+
     // 他のDeviceの関数
 
     /// vk::Queueを取得する
@@ -361,6 +412,35 @@ impl DeviceHandle {
         }
     }
 
+    /// QueueにVkSubmitInfo2でコマンドをSubmitする。
+    ///
+    /// SemaphoreSubmitInfoで待機するsemaphoreとsignalするsemaphoreを、
+    /// それぞれのstage maskとtimeline semaphoreの値とともに指定できる。
+    /// semaphoreの待機とsignalはsubmitするqueueの上で行われるので、
+    /// 例えばtransfer queueへのsubmitでsignalしたsemaphoreをcompute queueへのsubmitで待機すると、
+    /// queue familyをまたいでtransferの完了後にcomputeを実行するように直列化できる。
+    /// EXCLUSIVEなリソースをqueue family間で受け渡す場合は、
+    /// semaphoreとは別にqueue familyのownershipを移すbarrierが必要になる。
+    ///
+    /// 待機するsemaphoreのない一度きりの処理には従来のqueue_submitを使えばよい。
+    pub fn queue_submit2(
+        &self,
+        queue: vk::Queue,
+        submit_infos: &[vk::SubmitInfo2],
+        fence: Option<crate::FenceHandle>,
+    ) {
+        unsafe {
+            self.data()
+                .device
+                .queue_submit2(
+                    queue,
+                    submit_infos,
+                    fence.map(|f| f.fence_raw()).unwrap_or(vk::Fence::null()),
+                )
+                .expect("Failed to submit queue.");
+        }
+    }
+
     /// Queueにpresentを行う
     pub fn queue_present(
         &self,
@@ -412,6 +492,28 @@ impl DeviceHandle {
         }
     }
 
+    /// timeline semaphoreがそれぞれの値に達するのを待機する
+    pub fn wait_semaphores(
+        &self,
+        semaphores: &[crate::SemaphoreHandle],
+        values: &[u64],
+        timeout: u64,
+    ) {
+        unsafe {
+            let semaphores = semaphores
+                .iter()
+                .map(|semaphore| semaphore.semaphore_raw())
+                .collect::<Vec<_>>();
+            let wait_info = vk::SemaphoreWaitInfo::builder()
+                .semaphores(&semaphores)
+                .values(values);
+            self.data()
+                .device
+                .wait_semaphores(&wait_info, timeout)
+                .expect("Failed to wait semaphore.")
+        }
+    }
+
     /// AccelerationStructureのビルドサイズを取得する
     pub fn get_acceleration_structure_build_sizes(
         &self,
@@ -437,6 +539,64 @@ impl DeviceHandle {
             .get_physical_device_memory_properties(self.data().physical_device)
     }
 
+    /// physical device propertiesを取得する
+    pub fn get_physical_device_properties(&self) -> vk::PhysicalDeviceProperties {
+        self.data()
+            .instance
+            .get_physical_device_properties(self.data().physical_device)
+    }
+
+    /// physical deviceのformatのpropertiesを取得する
+    pub fn get_physical_device_format_properties(
+        &self,
+        format: vk::Format,
+    ) -> vk::FormatProperties {
+        self.data()
+            .instance
+            .get_physical_device_format_properties(self.data().physical_device, format)
+    }
+
+    /// Device作成時に有効化したphysical device featuresを取得する
+    pub fn enabled_features(&self) -> vk::PhysicalDeviceFeatures {
+        self.data().enabled_features
+    }
+
+    /// Device作成時にdevice拡張を有効化したかどうかを取得する
+    pub fn is_extension_enabled(&self, name: &std::ffi::CStr) -> bool {
+        self.data()
+            .enabled_extension_names
+            .iter()
+            .any(|enabled| enabled.as_c_str() == name)
+    }
+
+    /// queueにdebug utilsのlabelの開始を積む
+    ///
+    /// RenderDocなどのキャプチャでqueue_end_labelまでのsubmitがnameの区間として表示される。
+    /// debug utilsが有効でない場合は何もしない。
+    pub fn queue_begin_label(&self, queue: vk::Queue, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils_loader) = self.debug_utils_loader() {
+            let name = std::ffi::CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+            unsafe { debug_utils_loader.queue_begin_debug_utils_label(queue, &label) }
+        }
+    }
+
+    /// queue_begin_labelで開始したlabelを終了する
+    ///
+    /// debug utilsが有効でない場合は何もしない。
+    pub fn queue_end_label(&self, queue: vk::Queue) {
+        if let Some(debug_utils_loader) = self.debug_utils_loader() {
+            unsafe { debug_utils_loader.queue_end_debug_utils_label(queue) }
+        }
+    }
+
+    // debug utilsが有効な場合にそのloaderを取得する
+    pub(crate) fn debug_utils_loader(&self) -> Option<&ash::extensions::ext::DebugUtils> {
+        self.data().instance.debug_utils_loader()
+    }
+
     // raw
 
     /// InstanceHandleを取得する
@@ -452,6 +612,13 @@ impl DeviceHandle {
         self.data().device.clone()
     }
 
+    /// DeviceとそのDeviceを破棄させないためのガードに分ける。
+    /// ## Safety
+    /// 取り出したDeviceはガードがdropされると無効になるので、ガードより長く使ってはいけない。
+    pub unsafe fn into_raw(self) -> (ash::Device, crate::RawHandleGuard<Self>) {
+        (self.device_raw(), crate::RawHandleGuard::new(self))
+    }
+
     /// Swapchainを取得する
     /// ## Safety
     /// 参照カウントの管理から中身を取り出すので注意。
@@ -1,18 +1,32 @@
 //! 参照カウンタで管理して、参照がすべて破棄された際に
 //! Imageの破棄の処理まで行うImageHandleを定義する。
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use ash::vk;
 use std::{
+    ffi::c_void,
     fmt::Debug,
     ops::Deref,
     ptr::NonNull,
     sync::atomic::{fence, AtomicUsize, Ordering},
 };
 
+/// 外部のAPIからimportするexternal memoryのハンドル。
+/// LinuxではOPAQUE_FDのfd、WindowsではOPAQUE_WIN32のHANDLEになる。
+#[cfg(target_os = "linux")]
+pub type ExternalMemoryHandle = std::os::raw::c_int;
+/// 外部のAPIからimportするexternal memoryのハンドル。
+/// LinuxではOPAQUE_FDのfd、WindowsではOPAQUE_WIN32のHANDLEになる。
+#[cfg(target_os = "windows")]
+pub type ExternalMemoryHandle = vk::HANDLE;
+
 struct ImageHandleData {
     device: crate::DeviceHandle,
     image: vk::Image,
+    // external memoryからimportしたメモリ。Imageと一緒に破棄する
+    memory: Option<vk::DeviceMemory>,
+    format: vk::Format,
+    extent: vk::Extent3D,
     ref_count: AtomicUsize,
 }
 impl ImageHandleData {
@@ -23,6 +37,90 @@ impl ImageHandleData {
         Ok(Self {
             device,
             image,
+            memory: None,
+            format: image_create_info.format,
+            extent: image_create_info.extent,
+            ref_count: AtomicUsize::new(1),
+        })
+    }
+
+    fn from_external_memory(
+        device: crate::DeviceHandle,
+        image_create_info: &vk::ImageCreateInfo,
+        external_handle: ExternalMemoryHandle,
+    ) -> Result<Self> {
+        #[cfg(target_os = "windows")]
+        let handle_type = vk::ExternalMemoryHandleTypeFlags::OPAQUE_WIN32;
+        #[cfg(target_os = "linux")]
+        let handle_type = vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD;
+
+        // create image
+        // 元のcreate infoのp_nextのチェーンの先頭にExternalMemoryImageCreateInfoを追加する
+        let mut external_memory_image_create_info = vk::ExternalMemoryImageCreateInfo::builder()
+            .handle_types(handle_type)
+            .build();
+        external_memory_image_create_info.p_next = image_create_info.p_next;
+        let mut image_create_info = *image_create_info;
+        image_create_info.p_next = &external_memory_image_create_info
+            as *const vk::ExternalMemoryImageCreateInfo
+            as *const c_void;
+        let image = unsafe { ash::Device::create_image(&device, &image_create_info, None)? };
+
+        // memoryのimport
+        let image_memory_requirement = unsafe { device.get_image_memory_requirements(image) };
+        let physical_device_memory_properties = device.get_physical_device_memory_properties();
+        let Some(memory_type_index) = physical_device_memory_properties.memory_types
+            [..physical_device_memory_properties.memory_type_count as usize]
+            .iter()
+            .enumerate()
+            .position(|(i, memory_type)| {
+                (image_memory_requirement.memory_type_bits & (1 << i)) > 0
+                    && memory_type
+                        .property_flags
+                        .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+            })
+        else {
+            unsafe { device.destroy_image(image, None) };
+            bail!("No suitable memory type for external memory.");
+        };
+
+        #[cfg(target_os = "windows")]
+        let mut import_memory_info = vk::ImportMemoryWin32HandleInfoKHR::builder()
+            .handle_type(handle_type)
+            .handle(external_handle);
+        #[cfg(target_os = "linux")]
+        let mut import_memory_info = vk::ImportMemoryFdInfoKHR::builder()
+            .handle_type(handle_type)
+            .fd(external_handle);
+        let mut dedicated_allocate_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+        let memory_allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(image_memory_requirement.size)
+            .memory_type_index(memory_type_index as u32)
+            .push_next(&mut import_memory_info)
+            .push_next(&mut dedicated_allocate_info);
+        let memory = match unsafe { device.allocate_memory(&memory_allocate_info, None) } {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { device.destroy_image(image, None) };
+                return Err(err.into());
+            }
+        };
+
+        // imageとメモリのバインド
+        if let Err(err) = unsafe { device.bind_image_memory(image, memory, 0) } {
+            unsafe {
+                device.destroy_image(image, None);
+                device.free_memory(memory, None);
+            }
+            return Err(err.into());
+        }
+
+        Ok(Self {
+            device,
+            image,
+            memory: Some(memory),
+            format: image_create_info.format,
+            extent: image_create_info.extent,
             ref_count: AtomicUsize::new(1),
         })
     }
@@ -43,10 +141,46 @@ impl ImageHandle {
         Self { ptr }
     }
 
+    /// 外部のAPIがexportしたexternal memoryをimportしてImageHandleを作成する。
+    ///
+    /// image_create_infoにはExternalMemoryImageCreateInfoが自動で追加される。
+    /// formatやextent、usageなどはexportした側で作成したimageと同じものを指定する必要がある。
+    /// importしたメモリはdedicated allocationとしてImageにバインドし、Imageと一緒に破棄する。
+    ///
+    /// deviceの作成時に`VK_KHR_external_memory`と、Linuxでは`VK_KHR_external_memory_fd`、
+    /// Windowsでは`VK_KHR_external_memory_win32`が有効になっている必要がある
+    /// (`utils::get_required_device_extensions`には含まれている)。
+    ///
+    /// Linuxではimportに成功するとfdの所有権はVulkanに移るので、呼び出し側でcloseしてはいけない。
+    /// WindowsではHANDLEの所有権は移らないので、不要になったら呼び出し側でcloseする。
+    pub fn from_external_memory(
+        device: &crate::DeviceHandle,
+        image_create_info: &vk::ImageCreateInfo,
+        external_handle: ExternalMemoryHandle,
+    ) -> Result<Self> {
+        let data = ImageHandleData::from_external_memory(
+            device.clone(),
+            image_create_info,
+            external_handle,
+        )?;
+        let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(data))) };
+        Ok(Self { ptr })
+    }
+
     // imageの関数
 
     // その他のimageの関数
 
+    /// 作成時に指定したImageのフォーマットを取得する
+    pub fn format(&self) -> vk::Format {
+        self.data().format
+    }
+
+    /// 作成時に指定したImageのサイズを取得する
+    pub fn extent(&self) -> vk::Extent3D {
+        self.data().extent
+    }
+
     /// Imageのメモリ要件を取得する
     pub fn get_image_memory_requirements(&self) -> vk::MemoryRequirements {
         unsafe {
@@ -81,6 +215,13 @@ impl ImageHandle {
         self.data().image.clone()
     }
 
+    /// vk::ImageとそのImageを破棄させないためのガードに分ける。
+    /// ## Safety
+    /// 取り出したvk::Imageはガードがdropされると無効になるので、ガードより長く使ってはいけない。
+    pub unsafe fn into_raw(self) -> (vk::Image, crate::RawHandleGuard<Self>) {
+        (self.image_raw(), crate::RawHandleGuard::new(self))
+    }
+
     fn data(&self) -> &ImageHandleData {
         unsafe { self.ptr.as_ref() }
     }
@@ -126,6 +267,11 @@ impl Drop for ImageHandle {
 
                 // imageの破棄
                 data.device.destroy_image(data.image, None);
+
+                // importしたメモリの解放
+                if let Some(memory) = data.memory {
+                    data.device.free_memory(memory, None);
+                }
             }
         }
     }
@@ -49,14 +49,16 @@ pub struct InstanceHandleData {
     instance: ash::Instance,
     debug_utils_loader: DebugUtils,
     debug_messenger: vk::DebugUtilsMessengerEXT,
+    // VK_EXT_debug_utilsを有効にしたかどうか
+    debug_utils_enabled: bool,
     ref_count: AtomicUsize,
 }
 impl InstanceHandleData {
-    fn new(raw_display_handle: raw_window_handle::RawDisplayHandle) -> Result<Self> {
+    fn new(raw_display_handle: Option<raw_window_handle::RawDisplayHandle>) -> Result<Self> {
         let entry = unsafe { ash::Entry::load()? };
 
         // instanceの作成とdebug utilsの設定
-        let (instance, debug_utils_loader, debug_messenger) = {
+        let (instance, debug_utils_loader, debug_messenger, debug_utils_enabled) = {
             let app_name = std::ffi::CString::new("Hello Triangle")?;
             let app_info = vk::ApplicationInfo::builder()
                 .application_name(&app_name)
@@ -78,10 +80,30 @@ impl InstanceHandleData {
                     )
                     .pfn_user_callback(Some(vulkan_debug_utils_callback))
                     .build();
-            let mut extension_names = vec![DebugUtils::name().as_ptr()];
-            for &extension in ash_window::enumerate_required_extensions(raw_display_handle)? {
-                let name = unsafe { CStr::from_ptr(extension).as_ptr() };
-                extension_names.push(name);
+            let available_extensions = entry.enumerate_instance_extension_properties(None)?;
+            let is_extension_available = |extension_name: &CStr| {
+                available_extensions.iter().any(|extension| {
+                    let name = unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) };
+                    name == extension_name
+                })
+            };
+            // validationを使う場合は必須、それ以外はサポートされていれば
+            // RenderDocなどのlabelのためにdebug utilsを有効にする
+            let debug_utils_enabled =
+                ENABLE_VALIDATION_LAYERS || is_extension_available(DebugUtils::name());
+            let mut extension_names = vec![];
+            if debug_utils_enabled {
+                extension_names.push(DebugUtils::name().as_ptr());
+            }
+            if let Some(raw_display_handle) = raw_display_handle {
+                for &extension in ash_window::enumerate_required_extensions(raw_display_handle)? {
+                    let name = unsafe { CStr::from_ptr(extension).as_ptr() };
+                    extension_names.push(name);
+                }
+                // HDRのswapchainのcolor spaceを使えるように、サポートされていれば有効にする
+                if is_extension_available(vk::ExtSwapchainColorspaceFn::name()) {
+                    extension_names.push(vk::ExtSwapchainColorspaceFn::name().as_ptr());
+                }
             }
             let raw_layer_names = VALIDATION
                 .iter()
@@ -114,7 +136,12 @@ impl InstanceHandleData {
                 vk::DebugUtilsMessengerEXT::null()
             };
 
-            (instance, debug_utils_loader, debug_messenger)
+            (
+                instance,
+                debug_utils_loader,
+                debug_messenger,
+                debug_utils_enabled,
+            )
         };
 
         Ok(Self {
@@ -122,6 +149,7 @@ impl InstanceHandleData {
             instance,
             debug_utils_loader,
             debug_messenger,
+            debug_utils_enabled,
             ref_count: AtomicUsize::new(1),
         })
     }
@@ -135,12 +163,19 @@ impl InstanceHandle {
     /// InstanceHandlwを作成する
     pub fn new(raw_display_handle: raw_window_handle::RawDisplayHandle) -> Self {
         let data = Box::new(
-            InstanceHandleData::new(raw_display_handle).expect("Failed to create instance."),
+            InstanceHandleData::new(Some(raw_display_handle)).expect("Failed to create instance."),
         );
         let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(data)) };
         Self { ptr }
     }
 
+    /// surfaceを作成しないheadlessなInstanceHandleを作成する
+    pub fn new_headless() -> Self {
+        let data = Box::new(InstanceHandleData::new(None).expect("Failed to create instance."));
+        let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(data)) };
+        Self { ptr }
+    }
+
     // Instanceの関数
 
     // create系
@@ -201,6 +236,43 @@ impl InstanceHandle {
         }
     }
 
+    /// 物理デバイスのプロパティを取得する
+    pub fn get_physical_device_properties(
+        &self,
+        physical_device: vk::PhysicalDevice,
+    ) -> vk::PhysicalDeviceProperties {
+        unsafe {
+            self.data()
+                .instance
+                .get_physical_device_properties(physical_device)
+        }
+    }
+
+    /// 物理デバイスがサポートする機能を取得する
+    pub fn get_physical_device_features(
+        &self,
+        physical_device: vk::PhysicalDevice,
+    ) -> vk::PhysicalDeviceFeatures {
+        unsafe {
+            self.data()
+                .instance
+                .get_physical_device_features(physical_device)
+        }
+    }
+
+    /// 物理デバイスのformatのプロパティを取得する
+    pub fn get_physical_device_format_properties(
+        &self,
+        physical_device: vk::PhysicalDevice,
+        format: vk::Format,
+    ) -> vk::FormatProperties {
+        unsafe {
+            self.data()
+                .instance
+                .get_physical_device_format_properties(physical_device, format)
+        }
+    }
+
     /// 物理デバイスのプロパティを取得する
     pub fn get_physical_device_properties2(
         &self,
@@ -214,6 +286,12 @@ impl InstanceHandle {
         }
     }
 
+    /// debug utilsが有効な場合にそのloaderを取得する
+    pub(crate) fn debug_utils_loader(&self) -> Option<&DebugUtils> {
+        let data = self.data();
+        data.debug_utils_enabled.then_some(&data.debug_utils_loader)
+    }
+
     // raw
 
     /// ash::Entryを取得する
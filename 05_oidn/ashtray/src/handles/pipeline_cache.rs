@@ -0,0 +1,128 @@
+//! 参照カウンタで管理して、参照がすべて破棄された際に
+//! PipelineCacheの破棄の処理まで行うPipelineCacheHandleを定義する。
+
+use anyhow::Result;
+use ash::vk;
+use std::{
+    fmt::Debug,
+    ops::Deref,
+    ptr::NonNull,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+struct PipelineCacheHandleData {
+    device: crate::DeviceHandle,
+    pipeline_cache: vk::PipelineCache,
+    ref_count: AtomicUsize,
+}
+impl PipelineCacheHandleData {
+    fn new(
+        device: crate::DeviceHandle,
+        pipeline_cache_create_info: &vk::PipelineCacheCreateInfo,
+    ) -> Result<Self> {
+        // create pipeline cache
+        let pipeline_cache = unsafe {
+            ash::Device::create_pipeline_cache(&device, pipeline_cache_create_info, None)?
+        };
+
+        Ok(Self {
+            device,
+            pipeline_cache,
+            ref_count: AtomicUsize::new(1),
+        })
+    }
+}
+
+/// vk::PipelineCacheを参照カウントで管理するためのハンドル
+pub struct PipelineCacheHandle {
+    ptr: NonNull<PipelineCacheHandleData>,
+}
+impl PipelineCacheHandle {
+    pub(crate) fn new(
+        device_handle: crate::DeviceHandle,
+        pipeline_cache_create_info: &vk::PipelineCacheCreateInfo,
+    ) -> Self {
+        let data = Box::new(
+            PipelineCacheHandleData::new(device_handle, pipeline_cache_create_info)
+                .expect("Failed to create pipeline cache."),
+        );
+        let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(data)) };
+        Self { ptr }
+    }
+
+    /// pipeline cacheの中身をバイト列として取得する。
+    /// ファイルに保存しておき、次回起動時にcreate_pipeline_cacheに渡すことで再利用できる。
+    pub fn get_data(&self) -> Vec<u8> {
+        unsafe {
+            self.data()
+                .device
+                .get_pipeline_cache_data(self.data().pipeline_cache)
+                .expect("Failed to get pipeline cache data.")
+        }
+    }
+
+    // raw
+
+    /// DeviceHandleを取得する
+    pub fn device(&self) -> crate::DeviceHandle {
+        self.data().device.clone()
+    }
+
+    /// vk::PipelineCacheを取得する
+    /// ## Safety
+    /// 参照カウントの管理から中身を取り出すので注意。
+    /// Handleが破棄されると、この関数で取り出したvk::PipelineCacheは無効になる。
+    pub unsafe fn pipeline_cache_raw(&self) -> vk::PipelineCache {
+        self.data().pipeline_cache
+    }
+
+    fn data(&self) -> &PipelineCacheHandleData {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+// Debugトレイトの実装
+impl Debug for PipelineCacheHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PipelineCacheHandle").finish()
+    }
+}
+
+// PipelineCacheHandleDataの中身はSendかつSyncなのでPipelineCacheHandleはSend
+unsafe impl Send for PipelineCacheHandle {}
+// PipelineCacheHandleDataの中身はSendかつSyncなのでPipelineCacheHandleはSync
+unsafe impl Sync for PipelineCacheHandle {}
+
+// PipelineCacheHandleはvk::PipelineCacheにDerefする
+impl Deref for PipelineCacheHandle {
+    type Target = vk::PipelineCache;
+    fn deref(&self) -> &Self::Target {
+        &self.data().pipeline_cache
+    }
+}
+
+// Cloneで参照カウントを増やす
+impl Clone for PipelineCacheHandle {
+    fn clone(&self) -> Self {
+        if self.data().ref_count.fetch_add(1, Ordering::Relaxed) > usize::MAX / 2 {
+            panic!("Too many references to PipelineCacheHandle");
+        }
+        Self { ptr: self.ptr }
+    }
+}
+
+// Drop時に参照カウントを減らし、0になったら破棄する
+impl Drop for PipelineCacheHandle {
+    fn drop(&mut self) {
+        if self.data().ref_count.fetch_sub(1, Ordering::Release) == 1 {
+            fence(Ordering::Acquire);
+            unsafe {
+                let data = Box::from_raw(self.ptr.as_ptr());
+
+                // pipeline cacheの破棄
+                data.device
+                    .destroy_pipeline_cache(data.pipeline_cache, None);
+            }
+        }
+    }
+}
@@ -13,6 +13,7 @@ use std::{
 struct PipelineLayoutHandleData {
     device: crate::DeviceHandle,
     pipeline_layout: vk::PipelineLayout,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
     ref_count: AtomicUsize,
 }
 impl PipelineLayoutHandleData {
@@ -25,9 +26,23 @@ impl PipelineLayoutHandleData {
             ash::Device::create_pipeline_layout(&device, pipeline_layout_create_info, None)?
         };
 
+        // push constantsのサイズをチェックできるようにrangeを保持しておく
+        let push_constant_ranges = if pipeline_layout_create_info.push_constant_range_count == 0 {
+            vec![]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(
+                    pipeline_layout_create_info.p_push_constant_ranges,
+                    pipeline_layout_create_info.push_constant_range_count as usize,
+                )
+            }
+            .to_vec()
+        };
+
         Ok(Self {
             device,
             pipeline_layout,
+            push_constant_ranges,
             ref_count: AtomicUsize::new(1),
         })
     }
@@ -50,6 +65,11 @@ impl PipelineLayoutHandle {
         Self { ptr }
     }
 
+    /// 作成時に指定したpush constant rangeを取得する
+    pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
+        &self.data().push_constant_ranges
+    }
+
     // raw
 
     /// DeviceHandleを取得する
@@ -0,0 +1,136 @@
+//! 参照カウンタで管理して、参照がすべて破棄された際に
+//! QueryPoolの破棄の処理まで行うQueryPoolHandleを定義する。
+
+use anyhow::Result;
+use ash::vk;
+use std::{
+    fmt::Debug,
+    ops::Deref,
+    ptr::NonNull,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+struct QueryPoolHandleData {
+    device: crate::DeviceHandle,
+    query_pool: vk::QueryPool,
+    ref_count: AtomicUsize,
+}
+impl QueryPoolHandleData {
+    fn new(
+        device: crate::DeviceHandle,
+        query_pool_create_info: &vk::QueryPoolCreateInfo,
+    ) -> Result<Self> {
+        // create query pool
+        let query_pool =
+            unsafe { ash::Device::create_query_pool(&device, query_pool_create_info, None)? };
+
+        Ok(Self {
+            device,
+            query_pool,
+            ref_count: AtomicUsize::new(1),
+        })
+    }
+}
+
+/// vk::QueryPoolを参照カウントで管理するためのハンドル
+pub struct QueryPoolHandle {
+    ptr: NonNull<QueryPoolHandleData>,
+}
+impl QueryPoolHandle {
+    pub(crate) fn new(
+        device_handle: crate::DeviceHandle,
+        query_pool_create_info: &vk::QueryPoolCreateInfo,
+    ) -> Self {
+        let data = Box::new(
+            QueryPoolHandleData::new(device_handle, query_pool_create_info)
+                .expect("Failed to create query pool."),
+        );
+        let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(data)) };
+        Self { ptr }
+    }
+
+    /// first_queryからdata.len()個のqueryの結果を取得する。
+    pub fn get_query_pool_results<T>(
+        &self,
+        first_query: u32,
+        data: &mut [T],
+        flags: vk::QueryResultFlags,
+    ) {
+        unsafe {
+            self.data()
+                .device
+                .get_query_pool_results(
+                    self.data().query_pool,
+                    first_query,
+                    data.len() as u32,
+                    data,
+                    flags,
+                )
+                .expect("Failed to get query pool results.")
+        }
+    }
+
+    // raw
+
+    /// DeviceHandleを取得する
+    pub fn device(&self) -> crate::DeviceHandle {
+        self.data().device.clone()
+    }
+
+    /// vk::QueryPoolを取得する
+    /// ## Safety
+    /// 参照カウントの管理から中身を取り出すので注意。
+    /// Handleが破棄されると、この関数で取り出したvk::QueryPoolは無効になる。
+    pub unsafe fn query_pool_raw(&self) -> vk::QueryPool {
+        self.data().query_pool
+    }
+
+    fn data(&self) -> &QueryPoolHandleData {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+// Debugトレイトの実装
+impl Debug for QueryPoolHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueryPoolHandle").finish()
+    }
+}
+
+// QueryPoolHandleDataの中身はSendかつSyncなのでQueryPoolHandleはSend
+unsafe impl Send for QueryPoolHandle {}
+// QueryPoolHandleDataの中身はSendかつSyncなのでQueryPoolHandleはSync
+unsafe impl Sync for QueryPoolHandle {}
+
+// QueryPoolHandleはvk::QueryPoolにDerefする
+impl Deref for QueryPoolHandle {
+    type Target = vk::QueryPool;
+    fn deref(&self) -> &Self::Target {
+        &self.data().query_pool
+    }
+}
+
+// Cloneで参照カウントを増やす
+impl Clone for QueryPoolHandle {
+    fn clone(&self) -> Self {
+        if self.data().ref_count.fetch_add(1, Ordering::Relaxed) > usize::MAX / 2 {
+            panic!("Too many references to QueryPoolHandle");
+        }
+        Self { ptr: self.ptr }
+    }
+}
+
+// Drop時に参照カウントを減らし、0になったら破棄する
+impl Drop for QueryPoolHandle {
+    fn drop(&mut self) {
+        if self.data().ref_count.fetch_sub(1, Ordering::Release) == 1 {
+            fence(Ordering::Acquire);
+            unsafe {
+                let data = Box::from_raw(self.ptr.as_ptr());
+
+                // query poolの破棄
+                data.device.destroy_query_pool(data.query_pool, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Handleから取り出した生のVulkanのハンドルの寿命を管理するRawHandleGuardを定義する。
+
+use std::fmt::Debug;
+
+/// `into_raw`で取り出した生のハンドルが有効な間、元のHandleの参照を保持するガード。
+///
+/// ガードが生きている間は参照カウントが残るので、生のハンドルは破棄されない。
+/// ガードをdropすると通常のHandleのdropと同じく参照カウントを減らし、
+/// 最後の参照だった場合はVulkanのObjectを破棄する。
+/// そのため生のハンドルをガードより長く使ってはいけない。
+/// FFIで外部のライブラリに渡す場合は、ライブラリが使い終わるまでガードを保持しておく。
+pub struct RawHandleGuard<H> {
+    handle: H,
+}
+impl<H> RawHandleGuard<H> {
+    pub(crate) fn new(handle: H) -> Self {
+        Self { handle }
+    }
+
+    /// ガードから元のHandleを取り出す。
+    /// 生のハンドルの管理を参照カウントに戻すときに使う。
+    pub fn into_handle(self) -> H {
+        self.handle
+    }
+
+    /// ガードが保持しているHandleを参照する。
+    pub fn handle(&self) -> &H {
+        &self.handle
+    }
+}
+
+// Debugトレイトの実装
+impl<H: Debug> Debug for RawHandleGuard<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RawHandleGuard")
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
@@ -46,6 +46,11 @@ impl SamplerHandle {
         Self { ptr }
     }
 
+    // このハンドルが唯一の参照かどうか。SamplerCacheが使われていないsamplerを破棄するのに使う
+    pub(crate) fn is_unique(&self) -> bool {
+        self.data().ref_count.load(Ordering::Acquire) == 1
+    }
+
     // raw
 
     /// DeviceHandleを取得する
@@ -9,6 +9,8 @@ mod image;
 pub use image::*;
 mod buffer;
 pub use buffer::*;
+mod descriptor_pool;
+pub use descriptor_pool::*;
 mod descriptor_set;
 pub use descriptor_set::*;
 mod shader;
@@ -21,3 +23,5 @@ mod ray_tracing;
 pub use ray_tracing::*;
 mod shared_buffer;
 pub use shared_buffer::*;
+mod timer;
+pub use timer::*;
@@ -10,6 +10,48 @@ pub struct BufferObjects {
     pub allocation: crate::AllocationHandle,
     /// Bufferのデバイスアドレス
     pub device_address: u64,
+    // 作成時に指定したBufferのサイズ。
+    // allocationはアラインメントのために大きく確保されることがあるので、こちらを正とする
+    size: u64,
+}
+impl BufferObjects {
+    /// 作成時に指定したBufferのサイズを取得する
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Hostから見えるBufferの中身をBufferのサイズ分だけスライスとして取得する
+    ///
+    /// Hostから見えないメモリの場合はNoneを返す。
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        self.allocation
+            .mapped_slice()
+            .map(|slice| &slice[..self.size as usize])
+    }
+
+    /// Hostから見えるBufferの中身をBufferのサイズ分だけ可変スライスとして取得する
+    ///
+    /// Hostから見えないメモリの場合はNoneを返す。
+    pub fn mapped_slice_mut(&mut self) -> Option<&mut [u8]> {
+        let size = self.size as usize;
+        self.allocation
+            .mapped_slice_mut()
+            .map(|slice| &mut slice[..size])
+    }
+
+    /// Hostから見えるBufferの先頭にdataを書き込む
+    ///
+    /// dataがBufferのサイズを超える場合やHostから見えないメモリの場合はpanicする。
+    pub fn write<T: Copy>(&mut self, data: &[T]) {
+        assert!(
+            std::mem::size_of_val(data) as u64 <= self.size,
+            "Data size {} exceeds buffer size {}.",
+            std::mem::size_of_val(data),
+            self.size
+        );
+        presser::copy_from_slice_to_offset_with_align(data, &mut *self.allocation, 0, 4)
+            .expect("Failed to write data to buffer.");
+    }
 }
 
 /// HostのBufferを作成する関数
@@ -45,6 +87,7 @@ pub fn create_host_buffer(
         buffer,
         allocation,
         device_address,
+        size: buffer_size,
     }
 }
 
@@ -94,6 +137,7 @@ pub fn create_host_buffer_with_data<T: Copy>(
         buffer,
         allocation,
         device_address,
+        size: buffer_size,
     }
 }
 
@@ -130,6 +174,7 @@ pub fn create_device_local_buffer(
         buffer,
         allocation,
         device_address,
+        size: buffer_size,
     }
 }
 
@@ -206,5 +251,6 @@ pub fn create_device_local_buffer_with_data<T: Copy>(
         buffer,
         allocation,
         device_address,
+        size: buffer_size,
     }
 }
@@ -3,24 +3,53 @@ use ash::vk;
 /// ComputePipelineを作成するヘルパー関数
 pub fn create_compute_pipeline(
     device: &crate::DeviceHandle,
+    pipeline_cache: &crate::PipelineCacheHandle,
     pipeline_layout: &crate::PipelineLayoutHandle,
     shader_module: &crate::ShaderModuleHandle,
 ) -> crate::ComputePipelineHandle {
-    let entry_name = std::ffi::CString::new("main").unwrap();
-    let create_info = vk::ComputePipelineCreateInfo::builder()
-        .stage(
-            *vk::PipelineShaderStageCreateInfo::builder()
-                .stage(vk::ShaderStageFlags::COMPUTE)
-                .module(**shader_module)
-                .name(entry_name.as_c_str()),
-        )
-        .layout(**pipeline_layout);
-    device
-        .create_compute_pipelines(
-            vk::PipelineCache::null(),
-            std::slice::from_ref(&create_info),
-        )
-        .into_iter()
-        .next()
-        .unwrap()
+    create_compute_pipelines_batch(
+        device,
+        pipeline_cache,
+        &[(pipeline_layout, shader_module, "main")],
+    )
+    .into_iter()
+    .next()
+    .unwrap()
+}
+
+/// 複数のComputePipelineを1回のvkCreateComputePipelinesでまとめて作成するヘルパー関数
+///
+/// pipelinesの要素は(pipeline layout, shader module, entry pointの名前)。
+/// 返り値のpipelineはpipelinesと同じ順番に並ぶ。
+pub fn create_compute_pipelines_batch(
+    device: &crate::DeviceHandle,
+    pipeline_cache: &crate::PipelineCacheHandle,
+    pipelines: &[(
+        &crate::PipelineLayoutHandle,
+        &crate::ShaderModuleHandle,
+        &str,
+    )],
+) -> Vec<crate::ComputePipelineHandle> {
+    let entry_names = pipelines
+        .iter()
+        .map(|(_, _, entry_name)| {
+            std::ffi::CString::new(*entry_name).expect("Entry name contains a nul byte.")
+        })
+        .collect::<Vec<_>>();
+    let create_infos = pipelines
+        .iter()
+        .zip(&entry_names)
+        .map(|((pipeline_layout, shader_module, _), entry_name)| {
+            vk::ComputePipelineCreateInfo::builder()
+                .stage(
+                    *vk::PipelineShaderStageCreateInfo::builder()
+                        .stage(vk::ShaderStageFlags::COMPUTE)
+                        .module(***shader_module)
+                        .name(entry_name.as_c_str()),
+                )
+                .layout(***pipeline_layout)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    device.create_compute_pipelines(pipeline_cache, &create_infos)
 }
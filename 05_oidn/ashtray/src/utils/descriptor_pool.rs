@@ -0,0 +1,113 @@
+use ash::vk;
+
+/// DescriptorTypeとその数の組からDescriptorPoolを作成する。
+///
+/// flagsにUPDATE_AFTER_BINDを含める場合は、
+/// このpoolから割り当てるdescriptor setのlayoutもUPDATE_AFTER_BIND_POOLで作成する必要がある。
+/// FREE_DESCRIPTOR_SETを含めない場合は個別のdescriptor setの解放ができないので、
+/// DescriptorSetHandleを破棄してもpoolを破棄するまでdescriptorは返却されない。
+pub fn create_descriptor_pool(
+    device: &crate::DeviceHandle,
+    sizes: &[(vk::DescriptorType, u32)],
+    max_sets: u32,
+    flags: vk::DescriptorPoolCreateFlags,
+) -> crate::DescriptorPoolHandle {
+    let pool_sizes = sizes
+        .iter()
+        .map(|&(ty, descriptor_count)| {
+            vk::DescriptorPoolSize::builder()
+                .ty(ty)
+                .descriptor_count(descriptor_count)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    device.create_descriptor_pool(
+        &vk::DescriptorPoolCreateInfo::builder()
+            .flags(flags)
+            .max_sets(max_sets)
+            .pool_sizes(&pool_sizes),
+    )
+}
+
+/// 割り当てに失敗したら同じサイズのDescriptorPoolを追加して割り当て直すDescriptorPool。
+///
+/// 1つのpoolに収まるdescriptorの数を事前に決められない場合に使う。
+/// 作成したpoolはDescriptorSetHandleからも参照されるので、
+/// このstructを破棄しても割り当て済みのdescriptor setは有効なまま残る。
+pub struct GrowableDescriptorPool {
+    device: crate::DeviceHandle,
+    sizes: Vec<(vk::DescriptorType, u32)>,
+    max_sets: u32,
+    flags: vk::DescriptorPoolCreateFlags,
+    pools: Vec<crate::DescriptorPoolHandle>,
+}
+impl GrowableDescriptorPool {
+    /// 1つ目のDescriptorPoolを作成する。引数はcreate_descriptor_poolと同じ。
+    pub fn new(
+        device: &crate::DeviceHandle,
+        sizes: &[(vk::DescriptorType, u32)],
+        max_sets: u32,
+        flags: vk::DescriptorPoolCreateFlags,
+    ) -> Self {
+        let pool = create_descriptor_pool(device, sizes, max_sets, flags);
+        Self {
+            device: device.clone(),
+            sizes: sizes.to_vec(),
+            max_sets,
+            flags,
+            pools: vec![pool],
+        }
+    }
+
+    /// layoutのdescriptor setを1つ割り当てる。
+    /// variable_descriptor_countがSomeの場合は、
+    /// VARIABLE_DESCRIPTOR_COUNTのbindingのdescriptorの数としてその値を使う。
+    ///
+    /// 最後に作成したpoolが足りない場合はpoolを追加して割り当て直す。
+    /// 新しいpoolでも足りない場合は1つのpoolのサイズを超えた要求なのでpanicする。
+    pub fn allocate(
+        &mut self,
+        layout: &crate::DescriptorSetLayoutHandle,
+        variable_descriptor_count: Option<u32>,
+    ) -> crate::DescriptorSetHandle {
+        let pool = self.pools.last().unwrap().clone();
+        match self.try_allocate(&pool, layout, variable_descriptor_count) {
+            Ok(set) => set,
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                let pool =
+                    create_descriptor_pool(&self.device, &self.sizes, self.max_sets, self.flags);
+                self.pools.push(pool.clone());
+                self.try_allocate(&pool, layout, variable_descriptor_count)
+                    .expect("Failed to allocate descriptor set from a new descriptor pool.")
+            }
+            Err(err) => panic!("Failed to allocate descriptor set: {err}"),
+        }
+    }
+
+    /// 作成したDescriptorPoolの数を返す
+    pub fn pool_count(&self) -> usize {
+        self.pools.len()
+    }
+
+    fn try_allocate(
+        &self,
+        pool: &crate::DescriptorPoolHandle,
+        layout: &crate::DescriptorSetLayoutHandle,
+        variable_descriptor_count: Option<u32>,
+    ) -> Result<crate::DescriptorSetHandle, vk::Result> {
+        let set_layouts = [**layout];
+        let descriptor_counts = variable_descriptor_count.map(|count| [count]);
+        let mut count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder();
+        let mut allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(**pool)
+            .set_layouts(&set_layouts);
+        if let Some(descriptor_counts) = descriptor_counts.as_ref() {
+            count_info = count_info.descriptor_counts(descriptor_counts);
+            allocate_info = allocate_info.push_next(&mut count_info);
+        }
+        let sets = self
+            .device
+            .try_allocate_descriptor_sets(pool, &allocate_info)?;
+        Ok(sets.into_iter().next().unwrap())
+    }
+}
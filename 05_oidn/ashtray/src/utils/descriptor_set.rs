@@ -35,17 +35,12 @@ impl DescriptorSetUniformBufferHandles {
             .push_next(&mut binding_flags)
             .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL);
         let layout = device.create_descriptor_set_layout(&create_info);
-        let pool = device.create_descriptor_pool(
-            &vk::DescriptorPoolCreateInfo::builder()
-                .flags(
-                    vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
-                        | vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
-                )
-                .max_sets(1)
-                .pool_sizes(&[vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(MAX_BINDLESS_RESOURCES)
-                    .build()]),
+        let pool = create_descriptor_pool(
+            device,
+            &[(vk::DescriptorType::UNIFORM_BUFFER, MAX_BINDLESS_RESOURCES)],
+            1,
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
+                | vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
         );
         let mut count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
             .descriptor_counts(&[MAX_BINDLESS_RESOURCES - 1]);
@@ -116,17 +111,15 @@ impl DescriptorSetCombinedImageSamplerHandles {
             .push_next(&mut binding_flags)
             .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL);
         let layout = device.create_descriptor_set_layout(&create_info);
-        let pool = device.create_descriptor_pool(
-            &vk::DescriptorPoolCreateInfo::builder()
-                .flags(
-                    vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
-                        | vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
-                )
-                .max_sets(1)
-                .pool_sizes(&[vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .descriptor_count(MAX_BINDLESS_RESOURCES)
-                    .build()]),
+        let pool = create_descriptor_pool(
+            device,
+            &[(
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                MAX_BINDLESS_RESOURCES,
+            )],
+            1,
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
+                | vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
         );
         let mut count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
             .descriptor_counts(&[MAX_BINDLESS_RESOURCES - 1]);
@@ -197,17 +190,12 @@ impl DescriptorSetStorageBufferHandles {
             .push_next(&mut binding_flags)
             .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL);
         let layout = device.create_descriptor_set_layout(&create_info);
-        let pool = device.create_descriptor_pool(
-            &vk::DescriptorPoolCreateInfo::builder()
-                .flags(
-                    vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
-                        | vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
-                )
-                .max_sets(1)
-                .pool_sizes(&[vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::STORAGE_BUFFER)
-                    .descriptor_count(MAX_BINDLESS_RESOURCES)
-                    .build()]),
+        let pool = create_descriptor_pool(
+            device,
+            &[(vk::DescriptorType::STORAGE_BUFFER, MAX_BINDLESS_RESOURCES)],
+            1,
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
+                | vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
         );
         let mut count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
             .descriptor_counts(&[MAX_BINDLESS_RESOURCES - 1]);
@@ -278,17 +266,12 @@ impl DescriptorSetStorageImageHandles {
             .push_next(&mut binding_flags)
             .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL);
         let layout = device.create_descriptor_set_layout(&create_info);
-        let pool = device.create_descriptor_pool(
-            &vk::DescriptorPoolCreateInfo::builder()
-                .flags(
-                    vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
-                        | vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
-                )
-                .max_sets(1)
-                .pool_sizes(&[vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::STORAGE_IMAGE)
-                    .descriptor_count(MAX_BINDLESS_RESOURCES)
-                    .build()]),
+        let pool = create_descriptor_pool(
+            device,
+            &[(vk::DescriptorType::STORAGE_IMAGE, MAX_BINDLESS_RESOURCES)],
+            1,
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
+                | vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
         );
         let mut count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
             .descriptor_counts(&[MAX_BINDLESS_RESOURCES - 1]);
@@ -310,9 +293,15 @@ impl DescriptorSetStorageImageHandles {
 
     /// 指定したarray_elementのdescriptor setを更新する
     pub fn update(&self, storage_image: &ImageHandles, array_element: u32) {
+        self.update_image_view(&storage_image.image_view, array_element);
+    }
+
+    /// 指定したarray_elementのdescriptor setをImageViewHandleで更新する。
+    /// ImageHandlesにまとまっていない外部から渡されたimageを登録するのに使う。
+    pub fn update_image_view(&self, image_view: &crate::ImageViewHandle, array_element: u32) {
         // storage imageの書き込み
         let storage_image_info = vk::DescriptorImageInfo::builder()
-            .image_view(*storage_image.image_view)
+            .image_view(**image_view)
             .image_layout(vk::ImageLayout::GENERAL);
         let storage_image_write = vk::WriteDescriptorSet::builder()
             .dst_set(*self.set)
@@ -329,6 +318,8 @@ impl DescriptorSetStorageImageHandles {
 
 /// AccelerationStructureのDescriptorSetをまとめた構造体
 pub struct DescriptorSetAccelerationStructureHandles {
+    /// DeviceHandle
+    pub device: crate::DeviceHandle,
     /// descriptor setのDescriptorPoolHandle
     pub pool: crate::DescriptorPoolHandle,
     /// descriptor setのDescriptorSetLayoutHandle
@@ -364,19 +355,12 @@ impl DescriptorSetAccelerationStructureHandles {
         };
 
         // descriptor poolの作成
-        let pool = {
-            let descriptor_pool_size_acceleration_structure = vk::DescriptorPoolSize::builder()
-                .ty(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                .descriptor_count(1);
-            let descriptor_pool_sizes = [descriptor_pool_size_acceleration_structure.build()];
-
-            let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::builder()
-                .max_sets(1)
-                .pool_sizes(&descriptor_pool_sizes)
-                .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET);
-
-            device.create_descriptor_pool(&descriptor_pool_create_info)
-        };
+        let pool = create_descriptor_pool(
+            device,
+            &[(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, 1)],
+            1,
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+        );
 
         // descriptor setの作成
         let descriptor_set = {
@@ -409,6 +393,7 @@ impl DescriptorSetAccelerationStructureHandles {
         };
 
         DescriptorSetAccelerationStructureHandles {
+            device: device.clone(),
             pool,
             layout,
             set: descriptor_set,
@@ -19,11 +19,12 @@ pub fn create_storage_image(
     image_transfer_command_buffer: &crate::CommandBufferHandle,
     width: u32,
     height: u32,
+    format: vk::Format,
 ) -> ImageHandles {
     // imageの生成
     let image_create_info = vk::ImageCreateInfo::builder()
         .image_type(vk::ImageType::TYPE_2D)
-        .format(vk::Format::R32G32B32A32_SFLOAT)
+        .format(format)
         .extent(vk::Extent3D {
             width,
             height,
@@ -32,7 +33,11 @@ pub fn create_storage_image(
         .mip_levels(1)
         .array_layers(1)
         .samples(vk::SampleCountFlags::TYPE_1)
-        .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST)
+        .usage(
+            vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+        )
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
         .initial_layout(vk::ImageLayout::UNDEFINED);
     let image = device.create_image(&image_create_info);
@@ -53,7 +58,7 @@ pub fn create_storage_image(
     // image_viewの作成
     let image_view_create_info = vk::ImageViewCreateInfo::builder()
         .view_type(vk::ImageViewType::TYPE_2D)
-        .format(vk::Format::R32G32B32A32_SFLOAT)
+        .format(format)
         .components(
             vk::ComponentMapping::builder()
                 .r(vk::ComponentSwizzle::IDENTITY)
@@ -209,6 +214,7 @@ pub fn create_shader_readonly_image(
 }
 
 /// storage imageを作成する関数
+#[allow(clippy::too_many_arguments)]
 pub fn create_shader_readonly_image_with_data(
     device: &crate::DeviceHandle,
     queue_handles: &QueueHandles,
@@ -359,30 +365,142 @@ pub fn create_shader_readonly_image_with_data(
     }
 }
 
-/// samplerをNEARESTで作成するヘルパー関数
-pub fn create_sampler(device: &crate::DeviceHandle) -> crate::SamplerHandle {
-    let create_info = vk::SamplerCreateInfo::builder()
-        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
-        .mag_filter(vk::Filter::NEAREST)
-        .min_filter(vk::Filter::NEAREST)
-        .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
-    device.create_sampler(&create_info)
+/// create_samplerで作成するsamplerの設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerDesc {
+    /// 拡大時のフィルタ
+    pub mag_filter: vk::Filter,
+    /// 縮小時のフィルタ
+    pub min_filter: vk::Filter,
+    /// mipmap間のフィルタ
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// u, v, wすべてに使うアドレスモード
+    pub address_mode: vk::SamplerAddressMode,
+    /// 異方性フィルタリングの最大値。1.0以下の場合は異方性フィルタリングを無効にする。
+    /// deviceの上限を超える場合は上限に切り詰められる。
+    pub max_anisotropy: f32,
+    /// 参照するmipmapの最大のLOD
+    pub max_lod: f32,
 }
+impl SamplerDesc {
+    /// NEARESTでCLAMP_TO_EDGEのsamplerの設定。
+    /// 画面へのblitなどpixelをそのまま読みたい場合に使う。
+    pub fn nearest_clamp() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            max_anisotropy: 1.0,
+            max_lod: 0.0,
+        }
+    }
 
-/// samplerをLinearで作成するヘルパー関数
-pub fn create_sampler_image(device: &crate::DeviceHandle) -> crate::SamplerHandle {
+    /// LINEARでREPEATの異方性フィルタリングありのsamplerの設定。
+    /// マテリアルのテクスチャに使う。
+    pub fn linear_repeat() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: 16.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+        }
+    }
+}
+
+/// SamplerDescの設定でsamplerを作成するヘルパー関数。
+/// deviceでsamplerAnisotropyが有効になっていない場合は異方性フィルタリングを無効にする。
+pub fn create_sampler(device: &crate::DeviceHandle, desc: &SamplerDesc) -> crate::SamplerHandle {
+    let max_anisotropy = desc.max_anisotropy.min(
+        device
+            .get_physical_device_properties()
+            .limits
+            .max_sampler_anisotropy,
+    );
+    let anisotropy_enable =
+        device.enabled_features().sampler_anisotropy == vk::TRUE && max_anisotropy > 1.0;
     let create_info = vk::SamplerCreateInfo::builder()
-        .address_mode_u(vk::SamplerAddressMode::REPEAT)
-        .address_mode_v(vk::SamplerAddressMode::REPEAT)
-        .address_mode_w(vk::SamplerAddressMode::REPEAT)
-        .mag_filter(vk::Filter::LINEAR)
-        .min_filter(vk::Filter::LINEAR)
-        .mipmap_mode(vk::SamplerMipmapMode::LINEAR);
+        .address_mode_u(desc.address_mode)
+        .address_mode_v(desc.address_mode)
+        .address_mode_w(desc.address_mode)
+        .mag_filter(desc.mag_filter)
+        .min_filter(desc.min_filter)
+        .mipmap_mode(desc.mipmap_mode)
+        .anisotropy_enable(anisotropy_enable)
+        .max_anisotropy(if anisotropy_enable {
+            max_anisotropy
+        } else {
+            1.0
+        })
+        .max_lod(desc.max_lod);
     device.create_sampler(&create_info)
 }
 
+// SamplerCacheのkey。f32はbit列で比較する
+#[derive(PartialEq, Eq, Hash)]
+struct SamplerKey {
+    mag_filter: vk::Filter,
+    min_filter: vk::Filter,
+    mipmap_mode: vk::SamplerMipmapMode,
+    address_mode: vk::SamplerAddressMode,
+    max_anisotropy: u32,
+    max_lod: u32,
+}
+impl From<&SamplerDesc> for SamplerKey {
+    fn from(desc: &SamplerDesc) -> Self {
+        Self {
+            mag_filter: desc.mag_filter,
+            min_filter: desc.min_filter,
+            mipmap_mode: desc.mipmap_mode,
+            address_mode: desc.address_mode,
+            max_anisotropy: desc.max_anisotropy.to_bits(),
+            max_lod: desc.max_lod.to_bits(),
+        }
+    }
+}
+
+/// 同じSamplerDescに対して同じSamplerHandleを返すsamplerのキャッシュ。
+///
+/// テクスチャごとにsamplerを作らずに共有することで、設定の種類の数しかsamplerを作らない。
+/// 返したsamplerは参照カウントで生存し、キャッシュ以外の参照がなくなったものは
+/// 次にgetを呼んだときにキャッシュから外して通常のDropで破棄する。
+/// 使い回す必要のないsamplerはcreate_samplerで直接作成してよい。
+pub struct SamplerCache {
+    device: crate::DeviceHandle,
+    samplers: std::sync::Mutex<std::collections::HashMap<SamplerKey, crate::SamplerHandle>>,
+}
+impl SamplerCache {
+    /// 空のSamplerCacheを作成する
+    pub fn new(device: &crate::DeviceHandle) -> Self {
+        Self {
+            device: device.clone(),
+            samplers: std::sync::Mutex::new(std::collections::HashMap::new()),
+        }
+    }
+
+    /// descの設定のsamplerを返す。キャッシュにない場合はcreate_samplerで作成する。
+    pub fn get(&self, desc: &SamplerDesc) -> crate::SamplerHandle {
+        let mut samplers = self.samplers.lock().unwrap();
+        samplers.retain(|_, sampler| !sampler.is_unique());
+        samplers
+            .entry(SamplerKey::from(desc))
+            .or_insert_with(|| create_sampler(&self.device, desc))
+            .clone()
+    }
+
+    /// キャッシュに残っているsamplerの数
+    pub fn len(&self) -> usize {
+        self.samplers.lock().unwrap().len()
+    }
+
+    /// キャッシュが空かどうか
+    pub fn is_empty(&self) -> bool {
+        self.samplers.lock().unwrap().is_empty()
+    }
+}
+
 /// BindlessなDescriptorSetをまとめた構造体
 pub struct BindlessDescriptorSets {
     /// uniform bufferのdescriptor set
@@ -409,3 +527,191 @@ impl BindlessDescriptorSets {
         }
     }
 }
+
+// save_to_fileで読み戻せるformatの1pixelのバイト数
+fn readback_bytes_per_pixel(format: vk::Format) -> Option<u64> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+// 読み戻したデータをformatに従ってpixelごとのRGBAのf32に変換する。
+// 8bitのformatは0から1に正規化するだけで、sRGBのformatでもリニアには変換しない
+fn decode_pixels(format: vk::Format, data: &[u8]) -> Vec<[f32; 4]> {
+    let f32_at =
+        |bytes: &[u8], i: usize| f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+    match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => data
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]].map(|c| c as f32 / 255.0))
+            .collect(),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => data
+            .chunks_exact(4)
+            .map(|p| [p[2], p[1], p[0], p[3]].map(|c| c as f32 / 255.0))
+            .collect(),
+        vk::Format::R16G16B16A16_SFLOAT => data
+            .chunks_exact(8)
+            .map(|p| {
+                let c = |i: usize| {
+                    exr::prelude::f16::from_bits(u16::from_le_bytes([p[i * 2], p[i * 2 + 1]]))
+                        .to_f32()
+                };
+                [c(0), c(1), c(2), c(3)]
+            })
+            .collect(),
+        vk::Format::R32G32B32A32_SFLOAT => data
+            .chunks_exact(16)
+            .map(|p| [f32_at(p, 0), f32_at(p, 1), f32_at(p, 2), f32_at(p, 3)])
+            .collect(),
+        vk::Format::R32_SFLOAT => data
+            .chunks_exact(4)
+            .map(|p| {
+                let v = f32_at(p, 0);
+                [v, v, v, 1.0]
+            })
+            .collect(),
+        _ => unreachable!("Unsupported format: {format:?}"),
+    }
+}
+
+impl crate::ImageHandle {
+    /// Imageの内容をGPUから読み戻して画像ファイルとして保存する。デバッグ用。
+    ///
+    /// layoutには現在のImageのlayoutを指定する。
+    /// 一時的にTRANSFER_SRC_OPTIMALに遷移してstaging bufferにコピーし、元のlayoutに戻す。
+    /// queueには最後にImageに書き込んだqueueを指定する。
+    /// ImageはEXCLUSIVEなので、queue family ownershipを移さずにそのqueueでコピーする。
+    /// 実行中のコマンドがImageに書き込んでいる場合は、呼び出し側で完了を待ってから呼び出すこと。
+    /// Imageのusageには`TRANSFER_SRC`が含まれている必要がある。
+    ///
+    /// 対応しているformatは`R8G8B8A8_UNORM`、`R8G8B8A8_SRGB`、`B8G8R8A8_UNORM`、
+    /// `B8G8R8A8_SRGB`、`R16G16B16A16_SFLOAT`、`R32G32B32A32_SFLOAT`、`R32_SFLOAT`。
+    /// pathの拡張子が`exr`の場合はRGBAのf32のOpenEXRとして保存し、
+    /// それ以外の場合は拡張子から決まる形式で8bitのRGBAとして保存する。
+    /// 8bitで保存する場合、floatのformatの値は0から1にclampするだけでトーンマッピングはしない。
+    pub fn save_to_file(
+        &self,
+        device: &crate::DeviceHandle,
+        queue: &QueueHandle,
+        allocator: &crate::AllocatorHandle,
+        layout: vk::ImageLayout,
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let format = self.format();
+        let vk::Extent3D { width, height, .. } = self.extent();
+        let Some(bytes_per_pixel) = readback_bytes_per_pixel(format) else {
+            anyhow::bail!("Unsupported image format for save_to_file: {format:?}");
+        };
+        if layout == vk::ImageLayout::UNDEFINED {
+            anyhow::bail!("Image in UNDEFINED layout has no contents to save");
+        }
+
+        // buffer_row_lengthとbuffer_image_heightをimageのサイズにして、
+        // 行の間に隙間のない状態でbufferに詰める
+        let buffer_size = width as u64 * height as u64 * bytes_per_pixel;
+        let staging_buffer = create_host_buffer(
+            device,
+            allocator,
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+        );
+
+        let command_pool = device.create_command_pool(
+            &vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(queue.family_index),
+        );
+        let command_buffer = &allocate_command_buffers(device, &command_pool, 1)[0];
+        begin_onetime_command_buffer(command_buffer);
+        cmd_image_barriers(
+            command_buffer,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_WRITE,
+            layout,
+            vk::PipelineStageFlags2::COPY,
+            vk::AccessFlags2::TRANSFER_READ,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self,
+        );
+        command_buffer.cmd_copy_image_to_buffer(
+            self,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            &staging_buffer.buffer,
+            &[vk::BufferImageCopy::builder()
+                .buffer_offset(0)
+                .buffer_row_length(width)
+                .buffer_image_height(height)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image_extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .build()],
+        );
+        cmd_image_barriers(
+            command_buffer,
+            vk::PipelineStageFlags2::COPY,
+            vk::AccessFlags2::TRANSFER_READ,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+            layout,
+            self,
+        );
+        command_buffer.end_command_buffer();
+
+        let fence = create_fence(device);
+        device.queue_submit(
+            queue.queue,
+            std::slice::from_ref(
+                &vk::SubmitInfo::builder()
+                    .command_buffers(&[**command_buffer])
+                    .wait_dst_stage_mask(&[])
+                    .wait_semaphores(&[]),
+            ),
+            Some(fence.clone()),
+        );
+        device.wait_fences(&[fence], u64::MAX);
+
+        let data = staging_buffer
+            .mapped_slice()
+            .ok_or_else(|| anyhow::anyhow!("Staging buffer is not host visible"))?;
+        let pixels = decode_pixels(format, data);
+
+        let is_exr = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+        if is_exr {
+            exr::prelude::write_rgba_file(path, width as usize, height as usize, |x, y| {
+                let [r, g, b, a] = pixels[y * width as usize + x];
+                (r, g, b, a)
+            })?;
+        } else {
+            let bytes = pixels
+                .iter()
+                .flat_map(|pixel| pixel.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+                .collect::<Vec<_>>();
+            let image = ::image::RgbaImage::from_raw(width, height, bytes)
+                .ok_or_else(|| anyhow::anyhow!("Failed to create image from staging buffer"))?;
+            image.save(path)?;
+        }
+
+        Ok(())
+    }
+}
@@ -12,18 +12,178 @@ pub struct BlasObjects {
     pub vertex_buffer: BufferObjects,
     /// BlasのIndexBuffer
     pub index_buffer: BufferObjects,
+    /// Blasのvertex formatとindex type
+    pub format: BlasGeometryFormat,
+}
+
+/// Blasをビルドする際のvertex bufferの頂点位置のformatとindex bufferのindex type
+///
+/// デフォルトはR32G32B32_SFLOATとUINT32。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlasGeometryFormat {
+    /// 頂点の先頭に置かれた頂点位置のformat
+    pub vertex_format: vk::Format,
+    /// indexの型。UINT16かUINT32に対応する。
+    pub index_type: vk::IndexType,
+}
+impl Default for BlasGeometryFormat {
+    fn default() -> Self {
+        Self {
+            vertex_format: vk::Format::R32G32B32_SFLOAT,
+            index_type: vk::IndexType::UINT32,
+        }
+    }
+}
+impl BlasGeometryFormat {
+    /// index typeの1つのindexのバイト数
+    pub fn index_size(&self) -> usize {
+        match self.index_type {
+            vk::IndexType::UINT16 => 2,
+            vk::IndexType::UINT32 => 4,
+            index_type => panic!("Unsupported index type for blas: {index_type:?}"),
+        }
+    }
+}
+
+/// acceleration structureのビルドでトレースの速さ、ビルドの速さ、メモリのどれを優先するか
+///
+/// デフォルトは静的なsceneに向いたFastTrace。
+/// 毎フレーム変形するmeshや作り直すTlasはFastBuildにするとビルドの時間を減らせるが、
+/// トレースは遅くなる。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildPreference {
+    /// PREFER_FAST_TRACEでビルドする
+    #[default]
+    FastTrace,
+    /// PREFER_FAST_BUILDでビルドする
+    FastBuild,
+    /// LOW_MEMORYでビルドする
+    LowMemory,
+}
+impl BuildPreference {
+    /// 対応するbuild flags
+    pub fn build_flags(&self) -> vk::BuildAccelerationStructureFlagsKHR {
+        match self {
+            BuildPreference::FastTrace => vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+            BuildPreference::FastBuild => vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD,
+            BuildPreference::LowMemory => vk::BuildAccelerationStructureFlagsKHR::LOW_MEMORY,
+        }
+    }
+}
+
+/// vertex formatがacceleration structureのビルドの頂点位置として使えるかを返す
+pub fn is_blas_vertex_format_supported(device: &crate::DeviceHandle, format: vk::Format) -> bool {
+    device
+        .get_physical_device_format_properties(format)
+        .buffer_features
+        .contains(vk::FormatFeatureFlags::ACCELERATION_STRUCTURE_VERTEX_BUFFER_KHR)
+}
+
+// Blasの三角形のgeometryを作成する
+fn blas_geometry(
+    vertex_address: vk::DeviceAddress,
+    index_address: vk::DeviceAddress,
+    vertex_count: usize,
+    vertex_stride: usize,
+    format: BlasGeometryFormat,
+    transparent: bool,
+) -> vk::AccelerationStructureGeometryKHR {
+    let geometry_triangle_date = vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+        .vertex_format(format.vertex_format)
+        .vertex_data(vk::DeviceOrHostAddressConstKHR {
+            device_address: vertex_address,
+        })
+        .max_vertex(vertex_count as u32 - 1)
+        .vertex_stride(vertex_stride as u64)
+        .index_type(format.index_type)
+        .index_data(vk::DeviceOrHostAddressConstKHR {
+            device_address: index_address,
+        });
+    let mut geometry = vk::AccelerationStructureGeometryKHR::builder()
+        .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+        .geometry(vk::AccelerationStructureGeometryDataKHR {
+            triangles: *geometry_triangle_date,
+        });
+    if !transparent {
+        geometry = geometry.flags(vk::GeometryFlagsKHR::OPAQUE);
+    }
+    *geometry
+}
+
+// Blasのbuild flagsを作成する
+fn blas_build_flags(
+    preference: BuildPreference,
+    compact: bool,
+) -> vk::BuildAccelerationStructureFlagsKHR {
+    let mut build_flags = preference.build_flags();
+    if compact {
+        build_flags |= vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION;
+    }
+    build_flags
+}
+
+/// cerate_blasと同じ設定でBlasをビルドする場合に必要なサイズを、bufferを確保せずに取得する
+///
+/// compactがtrueの場合のacceleration_structure_sizeはコンパクション前のサイズになる。
+#[allow(clippy::too_many_arguments)]
+pub fn get_blas_build_sizes(
+    device: &crate::DeviceHandle,
+    vertex_count: usize,
+    vertex_stride: usize,
+    index_count: usize,
+    format: BlasGeometryFormat,
+    transparent: bool,
+    preference: BuildPreference,
+    compact: bool,
+) -> vk::AccelerationStructureBuildSizesInfoKHR {
+    let geometry = blas_geometry(0, 0, vertex_count, vertex_stride, format, transparent);
+    let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+        .geometries(std::slice::from_ref(&geometry))
+        .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+        .flags(blas_build_flags(preference, compact))
+        .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+        .src_acceleration_structure(vk::AccelerationStructureKHR::null());
+    device.get_acceleration_structure_build_sizes(
+        vk::AccelerationStructureBuildTypeKHR::DEVICE,
+        &build_geometry_info,
+        &[(index_count / 3) as u32],
+    )
 }
 
 /// Blasを作成するヘルパー関数
-pub fn cerate_blas<T: Copy>(
+///
+/// preferenceでトレースとビルドの速さのどちらを優先するかを選ぶ。
+/// compactがtrueの場合はALLOW_COMPACTIONでビルドしてからcompact_blasでコンパクションし、
+/// コンパクション後のBlasを返す。
+///
+/// verticesの各要素の先頭にformat.vertex_formatの頂点位置が置かれている必要があり、
+/// indicesの要素のサイズはformat.index_typeと一致している必要がある。
+/// indicesの末尾には三角形に満たない余りのindexをパディングとして置いてもよい。
+#[allow(clippy::too_many_arguments)]
+pub fn cerate_blas<T: Copy, I: Copy>(
     device: &crate::DeviceHandle,
     queue_handles: &QueueHandles,
     compute_command_pool: &crate::CommandPoolHandle,
     allocator: &crate::AllocatorHandle,
     vertices: &[T],
-    indices: &[u32],
+    indices: &[I],
+    format: BlasGeometryFormat,
     transparent: bool,
+    preference: BuildPreference,
+    compact: bool,
 ) -> BlasObjects {
+    assert_eq!(
+        std::mem::size_of::<I>(),
+        format.index_size(),
+        "The index element size doesn't match the index type {:?}",
+        format.index_type
+    );
+    assert!(
+        is_blas_vertex_format_supported(device, format.vertex_format),
+        "The vertex format {:?} is not supported for acceleration structure build",
+        format.vertex_format
+    );
+
     let vertex_buffer = create_host_buffer_with_data(
         &device,
         &allocator,
@@ -42,37 +202,169 @@ pub fn cerate_blas<T: Copy>(
     );
 
     // geometryを作成
-    let geometry_triangle_date = vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
-        .vertex_format(vk::Format::R32G32B32_SFLOAT)
-        .vertex_data(vk::DeviceOrHostAddressConstKHR {
-            device_address: vertex_buffer.device_address,
+    let geometry = blas_geometry(
+        vertex_buffer.device_address,
+        index_buffer.device_address,
+        vertices.len(),
+        std::mem::size_of::<T>(),
+        format,
+        transparent,
+    );
+
+    let (blas, blas_buffer) = build_blas(
+        device,
+        queue_handles,
+        compute_command_pool,
+        allocator,
+        &geometry,
+        (indices.len() / 3) as u32,
+        blas_build_flags(preference, compact),
+    );
+    let blas = BlasObjects {
+        blas,
+        blas_buffer,
+        vertex_buffer,
+        index_buffer,
+        format,
+    };
+
+    // コンパクションする場合、元のblasはこの関数を抜ける際に破棄される
+    if compact {
+        compact_blas(
+            device,
+            queue_handles,
+            compute_command_pool,
+            allocator,
+            &blas,
+        )
+    } else {
+        blas
+    }
+}
+
+// AABBのBlasのgeometryを作成する
+fn aabb_geometry(aabbs_address: vk::DeviceAddress) -> vk::AccelerationStructureGeometryKHR {
+    let geometry_aabbs_data = vk::AccelerationStructureGeometryAabbsDataKHR::builder()
+        .data(vk::DeviceOrHostAddressConstKHR {
+            device_address: aabbs_address,
         })
-        // .vertex_stride(std::mem::size_of::<T>() as u64)
-        .max_vertex(indices.len() as u32 - 1)
-        .vertex_stride(std::mem::size_of::<T>() as u64)
-        .index_type(vk::IndexType::UINT32)
-        .index_data(vk::DeviceOrHostAddressConstKHR {
-            device_address: index_buffer.device_address,
-        });
-    let mut geometry = vk::AccelerationStructureGeometryKHR::builder()
-        .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+        .stride(std::mem::size_of::<vk::AabbPositionsKHR>() as u64);
+    vk::AccelerationStructureGeometryKHR::builder()
+        .geometry_type(vk::GeometryTypeKHR::AABBS)
         .geometry(vk::AccelerationStructureGeometryDataKHR {
-            triangles: *geometry_triangle_date,
-        });
-    if !transparent {
-        geometry = geometry.flags(vk::GeometryFlagsKHR::OPAQUE);
+            aabbs: *geometry_aabbs_data,
+        })
+        .flags(vk::GeometryFlagsKHR::OPAQUE)
+        .build()
+}
+
+/// create_aabb_blasでaabb_count個のAABBのBlasをビルドする場合に必要なサイズを、
+/// bufferを確保せずに取得する
+pub fn get_aabb_blas_build_sizes(
+    device: &crate::DeviceHandle,
+    aabb_count: usize,
+    preference: BuildPreference,
+    compact: bool,
+) -> vk::AccelerationStructureBuildSizesInfoKHR {
+    let geometry = aabb_geometry(0);
+    let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+        .geometries(std::slice::from_ref(&geometry))
+        .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+        .flags(blas_build_flags(preference, compact))
+        .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+        .src_acceleration_structure(vk::AccelerationStructureKHR::null());
+    device.get_acceleration_structure_build_sizes(
+        vk::AccelerationStructureBuildTypeKHR::DEVICE,
+        &build_geometry_info,
+        &[aabb_count as u32],
+    )
+}
+
+/// AABBのBlasを作成するヘルパー関数
+///
+/// 球などの三角形以外の形状を、primitiveごとのAABBとintersection shaderで表すのに使う。
+/// AABBのBlasではvertex_bufferにaabbsを、index_bufferにprimitive_dataを置くので、
+/// intersection shaderやclosest hit shaderはinstanceのvertex bufferとindex bufferの
+/// device addressからgl_PrimitiveIDの要素を読める。
+/// primitive_dataには色などのprimitiveごとのデータをaabbsと同じ順で渡す。
+#[allow(clippy::too_many_arguments)]
+pub fn create_aabb_blas<T: Copy>(
+    device: &crate::DeviceHandle,
+    queue_handles: &QueueHandles,
+    compute_command_pool: &crate::CommandPoolHandle,
+    allocator: &crate::AllocatorHandle,
+    aabbs: &[vk::AabbPositionsKHR],
+    primitive_data: &[T],
+    preference: BuildPreference,
+    compact: bool,
+) -> BlasObjects {
+    let aabb_buffer = create_host_buffer_with_data(
+        device,
+        allocator,
+        aabbs,
+        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+    );
+    let primitive_data_buffer = create_host_buffer_with_data(
+        device,
+        allocator,
+        primitive_data,
+        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
+    );
+
+    // geometryを作成
+    let geometry = aabb_geometry(aabb_buffer.device_address);
+
+    let (blas, blas_buffer) = build_blas(
+        device,
+        queue_handles,
+        compute_command_pool,
+        allocator,
+        &geometry,
+        aabbs.len() as u32,
+        blas_build_flags(preference, compact),
+    );
+    let blas = BlasObjects {
+        blas,
+        blas_buffer,
+        vertex_buffer: aabb_buffer,
+        index_buffer: primitive_data_buffer,
+        format: BlasGeometryFormat::default(),
+    };
+
+    // コンパクションする場合、元のblasはこの関数を抜ける際に破棄される
+    if compact {
+        compact_blas(
+            device,
+            queue_handles,
+            compute_command_pool,
+            allocator,
+            &blas,
+        )
+    } else {
+        blas
     }
+}
 
+// geometryをbuild_flagsでビルドし、Blasとそのbufferを返す。
+fn build_blas(
+    device: &crate::DeviceHandle,
+    queue_handles: &QueueHandles,
+    compute_command_pool: &crate::CommandPoolHandle,
+    allocator: &crate::AllocatorHandle,
+    geometry: &vk::AccelerationStructureGeometryKHR,
+    primitive_count: u32,
+    build_flags: vk::BuildAccelerationStructureFlagsKHR,
+) -> (crate::AccelerationStructureHandle, BufferObjects) {
     // build geometry infoを作成
     let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
-        .geometries(std::slice::from_ref(&geometry))
+        .geometries(std::slice::from_ref(geometry))
         .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
-        .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+        .flags(build_flags)
         .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
         .src_acceleration_structure(vk::AccelerationStructureKHR::null());
 
     // 必要なバッファサイズを取得
-    let primitive_count = (indices.len() / 3) as u32;
     let build_size_info = device.get_acceleration_structure_build_sizes(
         vk::AccelerationStructureBuildTypeKHR::DEVICE,
         &build_geometry_info,
@@ -109,9 +401,9 @@ pub fn cerate_blas<T: Copy>(
     {
         // build用にbuild geometry infoを作成
         let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
-            .geometries(std::slice::from_ref(&geometry))
+            .geometries(std::slice::from_ref(geometry))
             .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+            .flags(build_flags)
             .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
             .src_acceleration_structure(vk::AccelerationStructureKHR::null())
             .dst_acceleration_structure(*blas)
@@ -172,15 +464,312 @@ pub fn cerate_blas<T: Copy>(
         );
         device.wait_fences(&[fence], u64::MAX);
 
-        BlasObjects {
+        // scratch bufferはこの関数を抜ける際に破棄される
+        (blas, blas_buffer)
+    }
+}
+
+/// Blasをコンパクションするヘルパー関数
+///
+/// blasはALLOW_COMPACTIONのフラグを付けてビルドされている必要がある。
+/// コンパクション後のサイズをquery poolで取得し、そのサイズのbufferに
+/// COMPACTモードでコピーしたBlasObjectsを返す。
+/// vertex bufferとindex bufferは元のBlasObjectsと共有する。
+pub fn compact_blas(
+    device: &crate::DeviceHandle,
+    queue_handles: &QueueHandles,
+    compute_command_pool: &crate::CommandPoolHandle,
+    allocator: &crate::AllocatorHandle,
+    blas: &BlasObjects,
+) -> BlasObjects {
+    // query poolの作成
+    let query_pool = device.create_query_pool(
+        &vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR)
+            .query_count(1),
+    );
+
+    // コマンドバッファの確保
+    let command_buffer = {
+        let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(**compute_command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        let command_buffers =
+            device.allocate_command_buffers(compute_command_pool, &command_buffer_allocate_info);
+        command_buffers.into_iter().next().unwrap()
+    };
+
+    // コンパクション後のサイズを取得する
+    let compacted_size = {
+        begin_onetime_command_buffer(&command_buffer);
+        command_buffer.cmd_reset_query_pool(&query_pool, 0, 1);
+        command_buffer.cmd_write_acceleration_structures_properties(
+            std::slice::from_ref(&blas.blas),
+            vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+            &query_pool,
+            0,
+        );
+        command_buffer.end_command_buffer();
+
+        let buffers_to_submit = [*command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&buffers_to_submit)
+            .build();
+        let fence = create_fence(device);
+        device.queue_submit(
+            queue_handles.compute.queue,
+            &[submit_info],
+            Some(fence.clone()),
+        );
+        device.wait_fences(&[fence], u64::MAX);
+
+        let mut compacted_size = [0u64];
+        query_pool.get_query_pool_results(
+            0,
+            &mut compacted_size,
+            vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+        );
+        compacted_size[0]
+    };
+
+    // コンパクション後のサイズでバッファを確保
+    let compacted_blas_buffer = create_device_local_buffer(
+        device,
+        allocator,
+        compacted_size,
+        vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+    );
+
+    // コンパクション後のblasの作成
+    let compacted_blas = device.create_acceleration_structure(
+        &vk::AccelerationStructureCreateInfoKHR::builder()
+            .buffer(*compacted_blas_buffer.buffer)
+            .size(compacted_size)
+            .offset(0)
+            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL),
+    );
+
+    // COMPACTモードでコピーする
+    {
+        begin_onetime_command_buffer(&command_buffer);
+        command_buffer.cmd_copy_acceleration_structure(
+            &vk::CopyAccelerationStructureInfoKHR::builder()
+                .src(*blas.blas)
+                .dst(*compacted_blas)
+                .mode(vk::CopyAccelerationStructureModeKHR::COMPACT),
+        );
+        // メモリバリア
+        let barrier = vk::MemoryBarrier2KHR::builder()
+            .src_stage_mask(vk::PipelineStageFlags2KHR::ACCELERATION_STRUCTURE_BUILD_KHR)
+            .src_access_mask(vk::AccessFlags2KHR::ACCELERATION_STRUCTURE_WRITE_KHR)
+            .dst_stage_mask(vk::PipelineStageFlags2KHR::ACCELERATION_STRUCTURE_BUILD_KHR)
+            .dst_access_mask(vk::AccessFlags2KHR::ACCELERATION_STRUCTURE_READ_KHR);
+        command_buffer.cmd_pipeline_barrier2(
+            &vk::DependencyInfoKHR::builder()
+                .memory_barriers(std::slice::from_ref(&barrier))
+                .build(),
+        );
+        command_buffer.end_command_buffer();
+
+        let buffers_to_submit = [*command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&buffers_to_submit)
+            .build();
+        let fence = create_fence(device);
+        device.queue_submit(
+            queue_handles.compute.queue,
+            &[submit_info],
+            Some(fence.clone()),
+        );
+        device.wait_fences(&[fence], u64::MAX);
+    }
+
+    BlasObjects {
+        blas: compacted_blas,
+        blas_buffer: compacted_blas_buffer,
+        vertex_buffer: blas.vertex_buffer.clone(),
+        index_buffer: blas.index_buffer.clone(),
+        format: blas.format,
+    }
+}
+
+/// 頂点を書き換えてrefitできるBlas関連のオブジェクトをまとめた構造体
+#[derive(Clone)]
+pub struct UpdatableBlasObjects {
+    /// BlasObjects。vertex bufferはdevice localで、compute shaderから書き換えられる
+    pub blas: BlasObjects,
+    /// cmd_update_blasでrefitする際に使い回すscratch buffer
+    pub update_scratch_buffer: BufferObjects,
+    vertex_count: usize,
+    vertex_stride: usize,
+    primitive_count: u32,
+    transparent: bool,
+}
+
+/// 頂点を書き換えてcmd_update_blasでrefitできるBlasを作成するヘルパー関数
+///
+/// ALLOW_UPDATEとPREFER_FAST_BUILDでビルドし、コンパクションはしない。
+/// vertex bufferはSTORAGE_BUFFERのdevice localなbufferで、verticesを初期値として持つ。
+/// skinningなどで頂点を書き換えたあとにcmd_update_blasを積むと、
+/// Blasとそのbufferのhandleとdevice addressを変えずにrefitできる。
+/// refitでは三角形の接続は変えられないので、indicesは作成時のものを使い続ける。
+///
+/// verticesとindicesの要件はcerate_blasと同じ。
+#[allow(clippy::too_many_arguments)]
+pub fn create_updatable_blas<T: Copy, I: Copy>(
+    device: &crate::DeviceHandle,
+    queue_handles: &QueueHandles,
+    compute_command_pool: &crate::CommandPoolHandle,
+    transfer_command_pool: &crate::CommandPoolHandle,
+    allocator: &crate::AllocatorHandle,
+    vertices: &[T],
+    indices: &[I],
+    format: BlasGeometryFormat,
+    transparent: bool,
+) -> UpdatableBlasObjects {
+    assert_eq!(
+        std::mem::size_of::<I>(),
+        format.index_size(),
+        "The index element size doesn't match the index type {:?}",
+        format.index_type
+    );
+    assert!(
+        is_blas_vertex_format_supported(device, format.vertex_format),
+        "The vertex format {:?} is not supported for acceleration structure build",
+        format.vertex_format
+    );
+
+    let vertex_buffer = create_device_local_buffer_with_data(
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
+        vertices,
+        vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+    );
+    let index_buffer = create_host_buffer_with_data(
+        device,
+        allocator,
+        indices,
+        vk::BufferUsageFlags::INDEX_BUFFER
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+    );
+
+    // geometryを作成
+    let vertex_stride = std::mem::size_of::<T>();
+    let geometry = blas_geometry(
+        vertex_buffer.device_address,
+        index_buffer.device_address,
+        vertices.len(),
+        vertex_stride,
+        format,
+        transparent,
+    );
+    let primitive_count = (indices.len() / 3) as u32;
+    let build_flags = updatable_blas_build_flags();
+
+    let (blas, blas_buffer) = build_blas(
+        device,
+        queue_handles,
+        compute_command_pool,
+        allocator,
+        &geometry,
+        primitive_count,
+        build_flags,
+    );
+
+    // refit用のscratch bufferの作成
+    let build_size_info = device.get_acceleration_structure_build_sizes(
+        vk::AccelerationStructureBuildTypeKHR::DEVICE,
+        &vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .geometries(std::slice::from_ref(&geometry))
+            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+            .flags(build_flags)
+            .mode(vk::BuildAccelerationStructureModeKHR::UPDATE),
+        &[primitive_count],
+    );
+    let update_scratch_buffer = create_device_local_buffer(
+        device,
+        allocator,
+        build_size_info.update_scratch_size,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+    );
+
+    UpdatableBlasObjects {
+        blas: BlasObjects {
             blas,
             blas_buffer,
             vertex_buffer,
             index_buffer,
-        }
+            format,
+        },
+        update_scratch_buffer,
+        vertex_count: vertices.len(),
+        vertex_stride,
+        primitive_count,
+        transparent,
     }
 }
 
+// refitできるBlasのbuild flags
+fn updatable_blas_build_flags() -> vk::BuildAccelerationStructureFlagsKHR {
+    BuildPreference::FastBuild.build_flags() | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE
+}
+
+/// create_updatable_blasで作成したBlasを、vertex bufferの今の頂点でrefitするコマンドを積む
+///
+/// vertex bufferへの書き込みはこのコマンドより前にACCELERATION_STRUCTURE_BUILDの
+/// stageから見えるようにしておく必要がある。
+/// 積んだコマンドの後では、Tlasのビルドで更新後のBlasを読める。
+pub fn cmd_update_blas(command_buffer: &crate::CommandBufferHandle, blas: &UpdatableBlasObjects) {
+    let geometry = blas_geometry(
+        blas.blas.vertex_buffer.device_address,
+        blas.blas.index_buffer.device_address,
+        blas.vertex_count,
+        blas.vertex_stride,
+        blas.blas.format,
+        blas.transparent,
+    );
+    let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+        .geometries(std::slice::from_ref(&geometry))
+        .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+        .flags(updatable_blas_build_flags())
+        .mode(vk::BuildAccelerationStructureModeKHR::UPDATE)
+        .src_acceleration_structure(*blas.blas.blas)
+        .dst_acceleration_structure(*blas.blas.blas)
+        .scratch_data(vk::DeviceOrHostAddressKHR {
+            device_address: blas.update_scratch_buffer.device_address,
+        });
+    let acceleration_structure_build_range_info =
+        vk::AccelerationStructureBuildRangeInfoKHR::builder()
+            .primitive_count(blas.primitive_count)
+            .primitive_offset(0)
+            .first_vertex(0)
+            .transform_offset(0);
+
+    command_buffer.cmd_build_acceleration_structures(
+        std::slice::from_ref(&build_geometry_info),
+        &[std::slice::from_ref(
+            &acceleration_structure_build_range_info,
+        )],
+    );
+    let barrier = vk::MemoryBarrier2KHR::builder()
+        .src_stage_mask(vk::PipelineStageFlags2KHR::ACCELERATION_STRUCTURE_BUILD_KHR)
+        .src_access_mask(vk::AccessFlags2KHR::ACCELERATION_STRUCTURE_WRITE_KHR)
+        .dst_stage_mask(vk::PipelineStageFlags2KHR::ACCELERATION_STRUCTURE_BUILD_KHR)
+        .dst_access_mask(vk::AccessFlags2KHR::ACCELERATION_STRUCTURE_READ_KHR);
+    command_buffer.cmd_pipeline_barrier2(
+        &vk::DependencyInfoKHR::builder()
+            .memory_barriers(std::slice::from_ref(&barrier))
+            .build(),
+    );
+}
+
 /// Tlas関連のオブジェクトをまとめた構造体
 #[derive(Clone)]
 pub struct TlasObjects {
@@ -188,142 +777,362 @@ pub struct TlasObjects {
     pub tlas: crate::AccelerationStructureHandle,
     /// TlasのBufferObjects
     pub tlas_buffer: BufferObjects,
+    /// TlasのinstanceのBufferObjects。cmd_update_tlasで書き換えるのでHostから見える
+    pub instances_buffer: BufferObjects,
+    /// cmd_update_tlasでビルドし直す際に使い回すscratch buffer
+    pub scratch_buffer: BufferObjects,
     /// TlasのInstanceParamのBufferObjects
     pub instance_params_buffer: BufferObjects,
+    /// cmd_update_tlasでInstanceParamを書き換える際のstaging buffer
+    pub instance_params_staging_buffer: BufferObjects,
     /// TlasのMaterialのBufferObjects
     pub materials_buffer: BufferObjects,
+    /// motion instanceのTlasかどうか
+    pub motion: bool,
+    /// Tlasをビルドした際のpreference
+    pub preference: BuildPreference,
 }
 
-/// Tlasを作成するヘルパー関数
-pub fn create_tlas<Material: Copy>(
+// shaderから読むinstanceごとのパラメータ。
+// transform_endはmotion instanceのtime 1.0でのtransformで、動かないinstanceではtransformと同じ
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct InstanceParam {
+    address_index: u64,
+    address_vertex: u64,
+    transform: glam::Mat4,
+    transform_end: glam::Mat4,
+    material_index: u32,
+    // 0ならUINT32、1ならUINT16のindex buffer
+    index_type: u32,
+    light_link_group: u32,
+    padding_2: u32,
+}
+
+// motion instanceのbufferにはVkAccelerationStructureMotionInstanceNVを160バイトの間隔で並べる
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct MotionInstance(vk::AccelerationStructureMotionInstanceNV);
+
+/// deviceでVK_NV_ray_tracing_motion_blurが有効で、motion instanceのTlasを作れるかどうか
+pub fn is_ray_tracing_motion_blur_enabled(device: &crate::DeviceHandle) -> bool {
+    device.is_extension_enabled(vk::NvRayTracingMotionBlurFn::name())
+}
+
+/// Tlasのinstanceのbufferの1つのinstanceのバイト数
+pub fn get_tlas_instance_size(motion: bool) -> usize {
+    if motion {
+        std::mem::size_of::<MotionInstance>()
+    } else {
+        std::mem::size_of::<vk::AccelerationStructureInstanceKHR>()
+    }
+}
+
+// Tlasのinstanceのgeometryを作成する
+fn tlas_geometry(instances_address: vk::DeviceAddress) -> vk::AccelerationStructureGeometryKHR {
+    *vk::AccelerationStructureGeometryKHR::builder()
+        .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+        .geometry(vk::AccelerationStructureGeometryDataKHR {
+            instances: *vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+                .array_of_pointers(false)
+                .data(vk::DeviceOrHostAddressConstKHR {
+                    device_address: instances_address,
+                }),
+        })
+}
+
+// Tlasのbuild flagsを作成する
+fn tlas_build_flags(
+    preference: BuildPreference,
+    motion: bool,
+) -> vk::BuildAccelerationStructureFlagsKHR {
+    let mut build_flags = preference.build_flags();
+    if motion {
+        build_flags |= vk::BuildAccelerationStructureFlagsKHR::MOTION_NV;
+    }
+    build_flags
+}
+
+/// create_tlasでinstance_count個のinstanceのTlasをビルドする場合に必要なサイズを、
+/// bufferを確保せずに取得する
+///
+/// motionがtrueの場合はmotion instanceのTlasのサイズになる。
+pub fn get_tlas_build_sizes(
     device: &crate::DeviceHandle,
-    queue_handles: &QueueHandles,
-    compute_command_pool: &crate::CommandPoolHandle,
-    transfer_command_pool: &crate::CommandPoolHandle,
-    allocator: &crate::AllocatorHandle,
-    instances: &[(BlasObjects, glam::Mat4, u32, u32)],
-    materials: &[Material],
-) -> TlasObjects {
-    #[repr(C)]
-    #[derive(Clone, Copy)]
-    struct InstanceParam {
-        pub address_index: u64,
-        pub address_vertex: u64,
-        pub transform: glam::Mat4,
-        pub material_index: u32,
-        pub padding_1: u32,
-        pub padding_2: u64,
+    instance_count: usize,
+    preference: BuildPreference,
+    motion: bool,
+) -> vk::AccelerationStructureBuildSizesInfoKHR {
+    let geometry = tlas_geometry(0);
+    let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+        .geometries(std::slice::from_ref(&geometry))
+        .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+        .flags(tlas_build_flags(preference, motion))
+        .src_acceleration_structure(vk::AccelerationStructureKHR::null());
+    device.get_acceleration_structure_build_sizes(
+        vk::AccelerationStructureBuildTypeKHR::DEVICE,
+        &build_geometry_info,
+        &[instance_count as u32],
+    )
+}
+
+// instancesのtransformをmatrixの上3行にする
+fn transform_matrix(transform: &glam::Mat4) -> vk::TransformMatrixKHR {
+    vk::TransformMatrixKHR {
+        matrix: transform.transpose().to_cols_array()[..12]
+            .try_into()
+            .unwrap(),
     }
+}
 
-    // instancesを作成
-    let instances_data = instances
-        .iter()
-        .map(|(blas, transform, _material_index, sbt_offset)| {
+// instancesをTlasのinstanceのbufferに書き込む。
+// motionの場合はtransform_endsがSomeのinstanceをmatrix motion instanceにする
+fn write_tlas_instances<Material: Copy>(
+    instances_buffer: &mut BufferObjects,
+    instances: &[(BlasObjects, glam::Mat4, u32, u32, u8, u32)],
+    transform_ends: &[Option<glam::Mat4>],
+    materials: &[Material],
+    motion: bool,
+    cull_disabled: impl Fn(&Material) -> bool,
+) {
+    let instances_data = instances.iter().enumerate().map(
+        |(index, (blas, transform, material_index, sbt_offset, ray_mask, _light_link_group))| {
+            let mut flags = vk::GeometryInstanceFlagsKHR::TRIANGLE_FRONT_COUNTERCLOCKWISE;
+            if cull_disabled(&materials[*material_index as usize]) {
+                flags |= vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE;
+            }
             vk::AccelerationStructureInstanceKHR {
-                transform: vk::TransformMatrixKHR {
-                    matrix: transform.transpose().to_cols_array()[..12]
-                        .try_into()
-                        .unwrap(),
-                },
+                transform: transform_matrix(transform),
                 instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
                     *sbt_offset,
-                    vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
+                    flags.as_raw() as u8,
                 ),
-                instance_custom_index_and_mask: vk::Packed24_8::new(0, 0xFF),
+                instance_custom_index_and_mask: vk::Packed24_8::new(index as u32, *ray_mask),
                 acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
                     device_handle: blas.blas.get_acceleration_structure_device_address(),
                 },
             }
+        },
+    );
+
+    if !motion {
+        instances_buffer.write(&instances_data.collect::<Vec<_>>());
+        return;
+    }
+
+    let motion_instances_data = instances_data
+        .zip(transform_ends)
+        .map(|(instance, transform_end)| {
+            let (ty, data) = match transform_end {
+                Some(transform_end) => (
+                    vk::AccelerationStructureMotionInstanceTypeNV::MATRIX_MOTION,
+                    vk::AccelerationStructureMotionInstanceDataNV {
+                        matrix_motion_instance: vk::AccelerationStructureMatrixMotionInstanceNV {
+                            transform_t0: instance.transform,
+                            transform_t1: transform_matrix(transform_end),
+                            instance_custom_index_and_mask: instance.instance_custom_index_and_mask,
+                            instance_shader_binding_table_record_offset_and_flags: instance
+                                .instance_shader_binding_table_record_offset_and_flags,
+                            acceleration_structure_reference: instance
+                                .acceleration_structure_reference,
+                        },
+                    },
+                ),
+                None => (
+                    vk::AccelerationStructureMotionInstanceTypeNV::STATIC,
+                    vk::AccelerationStructureMotionInstanceDataNV {
+                        static_instance: instance,
+                    },
+                ),
+            };
+            MotionInstance(vk::AccelerationStructureMotionInstanceNV {
+                ty,
+                flags: vk::AccelerationStructureMotionInstanceFlagsNV::empty(),
+                data,
+            })
         })
         .collect::<Vec<_>>();
+    instances_buffer.write(&motion_instances_data);
+}
 
-    // instancesのbufferを作成
-    let instances_buffer = create_host_buffer_with_data(
-        &device,
-        &allocator,
-        &instances_data,
-        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
-    );
-
-    // geometryを作成
-    let instance_data_device_address = vk::DeviceOrHostAddressConstKHR {
-        device_address: instances_buffer.device_address,
-    };
-    let geometry = vk::AccelerationStructureGeometryKHR::builder()
-        .geometry_type(vk::GeometryTypeKHR::INSTANCES)
-        .geometry(vk::AccelerationStructureGeometryDataKHR {
-            instances: *vk::AccelerationStructureGeometryInstancesDataKHR::builder()
-                .array_of_pointers(false)
-                .data(instance_data_device_address),
-        });
+// instancesからshaderに渡すInstanceParamを作成する
+fn tlas_instance_params(
+    instances: &[(BlasObjects, glam::Mat4, u32, u32, u8, u32)],
+    transform_ends: &[Option<glam::Mat4>],
+) -> Vec<InstanceParam> {
+    instances
+        .iter()
+        .zip(transform_ends)
+        .map(
+            |(
+                (blas, transform, material, _sbt_offset, _ray_mask, light_link_group),
+                transform_end,
+            )| {
+                InstanceParam {
+                    address_index: blas.index_buffer.device_address,
+                    address_vertex: blas.vertex_buffer.device_address,
+                    transform: *transform,
+                    transform_end: transform_end.unwrap_or(*transform),
+                    material_index: *material,
+                    index_type: match blas.format.index_type {
+                        vk::IndexType::UINT16 => 1,
+                        _ => 0,
+                    },
+                    light_link_group: *light_link_group,
+                    padding_2: 0,
+                }
+            },
+        )
+        .collect()
+}
 
-    // build geometry infoを作成
+// tlasをinstances_bufferのinstanceでビルドするコマンドを積む。
+// ビルドしたTlasをacceleration structureのビルドとray tracingで読めるようにするバリアも積む
+fn cmd_build_tlas(
+    command_buffer: &crate::CommandBufferHandle,
+    tlas: &crate::AccelerationStructureHandle,
+    instances_buffer: &BufferObjects,
+    scratch_buffer: &BufferObjects,
+    instance_count: u32,
+    build_flags: vk::BuildAccelerationStructureFlagsKHR,
+) {
+    let geometry = tlas_geometry(instances_buffer.device_address);
     let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
         .geometries(std::slice::from_ref(&geometry))
         .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
-        .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
-        .src_acceleration_structure(vk::AccelerationStructureKHR::null());
+        .flags(build_flags)
+        .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+        .src_acceleration_structure(vk::AccelerationStructureKHR::null())
+        .dst_acceleration_structure(**tlas)
+        .scratch_data(vk::DeviceOrHostAddressKHR {
+            device_address: scratch_buffer.device_address,
+        });
+    let acceleration_structure_build_range_info =
+        vk::AccelerationStructureBuildRangeInfoKHR::builder()
+            .primitive_count(instance_count)
+            .first_vertex(0)
+            .primitive_offset(0)
+            .transform_offset(0);
+
+    command_buffer.cmd_build_acceleration_structures(
+        std::slice::from_ref(&build_geometry_info),
+        &[std::slice::from_ref(
+            &acceleration_structure_build_range_info,
+        )],
+    );
+    let barrier = vk::MemoryBarrier2KHR::builder()
+        .src_stage_mask(vk::PipelineStageFlags2KHR::ACCELERATION_STRUCTURE_BUILD_KHR)
+        .src_access_mask(vk::AccessFlags2KHR::ACCELERATION_STRUCTURE_WRITE_KHR)
+        .dst_stage_mask(
+            vk::PipelineStageFlags2KHR::ACCELERATION_STRUCTURE_BUILD_KHR
+                | vk::PipelineStageFlags2KHR::RAY_TRACING_SHADER_KHR,
+        )
+        .dst_access_mask(vk::AccessFlags2KHR::ACCELERATION_STRUCTURE_READ_KHR);
+    command_buffer.cmd_pipeline_barrier2(
+        &vk::DependencyInfoKHR::builder()
+            .memory_barriers(std::slice::from_ref(&barrier))
+            .build(),
+    );
+}
+
+/// Tlasを作成するヘルパー関数
+///
+/// instancesの要素は(blas, transform, material index, sbt offset, ray mask, light link group)。
+/// ray maskはinstanceのmaskとして書き込まれ、traceRayのcull maskとANDを取って
+/// 0になるinstanceはそのレイから無視される。
+/// light link groupはInstanceParamにそのまま書き込まれ、shaderでlight linkingに使われる。
+/// instanceのcustom indexにはinstancesの中でのindexを書き込む。
+/// 毎フレーム更新するTlasはpreferenceをFastBuildにするとビルドの時間を減らせる。
+///
+/// transform_endsがSomeの場合はmotion instanceのTlasを作成する。
+/// 要素がSomeのinstanceはtraceRayMotionNVのtime 0.0でtransform、1.0でその値になるように
+/// 行列の要素ごとに線形に補間され、Noneのinstanceは動かない。
+/// motion instanceのTlasはis_ray_tracing_motion_blur_enabledがtrueのdeviceでのみ作成でき、
+/// ray tracing pipelineはRAY_TRACING_ALLOW_MOTION_NVで作成する必要がある。
+/// InstanceParamにはtime 1.0でのtransformも書き込まれる。
+///
+/// 三角形はglTFと同じく反時計回りを表面とし、gl_HitKindEXTやtraceRayのray flagsでの
+/// カリングはこの向きで判定される。
+/// cull_disabledがtrueを返すmaterialのinstanceにはTRIANGLE_FACING_CULL_DISABLEを付けて、
+/// ray flagsでカリングを指定したレイでも両面がhitするようにする。
+#[allow(clippy::too_many_arguments)]
+pub fn create_tlas<Material: Copy>(
+    device: &crate::DeviceHandle,
+    queue_handles: &QueueHandles,
+    compute_command_pool: &crate::CommandPoolHandle,
+    transfer_command_pool: &crate::CommandPoolHandle,
+    allocator: &crate::AllocatorHandle,
+    instances: &[(BlasObjects, glam::Mat4, u32, u32, u8, u32)],
+    transform_ends: Option<&[Option<glam::Mat4>]>,
+    materials: &[Material],
+    preference: BuildPreference,
+    cull_disabled: impl Fn(&Material) -> bool,
+) -> TlasObjects {
+    let motion = transform_ends.is_some();
+    assert!(
+        !motion || is_ray_tracing_motion_blur_enabled(device),
+        "Motion instances require VK_NV_ray_tracing_motion_blur."
+    );
+    let static_transform_ends = vec![None; instances.len()];
+    let transform_ends = transform_ends.unwrap_or(&static_transform_ends);
+    let build_flags = tlas_build_flags(preference, motion);
+
+    // instancesのbufferを作成
+    let mut instances_buffer = create_host_buffer(
+        device,
+        allocator,
+        (instances.len() * get_tlas_instance_size(motion)) as u64,
+        vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+    );
+    write_tlas_instances(
+        &mut instances_buffer,
+        instances,
+        transform_ends,
+        materials,
+        motion,
+        cull_disabled,
+    );
 
     // TLASに必要なバッファサイズを取得
     let primitive_count = instances.len() as u32;
-    let build_size_info = device.get_acceleration_structure_build_sizes(
-        vk::AccelerationStructureBuildTypeKHR::DEVICE,
-        &build_geometry_info,
-        &[primitive_count],
-    );
+    let build_size_info = get_tlas_build_sizes(device, instances.len(), preference, motion);
 
     // bufferの作成
     let tlas_buffer = create_device_local_buffer(
-        &device,
-        &allocator,
+        device,
+        allocator,
         build_size_info.acceleration_structure_size,
         vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
             | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
     );
 
     // tlasの作成
-    let tlas = device.create_acceleration_structure(
-        &vk::AccelerationStructureCreateInfoKHR::builder()
-            .buffer(*tlas_buffer.buffer)
-            .size(build_size_info.acceleration_structure_size)
-            .offset(0)
-            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL),
-    );
+    let mut motion_info = vk::AccelerationStructureMotionInfoNV::builder()
+        .max_instances(primitive_count)
+        .build();
+    let mut tlas_create_info = vk::AccelerationStructureCreateInfoKHR::builder()
+        .buffer(*tlas_buffer.buffer)
+        .size(build_size_info.acceleration_structure_size)
+        .offset(0)
+        .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL);
+    if motion {
+        tlas_create_info = tlas_create_info
+            .create_flags(vk::AccelerationStructureCreateFlagsKHR::MOTION_NV)
+            .push_next(&mut motion_info);
+    }
+    let tlas = device.create_acceleration_structure(&tlas_create_info);
 
-    // scratch bufferの作成
+    // scratch bufferの作成。cmd_update_tlasでビルドし直す際にも使う
     let scratch_buffer = create_device_local_buffer(
-        &device,
-        &allocator,
+        device,
+        allocator,
         build_size_info.build_scratch_size,
         vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
     );
 
     // acceleration structureのビルドコマンド実行
-    let (tlas, tlas_buffer) = {
-        // build用にbuild geometry infoを作成
-        let build_geometry_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
-            .geometries(std::slice::from_ref(&geometry))
-            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
-            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
-            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
-            .src_acceleration_structure(vk::AccelerationStructureKHR::null())
-            .dst_acceleration_structure(*tlas)
-            .scratch_data(vk::DeviceOrHostAddressKHR {
-                device_address: device.get_buffer_device_address(
-                    &vk::BufferDeviceAddressInfo::builder()
-                        .buffer(*scratch_buffer.buffer)
-                        .build(),
-                ),
-            });
-        // build range infoを作成
-        let acceleration_structure_build_range_info =
-            vk::AccelerationStructureBuildRangeInfoKHR::builder()
-                .primitive_count(primitive_count)
-                .first_vertex(0)
-                .primitive_offset(0)
-                .transform_offset(0);
-
+    {
         // コマンドバッファの開始
         let command_buffer = {
             let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
@@ -331,27 +1140,19 @@ pub fn create_tlas<Material: Copy>(
                 .level(vk::CommandBufferLevel::PRIMARY)
                 .command_buffer_count(1);
             let command_buffers = device
-                .allocate_command_buffers(&compute_command_pool, &command_buffer_allocate_info);
+                .allocate_command_buffers(compute_command_pool, &command_buffer_allocate_info);
             command_buffers.into_iter().next().unwrap()
         };
         begin_onetime_command_buffer(&command_buffer);
 
         // コマンドのレコード
-        command_buffer.cmd_build_acceleration_structures(
-            std::slice::from_ref(&build_geometry_info),
-            &[std::slice::from_ref(
-                &acceleration_structure_build_range_info,
-            )],
-        );
-        let barrier = vk::MemoryBarrier2KHR::builder()
-            .src_stage_mask(vk::PipelineStageFlags2KHR::ACCELERATION_STRUCTURE_BUILD_KHR)
-            .src_access_mask(vk::AccessFlags2KHR::ACCELERATION_STRUCTURE_WRITE_KHR)
-            .dst_stage_mask(vk::PipelineStageFlags2KHR::ACCELERATION_STRUCTURE_BUILD_KHR)
-            .dst_access_mask(vk::AccessFlags2KHR::ACCELERATION_STRUCTURE_READ_KHR);
-        command_buffer.cmd_pipeline_barrier2(
-            &vk::DependencyInfoKHR::builder()
-                .memory_barriers(std::slice::from_ref(&barrier))
-                .build(),
+        cmd_build_tlas(
+            &command_buffer,
+            &tlas,
+            &instances_buffer,
+            &scratch_buffer,
+            primitive_count,
+            build_flags,
         );
 
         // コマンド終了とサブミット
@@ -360,56 +1161,124 @@ pub fn create_tlas<Material: Copy>(
         let submit_info = vk::SubmitInfo::builder()
             .command_buffers(&buffers_to_submit)
             .build();
-        let fence = create_fence(&device);
+        let fence = create_fence(device);
         device.queue_submit(
             queue_handles.compute.queue,
             &[submit_info],
             Some(fence.clone()),
         );
         device.wait_fences(&[fence], u64::MAX);
-
-        (tlas, tlas_buffer)
-    };
+    }
 
     // instance paramのbufferを作成
-    let instance_params = instances
-        .iter()
-        .map(|(blas, transform, material, _sbt_offset)| InstanceParam {
-            address_index: blas.index_buffer.device_address,
-            address_vertex: blas.vertex_buffer.device_address,
-            transform: transform.clone(),
-            material_index: *material,
-            padding_1: 0,
-            padding_2: 0,
-        })
-        .collect::<Vec<_>>();
+    let instance_params = tlas_instance_params(instances, transform_ends);
     let instance_params_buffer = create_device_local_buffer_with_data(
-        &device,
-        &queue_handles,
-        &transfer_command_pool,
-        &allocator,
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
         &instance_params,
         vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
     );
+    let instance_params_staging_buffer = create_host_buffer(
+        device,
+        allocator,
+        instance_params_buffer.size(),
+        vk::BufferUsageFlags::TRANSFER_SRC,
+    );
 
     // materialのbufferを作成
     let materials_buffer = create_device_local_buffer_with_data(
-        &device,
-        &queue_handles,
-        &transfer_command_pool,
-        &allocator,
-        &materials,
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
+        materials,
         vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::STORAGE_BUFFER,
     );
 
     TlasObjects {
         tlas,
         tlas_buffer,
+        instances_buffer,
+        scratch_buffer,
         instance_params_buffer,
+        instance_params_staging_buffer,
         materials_buffer,
+        motion,
+        preference,
     }
 }
 
+/// create_tlasで作成したTlasのinstanceを書き換えて、同じbufferにビルドし直すコマンドを積む
+///
+/// instancesとmaterialsはcreate_tlasに渡したものと同じ数、同じ順である必要があるが、
+/// transformとblasは差し替えられる。
+/// bufferは確保し直さないので、Tlasとその各bufferのhandleとdevice addressは変わらない。
+/// motion instanceのTlasではtransform_endsがcreate_tlasと同じ意味になり、
+/// そうでないTlasではtransform_endsは無視される。
+///
+/// instanceとInstanceParamはHostから書き込むので、前にこのTlasを更新したり
+/// traceに使ったりしたcommand bufferの完了を待ってから呼ぶ必要がある。
+/// 積んだコマンドの後では、ray tracing shaderから更新後のTlasとInstanceParamを読める。
+pub fn cmd_update_tlas<Material: Copy>(
+    command_buffer: &crate::CommandBufferHandle,
+    tlas: &mut TlasObjects,
+    instances: &[(BlasObjects, glam::Mat4, u32, u32, u8, u32)],
+    transform_ends: &[Option<glam::Mat4>],
+    materials: &[Material],
+    cull_disabled: impl Fn(&Material) -> bool,
+) {
+    let static_transform_ends = vec![None; instances.len()];
+    let transform_ends = if tlas.motion {
+        transform_ends
+    } else {
+        &static_transform_ends
+    };
+    assert_eq!(
+        (instances.len() * get_tlas_instance_size(tlas.motion)) as u64,
+        tlas.instances_buffer.size(),
+        "The instance count must match the one passed to create_tlas."
+    );
+
+    write_tlas_instances(
+        &mut tlas.instances_buffer,
+        instances,
+        transform_ends,
+        materials,
+        tlas.motion,
+        cull_disabled,
+    );
+    cmd_build_tlas(
+        command_buffer,
+        &tlas.tlas,
+        &tlas.instances_buffer,
+        &tlas.scratch_buffer,
+        instances.len() as u32,
+        tlas_build_flags(tlas.preference, tlas.motion),
+    );
+
+    // InstanceParamはstaging bufferからコピーする
+    tlas.instance_params_staging_buffer
+        .write(&tlas_instance_params(instances, transform_ends));
+    command_buffer.cmd_copy_buffer(
+        &tlas.instance_params_staging_buffer.buffer,
+        &tlas.instance_params_buffer.buffer,
+        &[vk::BufferCopy::builder()
+            .size(tlas.instance_params_buffer.size())
+            .build()],
+    );
+    command_buffer.cmd_pipeline_barrier2(
+        &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+            &vk::MemoryBarrier2KHR::builder()
+                .src_stage_mask(vk::PipelineStageFlags2KHR::COPY)
+                .src_access_mask(vk::AccessFlags2KHR::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2KHR::RAY_TRACING_SHADER_KHR)
+                .dst_access_mask(vk::AccessFlags2KHR::SHADER_STORAGE_READ),
+        )),
+    );
+}
+
 /// HitShaderGroupのShaderModuleをまとめた構造体
 pub struct HitShaderModules {
     /// ClosestHitShaderのShaderModuleHandle
@@ -443,16 +1312,20 @@ pub struct ShaderBindingTable {
 }
 
 /// RayTracingPipelineを作成するヘルパー関数
+///
+/// motion instanceのTlasをtraceする場合はflagsにRAY_TRACING_ALLOW_MOTION_NVを指定する。
 pub fn create_ray_tracing_pipelines(
     instance: &crate::InstanceHandle,
     physical_device: vk::PhysicalDevice,
     device: &crate::DeviceHandle,
     allocator: &crate::AllocatorHandle,
+    pipeline_cache: &crate::PipelineCacheHandle,
     raygen_shader_modules: &[crate::ShaderModuleHandle],
     miss_shader_modules: &[crate::ShaderModuleHandle],
     hit_shader_modules: &[HitShaderModules],
     descriptor_set_layouts: &[vk::DescriptorSetLayout],
     push_constant_ranges: &[vk::PushConstantRange],
+    flags: vk::PipelineCreateFlags,
 ) -> (
     crate::RayTracingPipelineHandle,
     crate::PipelineLayoutHandle,
@@ -581,9 +1454,15 @@ pub fn create_ray_tracing_pipelines(
         } else {
             vk::SHADER_UNUSED_KHR
         };
+        // intersection shaderがある場合はAABBのgeometry用のhit groupになる
+        let group_type = if hit_shader_module.intersection.is_some() {
+            vk::RayTracingShaderGroupTypeKHR::PROCEDURAL_HIT_GROUP
+        } else {
+            vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP
+        };
         hit_shader_groups.push(
             vk::RayTracingShaderGroupCreateInfoKHR::builder()
-                .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                .ty(group_type)
                 .general_shader(vk::SHADER_UNUSED_KHR)
                 .closest_hit_shader(closest_hit_shader_index)
                 .any_hit_shader(any_hit_shader_index)
@@ -603,12 +1482,13 @@ pub fn create_ray_tracing_pipelines(
             .stages(&shader_stages)
             .groups(&shader_groups)
             .max_pipeline_ray_recursion_depth(1)
+            .flags(flags)
             .layout(*pipeline_layout);
 
         let raytracing_pipeline = device
             .create_ray_tracing_pipelines(
                 vk::DeferredOperationKHR::null(),
-                vk::PipelineCache::null(),
+                pipeline_cache,
                 std::slice::from_ref(&pipeline_create_info),
             )
             .into_iter()
@@ -30,136 +30,269 @@ pub fn get_required_device_extensions(required_device_extensions: &[CString]) ->
     required_device_extensions
 }
 
-/// 適当なphysical deviceを選択する関数
+/// headlessで使う場合に必要なdevice拡張のリストを取得する関数。
+/// swapchainを使わないのでSwapchainの拡張は含まない。
+pub fn get_required_headless_device_extensions(
+    required_device_extensions: &[CString],
+) -> Vec<CString> {
+    get_required_device_extensions(required_device_extensions)
+        .into_iter()
+        .filter(|extension| extension.as_c_str() != Swapchain::name())
+        .collect()
+}
+
+/// physical deviceの選択方法
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PhysicalDevicePreference {
+    /// discrete GPU、integrated GPU、その他の順に優先して選択する
+    #[default]
+    PreferDiscrete,
+    /// デバイス名にこの文字列を含むものを選択する(大文字小文字は区別しない)
+    Name(String),
+    /// enumerate_suitable_devicesが返すリストのindexで選択する
+    Index(usize),
+}
+impl From<&str> for PhysicalDevicePreference {
+    /// 数値ならIndex、"discrete"ならPreferDiscrete、それ以外ならNameとして解釈する
+    fn from(value: &str) -> Self {
+        if let Ok(index) = value.parse() {
+            Self::Index(index)
+        } else if value.eq_ignore_ascii_case("discrete") {
+            Self::PreferDiscrete
+        } else {
+            Self::Name(value.to_owned())
+        }
+    }
+}
+
+/// 条件を満たすphysical deviceとそのデバイス名、デバイスの種類を列挙する関数
+pub fn enumerate_suitable_devices(
+    instance: &crate::InstanceHandle,
+    surface: &crate::SurfaceHandle,
+    required_device_extensions: &[CString],
+) -> Vec<(vk::PhysicalDevice, String, vk::PhysicalDeviceType)> {
+    enumerate_suitable_devices_impl(instance, Some(surface), required_device_extensions)
+}
+
+/// headlessで使う場合に条件を満たすphysical deviceとそのデバイス名、デバイスの種類を列挙する関数。
+/// presentとswapchainのサポートは確認しない。
+pub fn enumerate_suitable_headless_devices(
+    instance: &crate::InstanceHandle,
+    required_device_extensions: &[CString],
+) -> Vec<(vk::PhysicalDevice, String, vk::PhysicalDeviceType)> {
+    enumerate_suitable_devices_impl(instance, None, required_device_extensions)
+}
+
+/// preferenceに従ってphysical deviceを選択する関数
 pub fn select_physical_device(
     instance: &crate::InstanceHandle,
     surface: &crate::SurfaceHandle,
     required_device_extensions: &[CString],
+    preference: &PhysicalDevicePreference,
 ) -> vk::PhysicalDevice {
-    let physical_devices = instance.enumerate_physical_devices();
-
-    // GraphicsとTransfer、Compute、PresentをサポートしているQueueFamilyがある &&
-    // 必要なデバイス拡張機能に対応している &&
-    // swapchainに対応したフォーマット / presentationモードが一つ以上ある &&
-    // 必要なdevice featuresに対応しているような
-    // physical deviceを選択する
-    let physical_device = physical_devices.into_iter().find(|physical_device| {
-        // QueueFamilyの各種Queue対応の確認
-        let mut graphics_index = None;
-        let mut transfer_index = None;
-        let mut compute_index = None;
-        let mut present_index = None;
-        let queue_families = instance.get_physical_device_queue_family_properties(*physical_device);
-        for (i, queue_family) in queue_families.iter().enumerate() {
-            if queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                graphics_index = Some(i);
-            }
-            if queue_family.queue_flags.contains(vk::QueueFlags::TRANSFER) {
-                transfer_index = Some(i);
-            }
-            if queue_family.queue_flags.contains(vk::QueueFlags::COMPUTE) {
-                compute_index = Some(i);
-            }
-            let present_support =
-                surface.get_physical_device_surface_support(*physical_device, i as u32);
-            if present_support {
-                present_index = Some(i);
-            }
-            if graphics_index.is_some()
-                && transfer_index.is_some()
-                && compute_index.is_some()
-                && present_index.is_some()
-            {
-                break;
-            }
+    select_physical_device_from(
+        enumerate_suitable_devices(instance, surface, required_device_extensions),
+        preference,
+    )
+}
+
+/// headlessで使う場合にpreferenceに従ってphysical deviceを選択する関数。
+/// presentとswapchainのサポートは確認しない。
+pub fn select_headless_physical_device(
+    instance: &crate::InstanceHandle,
+    required_device_extensions: &[CString],
+    preference: &PhysicalDevicePreference,
+) -> vk::PhysicalDevice {
+    select_physical_device_from(
+        enumerate_suitable_headless_devices(instance, required_device_extensions),
+        preference,
+    )
+}
+
+// 条件を満たすphysical deviceのリストからpreferenceに合うものを選択する
+fn select_physical_device_from(
+    devices: Vec<(vk::PhysicalDevice, String, vk::PhysicalDeviceType)>,
+    preference: &PhysicalDevicePreference,
+) -> vk::PhysicalDevice {
+    let device = match preference {
+        PhysicalDevicePreference::PreferDiscrete => {
+            devices.iter().min_by_key(|(_, _, ty)| match *ty {
+                vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+                vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+                vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+                vk::PhysicalDeviceType::CPU => 3,
+                _ => 4,
+            })
         }
-        let is_queue_family_supported = graphics_index.is_some()
-            && transfer_index.is_some()
-            && compute_index.is_some()
-            && present_index.is_some();
+        PhysicalDevicePreference::Name(name) => {
+            let name = name.to_lowercase();
+            devices
+                .iter()
+                .find(|(_, device_name, _)| device_name.to_lowercase().contains(&name))
+        }
+        PhysicalDevicePreference::Index(index) => devices.get(*index),
+    };
 
-        // デバイス拡張の確認
-        let device_extensions = unsafe {
-            instance
-                .enumerate_device_extension_properties(*physical_device)
-                .unwrap()
-        };
-        let mut device_extensions_name = vec![];
-        for device_extension in device_extensions {
-            let name = unsafe {
-                std::ffi::CStr::from_ptr(device_extension.extension_name.as_ptr()).to_owned()
-            };
-            device_extensions_name.push(name);
+    match device {
+        Some((physical_device, _, _)) => *physical_device,
+        None => {
+            let names = devices
+                .iter()
+                .enumerate()
+                .map(|(i, (_, name, ty))| format!("{i}: {name} ({ty:?})"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            panic!("No suitable physical device for {preference:?}. Suitable devices: [{names}]")
+        }
+    }
+}
+
+// surfaceがNoneの場合はpresentとswapchainのサポートの確認をスキップする
+fn enumerate_suitable_devices_impl(
+    instance: &crate::InstanceHandle,
+    surface: Option<&crate::SurfaceHandle>,
+    required_device_extensions: &[CString],
+) -> Vec<(vk::PhysicalDevice, String, vk::PhysicalDeviceType)> {
+    instance
+        .enumerate_physical_devices()
+        .into_iter()
+        .filter(|&physical_device| {
+            is_suitable_physical_device(
+                instance,
+                surface,
+                physical_device,
+                required_device_extensions,
+            )
+        })
+        .map(|physical_device| {
+            let properties = instance.get_physical_device_properties(physical_device);
+            let name = unsafe { std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned();
+            (physical_device, name, properties.device_type)
+        })
+        .collect()
+}
+
+// GraphicsとTransfer、Compute、PresentをサポートしているQueueFamilyがある &&
+// 必要なデバイス拡張機能に対応している &&
+// swapchainに対応したフォーマット / presentationモードが一つ以上ある &&
+// 必要なdevice featuresに対応しているか確認する
+fn is_suitable_physical_device(
+    instance: &crate::InstanceHandle,
+    surface: Option<&crate::SurfaceHandle>,
+    physical_device: vk::PhysicalDevice,
+    required_device_extensions: &[CString],
+) -> bool {
+    // QueueFamilyの各種Queue対応の確認
+    let mut graphics_index = None;
+    let mut transfer_index = None;
+    let mut compute_index = None;
+    let mut present_index = None;
+    let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+    for (i, queue_family) in queue_families.iter().enumerate() {
+        if queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
+            graphics_index = Some(i);
+        }
+        if queue_family.queue_flags.contains(vk::QueueFlags::TRANSFER) {
+            transfer_index = Some(i);
+        }
+        if queue_family.queue_flags.contains(vk::QueueFlags::COMPUTE) {
+            compute_index = Some(i);
         }
-        let mut required_extensions = HashSet::new();
-        for extension in required_device_extensions.iter() {
-            required_extensions.insert(extension.to_owned());
+        let present_support = surface.is_none_or(|surface| {
+            surface.get_physical_device_surface_support(physical_device, i as u32)
+        });
+        if present_support {
+            present_index = Some(i);
         }
-        for extension_name in device_extensions_name {
-            required_extensions.remove(&extension_name);
+        if graphics_index.is_some()
+            && transfer_index.is_some()
+            && compute_index.is_some()
+            && present_index.is_some()
+        {
+            break;
         }
-        let is_device_extension_supported = required_extensions.is_empty();
+    }
+    let is_queue_family_supported = graphics_index.is_some()
+        && transfer_index.is_some()
+        && compute_index.is_some()
+        && present_index.is_some();
+
+    // デバイス拡張の確認
+    let device_extensions = unsafe {
+        instance
+            .enumerate_device_extension_properties(physical_device)
+            .unwrap()
+    };
+    let mut device_extensions_name = vec![];
+    for device_extension in device_extensions {
+        let name = unsafe {
+            std::ffi::CStr::from_ptr(device_extension.extension_name.as_ptr()).to_owned()
+        };
+        device_extensions_name.push(name);
+    }
+    let mut required_extensions = HashSet::new();
+    for extension in required_device_extensions.iter() {
+        required_extensions.insert(extension.to_owned());
+    }
+    for extension_name in device_extensions_name {
+        required_extensions.remove(&extension_name);
+    }
+    let is_device_extension_supported = required_extensions.is_empty();
 
-        // swapchainのサポート確認
-        let surface_formats = surface.get_physical_device_surface_formats(*physical_device);
+    // swapchainのサポート確認
+    let is_swapchain_supported = surface.is_none_or(|surface| {
+        let surface_formats = surface.get_physical_device_surface_formats(physical_device);
         let surface_present_modes =
-            surface.get_physical_device_surface_present_modes(*physical_device);
-        let is_swapchain_supported =
-            !surface_formats.is_empty() && !surface_present_modes.is_empty();
-
-        // featureのサポート確認
-        let mut supported_feature_vulkan_12 = vk::PhysicalDeviceVulkan12Features::builder().build();
-        let mut supported_feature_vulkan_13 = vk::PhysicalDeviceVulkan13Features::builder().build();
-        let mut physical_device_raytracing_pipeline_features_khr =
-            vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::builder()
-                .ray_tracing_pipeline(true)
-                .build();
-        let mut physical_device_acceleration_structure_feature_khr =
-            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::builder()
-                .acceleration_structure(true)
-                .build();
-        let mut supported_feature = vk::PhysicalDeviceFeatures2::builder()
-            .push_next(&mut supported_feature_vulkan_12)
-            .push_next(&mut supported_feature_vulkan_13)
-            .push_next(&mut physical_device_raytracing_pipeline_features_khr)
-            .push_next(&mut physical_device_acceleration_structure_feature_khr)
-            .build();
-        unsafe { instance.get_physical_device_features2(*physical_device, &mut supported_feature) };
-        let is_supported_device_features = supported_feature.features.shader_int64 == vk::TRUE
-            && supported_feature_vulkan_12.timeline_semaphore == vk::TRUE
-            && supported_feature_vulkan_12.scalar_block_layout == vk::TRUE
-            && supported_feature_vulkan_12.buffer_device_address == vk::TRUE
-            && supported_feature_vulkan_12.descriptor_indexing == vk::TRUE
-            && supported_feature_vulkan_12.descriptor_binding_partially_bound == vk::TRUE
-            && supported_feature_vulkan_12.shader_sampled_image_array_non_uniform_indexing
-                == vk::TRUE
-            && supported_feature_vulkan_12.descriptor_binding_sampled_image_update_after_bind
-                == vk::TRUE
-            && supported_feature_vulkan_12.shader_uniform_buffer_array_non_uniform_indexing
-                == vk::TRUE
-            && supported_feature_vulkan_12.descriptor_binding_uniform_buffer_update_after_bind
-                == vk::TRUE
-            && supported_feature_vulkan_12.shader_storage_image_array_non_uniform_indexing
-                == vk::TRUE
-            && supported_feature_vulkan_12.descriptor_binding_storage_image_update_after_bind
-                == vk::TRUE
-            && supported_feature_vulkan_12.shader_storage_buffer_array_non_uniform_indexing
-                == vk::TRUE
-            && supported_feature_vulkan_12.descriptor_binding_storage_buffer_update_after_bind
-                == vk::TRUE
-            && supported_feature_vulkan_12.descriptor_binding_variable_descriptor_count == vk::TRUE
-            && supported_feature_vulkan_12.runtime_descriptor_array == vk::TRUE
-            && supported_feature_vulkan_13.synchronization2 == vk::TRUE;
-
-        is_queue_family_supported
-            && is_swapchain_supported
-            && is_device_extension_supported
-            && is_supported_device_features
+            surface.get_physical_device_surface_present_modes(physical_device);
+        !surface_formats.is_empty() && !surface_present_modes.is_empty()
     });
 
-    let physical_device = physical_device.expect("No suitable physical device");
-
-    physical_device
+    // featureのサポート確認
+    let mut supported_feature_vulkan_12 = vk::PhysicalDeviceVulkan12Features::builder().build();
+    let mut supported_feature_vulkan_13 = vk::PhysicalDeviceVulkan13Features::builder().build();
+    let mut physical_device_raytracing_pipeline_features_khr =
+        vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::builder()
+            .ray_tracing_pipeline(true)
+            .build();
+    let mut physical_device_acceleration_structure_feature_khr =
+        vk::PhysicalDeviceAccelerationStructureFeaturesKHR::builder()
+            .acceleration_structure(true)
+            .build();
+    let mut supported_feature = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut supported_feature_vulkan_12)
+        .push_next(&mut supported_feature_vulkan_13)
+        .push_next(&mut physical_device_raytracing_pipeline_features_khr)
+        .push_next(&mut physical_device_acceleration_structure_feature_khr)
+        .build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut supported_feature) };
+    let is_supported_device_features = supported_feature.features.shader_int64 == vk::TRUE
+        && supported_feature_vulkan_12.timeline_semaphore == vk::TRUE
+        && supported_feature_vulkan_12.scalar_block_layout == vk::TRUE
+        && supported_feature_vulkan_12.buffer_device_address == vk::TRUE
+        && supported_feature_vulkan_12.descriptor_indexing == vk::TRUE
+        && supported_feature_vulkan_12.descriptor_binding_partially_bound == vk::TRUE
+        && supported_feature_vulkan_12.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+        && supported_feature_vulkan_12.descriptor_binding_sampled_image_update_after_bind
+            == vk::TRUE
+        && supported_feature_vulkan_12.shader_uniform_buffer_array_non_uniform_indexing == vk::TRUE
+        && supported_feature_vulkan_12.descriptor_binding_uniform_buffer_update_after_bind
+            == vk::TRUE
+        && supported_feature_vulkan_12.shader_storage_image_array_non_uniform_indexing == vk::TRUE
+        && supported_feature_vulkan_12.descriptor_binding_storage_image_update_after_bind
+            == vk::TRUE
+        && supported_feature_vulkan_12.shader_storage_buffer_array_non_uniform_indexing == vk::TRUE
+        && supported_feature_vulkan_12.descriptor_binding_storage_buffer_update_after_bind
+            == vk::TRUE
+        && supported_feature_vulkan_12.descriptor_binding_variable_descriptor_count == vk::TRUE
+        && supported_feature_vulkan_12.runtime_descriptor_array == vk::TRUE
+        && supported_feature_vulkan_13.synchronization2 == vk::TRUE;
+
+    is_queue_family_supported
+        && is_swapchain_supported
+        && is_device_extension_supported
+        && is_supported_device_features
 }
 
 /// 各種Queueのindexを格納する構造体
@@ -179,6 +312,24 @@ pub fn get_queue_indices(
     instance: &crate::InstanceHandle,
     surface: &crate::SurfaceHandle,
     physical_device: vk::PhysicalDevice,
+) -> QueueIndices {
+    get_queue_indices_impl(instance, Some(surface), physical_device)
+}
+
+/// headlessで使う場合の各種Queueのindexを取得する関数。
+/// presentは行わないので、present_indexはpresentのサポートを確認せずに選択する。
+pub fn get_headless_queue_indices(
+    instance: &crate::InstanceHandle,
+    physical_device: vk::PhysicalDevice,
+) -> QueueIndices {
+    get_queue_indices_impl(instance, None, physical_device)
+}
+
+// surfaceがNoneの場合はpresentのサポートの確認をスキップする
+fn get_queue_indices_impl(
+    instance: &crate::InstanceHandle,
+    surface: Option<&crate::SurfaceHandle>,
+    physical_device: vk::PhysicalDevice,
 ) -> QueueIndices {
     // get queue index
     let mut graphics_index = None;
@@ -204,8 +355,9 @@ pub fn get_queue_indices(
         if queue_family.queue_flags.contains(vk::QueueFlags::COMPUTE) {
             compute_index = Some(i);
         }
-        let present_support =
-            surface.get_physical_device_surface_support(physical_device, i as u32);
+        let present_support = surface.is_none_or(|surface| {
+            surface.get_physical_device_surface_support(physical_device, i as u32)
+        });
         if present_support {
             present_index = Some(i);
         }
@@ -244,6 +396,10 @@ pub fn create_device(
     // physical device features
     let mut physical_device_features = vk::PhysicalDeviceFeatures::builder().build();
     physical_device_features.shader_int64 = vk::TRUE;
+    // 異方性フィルタリングはサポートしている場合のみ有効化する
+    physical_device_features.sampler_anisotropy = instance
+        .get_physical_device_features(physical_device)
+        .sampler_anisotropy;
     let mut physical_device_vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder()
         .timeline_semaphore(true)
         .buffer_device_address(true)
@@ -272,18 +428,27 @@ pub fn create_device(
         vk::PhysicalDeviceAccelerationStructureFeaturesKHR::builder()
             .acceleration_structure(true)
             .build();
+    // motion blurのmotion instanceはサポートしている場合のみ有効化する
+    let ray_tracing_motion_blur = is_ray_tracing_motion_blur_supported(instance, physical_device);
+    let mut physical_device_ray_tracing_motion_blur_features_nv =
+        vk::PhysicalDeviceRayTracingMotionBlurFeaturesNV::builder()
+            .ray_tracing_motion_blur(true)
+            .build();
     // enable extension names
-    let enable_extension_names = required_device_extensions
+    let mut enable_extension_names = required_device_extensions
         .iter()
         .map(|s| s.to_owned())
         .collect::<Vec<_>>();
+    if ray_tracing_motion_blur {
+        enable_extension_names.push(vk::NvRayTracingMotionBlurFn::name().to_owned());
+    }
     let enable_extension_names = enable_extension_names
         .iter()
         .map(|s| s.as_ptr())
         .collect::<Vec<_>>();
 
     // device create info
-    let device_create_info = vk::DeviceCreateInfo::builder()
+    let mut device_create_info = vk::DeviceCreateInfo::builder()
         .push_next(&mut physical_device_vulkan_12_features)
         .push_next(&mut physical_device_vulkan_13_features)
         .push_next(&mut physical_device_raytracing_pipeline_features_khr)
@@ -291,11 +456,41 @@ pub fn create_device(
         .queue_create_infos(&queue_create_infos)
         .enabled_features(&physical_device_features)
         .enabled_extension_names(&enable_extension_names);
+    if ray_tracing_motion_blur {
+        device_create_info =
+            device_create_info.push_next(&mut physical_device_ray_tracing_motion_blur_features_nv);
+    }
 
     // create device
     instance.create_device(physical_device, &device_create_info)
 }
 
+// physical deviceがVK_NV_ray_tracing_motion_blurとそのmotion blurのfeatureをサポートしているか
+fn is_ray_tracing_motion_blur_supported(
+    instance: &crate::InstanceHandle,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    let device_extensions = unsafe {
+        instance
+            .enumerate_device_extension_properties(physical_device)
+            .unwrap()
+    };
+    let is_extension_supported = device_extensions.iter().any(|device_extension| {
+        let name = unsafe { std::ffi::CStr::from_ptr(device_extension.extension_name.as_ptr()) };
+        name == vk::NvRayTracingMotionBlurFn::name()
+    });
+    if !is_extension_supported {
+        return false;
+    }
+
+    let mut motion_blur_features = vk::PhysicalDeviceRayTracingMotionBlurFeaturesNV::default();
+    let mut supported_feature = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut motion_blur_features)
+        .build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut supported_feature) };
+    motion_blur_features.ray_tracing_motion_blur == vk::TRUE
+}
+
 /// Queueとそのindexを格納する構造体
 #[derive(Debug, Clone)]
 pub struct QueueHandle {
@@ -320,6 +515,31 @@ pub struct QueueHandles {
     pub present: QueueHandle,
 }
 
+/// Queueの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueKind {
+    /// Graphics Queue
+    Graphics,
+    /// Transfer Queue
+    Transfer,
+    /// Compute Queue
+    Compute,
+    /// Present Queue
+    Present,
+}
+
+impl QueueHandles {
+    /// QueueKindに対応するQueueHandleを取得する
+    pub fn get(&self, kind: QueueKind) -> &QueueHandle {
+        match kind {
+            QueueKind::Graphics => &self.graphics,
+            QueueKind::Transfer => &self.transfer,
+            QueueKind::Compute => &self.compute,
+            QueueKind::Present => &self.present,
+        }
+    }
+}
+
 /// 各種QueueのQueueHandleを取得する関数
 pub fn get_queue_handles(
     device: &crate::DeviceHandle,
@@ -391,6 +611,22 @@ pub fn create_compute_command_pool(
     device.create_command_pool(&command_pool_create_info)
 }
 
+/// フレームごとにまとめてリセットする用途のtransientなcommand poolを作成する関数
+///
+/// 個別のCommandBufferのリセットは行わず、
+/// `CommandPoolHandle::reset`でpoolごとリセットすることを想定している。
+pub fn create_transient_command_pool(
+    device: &crate::DeviceHandle,
+    queue_handles: &QueueHandles,
+    queue_kind: QueueKind,
+) -> crate::CommandPoolHandle {
+    let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+        .queue_family_index(queue_handles.get(queue_kind).family_index)
+        .build();
+    device.create_command_pool(&command_pool_create_info)
+}
+
 /// swapchainの関連オブジェクト
 pub struct SwapchainObjects {
     /// SwapchainHandle
@@ -399,28 +635,88 @@ pub struct SwapchainObjects {
     pub swapchain_images: Vec<vk::Image>,
     /// Swapchainのformat
     pub format: vk::Format,
+    /// Swapchainのcolor space
+    pub color_space: vk::ColorSpaceKHR,
     /// Swapchainのextent
     pub extent: vk::Extent2D,
 }
 
+/// formatがsRGBの伝達関数をハードウェアでかけるformatかどうかを返す関数
+///
+/// このformatのimageに書き込む色はlinearにしておく必要がある。
+pub fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8_SRGB
+            | vk::Format::R8G8_SRGB
+            | vk::Format::R8G8B8_SRGB
+            | vk::Format::B8G8R8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+/// HDRのswapchainのformatとcolor spaceの組み合わせ。優先度の高い順に並べる。
+/// scRGBはlinearで1.0が80nitsになり、HDR10はRec.2020の色域にPQの伝達関数をかける。
+const HDR_SURFACE_FORMATS: [(vk::Format, vk::ColorSpaceKHR); 3] = [
+    (
+        vk::Format::R16G16B16A16_SFLOAT,
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+    ),
+    (
+        vk::Format::A2B10G10R10_UNORM_PACK32,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    ),
+    (
+        vk::Format::A2R10G10B10_UNORM_PACK32,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    ),
+];
+
 /// swapchainを作成する関数
+///
+/// hdrがtrueの場合はHDR_SURFACE_FORMATSの中でsurfaceが対応しているものを選ぶ。
+/// 対応していない場合やVK_EXT_swapchain_colorspaceが無い場合はSDRのformatにフォールバックする。
+/// 選ばれたcolor spaceはSwapchainObjects::color_spaceで確認できる。
 pub fn create_swapchain_objects(
     width: u32,
     height: u32,
     surface: &crate::SurfaceHandle,
     physical_device: vk::PhysicalDevice,
     device: &crate::DeviceHandle,
+    hdr: bool,
 ) -> SwapchainObjects {
     let surface_capabilities = surface.get_physical_device_surface_capabilities(physical_device);
     let surface_formats = surface.get_physical_device_surface_formats(physical_device);
     let surface_present_modes = surface.get_physical_device_surface_present_modes(physical_device);
 
     // surfaceのformatの選択
-    let surface_format = surface_formats
-        .iter()
-        .find(|surface_format| surface_format.format == vk::Format::B8G8R8A8_UNORM)
-        .unwrap_or(&surface_formats[0])
-        .clone();
+    let hdr_surface_format = if hdr {
+        let hdr_surface_format = HDR_SURFACE_FORMATS
+            .iter()
+            .find_map(|&(format, color_space)| {
+                surface_formats
+                    .iter()
+                    .find(|surface_format| {
+                        surface_format.format == format && surface_format.color_space == color_space
+                    })
+                    .cloned()
+            });
+        if hdr_surface_format.is_none() {
+            eprintln!("[WARN] The surface does not support an HDR color space, fall back to SDR");
+        }
+        hdr_surface_format
+    } else {
+        None
+    };
+    let surface_format = hdr_surface_format.unwrap_or_else(|| {
+        surface_formats
+            .iter()
+            .find(|surface_format| surface_format.format == vk::Format::B8G8R8A8_UNORM)
+            .unwrap_or(&surface_formats[0])
+            .clone()
+    });
 
     // surfaceのpresent modeの選択
     let surface_present_mode = surface_present_modes
@@ -475,6 +771,7 @@ pub fn create_swapchain_objects(
         swapchain,
         swapchain_images,
         format: surface_format.format,
+        color_space: surface_format.color_space,
         extent: surface_extent,
     }
 }
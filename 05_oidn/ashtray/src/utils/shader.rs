@@ -1,19 +1,58 @@
+use anyhow::{bail, Result};
 use ash::vk;
 
-/// bytesを与えてShaderModuleを作成するヘルパー関数
-pub fn create_shader_module(
-    device: &crate::DeviceHandle,
-    bytes: &[u8],
-) -> crate::ShaderModuleHandle {
+// SPIR-Vのバイト列のエンディアンを判定してwordの列に変換する
+fn spirv_words(bytes: &[u8]) -> Result<Vec<u32>> {
+    if bytes.len() < 4 || !bytes.len().is_multiple_of(4) {
+        bail!("Invalid SPIR-V size: {}", bytes.len());
+    }
     let words = bytes
         .chunks_exact(4)
         .map(|x| x.try_into().unwrap())
         .map(match bytes[0] {
             0x03 => u32::from_le_bytes,
             0x07 => u32::from_be_bytes,
-            _ => panic!("Unknown endianness"),
+            _ => bail!("Unknown endianness"),
         })
         .collect::<Vec<u32>>();
+    if words[0] != 0x07230203 {
+        bail!("Invalid SPIR-V magic number: {:#010x}", words[0]);
+    }
+    Ok(words)
+}
+
+/// bytesを与えてShaderModuleを作成するヘルパー関数
+pub fn create_shader_module(
+    device: &crate::DeviceHandle,
+    bytes: &[u8],
+) -> crate::ShaderModuleHandle {
+    let words = spirv_words(bytes).expect("Failed to read SPIR-V");
     let create_info = vk::ShaderModuleCreateInfo::builder().code(&words);
     device.create_shader_module(&create_info)
 }
+
+/// SPIR-Vのファイルのpathを与えてShaderModuleを作成するヘルパー関数
+pub fn create_shader_module_from_file(
+    device: &crate::DeviceHandle,
+    path: impl AsRef<std::path::Path>,
+) -> Result<crate::ShaderModuleHandle> {
+    let bytes = std::fs::read(path)?;
+    let words = spirv_words(&bytes)?;
+    let create_info = vk::ShaderModuleCreateInfo::builder().code(&words);
+    Ok(device.create_shader_module(&create_info))
+}
+
+/// Tのサイズのpush constant rangeを作成するヘルパー関数
+///
+/// CommandBufferHandle::cmd_push_constantsで積む型と同じ型を指定することで、
+/// pipeline layoutのrangeと実際に積むサイズがずれないようにする。
+pub fn push_constant_range<T: bytemuck::Pod>(
+    stage_flags: vk::ShaderStageFlags,
+    offset: u32,
+) -> vk::PushConstantRange {
+    vk::PushConstantRange {
+        stage_flags,
+        offset,
+        size: std::mem::size_of::<T>() as u32,
+    }
+}
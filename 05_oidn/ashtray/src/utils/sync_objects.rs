@@ -6,6 +6,18 @@ pub fn create_fence(device: &crate::DeviceHandle) -> crate::FenceHandle {
     device.create_fence(&create_info)
 }
 
+/// initial_valueから始まるtimeline semaphoreを作成する関数
+pub fn create_timeline_semaphore(
+    device: &crate::DeviceHandle,
+    initial_value: u64,
+) -> crate::SemaphoreHandle {
+    let mut type_create_info = vk::SemaphoreTypeCreateInfo::builder()
+        .semaphore_type(vk::SemaphoreType::TIMELINE)
+        .initial_value(initial_value);
+    let create_info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_create_info);
+    device.create_semaphore(&create_info)
+}
+
 /// シグナル状態のFenceを作成する関数
 pub fn create_signaled_fence(device: &crate::DeviceHandle) -> crate::FenceHandle {
     let create_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
@@ -0,0 +1,133 @@
+use ash::vk;
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+/// GPUのパスの処理時間をtimestamp queryで計測するためのQueryPool
+///
+/// labelごとに開始と終了の2つのqueryを割り当てる。
+/// 計測は`CommandBufferHandle::timer_scope`で作るGpuTimerScopeで行い、
+/// 結果は`durations`で取得する。
+pub struct GpuTimerPool {
+    device: crate::DeviceHandle,
+    query_pool: crate::QueryPoolHandle,
+    max_scopes: u32,
+    timestamp_period: f32,
+    labels: Mutex<Vec<&'static str>>,
+    durations: Mutex<BTreeMap<&'static str, Duration>>,
+}
+impl GpuTimerPool {
+    /// max_scopes個のlabelを計測できるGpuTimerPoolを作成する
+    pub fn new(device: &crate::DeviceHandle, max_scopes: u32) -> Self {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(max_scopes * 2);
+        let query_pool = device.create_query_pool(&create_info);
+        let timestamp_period = device
+            .get_physical_device_properties()
+            .limits
+            .timestamp_period;
+        Self {
+            device: device.clone(),
+            query_pool,
+            max_scopes,
+            timestamp_period,
+            labels: Mutex::new(Vec::new()),
+            durations: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // labelに割り当てた最初のqueryのindexを返す。
+    // 割り当てられるqueryが残っていない場合はNoneを返す。
+    fn first_query(&self, label: &'static str) -> Option<u32> {
+        let mut labels = self.labels.lock().unwrap();
+        let index = match labels.iter().position(|&l| l == label) {
+            Some(index) => index,
+            None => {
+                if labels.len() as u32 >= self.max_scopes {
+                    return None;
+                }
+                labels.push(label);
+                labels.len() - 1
+            }
+        };
+        Some(index as u32 * 2)
+    }
+
+    /// 各labelの最新の処理時間を取得する。
+    ///
+    /// GPUの処理が終わっていないlabelは前回取得できた処理時間のままにする。
+    pub fn durations(&self) -> BTreeMap<&'static str, Duration> {
+        let labels = self.labels.lock().unwrap().clone();
+        let mut durations = self.durations.lock().unwrap();
+        for (index, label) in labels.into_iter().enumerate() {
+            // queryごとに[timestamp, availability]
+            let mut data = [[0u64; 2]; 2];
+            let result = unsafe {
+                self.device.get_query_pool_results(
+                    *self.query_pool,
+                    index as u32 * 2,
+                    2,
+                    &mut data,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+                )
+            };
+            if !matches!(result, Ok(()) | Err(vk::Result::NOT_READY))
+                || data[0][1] == 0
+                || data[1][1] == 0
+            {
+                continue;
+            }
+            let nanos = data[1][0].wrapping_sub(data[0][0]) as f64 * self.timestamp_period as f64;
+            durations.insert(label, Duration::from_nanos(nanos as u64));
+        }
+        durations.clone()
+    }
+}
+
+/// dropされるまでのGPUの処理時間を計測するスコープ
+///
+/// 作成時に開始のtimestampを、dropで終了のtimestampをcommand bufferに積む。
+/// command bufferをendする前にdropする必要がある。
+pub struct GpuTimerScope<'a> {
+    command_buffer: &'a crate::CommandBufferHandle,
+    pool: &'a GpuTimerPool,
+    first_query: Option<u32>,
+}
+impl Drop for GpuTimerScope<'_> {
+    fn drop(&mut self) {
+        if let Some(first_query) = self.first_query {
+            self.command_buffer.cmd_write_timestamp(
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                &self.pool.query_pool,
+                first_query + 1,
+            );
+        }
+        self.command_buffer.close_timer_scope();
+    }
+}
+
+impl crate::CommandBufferHandle {
+    /// labelの処理時間を計測するGpuTimerScopeを作成する。
+    ///
+    /// poolに割り当てられるqueryが残っていない場合は何も計測しない。
+    pub fn timer_scope<'a>(
+        &'a self,
+        pool: &'a GpuTimerPool,
+        label: &'static str,
+    ) -> GpuTimerScope<'a> {
+        let first_query = pool.first_query(label);
+        if let Some(first_query) = first_query {
+            self.cmd_reset_query_pool(&pool.query_pool, first_query, 2);
+            self.cmd_write_timestamp(
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                &pool.query_pool,
+                first_query,
+            );
+        }
+        self.open_timer_scope();
+        GpuTimerScope {
+            command_buffer: self,
+            pool,
+            first_query,
+        }
+    }
+}
@@ -6,17 +6,65 @@ use glb_data::*;
 use std::error::Error;
 use std::path::Path;
 
+/// KHR_draco_mesh_compressionで圧縮されたmeshを含むglbを読み込もうとしたときのエラー。
+///
+/// Dracoのdecoderを組み込んでいないので、圧縮されたprimitiveの頂点を読めない。
+/// そのまま読み込むと頂点のない空のmeshになってしまうので、読み込み前にエラーにする。
+#[derive(Debug, Clone)]
+pub struct DracoUnsupportedError {
+    /// KHR_draco_mesh_compressionを使っているprimitiveの(mesh index, primitive index)
+    pub primitives: Vec<(usize, usize)>,
+}
+impl std::fmt::Display for DracoUnsupportedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} primitive(s) use KHR_draco_mesh_compression, but Draco decoding is not supported; \
+             re-export the glb without Draco compression",
+            self.primitives.len()
+        )
+    }
+}
+impl Error for DracoUnsupportedError {}
+
+// KHR_draco_mesh_compressionを使っているprimitiveを探す。
+// gltf::importはextensionsRequiredに未対応の拡張があるとvalidationで失敗するので、
+// validationせずにjsonだけを読んで調べる。
+fn find_draco_primitives(path: &Path) -> Result<Vec<(usize, usize)>, Box<dyn Error + Send + Sync>> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let gltf = gltf::Gltf::from_reader_without_validation(reader)?;
+    let mut primitives = vec![];
+    for mesh in gltf.meshes() {
+        for primitive in mesh.primitives() {
+            if primitive
+                .extension_value("KHR_draco_mesh_compression")
+                .is_some()
+            {
+                primitives.push((mesh.index(), primitive.index()));
+            }
+        }
+    }
+    Ok(primitives)
+}
+
+/// glbを読み込んでファイル内のsceneのリストを返す。
+/// KHR_draco_mesh_compressionで圧縮されたmeshを含む場合はDracoUnsupportedErrorを返す。
 pub fn load<P>(path: P) -> Result<Vec<Scene>, Box<dyn Error + Send + Sync>>
 where
     P: AsRef<Path>,
 {
+    let primitives = find_draco_primitives(path.as_ref())?;
+    if !primitives.is_empty() {
+        return Err(Box::new(DracoUnsupportedError { primitives }));
+    }
+
     let (doc, buffers, images) = gltf::import(&path)?;
 
     let mut data = GlbData::new(buffers, images, &path);
 
     let mut res = vec![];
     for scene in doc.scenes() {
-        res.push(Scene::load(scene, &mut data));
+        res.push(Scene::load(scene, &doc, &mut data));
     }
     Ok(res)
 }
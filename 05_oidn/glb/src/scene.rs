@@ -1,3 +1,5 @@
+pub mod animation;
+pub mod light;
 pub mod model;
 
 use glam::Mat4;
@@ -5,16 +7,52 @@ use gltf::scene::Node;
 
 use crate::GlbData;
 
-pub use model::{AlphaMode, Model};
+pub use animation::Animation;
+pub use light::{Light, LightKind};
+pub use model::{AlphaMode, IndexFormat, Model, Skin};
 
+/// glTFのsceneのnodeの階層を平坦化したもの。
+/// modelsはnodeの木を深さ優先でたどり、meshを持つnodeのprimitiveごとに1つずつ並ぶ。
+/// lightsは同じ順にたどり、KHR_lights_punctualのlightを持つnodeごとに1つずつ並ぶ。
+/// animationはファイル内のanimationでmodelsのtransformを動かすためのデータ。
 #[derive(Default, Clone, Debug)]
 pub struct Scene {
     pub models: Vec<Model>,
+    pub lights: Vec<Light>,
+    pub animation: Animation,
 }
 
 impl Scene {
-    pub(crate) fn load(gltf_scene: gltf::Scene, data: &mut GlbData) -> Self {
-        let mut scene = Self::default();
+    /// 平坦化したprimitiveごとの(mesh index, primitive index, world transform)のリストを返す。
+    /// world transformはルートから親のnodeのtransformをすべて合成したもの。
+    pub fn flattened_meshes(&self) -> Vec<(usize, usize, Mat4)> {
+        self.models
+            .iter()
+            .map(|model| {
+                (
+                    model.mesh_index(),
+                    model.primitive_index(),
+                    model.transform(),
+                )
+            })
+            .collect()
+    }
+
+    /// time秒の時点のanimationのposeに動かすための、modelsごとのtransformを返す。
+    /// 詳しくはAnimation::sampleを参照。
+    pub fn sample_animation(&self, time: f32) -> Vec<Mat4> {
+        self.animation.sample(time)
+    }
+
+    pub(crate) fn load(
+        gltf_scene: gltf::Scene,
+        document: &gltf::Document,
+        data: &mut GlbData,
+    ) -> Self {
+        let mut scene = Self {
+            animation: Animation::load(document, data),
+            ..Default::default()
+        };
 
         for node in gltf_scene.nodes() {
             scene.read_node(&node, Mat4::IDENTITY, data);
@@ -31,7 +69,106 @@ impl Scene {
 
         if let Some(mesh) = node.mesh() {
             for primitive in mesh.primitives() {
-                self.models.push(Model::load(primitive, transform, data));
+                self.models
+                    .push(Model::load(&mesh, primitive, transform, node.skin(), data));
+                self.animation.model_nodes.push(node.index());
+            }
+        }
+
+        if let Some(light) = node.light() {
+            self.lights.push(Light::load(light, transform));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use glam::{Mat3, Quat, Vec3};
+
+    // 親nodeと子nodeがそれぞれ非一様なscaleを持ち、どちらも同じ三角形のmeshを持つglTF
+    fn two_level_gltf() -> Vec<u8> {
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 1.0]];
+        let normal = Vec3::new(0.0, -1.0, 1.0).normalize().to_array();
+        let bytes: Vec<u8> = positions
+            .iter()
+            .chain([normal; 3].iter())
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": [
+                    {{ "mesh": 0, "children": [1], "translation": [1, 2, 3], "scale": [2, 1, 1] }},
+                    {{ "mesh": 1, "rotation": [0, 0, {s}, {s}], "scale": [1, 3, 1] }}
+                ],
+                "meshes": [
+                    {{ "primitives": [{{ "attributes": {{ "POSITION": 0, "NORMAL": 1 }} }}] }},
+                    {{ "primitives": [{{ "attributes": {{ "POSITION": 0, "NORMAL": 1 }} }}] }}
+                ],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                       "min": [0, 0, 0], "max": [1, 1, 1] }},
+                    {{ "bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3,
+                       "type": "VEC3" }}
+                ],
+                "bufferViews": [{{ "buffer": 0, "byteLength": {len} }}],
+                "buffers": [{{
+                    "byteLength": {len},
+                    "uri": "data:application/octet-stream;base64,{data}"
+                }}]
+            }}"#,
+            len = bytes.len(),
+            data = STANDARD.encode(&bytes),
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn flattened_meshes_of_two_level_hierarchy() {
+        let (doc, buffers, images) = gltf::import_slice(two_level_gltf()).unwrap();
+        let mut data = GlbData::new(buffers, images, "");
+        let scene = Scene::load(doc.scenes().next().unwrap(), &doc, &mut data);
+
+        let parent = Mat4::from_scale_rotation_translation(
+            Vec3::new(2.0, 1.0, 1.0),
+            Quat::IDENTITY,
+            Vec3::new(1.0, 2.0, 3.0),
+        );
+        let child = parent
+            * Mat4::from_scale_rotation_translation(
+                Vec3::new(1.0, 3.0, 1.0),
+                Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+                Vec3::ZERO,
+            );
+
+        // 子のnodeが先に並び、子のtransformには親のtransformが合成される
+        let flattened = scene.flattened_meshes();
+        assert_eq!(flattened.len(), 2);
+        assert_eq!((flattened[0].0, flattened[0].1), (1, 0));
+        assert!(flattened[0].2.abs_diff_eq(child, 1e-5));
+        assert_eq!((flattened[1].0, flattened[1].1), (0, 0));
+        assert!(flattened[1].2.abs_diff_eq(parent, 1e-5));
+
+        for (model, transform) in scene.models.iter().zip([child, parent]) {
+            let vertices = model.vertices();
+            let expected_normal = (Mat3::from_mat4(transform).inverse().transpose()
+                * Vec3::new(0.0, -1.0, 1.0))
+            .normalize();
+
+            // 変換後の法線は変換後の三角形の面に垂直になる
+            let e1 = vertices[1].position - vertices[0].position;
+            let e2 = vertices[2].position - vertices[0].position;
+            for vertex in vertices {
+                assert!(vertex.normal.abs_diff_eq(expected_normal, 1e-5));
+                assert!(vertex.normal.dot(e1).abs() < 1e-5);
+                assert!(vertex.normal.dot(e2).abs() < 1e-5);
             }
         }
     }
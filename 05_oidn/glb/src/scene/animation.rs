@@ -0,0 +1,217 @@
+use glam::{Mat4, Quat, Vec3, Vec4};
+use gltf::animation::{util::ReadOutputs, Interpolation, Property};
+
+use crate::GlbData;
+
+// nodeのrest poseのtransformと親のnode
+#[derive(Clone, Debug)]
+struct AnimationNode {
+    parent: Option<usize>,
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+}
+
+// 1つのnodeの1つのpropertyを動かすkeyframeの列
+#[derive(Clone, Debug)]
+struct AnimationChannel {
+    node: usize,
+    property: Property,
+    interpolation: Interpolation,
+    inputs: Vec<f32>,
+    // translationとscaleはxyzのみを使う。
+    // CubicSplineではkeyframeごとに(in-tangent, value, out-tangent)の3つが並ぶ。
+    outputs: Vec<Vec4>,
+}
+impl AnimationChannel {
+    fn load(channel: gltf::animation::Channel, data: &GlbData) -> Option<Self> {
+        let reader = channel.reader(|buffer| Some(&data.buffers[buffer.index()]));
+        let inputs = reader.read_inputs()?.collect::<Vec<_>>();
+        let outputs = match reader.read_outputs()? {
+            ReadOutputs::Translations(translations) => translations
+                .map(|t| Vec3::from_array(t).extend(0.0))
+                .collect::<Vec<_>>(),
+            ReadOutputs::Rotations(rotations) => {
+                rotations.into_f32().map(Vec4::from_array).collect()
+            }
+            ReadOutputs::Scales(scales) => scales
+                .map(|s| Vec3::from_array(s).extend(0.0))
+                .collect::<Vec<_>>(),
+            // morph targetには対応しない
+            ReadOutputs::MorphTargetWeights(_) => return None,
+        };
+        if inputs.is_empty() {
+            return None;
+        }
+        Some(Self {
+            node: channel.target().node().index(),
+            property: channel.target().property(),
+            interpolation: channel.sampler().interpolation(),
+            inputs,
+            outputs,
+        })
+    }
+
+    // i番目のkeyframeの値
+    fn value(&self, i: usize) -> Vec4 {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.outputs[i * 3 + 1],
+            _ => self.outputs[i],
+        }
+    }
+
+    // timeの値を計算する。keyframeの範囲外では最初か最後のkeyframeの値になる。
+    fn sample(&self, time: f32) -> Vec4 {
+        let last = self.inputs.len() - 1;
+        if time <= self.inputs[0] {
+            return self.value(0);
+        }
+        if time >= self.inputs[last] {
+            return self.value(last);
+        }
+        let next = self.inputs.partition_point(|&t| t <= time);
+        let prev = next - 1;
+        let dt = self.inputs[next] - self.inputs[prev];
+        let t = (time - self.inputs[prev]) / dt;
+        let is_rotation = self.property == Property::Rotation;
+        match self.interpolation {
+            Interpolation::Step => self.value(prev),
+            Interpolation::Linear if is_rotation => Quat::from_vec4(self.value(prev))
+                .slerp(Quat::from_vec4(self.value(next)), t)
+                .into(),
+            Interpolation::Linear => self.value(prev).lerp(self.value(next), t),
+            Interpolation::CubicSpline => {
+                // Hermite補間
+                let t2 = t * t;
+                let t3 = t2 * t;
+                let out_tangent = self.outputs[prev * 3 + 2];
+                let in_tangent = self.outputs[next * 3];
+                let value = (2.0 * t3 - 3.0 * t2 + 1.0) * self.value(prev)
+                    + (t3 - 2.0 * t2 + t) * dt * out_tangent
+                    + (-2.0 * t3 + 3.0 * t2) * self.value(next)
+                    + (t3 - t2) * dt * in_tangent;
+                if is_rotation {
+                    value.normalize_or_zero()
+                } else {
+                    value
+                }
+            }
+        }
+    }
+}
+
+/// glTFのanimationでnodeのtransformを動かすためのデータ。
+///
+/// ファイル内のすべてのanimationのchannelを同時に再生する。
+/// morph targetには対応しない。skinのjointの行列はjoint_matricesで計算する。
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    nodes: Vec<AnimationNode>,
+    channels: Vec<AnimationChannel>,
+    // Scene::modelsの順に、modelが属するnodeのindex
+    pub(crate) model_nodes: Vec<usize>,
+}
+impl Animation {
+    pub(crate) fn load(document: &gltf::Document, data: &GlbData) -> Self {
+        let mut nodes = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                AnimationNode {
+                    parent: None,
+                    translation: Vec3::from_array(translation),
+                    rotation: Quat::from_array(rotation),
+                    scale: Vec3::from_array(scale),
+                }
+            })
+            .collect::<Vec<_>>();
+        for node in document.nodes() {
+            for child in node.children() {
+                nodes[child.index()].parent = Some(node.index());
+            }
+        }
+
+        let channels = document
+            .animations()
+            .flat_map(|animation| animation.channels())
+            .filter_map(|channel| AnimationChannel::load(channel, data))
+            .collect();
+
+        Self {
+            nodes,
+            channels,
+            model_nodes: vec![],
+        }
+    }
+
+    /// animationを持つかどうか。
+    pub fn is_animated(&self) -> bool {
+        !self.channels.is_empty()
+    }
+
+    /// animationの長さ(秒)。すべてのchannelの最後のkeyframeの時刻の最大値。
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.inputs.last().copied())
+            .fold(0.0, f32::max)
+    }
+
+    /// time秒の時点のposeに動かすための、modelごとのworld spaceのtransformを返す。
+    ///
+    /// modelの頂点には既にrest poseのworld transformが適用されているので、
+    /// 返すのはrest poseからの差分の`animated_world * rest_world^-1`。
+    /// 範囲外の時刻では最初か最後のkeyframeのposeになる。
+    pub fn sample(&self, time: f32) -> Vec<Mat4> {
+        let animated = self.animated_nodes(time);
+        self.model_nodes
+            .iter()
+            .map(|&node| {
+                world_transform(&animated, node) * world_transform(&self.nodes, node).inverse()
+            })
+            .collect()
+    }
+
+    /// time秒の時点のposeでのskinのjointごとの`joint_world * inverse_bind_matrix`を返す。
+    /// Model::skinned_verticesに渡してmodelの頂点を変形する。
+    pub fn joint_matrices(&self, time: f32, skin: &crate::Skin) -> Vec<Mat4> {
+        let animated = self.animated_nodes(time);
+        skin.joints()
+            .iter()
+            .zip(skin.inverse_bind_matrices())
+            .map(|(&joint, inverse_bind_matrix)| {
+                world_transform(&animated, joint) * *inverse_bind_matrix
+            })
+            .collect()
+    }
+
+    // time秒の時点のposeに動かしたnode
+    fn animated_nodes(&self, time: f32) -> Vec<AnimationNode> {
+        let mut animated = self.nodes.clone();
+        for channel in &self.channels {
+            let value = channel.sample(time);
+            let node = &mut animated[channel.node];
+            match channel.property {
+                Property::Translation => node.translation = value.truncate(),
+                Property::Rotation => node.rotation = Quat::from_vec4(value).normalize(),
+                Property::Scale => node.scale = value.truncate(),
+                Property::MorphTargetWeights => {}
+            }
+        }
+        animated
+    }
+}
+
+// ルートからnodeまでのtransformを合成する
+fn world_transform(nodes: &[AnimationNode], node: usize) -> Mat4 {
+    let mut transform = Mat4::IDENTITY;
+    let mut current = Some(node);
+    while let Some(index) = current {
+        let node = &nodes[index];
+        transform =
+            Mat4::from_scale_rotation_translation(node.scale, node.rotation, node.translation)
+                * transform;
+        current = node.parent;
+    }
+    transform
+}
@@ -0,0 +1,94 @@
+use glam::{Mat4, Vec3};
+use gltf::khr_lights_punctual::Kind;
+
+/// KHR_lights_punctualのlightの種類。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// 無限遠にあり、nodeのlocalの-Z方向に光を放つlight。
+    Directional,
+    /// nodeの位置から全方向に光を放つlight。
+    Point,
+    /// nodeの位置からlocalの-Z方向の円錐に光を放つlight。
+    /// 角度は円錐の中心からのradianで、inner_cone_angleから減衰し始めてouter_cone_angleで0になる。
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+/// glTFのnodeに付いたKHR_lights_punctualのlight。
+#[derive(Clone, Debug)]
+pub struct Light {
+    pub(crate) light_index: usize,
+    pub(crate) kind: LightKind,
+    pub(crate) color: Vec3,
+    pub(crate) intensity: f32,
+    pub(crate) range: Option<f32>,
+    pub(crate) transform: Mat4,
+}
+
+impl Light {
+    /// glTFのlightsの中のindex
+    pub fn light_index(&self) -> usize {
+        self.light_index
+    }
+
+    pub fn kind(&self) -> LightKind {
+        self.kind
+    }
+
+    /// linearな色
+    pub fn color(&self) -> Vec3 {
+        self.color
+    }
+
+    /// glTFの単位の強さ。
+    /// Directionalはlux(lm/m^2)、PointとSpotはcandela(lm/sr)。
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    /// 光が届く距離。Noneの場合は無限遠まで届く。
+    pub fn range(&self) -> Option<f32> {
+        self.range
+    }
+
+    /// ルートから親のnodeのtransformをすべて合成したworld transform。
+    pub fn transform(&self) -> Mat4 {
+        self.transform
+    }
+
+    /// lightの位置。Directionalでは使わない。
+    pub fn position(&self) -> Vec3 {
+        self.transform.transform_point3(Vec3::ZERO)
+    }
+
+    /// 光が進む方向(nodeのlocalの-Z方向)。Pointでは使わない。
+    pub fn direction(&self) -> Vec3 {
+        self.transform
+            .transform_vector3(Vec3::NEG_Z)
+            .normalize_or_zero()
+    }
+
+    pub(crate) fn load(light: gltf::khr_lights_punctual::Light, transform: Mat4) -> Self {
+        let kind = match light.kind() {
+            Kind::Directional => LightKind::Directional,
+            Kind::Point => LightKind::Point,
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        };
+        Self {
+            light_index: light.index(),
+            kind,
+            color: Vec3::from(light.color()),
+            intensity: light.intensity(),
+            range: light.range(),
+            transform,
+        }
+    }
+}
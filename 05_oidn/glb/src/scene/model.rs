@@ -1,18 +1,35 @@
 mod material;
+mod skin;
 mod vertex;
 
-use glam::{vec4, Mat4, Vec2, Vec3, Vec4};
-use std::sync::Arc;
+use glam::{vec4, Mat3, Mat4, Vec2, Vec3, Vec4};
+use std::{collections::HashMap, sync::Arc};
 
 use crate::GlbData;
 pub use material::*;
+pub use skin::*;
 pub use vertex::*;
 
+/// glTFのaccessorに格納されていたindexの型
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum IndexFormat {
+    /// u8とu16のindex。u8はu16として扱う。
+    U16,
+    #[default]
+    U32,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Model {
     pub(crate) vertices: Vec<Vertex>,
     pub(crate) indices: Option<Vec<u32>>,
+    pub(crate) index_format: IndexFormat,
+    pub(crate) has_normals: bool,
     pub(crate) material: Arc<Material>,
+    pub(crate) mesh_index: usize,
+    pub(crate) primitive_index: usize,
+    pub(crate) transform: Mat4,
+    pub(crate) skin: Option<Skin>,
 }
 
 impl Model {
@@ -24,10 +41,134 @@ impl Model {
         &self.vertices
     }
 
+    /// u32に変換したindex。元の型はindex_format()で取得できる。
     pub fn indices(&self) -> Option<&Vec<u32>> {
         self.indices.as_ref()
     }
 
+    /// accessorに格納されていたindexの型
+    pub fn index_format(&self) -> IndexFormat {
+        self.index_format
+    }
+
+    /// primitiveが法線を持ち、すべての頂点の法線が有限で長さが0でないかどうか
+    pub fn has_valid_normals(&self) -> bool {
+        self.has_normals
+            && self
+                .vertices
+                .iter()
+                .all(|v| v.normal.is_finite() && v.normal.length_squared() > 1e-12)
+    }
+
+    /// 隣接する三角形の法線から頂点の法線を計算し直す。
+    ///
+    /// UVの継ぎ目などで分割された頂点も同じ位置なら同じ頂点として扱い、
+    /// 位置を共有する三角形の法線を面積で重み付けして平均する。
+    /// 返り値はvertices()と同じ順の法線。どの三角形にも使われない頂点は+Zになる。
+    pub fn smooth_normals(&self) -> Vec<Vec3> {
+        let Some(indices) = &self.indices else {
+            return vec![Vec3::Z; self.vertices.len()];
+        };
+        // 頂点の位置のビット列を同じ頂点かどうかの判定に使う
+        let key = |i: u32| {
+            self.vertices[i as usize]
+                .position
+                .to_array()
+                .map(f32::to_bits)
+        };
+        let mut normals = HashMap::new();
+        for index in indices.chunks_exact(3) {
+            let p0 = self.vertices[index[0] as usize].position;
+            let p1 = self.vertices[index[1] as usize].position;
+            let p2 = self.vertices[index[2] as usize].position;
+            // 外積の長さは三角形の面積の2倍なので正規化せずに足すと面積で重み付けされる
+            let face_normal = (p1 - p0).cross(p2 - p0);
+            if !face_normal.is_finite() {
+                continue;
+            }
+            for &i in index {
+                *normals.entry(key(i)).or_insert(Vec3::ZERO) += face_normal;
+            }
+        }
+        (0..self.vertices.len() as u32)
+            .map(|i| {
+                normals
+                    .get(&key(i))
+                    .and_then(|n| n.try_normalize())
+                    .unwrap_or(Vec3::Z)
+            })
+            .collect()
+    }
+
+    /// glTFのmeshのindex
+    pub fn mesh_index(&self) -> usize {
+        self.mesh_index
+    }
+
+    /// mesh内のprimitiveのindex
+    pub fn primitive_index(&self) -> usize {
+        self.primitive_index
+    }
+
+    /// ルートから親のnodeのtransformをすべて合成したworld transform。
+    /// vertices()の頂点には既に適用されている。
+    pub fn transform(&self) -> Mat4 {
+        self.transform
+    }
+
+    /// nodeがskinを持ち、primitiveがJOINTS_0とWEIGHTS_0を持つ場合のskin。
+    pub fn skin(&self) -> Option<&Skin> {
+        self.skin.as_ref()
+    }
+
+    /// skinで変形した頂点を返す。skinが無い場合はvertices()をそのまま返す。
+    ///
+    /// joint_matricesはskinのjointごとの`joint_world * inverse_bind_matrix`で、
+    /// Animation::joint_matricesで計算できる。
+    /// glTFではskinを持つnodeのtransformは無視されるので、
+    /// vertices()に適用済みのtransformを戻してから変形し、world spaceの頂点を返す。
+    /// weightが全て0の頂点は変形しない。
+    pub fn skinned_vertices(&self, joint_matrices: &[Mat4]) -> Vec<Vertex> {
+        let Some(skin) = &self.skin else {
+            return self.vertices.clone();
+        };
+        let inverse_transform = self.transform.inverse();
+        // 法線はnormal matrixで変換されているので、その逆行列の転置行列で戻す
+        let inverse_normal_matrix = Mat3::from_mat4(self.transform).transpose();
+        let inverse_tangent_matrix = Mat3::from_mat4(inverse_transform);
+        self.vertices
+            .iter()
+            .zip(&skin.vertex_joints)
+            .zip(&skin.vertex_weights)
+            .map(|((vertex, joints), weights)| {
+                let skin_matrix = joints
+                    .iter()
+                    .zip(weights)
+                    .filter_map(|(&joint, &weight)| {
+                        joint_matrices
+                            .get(joint as usize)
+                            .map(|matrix| *matrix * weight)
+                    })
+                    .fold(Mat4::ZERO, |sum, matrix| sum + matrix);
+                if skin_matrix == Mat4::ZERO {
+                    return *vertex;
+                }
+                let normal_matrix = Mat3::from_mat4(skin_matrix).inverse().transpose();
+                let position = inverse_transform.transform_point3(vertex.position);
+                let normal = inverse_normal_matrix * vertex.normal;
+                let tangent = inverse_tangent_matrix * vertex.tangent.truncate();
+                Vertex {
+                    position: skin_matrix.transform_point3(position),
+                    normal: (normal_matrix * normal).normalize_or_zero(),
+                    tangent: (Mat3::from_mat4(skin_matrix) * tangent)
+                        .normalize_or_zero()
+                        .extend(vertex.tangent.w),
+                    tex_coords: vertex.tex_coords,
+                }
+            })
+            .collect()
+    }
+
     fn apply_transform_tangent(tangent: [f32; 4], transform: Mat4) -> Vec4 {
         let tang = vec4(tangent[0], tangent[1], tangent[2], 0.0);
         let mut tang = transform * tang;
@@ -35,12 +176,23 @@ impl Model {
         tang
     }
 
-    pub(crate) fn load(primitive: gltf::Primitive, transform: Mat4, data: &mut GlbData) -> Self {
+    pub(crate) fn load(
+        mesh: &gltf::Mesh,
+        primitive: gltf::Primitive,
+        transform: Mat4,
+        skin: Option<gltf::Skin>,
+        data: &mut GlbData,
+    ) -> Self {
         let buffers = &data.buffers;
         let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-        let indices = reader
-            .read_indices()
-            .map(|indices| indices.into_u32().collect());
+        let read_indices = reader.read_indices();
+        let index_format = match &read_indices {
+            Some(gltf::mesh::util::ReadIndices::U8(_) | gltf::mesh::util::ReadIndices::U16(_)) => {
+                IndexFormat::U16
+            }
+            _ => IndexFormat::U32,
+        };
+        let indices = read_indices.map(|indices| indices.into_u32().collect());
 
         let mut vertices: Vec<_> = reader
             .read_positions()
@@ -51,11 +203,13 @@ impl Model {
             })
             .collect();
 
-        if let Some(normals) = reader.read_normals() {
+        // 非一様なscaleを含む場合も法線が面に垂直になるように逆転置行列で変換する
+        let normals = reader.read_normals();
+        let has_normals = normals.is_some();
+        if let Some(normals) = normals {
+            let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
             for (i, normal) in normals.enumerate() {
-                vertices[i].normal = transform
-                    .transform_vector3(Vec3::from_array(normal))
-                    .normalize();
+                vertices[i].normal = (normal_matrix * Vec3::from_array(normal)).normalize();
             }
         }
         if let Some(tangents) = reader.read_tangents() {
@@ -71,10 +225,18 @@ impl Model {
             }
         }
 
+        let skin = skin.and_then(|skin| Skin::load(skin, &primitive, vertices.len(), data));
+
         Model {
             vertices,
             indices,
+            index_format,
+            has_normals,
             material: Material::load(primitive.material(), data),
+            mesh_index: mesh.index(),
+            primitive_index: primitive.index(),
+            transform,
+            skin,
         }
     }
 }
@@ -4,9 +4,53 @@ use std::sync::Arc;
 
 use crate::GlbData;
 
+/// KHR_texture_transformのoffset、rotation、scaleから
+/// UVに掛ける2x3のアフィン行列を行優先で作る。
+/// uv' = (m[0] * u + m[1] * v + m[2], m[3] * u + m[4] * v + m[5])
+fn uv_transform_matrix(offset: [f32; 2], rotation: f32, scale: [f32; 2]) -> [f32; 6] {
+    // translation * rotation * scale
+    let (sin, cos) = rotation.sin_cos();
+    [
+        cos * scale[0],
+        sin * scale[1],
+        offset[0],
+        -sin * scale[0],
+        cos * scale[1],
+        offset[1],
+    ]
+}
+
+fn load_uv_transform(info: &gltf::texture::Info) -> Option<[f32; 6]> {
+    info.texture_transform()
+        .map(|t| uv_transform_matrix(t.offset(), t.rotation(), t.scale()))
+}
+
+// normal textureはgltfクレートがKHR_texture_transformを読まないので、jsonから直接読む
+fn load_normal_uv_transform(texture: &gltf::material::NormalTexture) -> Option<[f32; 6]> {
+    let value = texture.extension_value("KHR_texture_transform")?;
+    let vec2 = |key: &str, default: [f32; 2]| {
+        value
+            .get(key)
+            .and_then(|v| v.as_array())
+            .and_then(|v| Some([v.first()?.as_f64()? as f32, v.get(1)?.as_f64()? as f32]))
+            .unwrap_or(default)
+    };
+    let rotation = value
+        .get("rotation")
+        .and_then(|v| v.as_f64())
+        .unwrap_or(0.0) as f32;
+    Some(uv_transform_matrix(
+        vec2("offset", [0.0, 0.0]),
+        rotation,
+        vec2("scale", [1.0, 1.0]),
+    ))
+}
+
 #[derive(Clone, Debug)]
 pub struct Emissive {
     pub texture: Option<Arc<RgbImage>>,
+    /// KHR_texture_transformのUVの2x3のアフィン行列(行優先)
+    pub uv_transform: Option<[f32; 6]>,
     pub factor: Vec3,
 }
 impl Emissive {
@@ -17,6 +61,9 @@ impl Emissive {
             texture: gltf_mat
                 .emissive_texture()
                 .map(|texture| data.load_rgb_image(&texture.texture())),
+            uv_transform: gltf_mat
+                .emissive_texture()
+                .and_then(|texture| load_uv_transform(&texture)),
             factor,
         }
     }
@@ -25,6 +72,7 @@ impl Default for Emissive {
     fn default() -> Self {
         Self {
             texture: None,
+            uv_transform: None,
             factor: Vec3::ZERO,
         }
     }
@@ -33,12 +81,15 @@ impl Default for Emissive {
 #[derive(Clone, Debug)]
 pub struct NormalMap {
     pub texture: Arc<RgbImage>,
+    /// KHR_texture_transformのUVの2x3のアフィン行列(行優先)
+    pub uv_transform: Option<[f32; 6]>,
     pub factor: f32,
 }
 impl NormalMap {
     pub(crate) fn load(gltf_mat: &gltf::Material, data: &mut GlbData) -> Option<Self> {
         gltf_mat.normal_texture().map(|texture| Self {
             texture: data.load_rgb_image(&texture.texture()),
+            uv_transform: load_normal_uv_transform(&texture),
             factor: texture.scale(),
         })
     }
@@ -48,10 +99,14 @@ impl NormalMap {
 pub struct PbrMaterial {
     pub base_color_factor: Vec4,
     pub base_color_texture: Option<Arc<RgbaImage>>,
+    /// base color textureのKHR_texture_transformのUVの2x3のアフィン行列(行優先)
+    pub base_color_uv_transform: Option<[f32; 6]>,
     pub metallic_texture: Option<Arc<GrayImage>>,
     pub metallic_factor: f32,
     pub roughness_texture: Option<Arc<GrayImage>>,
     pub roughness_factor: f32,
+    /// metallic roughness textureのKHR_texture_transformのUVの2x3のアフィン行列(行優先)
+    pub metallic_roughness_uv_transform: Option<[f32; 6]>,
 }
 impl PbrMaterial {
     pub(crate) fn load(pbr: gltf::material::PbrMetallicRoughness, data: &mut GlbData) -> Self {
@@ -61,12 +116,14 @@ impl PbrMaterial {
         };
         if let Some(texture) = pbr.base_color_texture() {
             material.base_color_texture = Some(data.load_base_color_image(&texture.texture()));
+            material.base_color_uv_transform = load_uv_transform(&texture);
         }
 
         material.roughness_factor = pbr.roughness_factor();
         material.metallic_factor = pbr.metallic_factor();
 
         if let Some(texture) = pbr.metallic_roughness_texture() {
+            material.metallic_roughness_uv_transform = load_uv_transform(&texture);
             if material.metallic_factor > 0. {
                 material.metallic_texture = Some(data.load_gray_image(&texture.texture(), 2));
             }
@@ -83,10 +140,147 @@ impl Default for PbrMaterial {
         PbrMaterial {
             base_color_factor: Vec4::ONE,
             base_color_texture: None,
+            base_color_uv_transform: None,
             metallic_factor: 0.,
             metallic_texture: None,
             roughness_factor: 0.,
             roughness_texture: None,
+            metallic_roughness_uv_transform: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Transmission {
+    /// transmission textureのRチャンネル
+    pub texture: Option<Arc<GrayImage>>,
+    /// KHR_texture_transformのUVの2x3のアフィン行列(行優先)
+    pub uv_transform: Option<[f32; 6]>,
+    pub factor: f32,
+}
+impl Transmission {
+    pub(crate) fn load(gltf_mat: &gltf::Material, data: &mut GlbData) -> Self {
+        let Some(transmission) = gltf_mat.transmission() else {
+            return Self::default();
+        };
+        Self {
+            texture: transmission
+                .transmission_texture()
+                .map(|texture| data.load_gray_image(&texture.texture(), 0)),
+            uv_transform: transmission
+                .transmission_texture()
+                .and_then(|texture| load_uv_transform(&texture)),
+            factor: transmission.transmission_factor(),
+        }
+    }
+}
+impl Default for Transmission {
+    fn default() -> Self {
+        Self {
+            texture: None,
+            uv_transform: None,
+            factor: 0.0,
+        }
+    }
+}
+
+/// KHR_materials_clearcoatのクリアコート層
+///
+/// gltfクレートがKHR_materials_clearcoatを読まないので、jsonから直接読む。
+/// textureは読まずにfactorのみを使う。
+#[derive(Clone, Debug, Default)]
+pub struct Clearcoat {
+    pub factor: f32,
+    pub roughness_factor: f32,
+}
+impl Clearcoat {
+    pub(crate) fn load(gltf_mat: &gltf::Material) -> Self {
+        let Some(value) = gltf_mat.extension_value("KHR_materials_clearcoat") else {
+            return Self::default();
+        };
+        let float = |key: &str| value.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0) as f32;
+        Self {
+            factor: float("clearcoatFactor"),
+            roughness_factor: float("clearcoatRoughnessFactor"),
+        }
+    }
+}
+
+/// KHR_materials_specularの誘電体の鏡面反射の強さと色
+///
+/// textureは読まずにfactorのみを使う。
+#[derive(Clone, Debug)]
+pub struct Specular {
+    pub factor: f32,
+    pub color_factor: Vec3,
+}
+impl Specular {
+    pub(crate) fn load(gltf_mat: &gltf::Material) -> Self {
+        let Some(specular) = gltf_mat.specular() else {
+            return Self::default();
+        };
+        Self {
+            factor: specular.specular_factor(),
+            color_factor: Vec3::from_array(specular.specular_color_factor()),
+        }
+    }
+}
+impl Default for Specular {
+    fn default() -> Self {
+        Self {
+            factor: 1.0,
+            color_factor: Vec3::ONE,
+        }
+    }
+}
+
+/// KHR_materials_iridescenceの薄膜干渉
+///
+/// gltfクレートがKHR_materials_iridescenceを読まないので、jsonから直接読む。
+/// textureは読まずにfactorのみを使う。
+#[derive(Clone, Debug)]
+pub struct Iridescence {
+    pub factor: f32,
+    /// 薄膜の屈折率
+    pub ior: f32,
+    /// 薄膜の厚さの最小値(nm)
+    pub thickness_minimum: f32,
+    /// 薄膜の厚さの最大値(nm)
+    pub thickness_maximum: f32,
+}
+impl Iridescence {
+    pub(crate) fn load(gltf_mat: &gltf::Material) -> Self {
+        let Some(value) = gltf_mat.extension_value("KHR_materials_iridescence") else {
+            return Self::default();
+        };
+        let default = Self::default();
+        let float = |key: &str, default: f32| {
+            value
+                .get(key)
+                .and_then(|v| v.as_f64())
+                .map_or(default, |v| v as f32)
+        };
+        Self {
+            factor: float("iridescenceFactor", default.factor),
+            ior: float("iridescenceIor", default.ior),
+            thickness_minimum: float("iridescenceThicknessMinimum", default.thickness_minimum),
+            thickness_maximum: float("iridescenceThicknessMaximum", default.thickness_maximum),
+        }
+    }
+
+    /// 薄膜の厚さ(nm)。
+    /// thickness textureがない場合は仕様に従ってthickness_maximumを使う。
+    pub fn thickness(&self) -> f32 {
+        self.thickness_maximum
+    }
+}
+impl Default for Iridescence {
+    fn default() -> Self {
+        Self {
+            factor: 0.0,
+            ior: 1.3,
+            thickness_minimum: 100.0,
+            thickness_maximum: 400.0,
         }
     }
 }
@@ -99,13 +293,46 @@ pub enum AlphaMode {
     Blend,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct Material {
     pub pbr: PbrMaterial,
     pub normal: Option<NormalMap>,
     pub emissive: Emissive,
+    /// KHR_materials_transmissionの透過率
+    pub transmission: Transmission,
+    /// KHR_materials_iorの屈折率
+    pub ior: f32,
+    /// KHR_materials_clearcoatのクリアコート層
+    pub clearcoat: Clearcoat,
+    /// KHR_materials_specularの誘電体の鏡面反射
+    pub specular: Specular,
+    /// KHR_materials_iridescenceの薄膜干渉
+    pub iridescence: Iridescence,
+    /// KHR_materials_dispersionの分散。20 / アッベ数で、0なら分散しない。
+    ///
+    /// gltfクレートがKHR_materials_dispersionを読まないので、jsonから直接読む。
+    pub dispersion: f32,
     pub alpha_mode: AlphaMode,
     pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            pbr: PbrMaterial::default(),
+            normal: None,
+            emissive: Emissive::default(),
+            transmission: Transmission::default(),
+            ior: 1.5,
+            clearcoat: Clearcoat::default(),
+            specular: Specular::default(),
+            iridescence: Iridescence::default(),
+            dispersion: 0.0,
+            alpha_mode: AlphaMode::default(),
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
 }
 impl Material {
     pub(crate) fn load(gltf_mat: gltf::Material, data: &mut GlbData) -> Arc<Self> {
@@ -119,13 +346,27 @@ impl Material {
             gltf::material::AlphaMode::Blend => AlphaMode::Blend,
         };
         let alpha_cutoff = gltf_mat.alpha_cutoff().unwrap_or(0.5);
+        let double_sided = gltf_mat.double_sided();
+        let ior = gltf_mat.ior().unwrap_or(1.5);
+        let dispersion = gltf_mat
+            .extension_value("KHR_materials_dispersion")
+            .and_then(|value| value.get("dispersion"))
+            .and_then(|v| v.as_f64())
+            .map_or(0.0, |v| v as f32);
 
         let material = Arc::new(Material {
             pbr: PbrMaterial::load(gltf_mat.pbr_metallic_roughness(), data),
             normal: NormalMap::load(&gltf_mat, data),
             emissive: Emissive::load(&gltf_mat, data),
+            transmission: Transmission::load(&gltf_mat, data),
+            ior,
+            clearcoat: Clearcoat::load(&gltf_mat),
+            specular: Specular::load(&gltf_mat),
+            iridescence: Iridescence::load(&gltf_mat),
+            dispersion,
             alpha_mode,
             alpha_cutoff,
+            double_sided,
         });
 
         // Add to the collection
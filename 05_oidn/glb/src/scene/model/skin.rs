@@ -0,0 +1,80 @@
+use glam::Mat4;
+
+use crate::GlbData;
+
+/// glTFのskinで頂点を変形するためのデータ。
+///
+/// 1頂点あたり4つまでのjointの影響に対応し、JOINTS_0とWEIGHTS_0のみを読む。
+#[derive(Clone, Debug, Default)]
+pub struct Skin {
+    // skinのjointごとのnodeのindex
+    pub(crate) joints: Vec<usize>,
+    // skinのjointごとのinverse bind matrix
+    pub(crate) inverse_bind_matrices: Vec<Mat4>,
+    // 頂点ごとのjointsの中のindex
+    pub(crate) vertex_joints: Vec<[u16; 4]>,
+    // 頂点ごとのweight。合計が1になるように正規化してある。
+    pub(crate) vertex_weights: Vec<[f32; 4]>,
+}
+impl Skin {
+    /// skinのjointのnodeのindex。
+    pub fn joints(&self) -> &[usize] {
+        &self.joints
+    }
+
+    /// skinのjointごとのinverse bind matrix。
+    pub fn inverse_bind_matrices(&self) -> &[Mat4] {
+        &self.inverse_bind_matrices
+    }
+
+    /// 頂点ごとの、影響するjointのjoints()の中のindex。
+    pub fn vertex_joints(&self) -> &[[u16; 4]] {
+        &self.vertex_joints
+    }
+
+    /// 頂点ごとの、vertex_joints()のjointのweight。合計が1になるように正規化してある。
+    pub fn vertex_weights(&self) -> &[[f32; 4]] {
+        &self.vertex_weights
+    }
+
+    pub(crate) fn load(
+        skin: gltf::Skin,
+        primitive: &gltf::Primitive,
+        vertex_count: usize,
+        data: &GlbData,
+    ) -> Option<Self> {
+        let buffers = &data.buffers;
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let vertex_joints = reader.read_joints(0)?.into_u16().collect::<Vec<_>>();
+        let vertex_weights = reader
+            .read_weights(0)?
+            .into_f32()
+            .map(|weights| {
+                let sum = weights.iter().sum::<f32>();
+                if sum > 0.0 {
+                    weights.map(|w| w / sum)
+                } else {
+                    [0.0; 4]
+                }
+            })
+            .collect::<Vec<_>>();
+        if vertex_joints.len() != vertex_count || vertex_weights.len() != vertex_count {
+            return None;
+        }
+
+        let joints = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
+        // inverse bind matrixが無い場合は単位行列とみなす
+        let inverse_bind_matrices = skin
+            .reader(|buffer| Some(&buffers[buffer.index()]))
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(|m| Mat4::from_cols_array_2d(&m)).collect())
+            .unwrap_or_else(|| vec![Mat4::IDENTITY; joints.len()]);
+
+        Some(Self {
+            joints,
+            inverse_bind_matrices,
+            vertex_joints,
+            vertex_weights,
+        })
+    }
+}
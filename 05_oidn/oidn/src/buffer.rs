@@ -6,7 +6,7 @@ use std::{
     sync::atomic::{fence, AtomicUsize, Ordering},
 };
 
-use crate::OidnDevice;
+use crate::{OidnDevice, OidnError};
 
 struct OidnBufferData {
     _device: OidnDevice,
@@ -14,7 +14,7 @@ struct OidnBufferData {
     ref_count: AtomicUsize,
 }
 impl OidnBufferData {
-    fn new(device: &OidnDevice, buffer: &ashtray::utils::SharedBuffer) -> Self {
+    fn new(device: &OidnDevice, buffer: &ashtray::utils::SharedBuffer) -> Result<Self, OidnError> {
         #[cfg(target_os = "windows")]
         let buffer = unsafe {
             let name = null();
@@ -38,20 +38,18 @@ impl OidnBufferData {
             )
         };
 
-        unsafe {
-            let mut error = std::ptr::null();
-            oidnGetDeviceError(device.device_raw(), &mut error);
-            if !error.is_null() {
-                let error = std::ffi::CStr::from_ptr(error);
-                panic!("OIDN new buffer error: {:?}", error);
+        if let Err(error) = device.error() {
+            if !buffer.is_null() {
+                unsafe { oidnReleaseBuffer(buffer) };
             }
+            return Err(error);
         }
 
-        Self {
+        Ok(Self {
             _device: device.clone(),
             buffer,
             ref_count: AtomicUsize::new(1),
-        }
+        })
     }
 }
 
@@ -59,10 +57,13 @@ pub struct OidnBuffer {
     ptr: NonNull<OidnBufferData>,
 }
 impl OidnBuffer {
-    pub(crate) fn new(device: &OidnDevice, buffer: &ashtray::utils::SharedBuffer) -> Self {
-        let data = OidnBufferData::new(device, buffer);
+    pub(crate) fn new(
+        device: &OidnDevice,
+        buffer: &ashtray::utils::SharedBuffer,
+    ) -> Result<Self, OidnError> {
+        let data = OidnBufferData::new(device, buffer)?;
         let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(data))) };
-        Self { ptr }
+        Ok(Self { ptr })
     }
 
     pub fn buffer_raw(&self) -> OIDNBuffer {
@@ -80,6 +81,9 @@ impl Debug for OidnBuffer {
     }
 }
 
+// OIDNのAPIはスレッドセーフで、参照カウントもatomicなのでOidnBufferはSend
+unsafe impl Send for OidnBuffer {}
+
 impl Clone for OidnBuffer {
     fn clone(&self) -> Self {
         self.data().ref_count.fetch_add(1, Ordering::SeqCst);
@@ -1,32 +1,38 @@
 use oidn_sys::*;
 use std::{
+    ffi::CString,
     fmt::Debug,
     ptr::NonNull,
     sync::atomic::{fence, AtomicUsize, Ordering},
 };
 
+use crate::{error::check_device_error, OidnError};
+
 struct OidnDeviceData {
     device: OIDNDevice,
     ref_count: AtomicUsize,
 }
 impl OidnDeviceData {
-    pub fn new() -> Self {
-        let device = unsafe { oidnNewDevice(OIDNDeviceType::OIDN_DEVICE_TYPE_DEFAULT) };
+    fn new(device: OIDNDevice) -> Result<Self, OidnError> {
+        if device.is_null() {
+            // deviceの作成に失敗した場合はnullに対してエラーを問い合わせる
+            check_device_error(device)?;
+            return Err(OidnError {
+                code: crate::OidnErrorCode::Unknown,
+                message: "failed to create OIDN device".into(),
+            });
+        }
         unsafe { oidnCommitDevice(device) };
 
-        unsafe {
-            let mut error = std::ptr::null();
-            oidnGetDeviceError(device, &mut error);
-            if !error.is_null() {
-                let error = std::ffi::CStr::from_ptr(error);
-                panic!("OIDN new device error: {:?}", error);
-            }
+        if let Err(error) = check_device_error(device) {
+            unsafe { oidnReleaseDevice(device) };
+            return Err(error);
         }
 
-        Self {
+        Ok(Self {
             device,
             ref_count: AtomicUsize::new(1),
-        }
+        })
     }
 }
 
@@ -34,20 +40,50 @@ pub struct OidnDevice {
     ptr: NonNull<OidnDeviceData>,
 }
 impl OidnDevice {
-    pub fn new() -> Self {
-        let data = OidnDeviceData::new();
+    pub fn new() -> Result<Self, OidnError> {
+        let device = unsafe { oidnNewDevice(OIDNDeviceType::OIDN_DEVICE_TYPE_DEFAULT) };
+        Self::from_raw_device(device)
+    }
+
+    // VkPhysicalDeviceIDPropertiesのdeviceUUIDと一致するGPUでdeviceを作成する。
+    // Vulkanと同じGPU上で動くので、共有bufferのデータがGPUから出ずにdenoiseできる。
+    pub fn new_on_gpu(device_uuid: &[u8; 16]) -> Result<Self, OidnError> {
+        let device = unsafe { oidnNewDeviceByUUID(device_uuid.as_ptr() as *const _) };
+        Self::from_raw_device(device)
+    }
+
+    fn from_raw_device(device: OIDNDevice) -> Result<Self, OidnError> {
+        let data = OidnDeviceData::new(device)?;
         let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(data))) };
-        Self { ptr }
+        Ok(Self { ptr })
+    }
+
+    // このplatformのVulkanの外部メモリをbufferとして共有できるかどうか
+    pub fn supports_shared_buffer(&self) -> bool {
+        let name = CString::new("externalMemoryTypes").unwrap();
+        let types = unsafe { oidnGetDeviceInt(self.device_raw(), name.as_ptr()) };
+        #[cfg(target_os = "windows")]
+        let flag = OIDNExternalMemoryTypeFlag::OIDN_EXTERNAL_MEMORY_TYPE_FLAG_OPAQUE_WIN32;
+        #[cfg(target_os = "linux")]
+        let flag = OIDNExternalMemoryTypeFlag::OIDN_EXTERNAL_MEMORY_TYPE_FLAG_OPAQUE_FD;
+        types & flag as i32 != 0
     }
 
-    pub fn new_filter(&self, ty: impl Into<String>) -> crate::OidnFilter {
+    pub fn new_filter(&self, ty: impl Into<String>) -> Result<crate::OidnFilter, OidnError> {
         crate::OidnFilter::new(self, ty)
     }
 
-    pub fn new_buffer(&self, buffer: &ashtray::utils::SharedBuffer) -> crate::OidnBuffer {
+    pub fn new_buffer(
+        &self,
+        buffer: &ashtray::utils::SharedBuffer,
+    ) -> Result<crate::OidnBuffer, OidnError> {
         crate::OidnBuffer::new(self, buffer)
     }
 
+    pub fn error(&self) -> Result<(), OidnError> {
+        check_device_error(self.device_raw())
+    }
+
     pub(crate) fn device_raw(&self) -> OIDNDevice {
         self.data().device
     }
@@ -63,6 +99,9 @@ impl Debug for OidnDevice {
     }
 }
 
+// OIDNのAPIはスレッドセーフで、参照カウントもatomicなのでOidnDeviceはSend
+unsafe impl Send for OidnDevice {}
+
 impl Clone for OidnDevice {
     fn clone(&self) -> Self {
         self.data().ref_count.fetch_add(1, Ordering::SeqCst);
@@ -0,0 +1,56 @@
+use oidn_sys::*;
+use std::{ffi::CStr, fmt::Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OidnErrorCode {
+    Unknown,
+    InvalidArgument,
+    InvalidOperation,
+    OutOfMemory,
+    UnsupportedHardware,
+    Cancelled,
+}
+impl OidnErrorCode {
+    fn from_raw(error: OIDNError) -> Option<Self> {
+        match error {
+            OIDNError::OIDN_ERROR_NONE => None,
+            OIDNError::OIDN_ERROR_UNKNOWN => Some(Self::Unknown),
+            OIDNError::OIDN_ERROR_INVALID_ARGUMENT => Some(Self::InvalidArgument),
+            OIDNError::OIDN_ERROR_INVALID_OPERATION => Some(Self::InvalidOperation),
+            OIDNError::OIDN_ERROR_OUT_OF_MEMORY => Some(Self::OutOfMemory),
+            OIDNError::OIDN_ERROR_UNSUPPORTED_HARDWARE => Some(Self::UnsupportedHardware),
+            OIDNError::OIDN_ERROR_CANCELLED => Some(Self::Cancelled),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OidnError {
+    pub code: OidnErrorCode,
+    pub message: String,
+}
+impl Display for OidnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OIDN error ({:?}): {}", self.code, self.message)
+    }
+}
+impl std::error::Error for OidnError {}
+
+// deviceに溜まっているエラーを取り出してResultに変換する
+pub(crate) fn check_device_error(device: OIDNDevice) -> Result<(), OidnError> {
+    let mut message = std::ptr::null();
+    let error = unsafe { oidnGetDeviceError(device, &mut message) };
+    match OidnErrorCode::from_raw(error) {
+        None => Ok(()),
+        Some(code) => {
+            let message = if message.is_null() {
+                String::new()
+            } else {
+                unsafe { CStr::from_ptr(message) }
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            Err(OidnError { code, message })
+        }
+    }
+}
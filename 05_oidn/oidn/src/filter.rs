@@ -6,7 +6,7 @@ use std::{
     sync::atomic::{fence, AtomicUsize, Ordering},
 };
 
-use crate::OidnDevice;
+use crate::{OidnDevice, OidnError};
 
 struct OidnFilterData {
     _device: OidnDevice,
@@ -16,26 +16,24 @@ struct OidnFilterData {
     ref_count: AtomicUsize,
 }
 impl OidnFilterData {
-    pub fn new(device: &OidnDevice, ty: String) -> Self {
+    pub fn new(device: &OidnDevice, ty: String) -> Result<Self, OidnError> {
         let ty = CString::new(ty).unwrap();
         let filter = unsafe { oidnNewFilter(device.device_raw(), ty.as_ptr()) };
 
-        unsafe {
-            let mut error = std::ptr::null();
-            oidnGetDeviceError(device.device_raw(), &mut error);
-            if !error.is_null() {
-                let error = std::ffi::CStr::from_ptr(error);
-                panic!("OIDN new filter error: {:?}", error);
+        if let Err(error) = device.error() {
+            if !filter.is_null() {
+                unsafe { oidnReleaseFilter(filter) };
             }
+            return Err(error);
         }
 
-        Self {
+        Ok(Self {
             _device: device.clone(),
             filter,
             width: 400,
             height: 300,
             ref_count: AtomicUsize::new(1),
-        }
+        })
     }
 }
 
@@ -43,10 +41,10 @@ pub struct OidnFilter {
     ptr: NonNull<OidnFilterData>,
 }
 impl OidnFilter {
-    pub(crate) fn new(device: &OidnDevice, ty: impl Into<String>) -> Self {
-        let data = OidnFilterData::new(device, ty.into());
+    pub(crate) fn new(device: &OidnDevice, ty: impl Into<String>) -> Result<Self, OidnError> {
+        let data = OidnFilterData::new(device, ty.into())?;
         let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(data))) };
-        Self { ptr }
+        Ok(Self { ptr })
     }
 
     pub fn hdr(&self, flag: bool) {
@@ -64,8 +62,15 @@ impl OidnFilter {
         self.data_mut().height = height;
     }
 
-    pub fn color(&self, buffer: &crate::OidnBuffer) {
-        let name = CString::new("color").unwrap();
+    pub fn clean_aux(&self, flag: bool) {
+        let name = CString::new("cleanAux").unwrap();
+        unsafe { oidnSetFilterBool(self.filter_raw(), name.as_ptr(), flag) };
+    }
+
+    // 名前を指定してbufferをfilterのimageとして設定する。
+    // formatはFLOAT3で、サイズはresizeで設定したものを使う。
+    pub fn set_image(&self, name: &str, buffer: &crate::OidnBuffer) {
+        let name = CString::new(name).unwrap();
         unsafe {
             oidnSetFilterImage(
                 self.data().filter,
@@ -81,68 +86,31 @@ impl OidnFilter {
         };
     }
 
+    pub fn color(&self, buffer: &crate::OidnBuffer) {
+        self.set_image("color", buffer);
+    }
+
     pub fn albedo(&self, buffer: &crate::OidnBuffer) {
-        let name = CString::new("albedo").unwrap();
-        unsafe {
-            oidnSetFilterImage(
-                self.data().filter,
-                name.as_ptr(),
-                buffer.buffer_raw(),
-                OIDNFormat::OIDN_FORMAT_FLOAT3,
-                self.data().width as usize,
-                self.data().height as usize,
-                0,
-                0,
-                0,
-            )
-        };
+        self.set_image("albedo", buffer);
     }
 
     pub fn normal(&self, buffer: &crate::OidnBuffer) {
-        let name = CString::new("normal").unwrap();
-        unsafe {
-            oidnSetFilterImage(
-                self.data().filter,
-                name.as_ptr(),
-                buffer.buffer_raw(),
-                OIDNFormat::OIDN_FORMAT_FLOAT3,
-                self.data().width as usize,
-                self.data().height as usize,
-                0,
-                0,
-                0,
-            )
-        };
+        self.set_image("normal", buffer);
     }
 
     pub fn output(&self, buffer: &crate::OidnBuffer) {
-        let name = CString::new("output").unwrap();
-        unsafe {
-            oidnSetFilterImage(
-                self.data().filter,
-                name.as_ptr(),
-                buffer.buffer_raw(),
-                OIDNFormat::OIDN_FORMAT_FLOAT3,
-                self.data().width as usize,
-                self.data().height as usize,
-                0,
-                0,
-                0,
-            )
-        };
+        self.set_image("output", buffer);
     }
 
-    pub fn execute(&self) {
+    pub fn commit(&self) -> Result<(), OidnError> {
         unsafe { oidnCommitFilter(self.filter_raw()) };
+        self.data()._device.error()
+    }
+
+    pub fn execute(&self) -> Result<(), OidnError> {
+        self.commit()?;
         unsafe { oidnExecuteFilter(self.filter_raw()) };
-        unsafe {
-            let mut error = std::ptr::null();
-            oidnGetDeviceError(self.data()._device.device_raw(), &mut error);
-            if !error.is_null() {
-                let error = std::ffi::CStr::from_ptr(error);
-                panic!("OIDN error: {:?}", error);
-            }
-        }
+        self.data()._device.error()
     }
 
     pub(crate) fn filter_raw(&self) -> OIDNFilter {
@@ -164,6 +132,9 @@ impl Debug for OidnFilter {
     }
 }
 
+// OIDNのAPIはスレッドセーフで、参照カウントもatomicなのでOidnFilterはSend
+unsafe impl Send for OidnFilter {}
+
 impl Clone for OidnFilter {
     fn clone(&self) -> Self {
         self.data().ref_count.fetch_add(1, Ordering::SeqCst);
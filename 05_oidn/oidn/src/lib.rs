@@ -1,7 +1,9 @@
 mod buffer;
 mod device;
+mod error;
 mod filter;
 
 pub use buffer::OidnBuffer;
 pub use device::OidnDevice;
+pub use error::{OidnError, OidnErrorCode};
 pub use filter::OidnFilter;
@@ -0,0 +1,405 @@
+use anyhow::{bail, Context, Result};
+use ashtray::{utils, InstanceHandle};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+const USAGE: &str = "Usage: renderer-cli --scene <scene.ron> --output <out.png> \
+[--samples <count>] [--width <px>] [--height <px>] [--gpu <name|index|discrete> | --multi-gpu] \
+[--frames <start>..<end> [--fps <fps>] [--time-range <start>..<end>] [--ffmpeg <out.mp4>]]";
+
+// コマンドライン引数
+struct Args {
+    scene: String,
+    output: String,
+    samples: u32,
+    width: u32,
+    height: u32,
+    gpu: utils::PhysicalDevicePreference,
+    // trueの場合は使える全てのGPUで画面を分割してレンダリングする
+    multi_gpu: bool,
+    // 連番画像を出力するframeの範囲[start, end)。
+    // Someの場合はoutputの#の並びをzero paddingしたframe番号に置き換えたファイルに保存する。
+    frames: Option<(u32, u32)>,
+    fps: f32,
+    // framesの範囲に割り当てるanimationの時刻の範囲(秒)。
+    // Noneの場合はframe番号をfpsで割った時刻を使う。
+    time_range: Option<(f32, f32)>,
+    // Someの場合は連番画像をffmpegでこの動画ファイルにまとめる
+    ffmpeg: Option<String>,
+}
+
+// "start..end"の形式の範囲をparseする
+fn parse_range<T: std::str::FromStr>(value: &str) -> Option<(T, T)> {
+    let (start, end) = value.split_once("..")?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut scene = None;
+        let mut output = None;
+        let mut samples = 1024;
+        let mut width = 1920;
+        let mut height = 1080;
+        let mut gpu = utils::PhysicalDevicePreference::default();
+        let mut multi_gpu = false;
+        let mut frames = None;
+        let mut fps = 24.0;
+        let mut time_range = None;
+        let mut ffmpeg = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("Missing value for {arg}\n{USAGE}"))
+            };
+            match arg.as_str() {
+                "--scene" => scene = Some(value()?),
+                "--output" => output = Some(value()?),
+                "--samples" => samples = value()?.parse().context("Invalid --samples")?,
+                "--width" => width = value()?.parse().context("Invalid --width")?,
+                "--height" => height = value()?.parse().context("Invalid --height")?,
+                "--gpu" => gpu = value()?.as_str().into(),
+                "--multi-gpu" => multi_gpu = true,
+                "--frames" => {
+                    frames = Some(parse_range(&value()?).context("Invalid --frames")?);
+                }
+                "--fps" => fps = value()?.parse().context("Invalid --fps")?,
+                "--time-range" => {
+                    time_range = Some(parse_range(&value()?).context("Invalid --time-range")?);
+                }
+                "--ffmpeg" => ffmpeg = Some(value()?),
+                _ => bail!("Unknown argument: {arg}\n{USAGE}"),
+            }
+        }
+
+        let output = output.with_context(|| format!("--output is required\n{USAGE}"))?;
+        if let Some((start, end)) = frames {
+            if start >= end {
+                bail!("--frames must not be empty: {start}..{end}");
+            }
+            if !output.contains('#') {
+                bail!("--output must contain # for the frame number with --frames: {output}");
+            }
+        } else if time_range.is_some() || ffmpeg.is_some() {
+            bail!("--time-range and --ffmpeg require --frames\n{USAGE}");
+        }
+        if multi_gpu && frames.is_some() {
+            bail!("--multi-gpu does not support --frames\n{USAGE}");
+        }
+        if fps <= 0.0 {
+            bail!("--fps must be positive: {fps}");
+        }
+
+        Ok(Self {
+            scene: scene.with_context(|| format!("--scene is required\n{USAGE}"))?,
+            output,
+            samples,
+            width,
+            height,
+            gpu,
+            multi_gpu,
+            frames,
+            fps,
+            time_range,
+            ffmpeg,
+        })
+    }
+
+    // frameのanimationの時刻。
+    // time_rangeがある場合はframesの範囲を時刻の範囲に線形に割り当てる。
+    fn frame_time(&self, frame: u32) -> f32 {
+        match (self.frames, self.time_range) {
+            (Some((start, end)), Some((time_start, time_end))) => {
+                let t = (frame - start) as f32 / (end - start) as f32;
+                time_start + (time_end - time_start) * t
+            }
+            _ => frame as f32 / self.fps,
+        }
+    }
+}
+
+// outputを最初の#の並びの前、#の数、#の並びの後ろに分ける
+fn split_frame_pattern(output: &str) -> (&str, usize, &str) {
+    let start = output.find('#').unwrap_or(output.len());
+    let width = output[start..].chars().take_while(|&c| c == '#').count();
+    (&output[..start], width, &output[start + width..])
+}
+
+// outputの最初の#の並びを、その長さでzero paddingしたframe番号に置き換える
+fn frame_path(output: &str, frame: u32) -> String {
+    let (prefix, width, suffix) = split_frame_pattern(output);
+    format!("{prefix}{frame:0width$}{suffix}")
+}
+
+// ffmpegでframe_path(output, start)から始まる連番画像を動画にまとめる
+fn encode_video(args: &Args, video: &str, start: u32) -> Result<()> {
+    let (prefix, width, suffix) = split_frame_pattern(&args.output);
+    let pattern = format!("{prefix}%0{width}d{suffix}");
+    let status = std::process::Command::new("ffmpeg")
+        .args(["-y", "-framerate", &args.fps.to_string()])
+        .args(["-start_number", &start.to_string(), "-i", &pattern])
+        .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", video])
+        .status()
+        .context("Failed to run ffmpeg. Is it installed and on PATH?")?;
+    if !status.success() {
+        bail!("ffmpeg exited with {status}");
+    }
+    Ok(())
+}
+
+// Ctrl-Cで立てるレンダリングの中断フラグ。
+// シグナルハンドラからはロックを取らずに読めるOnceLockに置く。
+static CANCEL: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+// 1回目のCtrl-Cでは中断フラグを立ててレンダリングを打ち切り、途中までの画像を保存する。
+// 既にフラグが立っている状態で押された場合はfalseを返し、待たずに終了させる。
+fn request_cancel() -> bool {
+    CANCEL
+        .get()
+        .is_some_and(|cancel| !cancel.swap(true, Ordering::Relaxed))
+}
+
+#[cfg(unix)]
+extern "C" fn handle_sigint(_signal: libc::c_int) {
+    if !request_cancel() {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn handle_console_ctrl(
+    ctrl_type: u32,
+) -> windows_sys::Win32::Foundation::BOOL {
+    use windows_sys::Win32::System::Console::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
+    // FALSEを返すと既定のハンドラがプロセスを終了する
+    let handled = (ctrl_type == CTRL_C_EVENT || ctrl_type == CTRL_BREAK_EVENT) && request_cancel();
+    handled as windows_sys::Win32::Foundation::BOOL
+}
+
+// Ctrl-Cのハンドラを登録して、レンダリングの中断フラグを返す
+fn install_cancel_handler() -> Arc<AtomicBool> {
+    let cancel = CANCEL
+        .get_or_init(|| Arc::new(AtomicBool::new(false)))
+        .clone();
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    #[cfg(windows)]
+    unsafe {
+        windows_sys::Win32::System::Console::SetConsoleCtrlHandler(Some(handle_console_ctrl), 1);
+    }
+    cancel
+}
+
+// animation_timeの時点のsceneをargs.samplesに達するまでレンダリングする
+fn render_frame(
+    renderer: &mut renderer::Renderer,
+    args: &Args,
+    animation_time: f32,
+    cancel: &AtomicBool,
+) -> renderer::NextImage {
+    renderer.render(renderer::Parameters {
+        width: args.width,
+        height: args.height,
+        max_sample_count: args.samples,
+        animation_time,
+        ..Default::default()
+    });
+    let next_image = renderer.render_until(args.samples, cancel, |sample_count, _| {
+        eprint!("\rsamples: {sample_count}/{}", args.samples);
+        true
+    });
+    eprintln!();
+    next_image
+}
+
+// frameの範囲[start, end)をそれぞれのanimationの時刻でレンダリングして連番画像に保存する。
+// 既に出力ファイルがあるframeは飛ばすので、中断した後に同じコマンドで再開できる。
+// 中断した場合は途中のframeを保存せずに終了する。
+fn render_sequence(
+    renderer: &mut renderer::Renderer,
+    args: &Args,
+    start: u32,
+    end: u32,
+    cancel: &AtomicBool,
+) -> Result<()> {
+    let start_time = Instant::now();
+    for frame in start..end {
+        let path = frame_path(&args.output, frame);
+        if Path::new(&path).exists() {
+            println!("Skipping frame {frame}, {path} already exists");
+            continue;
+        }
+
+        let time = args.frame_time(frame);
+        eprintln!(
+            "frame {frame} ({}/{}), time {time:.3}s",
+            frame - start + 1,
+            end - start
+        );
+        render_frame(renderer, args, time, cancel);
+        if cancel.load(Ordering::Relaxed) {
+            eprintln!("Interrupted, frame {frame} is not saved");
+            return Ok(());
+        }
+        renderer
+            .save_image(&path)
+            .with_context(|| format!("Failed to save image: {path}"))?;
+        println!("Saved to {path}");
+    }
+    println!(
+        "Rendered frames {start}..{end} in {:.2}s",
+        start_time.elapsed().as_secs_f64()
+    );
+
+    if let Some(video) = &args.ffmpeg {
+        encode_video(args, video, start)?;
+        println!("Encoded to {video}");
+    }
+    Ok(())
+}
+
+// 使える全てのGPUで画面を分割して1枚の画像をレンダリングして保存する。
+fn render_multi_gpu(args: &Args, scene: &renderer::Scene) -> Result<()> {
+    let instance = InstanceHandle::new_headless();
+    let required_device_extensions = utils::get_required_headless_device_extensions(&[]);
+    let devices =
+        utils::enumerate_suitable_headless_devices(&instance, &required_device_extensions);
+    if devices.is_empty() {
+        bail!("No suitable GPU found");
+    }
+    for (_, name, _) in &devices {
+        eprintln!("Using GPU: {name}");
+    }
+    let physical_devices = devices
+        .iter()
+        .map(|(physical_device, _, _)| *physical_device)
+        .collect::<Vec<_>>();
+
+    let mut renderer =
+        renderer::MultiGpuRenderer::new(&instance, &physical_devices, args.width, args.height);
+    renderer.load_scene(scene)?;
+
+    let cancel = install_cancel_handler();
+    let start_time = Instant::now();
+    let parameters = renderer::Parameters {
+        width: args.width,
+        height: args.height,
+        max_sample_count: args.samples,
+        ..Default::default()
+    };
+    let image = renderer.render_until(&parameters, args.samples, &cancel)?;
+    if cancel.load(Ordering::Relaxed) {
+        eprintln!("Interrupted, saving the partial image");
+    }
+    image
+        .save(&args.output)
+        .with_context(|| format!("Failed to save image: {}", args.output))?;
+
+    for (i, (y, rows)) in renderer.bands().into_iter().enumerate() {
+        eprintln!("GPU {i}: rows {y}..{}", y + rows);
+    }
+    println!(
+        "Rendered {} samples on {} GPUs in {:.2}s",
+        args.samples,
+        renderer.gpu_count(),
+        start_time.elapsed().as_secs_f64()
+    );
+    println!("Saved to {}", args.output);
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse()?;
+
+    // load scene file
+    let scene = renderer::Scene::load_from_file(&args.scene)
+        .with_context(|| format!("Failed to load scene: {}", args.scene))?;
+    if let Err(errors) = scene.validate() {
+        let messages = errors
+            .iter()
+            .map(|error| format!("  {error}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        bail!("Invalid scene: {}\n{messages}", args.scene);
+    }
+
+    if args.multi_gpu {
+        return render_multi_gpu(&args, &scene);
+    }
+
+    // create vulkan stuffs
+    let instance = InstanceHandle::new_headless();
+    let required_device_extensions = utils::get_required_headless_device_extensions(&[]);
+    let physical_device =
+        utils::select_headless_physical_device(&instance, &required_device_extensions, &args.gpu);
+    let queue_indices = utils::get_headless_queue_indices(&instance, physical_device);
+    let device = utils::create_device(
+        &instance,
+        physical_device,
+        &queue_indices,
+        &required_device_extensions,
+    );
+    let queue_handles = utils::get_queue_handles(&device, &queue_indices);
+    let command_pool = utils::create_graphics_command_pool(&device, &queue_handles);
+    let allocator = utils::create_allocator(&instance, physical_device, &device);
+    let pipeline_cache = device.create_pipeline_cache(None);
+
+    // create renderer
+    let mut renderer = renderer::Renderer::new(
+        args.width,
+        args.height,
+        instance.clone(),
+        physical_device,
+        device.clone(),
+        queue_handles.clone(),
+        command_pool.clone(),
+        allocator.clone(),
+        pipeline_cache,
+    );
+    renderer.load_scene(&scene)?;
+
+    let cancel = install_cancel_handler();
+    if let Some((start, end)) = args.frames {
+        render_sequence(&mut renderer, &args, start, end, &cancel)?;
+        device.wait_idle();
+        return Ok(());
+    }
+
+    // render
+    let start_time = Instant::now();
+    let next_image = render_frame(&mut renderer, &args, 0.0, &cancel);
+    if cancel.load(Ordering::Relaxed) {
+        eprintln!(
+            "Interrupted, saving the partial image with {} samples",
+            next_image.sample_count
+        );
+    }
+
+    // save image
+    renderer
+        .save_image(&args.output)
+        .with_context(|| format!("Failed to save image: {}", args.output))?;
+    let total_time = start_time.elapsed();
+
+    let rendering_secs = next_image.rendering_time.as_secs_f64();
+    println!(
+        "Rendered {} samples in {:.2}s ({:.2} samples/sec), total {:.2}s",
+        next_image.sample_count,
+        rendering_secs,
+        next_image.sample_count as f64 / rendering_secs.max(f64::EPSILON),
+        total_time.as_secs_f64(),
+    );
+    println!("Saved to {}", args.output);
+
+    device.wait_idle();
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 REM shader compile script
 
 glslc.exe src/shaders/src/entry/raygen.rgen -O --target-env=vulkan1.2 -o src/shaders/spv/raygen.rgen.spv
+glslc.exe src/shaders/src/entry/raygen.rgen -DRAY_MOTION_BLUR -O --target-env=vulkan1.2 -o src/shaders/spv/raygen_motion.rgen.spv
 
 glslc.exe src/shaders/src/entry/material/closesthit.rchit -O --target-env=vulkan1.2 -o src/shaders/spv/material/closesthit.rchit.spv
 glslc.exe src/shaders/src/entry/material/anyhit.rahit -O --target-env=vulkan1.2 -o src/shaders/spv/material/anyhit.rahit.spv
@@ -11,9 +12,13 @@ glslc.exe src/shaders/src/entry/shadow/anyhit_alpha_blend.rahit -O --target-env=
 glslc.exe src/shaders/src/entry/shadow/anyhit_alpha_mask.rahit -O --target-env=vulkan1.2 -o src/shaders/spv/shadow/anyhit_alpha_mask.rahit.spv
 glslc.exe src/shaders/src/entry/shadow/miss.rmiss -O --target-env=vulkan1.2 -o src/shaders/spv/shadow/miss.rmiss.spv
 
+glslc.exe src/shaders/src/entry/sphere/closesthit.rchit -O --target-env=vulkan1.2 -o src/shaders/spv/sphere/closesthit.rchit.spv
+glslc.exe src/shaders/src/entry/sphere/intersection.rint -O --target-env=vulkan1.2 -o src/shaders/spv/sphere/intersection.rint.spv
+
 glslc.exe src/shaders/src/entry/resolve.comp -O --target-env=vulkan1.2 -o src/shaders/spv/resolve.comp.spv
 
-glslc.exe src/shaders/src/entry/before_denoise.comp -O --target-env=vulkan1.2 -o src/shaders/spv/before_denoise.comp.spv
-glslc.exe src/shaders/src/entry/after_denoise.comp -O --target-env=vulkan1.2 -o src/shaders/spv/after_denoise.comp.spv
+glslc.exe src/shaders/src/entry/a_trous.comp -O --target-env=vulkan1.2 -o src/shaders/spv/a_trous.comp.spv
 
 glslc.exe src/shaders/src/entry/output.comp -O --target-env=vulkan1.2 -o src/shaders/spv/output.comp.spv
+
+glslc.exe src/shaders/src/entry/skinning.comp -O --target-env=vulkan1.2 -o src/shaders/spv/skinning.comp.spv
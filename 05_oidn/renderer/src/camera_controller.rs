@@ -0,0 +1,220 @@
+//! マウスやキーボードの移動量からカメラの位置と向きを計算するCameraControllerを定義する。
+
+use crate::{CameraProjection, Parameters};
+
+// CameraControllerを作るときのカメラからtargetまでの距離
+const DEFAULT_TARGET_DISTANCE: f32 = 5.0;
+// orbitのdollyでtargetに近づける最小の距離
+const MIN_TARGET_DISTANCE: f32 = 1e-3;
+
+/// CameraControllerの操作方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CameraMode {
+    /// targetの点を中心にカメラを回転させ、dollyでtargetに近づいたり離れたりする。
+    #[default]
+    Orbit,
+    /// カメラの位置で向きを変え、dollyで視線方向に沿って移動する。
+    Fly,
+}
+
+/// マウスやキーボードの移動量からカメラの位置と向きを計算し、Parametersに書き込む。
+///
+/// 向きはParametersと同じくYXZの順のオイラー角(degree)で持つ。
+/// Fly modeでもカメラの前方にtargetを持ち、panとdollyの移動量はtargetまでの距離に比例する。
+/// Parametersのカメラが変わるとRendererは蓄積をリセットするので、
+/// 動かすたびにサンプリングがやり直される。
+#[derive(Debug, Clone)]
+pub struct CameraController {
+    pub mode: CameraMode,
+    /// orbitで1ピクセルあたりに回転する角度(degree)。
+    pub rotate_speed: f32,
+    /// panで1ピクセルあたりに動かす距離の、targetまでの距離に対する割合。
+    pub pan_speed: f32,
+    /// dollyで1単位あたりに動かす距離の、targetまでの距離に対する割合。
+    pub dolly_speed: f32,
+    position: glam::Vec3,
+    rotate_x: f32,
+    rotate_y: f32,
+    rotate_z: f32,
+    fov: f32,
+    target: glam::Vec3,
+}
+impl Default for CameraController {
+    fn default() -> Self {
+        Self::from_parameters(&Parameters::default())
+    }
+}
+impl CameraController {
+    /// Parametersのカメラの位置と向きとfovから作成する。
+    /// targetはカメラの前方のDEFAULT_TARGET_DISTANCEの位置に置く。
+    pub fn from_parameters(parameters: &Parameters) -> Self {
+        let fov = match parameters.camera_projection {
+            CameraProjection::Perspective { fov } => fov,
+            _ => 60.0,
+        };
+        let mut controller = Self {
+            mode: CameraMode::default(),
+            rotate_speed: 0.2,
+            pan_speed: 0.002,
+            dolly_speed: 0.002,
+            position: glam::Vec3::ZERO,
+            rotate_x: 0.0,
+            rotate_y: 0.0,
+            rotate_z: 0.0,
+            fov,
+            target: glam::Vec3::ZERO,
+        };
+        controller.target = controller.forward() * DEFAULT_TARGET_DISTANCE;
+        controller.set_camera(
+            glam::vec3(
+                parameters.position_x,
+                parameters.position_y,
+                parameters.position_z,
+            ),
+            glam::vec3(
+                parameters.rotate_x,
+                parameters.rotate_y,
+                parameters.rotate_z,
+            ),
+        );
+        controller
+    }
+
+    /// カメラの位置と向き(degree)を直接設定する。
+    /// targetまでの距離はそのままに、targetを新しいカメラの前方に動かす。
+    pub fn set_camera(&mut self, position: glam::Vec3, rotation: glam::Vec3) {
+        let distance = self.distance();
+        self.position = position;
+        self.rotate_x = rotation.x;
+        self.rotate_y = rotation.y;
+        self.rotate_z = rotation.z;
+        self.target = self.position + self.forward() * distance;
+    }
+
+    /// 透視投影の垂直方向の画角(degree)を設定する。
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov;
+    }
+
+    /// Orbit modeで回転の中心にする点を設定し、カメラをその点に向ける。
+    /// カメラの位置と同じ点の場合は何もしない。
+    pub fn set_target(&mut self, point: glam::Vec3) {
+        let Some(forward) = (point - self.position).try_normalize() else {
+            return;
+        };
+        self.target = point;
+        self.rotate_x = forward.y.clamp(-1.0, 1.0).asin().to_degrees();
+        self.rotate_y = (-forward.x).atan2(-forward.z).to_degrees();
+    }
+
+    /// マウスの移動量(ピクセル)でカメラを回転させる。
+    /// Orbitではtargetを中心にカメラを回し、Flyではカメラの位置で向きだけを変える。
+    pub fn orbit(&mut self, dx: f32, dy: f32) {
+        let distance = self.distance();
+        self.rotate_y = (self.rotate_y + dx * self.rotate_speed + 180.0).rem_euclid(360.0) - 180.0;
+        self.rotate_x = (self.rotate_x + dy * self.rotate_speed).clamp(-90.0, 90.0);
+        match self.mode {
+            CameraMode::Orbit => self.position = self.target - self.forward() * distance,
+            CameraMode::Fly => self.target = self.position + self.forward() * distance,
+        }
+    }
+
+    /// マウスの移動量(ピクセル)でカメラとtargetを画面に平行に動かす。
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let rotate = self.rotate();
+        let right = rotate.transform_vector3(glam::Vec3::X);
+        let up = rotate.transform_vector3(glam::Vec3::Y);
+        let offset = (-right * dx + up * dy) * self.pan_speed * self.distance();
+        self.position += offset;
+        self.target += offset;
+    }
+
+    /// ホイールなどの移動量で前後に動かす。正の値で前に進む。
+    /// Orbitではtargetに近づき、Flyではtargetと一緒に視線方向に移動する。
+    pub fn dolly(&mut self, dz: f32) {
+        let distance = self.distance();
+        match self.mode {
+            CameraMode::Orbit => {
+                let distance = (distance * (-dz * self.dolly_speed).exp()).max(MIN_TARGET_DISTANCE);
+                self.position = self.target - self.forward() * distance;
+            }
+            CameraMode::Fly => {
+                let offset = self.forward() * dz * self.dolly_speed * distance;
+                self.position += offset;
+                self.target += offset;
+            }
+        }
+    }
+
+    /// カメラの位置
+    pub fn position(&self) -> glam::Vec3 {
+        self.position
+    }
+
+    /// カメラの向きのオイラー角(degree)。Parametersのrotate_x、rotate_y、rotate_zの順。
+    pub fn rotation(&self) -> glam::Vec3 {
+        glam::vec3(self.rotate_x, self.rotate_y, self.rotate_z)
+    }
+
+    /// Orbit modeで回転の中心にする点
+    pub fn target(&self) -> glam::Vec3 {
+        self.target
+    }
+
+    /// 透視投影の垂直方向の画角(degree)
+    pub fn fov(&self) -> f32 {
+        self.fov
+    }
+
+    /// カメラの位置と向きをParametersに書き込む。
+    /// 透視投影の場合はfovも書き込む。Parametersのカメラが変わった場合はtrueを返す。
+    pub fn apply(&self, parameters: &mut Parameters) -> bool {
+        let before = (
+            parameters.position_x,
+            parameters.position_y,
+            parameters.position_z,
+            parameters.rotate_x,
+            parameters.rotate_y,
+            parameters.rotate_z,
+            parameters.camera_projection,
+        );
+        parameters.position_x = self.position.x;
+        parameters.position_y = self.position.y;
+        parameters.position_z = self.position.z;
+        parameters.rotate_x = self.rotate_x;
+        parameters.rotate_y = self.rotate_y;
+        parameters.rotate_z = self.rotate_z;
+        if let CameraProjection::Perspective { fov } = &mut parameters.camera_projection {
+            *fov = self.fov;
+        }
+        before
+            != (
+                parameters.position_x,
+                parameters.position_y,
+                parameters.position_z,
+                parameters.rotate_x,
+                parameters.rotate_y,
+                parameters.rotate_z,
+                parameters.camera_projection,
+            )
+    }
+
+    fn rotate(&self) -> glam::Mat4 {
+        glam::Mat4::from_euler(
+            glam::EulerRot::YXZ,
+            self.rotate_y.to_radians(),
+            self.rotate_x.to_radians(),
+            self.rotate_z.to_radians(),
+        )
+    }
+
+    fn forward(&self) -> glam::Vec3 {
+        self.rotate().transform_vector3(glam::Vec3::NEG_Z)
+    }
+
+    fn distance(&self) -> f32 {
+        (self.target - self.position)
+            .length()
+            .max(MIN_TARGET_DISTANCE)
+    }
+}
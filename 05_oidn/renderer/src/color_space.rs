@@ -0,0 +1,54 @@
+//! レンダリングの作業色空間(working color space)の変換行列を定義する。
+//!
+//! glTFのテクスチャや色、skyのテクスチャはlinear sRGBとして読み込んでいるので、
+//! ACEScgで計算する場合はshaderでこれらの色をACEScgに変換してから使う。
+//! 出力時にはACEScgのままACESのRRT+ODTの近似をかけてsRGBのディスプレイ向けにする。
+//!
+//! 変換行列はlinear sRGB(Rec.709の原色、D65)とACEScg(AP1の原色、ACESの白色点≒D60)の間で、
+//! 白色点の違いをBradfordの色順応で合わせたもの。
+//! shaders/src/modules/color_space.glslの行列と値を揃えること。
+
+/// linear sRGB(D65)からACEScg(D60)への変換行列。
+///
+/// ```text
+/// | 0.6130974 0.3395231 0.0473794 |
+/// | 0.0701937 0.9163539 0.0134523 |
+/// | 0.0206156 0.1095698 0.8698151 |
+/// ```
+pub const LINEAR_SRGB_TO_ACESCG: glam::Mat3 = glam::Mat3::from_cols_array(&[
+    0.6130974, 0.0701937, 0.0206156, //
+    0.3395231, 0.9163539, 0.1095698, //
+    0.0473794, 0.0134523, 0.8698151,
+]);
+
+/// ACEScg(D60)からlinear sRGB(D65)への変換行列。LINEAR_SRGB_TO_ACESCGの逆行列。
+///
+/// ```text
+/// |  1.7050510 -0.6217921 -0.0832589 |
+/// | -0.1302564  1.1408047 -0.0105483 |
+/// | -0.0240033 -0.1289690  1.1529723 |
+/// ```
+pub const ACESCG_TO_LINEAR_SRGB: glam::Mat3 = glam::Mat3::from_cols_array(&[
+    1.705051, -0.1302564, -0.0240033, //
+    -0.6217921, 1.1408047, -0.128969, //
+    -0.0832589, -0.0105483, 1.1529723,
+]);
+
+impl crate::WorkingColorSpace {
+    /// linear sRGBの色を作業色空間の色に変換する。
+    pub fn from_linear_srgb(self, color: glam::Vec3) -> glam::Vec3 {
+        match self {
+            Self::LinearSrgb => color,
+            Self::AcesCg => LINEAR_SRGB_TO_ACESCG * color,
+        }
+    }
+
+    /// linear sRGB上の色の変換行列を、作業色空間の色に対して掛ける行列にする。
+    /// ホワイトバランスの行列など、linear sRGBで定義した行列を作業色空間で使うときに使う。
+    pub fn matrix_from_linear_srgb(self, matrix: glam::Mat3) -> glam::Mat3 {
+        match self {
+            Self::LinearSrgb => matrix,
+            Self::AcesCg => LINEAR_SRGB_TO_ACESCG * matrix * ACESCG_TO_LINEAR_SRGB,
+        }
+    }
+}
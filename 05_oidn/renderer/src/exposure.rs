@@ -0,0 +1,90 @@
+//! 物理カメラのパラメータ(絞り、シャッタースピード、ISO感度)から
+//! 出力時に掛ける線形の露出倍率を計算するヘルパー関数を定義する。
+//! また、輝度のhistogramから自動露出のEV100を計算するヘルパー関数も定義する。
+
+/// 絞り(F値)、シャッタースピード(秒)、ISO感度からEV100を計算する。
+///
+/// EV100 = log2(N^2 / t * 100 / ISO)
+pub fn ev100_from_physical(aperture: f32, shutter_speed: f32, iso: f32) -> f32 {
+    (aperture * aperture / shutter_speed * 100.0 / iso).log2()
+}
+
+/// EV100から放射輝度に掛ける線形の露出倍率を計算する。
+///
+/// 反射式露出計の式から平均輝度を L_avg = 1000 / 65 * 2^EV100 / 100 とし、
+/// それが18%グレーになるように 0.18 / L_avg を露出倍率とする。
+pub fn exposure_multiplier(ev100: f32) -> f32 {
+    let l_avg = 1000.0 / 65.0 * ev100.exp2() / 100.0;
+    0.18 / l_avg
+}
+
+/// Renderer::read_luminance_histogramで読み戻すhistogramのbinの数。
+/// bin 0は輝度が2^LUMINANCE_HISTOGRAM_MIN_LOG2未満の黒とみなすpixelの数で、
+/// 残りのbinにlog2輝度の範囲を均等に割り当てる。
+///
+/// resolve.compのLUMINANCE_HISTOGRAM_*と値を揃えること。
+pub const LUMINANCE_HISTOGRAM_BIN_COUNT: usize = 256;
+/// histogramのbin 1の下端のlog2輝度。
+pub const LUMINANCE_HISTOGRAM_MIN_LOG2: f32 = -12.0;
+/// histogramのbin 1からbin 255までが覆うlog2輝度の幅。範囲外の輝度は端のbinに入る。
+pub const LUMINANCE_HISTOGRAM_LOG2_RANGE: f32 = 32.0;
+
+// histogramのbinの中心のlog2輝度
+fn luminance_histogram_bin_log2(bin: usize) -> f32 {
+    let t = (bin as f32 - 0.5) / (LUMINANCE_HISTOGRAM_BIN_COUNT - 2) as f32;
+    LUMINANCE_HISTOGRAM_MIN_LOG2 + t * LUMINANCE_HISTOGRAM_LOG2_RANGE
+}
+
+/// 輝度のhistogramから、黒のpixelを除いたlog2輝度の平均を計算する。
+///
+/// 黒以外のpixelがない場合はNoneを返す。
+pub fn average_log2_luminance(histogram: &[u32]) -> Option<f32> {
+    let (sum, count) =
+        histogram
+            .iter()
+            .enumerate()
+            .skip(1)
+            .fold((0.0, 0u64), |(sum, count), (bin, &n)| {
+                (
+                    sum + luminance_histogram_bin_log2(bin) as f64 * n as f64,
+                    count + n as u64,
+                )
+            });
+    (count > 0).then(|| (sum / count as f64) as f32)
+}
+
+/// 輝度のhistogramから、黒のpixelを除いたlog2輝度の中央値を計算する。
+///
+/// 黒以外のpixelがない場合はNoneを返す。
+pub fn median_log2_luminance(histogram: &[u32]) -> Option<f32> {
+    let count = histogram.iter().skip(1).map(|&n| n as u64).sum::<u64>();
+    if count == 0 {
+        return None;
+    }
+    let mut cumulative = 0;
+    for (bin, &n) in histogram.iter().enumerate().skip(1) {
+        cumulative += n as u64;
+        if cumulative * 2 >= count {
+            return Some(luminance_histogram_bin_log2(bin));
+        }
+    }
+    None
+}
+
+/// 平均輝度が18%グレーになるEV100を計算する。exposure_multiplierの逆関数。
+pub fn ev100_from_average_luminance(l_avg: f32) -> f32 {
+    (l_avg * 100.0 * 65.0 / 1000.0).log2()
+}
+
+/// 輝度のhistogramから、log2輝度の平均(輝度の幾何平均)が18%グレーになるEV100を計算する。
+///
+/// 真っ黒なフレームなど黒以外のpixelがない場合は、露出倍率が1になるEV100を返す。
+pub fn auto_exposure_ev100(histogram: &[u32]) -> f32 {
+    let l_avg = average_log2_luminance(histogram).map_or(0.18, f32::exp2);
+    ev100_from_average_luminance(l_avg)
+}
+
+/// 絞り(F値)とシャッタースピード(秒)を固定して、EV100になるISO感度を計算する。
+pub fn iso_from_ev100(ev100: f32, aperture: f32, shutter_speed: f32) -> f32 {
+    aperture * aperture / shutter_speed * 100.0 / ev100.exp2()
+}
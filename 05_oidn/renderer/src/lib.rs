@@ -1,9 +1,18 @@
 use std::time::Duration;
 
+mod camera_controller;
+pub use camera_controller::{CameraController, CameraMode};
+pub mod color_space;
+pub mod exposure;
+mod multi_gpu;
+pub use multi_gpu::MultiGpuRenderer;
+mod point_cloud;
+pub use point_cloud::PointCloud;
 mod renderer;
-pub use renderer::Renderer;
+pub use renderer::{Renderer, RendererDesc};
 mod scene;
 pub use scene::*;
+pub mod white_balance;
 
 pub struct NextImage {
     pub image_view: ashtray::ImageViewHandle,
@@ -12,41 +21,390 @@ pub struct NextImage {
     pub rendering_time: Duration,
 }
 
+/// 蓄積が止まった理由。`Renderer::accumulation_limit`で取得する。
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccumulationLimit {
+    /// `Parameters::max_sample_count`に達した。
+    SampleCount,
+    /// `Parameters::max_render_seconds`の時間が経過した。
+    RenderTime,
+}
+
+/// Renderer::benchmarkでサンプリングを打ち切る条件。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchmarkMode {
+    /// 指定したサンプル数に達するまでサンプリングする。
+    EqualSamples(u32),
+    /// 指定した時間が経過するまでサンプリングする。
+    /// 経過時間はサンプルごとに確認するので、1サンプル分だけ超えることがある。
+    EqualTime(Duration),
+}
+
+/// Renderer::ray_statsで読み戻す、1サンプルでtraceしたレイの統計。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RayStats {
+    /// traceしたレイの数。shadow rayも含む。
+    pub traced: u32,
+    /// 何かにhitしたレイの数。shadow rayは遮蔽物に当たった場合にhitとする。
+    pub hits: u32,
+    /// 何にもhitしなかったレイの数。
+    pub misses: u32,
+}
+
+/// Renderer::blas_statsで返す、sceneの読み込みでビルドしたBLASの統計。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlasStats {
+    /// 実際にビルドしたBLASの数。
+    pub built: u32,
+    /// vertexとindexが既にビルドしたmeshと完全に一致したので、BLASを共有したmeshの数。
+    pub deduplicated: u32,
+}
+
+/// Renderer::benchmarkの結果。
+#[derive(Debug, Clone)]
+pub struct BenchmarkResult {
+    /// 露出補正やトーンマッピングをかける前のリニアな放射輝度のRGB。
+    /// 左上から行優先で1pixelあたり3つのf32が並ぶ。
+    pub image: Vec<f32>,
+    /// 完了したサンプル数。
+    pub sample_count: u32,
+    /// scene読み込み後からサンプリングが完了するまでの時間。
+    pub elapsed: Duration,
+    /// referenceを与えた場合のreferenceとの誤差。
+    pub error: Option<BenchmarkError>,
+}
+
+/// BenchmarkResult::imageとreferenceの画像のRGBの全チャンネルでの誤差。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkError {
+    pub mse: f64,
+    pub rmse: f64,
+}
+
+/// DisplayImage::Finalで表示する画像のdenoiseに使うdenoiser。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Denoiser {
+    /// denoiseせずにresolveした画像をそのまま使う。
+    None,
+    /// OIDNでdenoiseする。OIDNが使えない環境ではNoneと同じになる。
+    Oidn,
+    /// GPUのcompute shaderでedge-avoidingなÀ-Trous waveletのfilterを掛ける。
+    /// albedo、normal、depthのAOVを境界の判定に使うので、平坦な領域はぼかしつつ境界は残る。
+    /// OIDNほど綺麗にはならないが、OIDNなしで動き、プレビューには十分な速さで動く。
+    /// 強さはParametersの`a_trous_*`で調整する。
+    ATrous,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum DisplayImage {
     BaseColor,
+    /// 最初にhitした面のnormal mapを適用したworld spaceのshading normalを表示する。
+    /// 法線はレイの来た側を向いていて、各成分を`n * 0.5 + 0.5`で[0, 1]にして色とする。
+    /// OIDNに渡すnormalはこれを`[-1, 1]`に戻したもの。
     Normal,
     Resolved,
     Final,
+    /// 各pixelから出たレイが終了するまでのbounce数の平均をカラーマップで表示する。
+    BounceCount,
+    /// 最初にhitした面のnormal mapを適用する前のworld spaceの面の法線を表示する。
+    /// エンコードは`Normal`と同じで、レイの来た側を向いた法線を`n * 0.5 + 0.5`で色とする。
+    GeometricNormal,
+    /// カメラレイが最初にhitしたinstanceのindexをinstanceごとに異なる色で表示する。
+    /// indexはsceneのinstanceの順番で、何にもhitしなかったpixelは黒になる。
+    /// 値そのものは`Renderer::read_instance_ids`で読み戻せる。
+    InstanceId,
+    /// カメラレイが最初にhitした位置のview spaceでの線形なdepthを表示する。
+    /// depthはNDCではなく、カメラの位置(平行投影ではカメラの平面)から
+    /// カメラの前方向(-Z)に沿って測った距離。
+    /// `Parameters::aov_far_plane`で割って、近いほど白く遠いほど黒く表示する。
+    /// 値そのものは`Renderer::read_depth`で読み戻せる。
+    Depth,
+    /// カメラレイが最初にhitした位置のworld spaceでの座標を表示する。
+    /// `[-aov_far_plane, aov_far_plane]`の範囲を各成分`[0, 1]`にして色とする。
+    /// 値そのものは`Renderer::read_positions`で読み戻せる。
+    Position,
+    /// カメラレイが最初にhitした位置のambient occlusionをグレースケールで表示する。
+    /// 面の法線側の半球に`Parameters::ao_radius`の長さの短いレイを飛ばし、
+    /// 遮蔽されなかった割合を白(遮蔽なし)から黒(完全に遮蔽)で表示する。
+    /// 何にもhitしなかったpixelは白になる。
+    AmbientOcclusion,
+    /// traversalのコストの目安として、各pixelのレイで呼ばれたintersection shaderと
+    /// any hit shaderの回数の1サンプルあたりの平均をカラーマップで表示する。
+    /// ハードウェアのtraversalはBVHのノードの訪問回数を取れないので、その代わりに数える。
+    /// 不透明な三角形ではどちらのshaderも呼ばれないので、点群の球とalpha mask、
+    /// alpha blendの面が重なっているところほど赤くなる。
+    /// `Parameters::traversal_heatmap_scale`回で最大の色になる。
+    TraversalHeatmap,
 }
 
-#[derive(Debug, Clone)]
+/// Renderer::screenshot_hdrで出力するOpenEXRのチャンネルの精度。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum HdrPrecision {
+    /// 16bitの半精度浮動小数点数。
+    #[default]
+    Half,
+    /// 32bitの単精度浮動小数点数。
+    /// resolved imageは半精度なので精度は上がらないが、32bit floatを要求するツール向け。
+    Full,
+}
+
+/// レンダリングの計算を行う色空間(working color space)。
+/// 変換に使う行列は`color_space`モジュールを参照。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum WorkingColorSpace {
+    /// glTFやskyのテクスチャの色をそのままlinear sRGBとして計算する。
+    #[default]
+    LinearSrgb,
+    /// glTFやskyのテクスチャの色、太陽の色、背景色をACEScgに変換して計算する。
+    /// トーンマッピングを行う場合はReinhardの代わりにACESのRRT+ODTの近似でsRGBのディスプレイ向けにする。
+    /// トーンマッピングを行わない場合はlinear sRGBに戻して出力する。
+    /// 蓄積画像やdenoiserのalbedoなど途中の画像はACEScgのままになる。
+    AcesCg,
+}
+
+/// レイがhitした三角形を面の向きでカリングするモード。
+/// glTFと同じく、反時計回りに見える側を表面とする。
+///
+/// 両面(double sided)のmaterialと透過するmaterialはどのモードでもカリングしない。
+/// 片面のmaterialの裏面はカリングしない場合も黒い裏面としてhitするので、
+/// Backにすると裏面が見えなくなり、閉じたmeshの内側にhitする自己交差も無くなる。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum CullMode {
+    /// カリングしない。
+    #[default]
+    None,
+    /// 片面のmaterialの裏面をカリングする。
+    Back,
+    /// 片面のmaterialの表面をカリングする。
+    Front,
+}
+
+/// HDRのディスプレイに表示する場合の出力画像のエンコード。
+///
+/// Sdr以外ではトーンマッピングと伝達関数をかけずに、露出補正した値の1.0をSDRの基準の白(203nits)として
+/// swapchainのcolor spaceに合わせてエンコードし、output imageはR16G16B16A16_SFLOATになる。
+/// 明るい発光面はHDRのディスプレイの明るさで表示される。
+/// AOVなどのdebug用の表示はsRGBの色を基準の白の明るさでエンコードする。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum HdrOutput {
+    /// トーンマッピングしてSDRで出力する。
+    #[default]
+    Sdr,
+    /// EXTENDED_SRGB_LINEARのswapchain向けに、1.0が80nitsのlinear sRGBで出力する。
+    ScRgb,
+    /// HDR10_ST2084のswapchain向けに、Rec.2020の色域にPQの伝達関数をかけて出力する。
+    Hdr10Pq,
+}
+impl HdrOutput {
+    /// swapchainのcolor spaceに対応するエンコード。HDRのcolor spaceでなければSdrになる。
+    pub fn from_color_space(color_space: ash::vk::ColorSpaceKHR) -> Self {
+        match color_space {
+            ash::vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => HdrOutput::ScRgb,
+            ash::vk::ColorSpaceKHR::HDR10_ST2084_EXT => HdrOutput::Hdr10Pq,
+            _ => HdrOutput::Sdr,
+        }
+    }
+}
+
+/// 出力画像にかける伝達関数(transfer function)。
+/// トーンマッピングした後の[0, 1]の色にかける。
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum DisplayTransfer {
+    /// 1 / gammaのべき乗。
+    Gamma(f32),
+    /// sRGBの区分的な伝達関数。暗部では線形になるので、gamma 2.2とは暗部の色が異なる。
+    #[default]
+    Srgb,
+    /// 伝達関数をかけずにlinearのまま出力する。外部の表示パイプラインに渡す場合に使う。
+    Linear,
+}
+
+/// 画像を計算するintegratorの種類。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum Integrator {
+    /// カメラからパスを伸ばし、NEEとBSDFのサンプリングをMISで組み合わせるpath tracer。
+    #[default]
+    PathTracer,
+    /// 発光面からパスを伸ばし、各頂点をカメラにつないでフィルムにsplatするlight tracer。
+    /// 点光源に近い小さな発光面からのcausticsのように、path tracerでは収束の遅い光の経路に向く。
+    /// 発光面のみを光源とするのでsky、太陽、背景色の寄与は含まない。
+    /// カメラは透視投影のみに対応し、レンズの歪みは無視する。
+    LightTracer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum CameraProjection {
+    /// fovは垂直方向の画角(degree)。
+    Perspective { fov: f32 },
+    /// heightは画面の垂直方向に写るworld spaceでの高さ。
+    Orthographic { height: f32 },
+    /// カメラの位置から全天球を正距円筒図法(lat-long)で写す。fovは使わない。
+    /// 画像は横:縦 = 2:1である必要があり、そうでない場合は警告を出す。
+    ///
+    /// 画像の上端がカメラの上方向(+Y)、下端が下方向(-Y)で、縦方向に緯度が線形に変化する。
+    /// 横方向はカメラ空間の方向(x, z)について`atan2(x, z)`を左端の0から右端の2πまで線形にとる。
+    /// つまり画像の中心がカメラの前方向(-Z)、左右の端がカメラの後ろ方向(+Z)になる。
+    /// これはskyのテクスチャの参照と同じ規約なので、カメラを回転させずにレンダリングした画像は
+    /// そのままskyのテクスチャとして使える。
+    Equirectangular,
+}
+
+/// レンダリングのパラメータ。
+///
+/// `save_preset`と`load_preset`でRON形式のpresetファイルに保存、読み込みできる。
+/// PartialEqで比較しないフィールドも含めて全てのフィールドを保存する。
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct Parameters {
     pub width: u32,
     pub height: u32,
     pub max_sample_count: u32,
+    /// Someの場合は蓄積を始めてからこの秒数が経過したら、max_sample_countに達していなくても蓄積を止める。
+    /// 経過時間はサンプルのtraceの間で確認するので、実行中のtraceは中断しない。
+    /// 蓄積をやり直すと経過時間も0に戻る。
+    pub max_render_seconds: Option<f32>,
     pub display_image: DisplayImage,
     pub denoise_every_sample: bool,
+    pub denoise_strength: f32,
+    /// trueの場合はdenoiseの前にalbedoとnormalをそれぞれOIDNでprefilterしてから、
+    /// それらをノイズのないものとして扱ってcolorをdenoiseする。
+    /// エッジが綺麗に残るようになるが、denoiseにかかる時間が増える。
+    pub prefilter_aux: bool,
+    pub denoiser: Denoiser,
+    /// Denoiser::ATrousでfilterを掛ける回数。
+    /// i回目(0始まり)はstep幅2^iで間隔を空けた5x5のpixelを平均するので、
+    /// n回掛けると一辺が`4 * (2^n - 1) + 1`pixelの範囲をぼかす。
+    /// 回数を増やすほど低周波のノイズまで消えるが、その分だけpass数が増える。
+    pub a_trous_iterations: u32,
+    /// Denoiser::ATrousの色の差に対するsigma。
+    /// 差は明るい方の輝度で割った相対的な値で比較するので、露出に依存しない。
+    /// 大きいほど強くぼかす。ノイズは回数を重ねるごとに減るので、i回目は`2^-i`倍にして使う。
+    pub a_trous_sigma_color: f32,
+    /// Denoiser::ATrousのalbedoの差に対するsigma。小さいほどテクスチャの模様の境界が残る。
+    pub a_trous_sigma_albedo: f32,
+    /// Denoiser::ATrousのworld spaceの法線ベクトルの差の長さに対するsigma。
+    /// 小さいほど面の折れ目が残る。
+    pub a_trous_sigma_normal: f32,
+    /// Denoiser::ATrousのdepthの差に対するsigma。
+    /// 差は中心のdepthとstep幅で割った相対的な値で比較する。小さいほど物体の輪郭が残る。
+    pub a_trous_sigma_depth: f32,
     pub rotate_x: f32,
     pub rotate_y: f32,
     pub rotate_z: f32,
     pub position_x: f32,
     pub position_y: f32,
     pub position_z: f32,
-    pub fov: f32,
+    pub camera_projection: CameraProjection,
+    /// レンズの歪みの2次の係数k1。正の値でたる型、負の値で糸巻き型の歪みになる。
+    /// 画面の中心からの距離を画面の対角線の半分で割って正規化したrnを使って、
+    /// 画面上の点を`1 + k1 * rn^2 + k2 * rn^4`倍の位置のレイに移すので、
+    /// 解像度やアスペクト比を変えても歪み方は変わらない。
+    /// 0の場合は歪みのない透視投影になる。正距円筒図法では使わない。
+    pub lens_distortion: f32,
+    /// レンズの歪みの4次の係数k2。lens_distortionと同じく正の値でたる型の歪みになる。
+    pub lens_distortion_k2: f32,
     pub l_white: f32,
     pub aperture: f32,
     pub shutter_speed: f32,
+    /// glbのanimationを再生する時刻(秒)。
+    /// animationで動くnodeのinstanceはこの時刻のposeでTLASを作り直す。
+    /// keyframeの範囲外では最初か最後のkeyframeのposeになる。
+    /// 変更すると蓄積したサンプルはリセットされる。
+    pub animation_time: f32,
     pub iso: f32,
     pub max_recursion_depth: u32,
     pub sun_direction: glam::Vec2,
+    /// 太陽の垂直放射照度。sun_angleを変えても放射照度は変わらない。
     pub sun_strength: f32,
     pub sun_color: glam::Vec3,
+    /// 太陽の視直径(度)。実際の太陽は約0.53度。
+    /// shadow rayはこの角度を頂角とする円錐の中から方向をサンプリングするので、
+    /// 大きくするほど影を落とす物体から離れた場所の半影が広がる。
     pub sun_angle: f32,
     pub sun_enabled: u32,
     pub sky_rotation: f32,
     pub sky_strength: f32,
     pub sky_enabled: u32,
+    /// trueの場合はレイがhitしなかったときにskyを背景として使う。
+    /// falseの場合はskyの代わりにbackground_colorの一様な背景を使い、環境光としても寄与させる。
+    pub background_is_environment: bool,
+    pub background_color: glam::Vec3,
+    /// trueの場合は出力先のimageのformatがsRGBで、書き込み時にハードウェアがsRGBの伝達関数をかけるので、
+    /// display_transferをかけずに出力する。
+    /// 表示に使うswapchainのformatを`ashtray::utils::is_srgb_format`で判定した値を渡す。
+    pub output_is_srgb: bool,
+    /// トーンマッピングした出力画像にかける伝達関数。
+    /// トーンマッピングしない表示やAOVの表示にはかけない。
+    pub display_transfer: DisplayTransfer,
+    /// 乱数のseed。
+    /// Someの場合は(seed, pixel, sample index)のみから乱数を決定するので、
+    /// 同じシーンとパラメータであれば毎回同じ結果になる。
+    /// Noneの場合はseedを0として扱う。
+    pub seed: Option<u64>,
+    /// trueの場合は直接光(カメラまでに反射したsurfaceが1つ以下の寄与)のみをレンダリングする。
+    /// MISや光源のサンプリングの検証などのdebug用。
+    pub direct_lighting_only: bool,
+    /// trueの場合は間接光(カメラまでに反射したsurfaceが2つ以上の寄与)のみをレンダリングする。
+    /// direct_lighting_onlyと同時に指定すると何も寄与しなくなる。
+    pub indirect_only: bool,
+    /// trueの場合はNEEを行わず、BSDFのサンプリングのみで光源に当たった寄与を計算する。
+    /// NEEとMISの結果をbrute forceな結果と比較するのに使う。
+    pub disable_nee: bool,
+    /// trueの場合はtraceしたレイとhitしたレイの数を数えて、Renderer::ray_statsで読み戻せるようにする。
+    /// レイごとにatomicな加算を行うので、falseの場合はshaderで何もしない。
+    pub collect_stats: bool,
+    /// レンダリングする領域(x, y, width, height)。
+    /// 出力画像の左上を原点としたpixel座標で指定し、画像からはみ出した部分は切り詰められる。
+    /// Someの場合はこの領域のみレイを飛ばしてresolveと出力を行い、それ以外の部分は更新しない。
+    /// Noneの場合は画像全体をレンダリングする。
+    pub render_region: Option<(u32, u32, u32, u32)>,
+    /// Renderer::screenshot_hdrで出力するOpenEXRの精度。
+    pub hdr_precision: HdrPrecision,
+    /// DisplayImage::DepthとDisplayImage::Positionを表示するときに正規化に使う距離。
+    /// 表示にのみ使い、読み戻す値には影響しない。
+    pub aov_far_plane: f32,
+    /// DisplayImage::AmbientOcclusionで遮蔽を調べるレイの長さ(world space)。
+    /// ambient occlusionの表示中以外はレンダリングに影響しない。
+    pub ao_radius: f32,
+    /// DisplayImage::TraversalHeatmapでカラーマップの最大の色にする1サンプルあたりのshaderの呼び出し回数。
+    /// 表示にのみ使う。
+    pub traversal_heatmap_scale: f32,
+    /// hitした位置から次のレイを飛ばすときに自己交差を避けるための距離(world space)。
+    /// 0の場合はhitした三角形の座標の大きさと法線から始点を浮動小数点の誤差の分だけずらすので、
+    /// sceneのスケールによらずshadow acneや光漏れが起きにくい。
+    /// 0より大きい場合は始点をずらさずに、この距離より近いhitを無視する。
+    /// 自動のoffsetで問題が出るsceneのためのもので、通常は0のままでよい。
+    pub ray_epsilon: f32,
+    /// ホワイトバランスの色温度(ケルビン)。
+    /// トーンマッピングの前にlinearな色に色順応の行列として掛ける。
+    /// 6500Kでtintが0のときは何もしない。
+    pub white_balance_temp: f32,
+    /// ホワイトバランスのtint。正の値で画像をマゼンタ寄りに、負の値で緑寄りに補正する。
+    pub tint: f32,
+    /// renderの1回にかけるGPUの時間の目標(ミリ秒)。
+    /// 0より大きい場合はray_traceの処理時間から、この時間に収まるように1回のrenderでtraceするサンプル数を調整する。
+    /// 0の場合は1回のrenderで1サンプルだけtraceする。
+    pub target_frame_ms: f32,
+    /// trueの場合はwhite furnace testとしてレンダリングする。
+    /// 背景を放射輝度1の一様な環境光にして、sky、太陽、発光面は使わない。
+    /// エネルギーを保存するBSDFであれば、albedoが1の物体は背景と同じ1になる。
+    /// 値は`Renderer::read_accumulate`で読み戻して確認する。
+    pub white_furnace: bool,
+    /// レンダリングの計算を行う色空間。
+    pub working_color_space: WorkingColorSpace,
+    /// 画像を計算するintegrator。
+    pub integrator: Integrator,
+    /// trueの場合はパスごとに波長を1つサンプリングし、
+    /// KHR_materials_dispersionの分散を持つ透過で屈折率を波長に応じて変える。
+    /// プリズムやダイヤモンドの虹色の縁が出るが、分散のある透過を通ったパスの色のノイズが増える。
+    /// Integrator::PathTracerのみに対応する。
+    pub spectral: bool,
+    /// すべてのレイでの三角形の面の向きによるカリング。
+    pub cull_mode: CullMode,
+    /// HDRのディスプレイ向けの出力のエンコード。
+    /// Sdr以外ではoutput_is_srgbとdisplay_transferとl_whiteは使わず、
+    /// `Renderer::read_output_image`などの8bitでの読み戻しはできない。
+    pub hdr_output: HdrOutput,
 }
 impl Default for Parameters {
     fn default() -> Self {
@@ -54,18 +412,32 @@ impl Default for Parameters {
             width: 400,
             height: 300,
             max_sample_count: 256,
+            max_render_seconds: None,
             display_image: DisplayImage::Final,
             denoise_every_sample: false,
+            denoise_strength: 1.0,
+            prefilter_aux: false,
+            denoiser: Denoiser::Oidn,
+            a_trous_iterations: 5,
+            a_trous_sigma_color: 1.0,
+            a_trous_sigma_albedo: 0.1,
+            a_trous_sigma_normal: 0.2,
+            a_trous_sigma_depth: 0.1,
             rotate_x: 0.0,
             rotate_y: 0.0,
             rotate_z: 0.0,
             position_x: 0.0,
             position_y: 0.0,
             position_z: 0.0,
-            fov: 60.0_f32.to_radians(),
+            camera_projection: CameraProjection::Perspective {
+                fov: 60.0_f32.to_radians(),
+            },
+            lens_distortion: 0.0,
+            lens_distortion_k2: 0.0,
             l_white: 1.0,
             aperture: 16.0,
             shutter_speed: 1.0 / 100.0,
+            animation_time: 0.0,
             iso: 100.0,
             max_recursion_depth: 1,
             sun_direction: glam::Vec2::new(0.0, 0.0),
@@ -76,27 +448,181 @@ impl Default for Parameters {
             sky_rotation: 0.0,
             sky_strength: 0.0,
             sky_enabled: 0,
+            background_is_environment: true,
+            background_color: glam::Vec3::ZERO,
+            output_is_srgb: false,
+            display_transfer: DisplayTransfer::Srgb,
+            seed: None,
+            direct_lighting_only: false,
+            indirect_only: false,
+            disable_nee: false,
+            collect_stats: false,
+            render_region: None,
+            hdr_precision: HdrPrecision::Half,
+            aov_far_plane: 100.0,
+            ao_radius: 1.0,
+            traversal_heatmap_scale: 16.0,
+            ray_epsilon: 0.0,
+            white_balance_temp: white_balance::NEUTRAL_TEMPERATURE,
+            tint: 0.0,
+            target_frame_ms: 0.0,
+            white_furnace: false,
+            working_color_space: WorkingColorSpace::LinearSrgb,
+            integrator: Integrator::PathTracer,
+            spectral: false,
+            cull_mode: CullMode::None,
+            hdr_output: HdrOutput::Sdr,
         }
     }
 }
+impl Parameters {
+    /// otherに変更したときに、蓄積したサンプルを破棄してサンプリングをやり直す必要があるかどうか。
+    /// カメラやsceneやintegratorに影響するパラメータが変わった場合にtrueになる。
+    /// PartialEqの比較と同じで、出力時の処理のみに影響するパラメータの変更ではfalseになる。
+    pub fn requires_accumulation_reset(&self, other: &Self) -> bool {
+        self != other
+    }
+
+    /// otherに変更したときに、蓄積はそのままで出力画像を作り直す必要があるかどうか。
+    /// 露出、トーンマッピング、ホワイトバランス、表示する画像、denoiseのパラメータなど、
+    /// resolve以降の処理のみに影響するパラメータが変わった場合にtrueになる。
+    /// Renderer::renderは毎回出力画像を書き込むので、新しいサンプルがなくても変更はすぐに反映される。
+    pub fn requires_reprocess(&self, other: &Self) -> bool {
+        self.display_image != other.display_image
+            || self.denoise_strength != other.denoise_strength
+            || self.prefilter_aux != other.prefilter_aux
+            || self.denoiser != other.denoiser
+            || self.a_trous_iterations != other.a_trous_iterations
+            || self.a_trous_sigma_color != other.a_trous_sigma_color
+            || self.a_trous_sigma_albedo != other.a_trous_sigma_albedo
+            || self.a_trous_sigma_normal != other.a_trous_sigma_normal
+            || self.a_trous_sigma_depth != other.a_trous_sigma_depth
+            || self.l_white != other.l_white
+            || self.aperture != other.aperture
+            || self.iso != other.iso
+            || self.output_is_srgb != other.output_is_srgb
+            || self.display_transfer != other.display_transfer
+            || self.hdr_output != other.hdr_output
+            || self.aov_far_plane != other.aov_far_plane
+            || self.traversal_heatmap_scale != other.traversal_heatmap_scale
+            || self.white_balance_temp != other.white_balance_temp
+            || self.tint != other.tint
+    }
+
+    /// 全てのパラメータをRON形式のpresetファイルに書き出す。
+    pub fn save_preset(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// RON形式のpresetファイルからパラメータを読み込む。
+    /// ファイルに書かれていないフィールドはデフォルト値になる。
+    pub fn load_preset(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let parameters = ron::from_str(&text)?;
+        Ok(parameters)
+    }
+
+    /// 現在のカメラの向きとfovのまま、bounds(min, max)のAABB全体が画面に収まるように
+    /// カメラの位置を動かす。
+    /// AABBを囲む球が画面の縦横の狭い方の画角に収まる距離までカメラを下げる。
+    /// 平行投影の場合は球が収まるようにheightも変更する。
+    /// boundsにはScene::world_boundsの結果を渡す。
+    pub fn frame_scene(&mut self, bounds: (glam::Vec3, glam::Vec3)) {
+        let (min, max) = bounds;
+        let center = (min + max) / 2.0;
+        let radius = ((max - min).length() / 2.0).max(f32::EPSILON);
+        let aspect = self.width as f32 / self.height.max(1) as f32;
+
+        let rotate = glam::Mat4::from_euler(
+            glam::EulerRot::YXZ,
+            self.rotate_y.to_radians(),
+            self.rotate_x.to_radians(),
+            self.rotate_z.to_radians(),
+        );
+        let forward = rotate.transform_vector3(glam::Vec3::NEG_Z).normalize();
+
+        let distance = match &mut self.camera_projection {
+            CameraProjection::Perspective { fov } => {
+                // raygenと同じ投影で、縦方向の画角の半分のtanはatan(fov / 2)になる
+                let tan_half_fov = (fov.to_radians() / 2.0).atan() * aspect.min(1.0);
+                let sin_half_fov = tan_half_fov / (1.0 + tan_half_fov * tan_half_fov).sqrt();
+                radius / sin_half_fov
+            }
+            CameraProjection::Orthographic { height } => {
+                *height = 2.0 * radius / aspect.min(1.0);
+                // 平行投影では距離は写り方に影響しないので、球の手前にカメラを置く
+                2.0 * radius
+            }
+            // 全天球を写すので、カメラをAABBの中心に置く
+            CameraProjection::Equirectangular => 0.0,
+        };
+
+        let position = center - forward * distance;
+        self.position_x = position.x;
+        self.position_y = position.y;
+        self.position_z = position.z;
+    }
+
+    /// Scene::punctual_lightsで読み込んだDirectionalのlightを太陽として設定する。
+    /// 光が進む方向の逆を太陽の方位角と仰角(度)にし、luxのintensityをそのままsun_strengthにする。
+    /// sun_angleは変更しない。Directional以外のlightの場合は何もせずにfalseを返す。
+    pub fn set_sun_from_light(&mut self, light: &PunctualLight) -> bool {
+        let PunctualLight::Directional {
+            direction,
+            color,
+            intensity,
+        } = *light
+        else {
+            return false;
+        };
+        // sun.glslのsunDirectionの逆。xが方位角、yが仰角
+        let to_sun = -direction;
+        let elevation = to_sun.y.clamp(-1.0, 1.0).asin();
+        let azimuth = to_sun.z.atan2(to_sun.x);
+        self.sun_direction = glam::Vec2::new(azimuth.to_degrees(), elevation.to_degrees());
+        self.sun_strength = intensity;
+        self.sun_color = color;
+        self.sun_enabled = 1;
+        true
+    }
+}
+
+// 蓄積をリセットするかどうかの判定に使うための比較。
+// 露出やトーンマッピングなど、出力時の処理のみに影響するパラメータは比較しない。
+// それ以外の理由で蓄積をやり直したい場合はRenderer::reset_accumulationを明示的に呼び出す。
 impl PartialEq for Parameters {
     fn eq(&self, other: &Self) -> bool {
         self.width == other.width
             && self.height == other.height
             && self.max_sample_count == other.max_sample_count
+            && self.max_render_seconds == other.max_render_seconds
             // && self.display_image == other.display_image
             && self.denoise_every_sample == other.denoise_every_sample
+            // && self.denoise_strength == other.denoise_strength
+            // && self.prefilter_aux == other.prefilter_aux
+            // && self.denoiser == other.denoiser
+            // && self.a_trous_iterations == other.a_trous_iterations
+            // && self.a_trous_sigma_color == other.a_trous_sigma_color
+            // && self.a_trous_sigma_albedo == other.a_trous_sigma_albedo
+            // && self.a_trous_sigma_normal == other.a_trous_sigma_normal
+            // && self.a_trous_sigma_depth == other.a_trous_sigma_depth
             && self.rotate_x == other.rotate_x
             && self.rotate_y == other.rotate_y
             && self.rotate_z == other.rotate_z
             && self.position_x == other.position_x
             && self.position_y == other.position_y
             && self.position_z == other.position_z
-            && self.fov == other.fov
-            && self.l_white == other.l_white
-            && self.aperture == other.aperture
+            && self.camera_projection == other.camera_projection
+            && self.lens_distortion == other.lens_distortion
+            && self.lens_distortion_k2 == other.lens_distortion_k2
+            // && self.l_white == other.l_white
+            // && self.aperture == other.aperture
+            // shutter_speedはmotion blurの時間の幅にも使うので比較する
             && self.shutter_speed == other.shutter_speed
-            && self.iso == other.iso
+            && self.animation_time == other.animation_time
+            // && self.iso == other.iso
             && self.max_recursion_depth == other.max_recursion_depth
             && self.sun_direction == other.sun_direction
             && self.sun_strength == other.sun_strength
@@ -106,5 +632,29 @@ impl PartialEq for Parameters {
             && self.sky_rotation == other.sky_rotation
             && self.sky_strength == other.sky_strength
             && self.sky_enabled == other.sky_enabled
+            && self.background_is_environment == other.background_is_environment
+            && self.background_color == other.background_color
+            // && self.output_is_srgb == other.output_is_srgb
+            // && self.display_transfer == other.display_transfer
+            // && self.hdr_output == other.hdr_output
+            && self.seed == other.seed
+            && self.direct_lighting_only == other.direct_lighting_only
+            && self.indirect_only == other.indirect_only
+            && self.disable_nee == other.disable_nee
+            // && self.collect_stats == other.collect_stats
+            && self.render_region == other.render_region
+            && self.ao_radius == other.ao_radius
+            && self.ray_epsilon == other.ray_epsilon
+            && self.white_furnace == other.white_furnace
+            && self.working_color_space == other.working_color_space
+            && self.integrator == other.integrator
+            && self.spectral == other.spectral
+            && self.cull_mode == other.cull_mode
+        // && self.hdr_precision == other.hdr_precision
+        // && self.aov_far_plane == other.aov_far_plane
+        // && self.traversal_heatmap_scale == other.traversal_heatmap_scale
+        // && self.white_balance_temp == other.white_balance_temp
+        // && self.tint == other.tint
+        // && self.target_frame_ms == other.target_frame_ms
     }
 }
@@ -0,0 +1,169 @@
+//! 複数のGPUで画面を横長の帯に分けてレンダリングし、1枚の画像に合成するMultiGpuRendererを定義する。
+
+use ash::vk;
+use std::{sync::atomic::AtomicBool, time::Instant};
+
+// GPUごとのRendererと、そのGPUに割り当てる行数を決めるための処理速度
+struct GpuRenderer {
+    renderer: crate::Renderer,
+    // 1秒あたりにレンダリングできた行数。最初は全てのGPUで同じとする
+    rows_per_second: f64,
+    // 前回のrender_untilで割り当てた領域(y, height)
+    band: (u32, u32),
+}
+
+/// 複数のGPUに画面を横長の帯に分けて割り当ててレンダリングし、1枚の画像に合成する。
+///
+/// GPUごとに別々のdeviceとheadlessのRendererを作り、sceneもそれぞれのGPUに読み込む。
+/// 各Rendererは`Parameters::render_region`で割り当てた帯だけをレンダリングする。
+/// 帯の高さは前回のレンダリングで測った各GPUの処理速度に比例させるので、
+/// 性能の異なるGPUを組み合わせても終わる時間が揃うようになる。
+/// denoiseはRendererごとに画像全体に掛けるので、帯の境界付近は
+/// 1つのGPUでレンダリングした場合と結果が少し異なる。
+pub struct MultiGpuRenderer {
+    gpus: Vec<GpuRenderer>,
+}
+impl MultiGpuRenderer {
+    /// physical_devicesのそれぞれにheadlessのdeviceを作成し、Rendererを作成する。
+    pub fn new(
+        instance: &ashtray::InstanceHandle,
+        physical_devices: &[vk::PhysicalDevice],
+        width: u32,
+        height: u32,
+    ) -> Self {
+        assert!(
+            !physical_devices.is_empty(),
+            "MultiGpuRenderer needs at least one physical device"
+        );
+        let required_device_extensions =
+            ashtray::utils::get_required_headless_device_extensions(&[]);
+        let gpus = physical_devices
+            .iter()
+            .map(|&physical_device| {
+                let queue_indices =
+                    ashtray::utils::get_headless_queue_indices(instance, physical_device);
+                let device = ashtray::utils::create_device(
+                    instance,
+                    physical_device,
+                    &queue_indices,
+                    &required_device_extensions,
+                );
+                let queue_handles = ashtray::utils::get_queue_handles(&device, &queue_indices);
+                let command_pool =
+                    ashtray::utils::create_graphics_command_pool(&device, &queue_handles);
+                let allocator =
+                    ashtray::utils::create_allocator(instance, physical_device, &device);
+                let pipeline_cache = device.create_pipeline_cache(None);
+                let renderer = crate::Renderer::new(
+                    width,
+                    height,
+                    instance.clone(),
+                    physical_device,
+                    device,
+                    queue_handles,
+                    command_pool,
+                    allocator,
+                    pipeline_cache,
+                );
+                GpuRenderer {
+                    renderer,
+                    rows_per_second: 1.0,
+                    band: (0, 0),
+                }
+            })
+            .collect();
+        Self { gpus }
+    }
+
+    /// 全てのGPUにsceneを読み込む。
+    /// どれかのGPUでメモリが足りない場合はそのエラーを返す。
+    pub fn load_scene(&mut self, scene: &crate::Scene) -> Result<(), crate::SceneTooLargeError> {
+        for gpu in &mut self.gpus {
+            gpu.renderer.load_scene(scene)?;
+        }
+        Ok(())
+    }
+
+    /// 使っているGPUの数
+    pub fn gpu_count(&self) -> usize {
+        self.gpus.len()
+    }
+
+    /// 前回のrender_untilで各GPUに割り当てた領域(y, height)
+    pub fn bands(&self) -> Vec<(u32, u32)> {
+        self.gpus.iter().map(|gpu| gpu.band).collect()
+    }
+
+    /// parametersで各GPUの帯をtarget_samplesに達するまでレンダリングし、合成した画像を返す。
+    ///
+    /// 各GPUは別々のスレッドから並行して動かす。
+    /// cancelがtrueになると各GPUはその時点で打ち切り、途中までの画像を合成する。
+    /// parametersのrender_regionは無視して、割り当てた帯で上書きする。
+    pub fn render_until(
+        &mut self,
+        parameters: &crate::Parameters,
+        target_samples: u32,
+        cancel: &AtomicBool,
+    ) -> anyhow::Result<image::RgbaImage> {
+        let (width, height) = (parameters.width, parameters.height);
+        self.assign_bands(height);
+
+        let results = std::thread::scope(|scope| {
+            let handles = self
+                .gpus
+                .iter_mut()
+                .map(|gpu| {
+                    let parameters = crate::Parameters {
+                        render_region: Some((0, gpu.band.0, width, gpu.band.1)),
+                        ..parameters.clone()
+                    };
+                    scope.spawn(move || {
+                        let start = Instant::now();
+                        gpu.renderer.render(parameters);
+                        gpu.renderer
+                            .render_until(target_samples, cancel, |_, _| true);
+                        let elapsed = start.elapsed().as_secs_f64();
+                        if gpu.band.1 > 0 && elapsed > 0.0 {
+                            gpu.rows_per_second = gpu.band.1 as f64 / elapsed;
+                        }
+                        gpu.renderer.read_output_image()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Render thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        // 各GPUの出力画像から割り当てた帯の行だけをコピーする
+        let mut data = vec![0; width as usize * height as usize * 4];
+        for (gpu, pixels) in self.gpus.iter().zip(results) {
+            let pixels = pixels?;
+            let (y, rows) = gpu.band;
+            let start = (y * width * 4) as usize;
+            let end = ((y + rows) * width * 4) as usize;
+            data[start..end].copy_from_slice(&pixels[start..end]);
+        }
+        image::RgbaImage::from_raw(width, height, data)
+            .ok_or_else(|| anyhow::anyhow!("Failed to create the combined image"))
+    }
+
+    // 画像の高さを各GPUの処理速度に比例した行数の帯に分けて割り当てる
+    fn assign_bands(&mut self, height: u32) {
+        let total = self.gpus.iter().map(|gpu| gpu.rows_per_second).sum::<f64>();
+        let mut accumulated = 0.0;
+        let mut y = 0;
+        let count = self.gpus.len();
+        for (i, gpu) in self.gpus.iter_mut().enumerate() {
+            accumulated += gpu.rows_per_second;
+            let end = if i + 1 == count {
+                height
+            } else {
+                ((height as f64 * accumulated / total).round() as u32).clamp(y, height)
+            };
+            gpu.band = (y, end - y);
+            y = end;
+        }
+    }
+}
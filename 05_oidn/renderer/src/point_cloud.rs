@@ -0,0 +1,292 @@
+use ash::vk;
+
+/// 同じ半径の球の集合として描画する点群。
+///
+/// 球を三角形に分割せず、球ごとのAABBとintersection shaderのray-sphere交差で表す。
+/// 1つの球はAABBの24バイトと色の12バイトで済むので、三角形の球より大幅にメモリが少ない。
+/// Scene::point_cloudsに入れると、点群ごとに1つのAABBのBLASと1つのTLASのinstanceになり、
+/// 球はroughnessが1の拡散反射のmaterialで描画される。
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct PointCloud {
+    /// world spaceの球の中心
+    pub positions: Vec<glam::Vec3>,
+    /// すべての球に共通の半径
+    pub radius: f32,
+    /// 球ごとのlinear sRGBの色。空の場合はすべて白にする。
+    #[serde(default)]
+    pub colors: Vec<glam::Vec3>,
+}
+impl PointCloud {
+    /// `.ply`か`.xyz`のファイルから点群を読み込む。
+    ///
+    /// `.ply`はascii、binary_little_endian、binary_big_endianのvertexのelementから
+    /// x、y、zと、あればred、green、blueを読む。vertex以外のelementは無視する。
+    /// `.xyz`は1行に空白区切りで`x y z`か`x y z r g b`を並べたテキストで、
+    /// 色は0から255の値とする。空行と`#`で始まる行は無視する。
+    pub fn load_from_file(path: impl AsRef<std::path::Path>, radius: f32) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        let (positions, colors) = match extension.as_deref() {
+            Some("ply") => load_ply(&std::fs::read(path)?)?,
+            Some("xyz") => load_xyz(&std::fs::read_to_string(path)?)?,
+            _ => anyhow::bail!("Unsupported point cloud file: {}", path.display()),
+        };
+        Ok(Self {
+            positions,
+            radius,
+            colors,
+        })
+    }
+
+    /// 球ごとのAABB。`ashtray::utils::create_aabb_blas`にそのまま渡せる。
+    pub fn aabbs(&self) -> Vec<vk::AabbPositionsKHR> {
+        self.positions
+            .iter()
+            .map(|position| {
+                let min = *position - glam::Vec3::splat(self.radius);
+                let max = *position + glam::Vec3::splat(self.radius);
+                vk::AabbPositionsKHR {
+                    min_x: min.x,
+                    min_y: min.y,
+                    min_z: min.z,
+                    max_x: max.x,
+                    max_y: max.y,
+                    max_z: max.z,
+                }
+            })
+            .collect()
+    }
+
+    /// AABBと同じ順の球ごとの色。colorsが空の場合は白で埋める。
+    /// `ashtray::utils::create_aabb_blas`のprimitive_dataとして渡し、
+    /// shaderではgl_PrimitiveIDで引く。
+    pub fn primitive_colors(&self) -> Vec<glam::Vec3> {
+        if self.colors.is_empty() {
+            vec![glam::Vec3::ONE; self.positions.len()]
+        } else {
+            self.colors.clone()
+        }
+    }
+}
+
+// `.xyz`のテキストから位置と色を読む
+fn load_xyz(text: &str) -> anyhow::Result<(Vec<glam::Vec3>, Vec<glam::Vec3>)> {
+    let mut positions = vec![];
+    let mut colors = vec![];
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line
+            .split_whitespace()
+            .map(|value| value.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| anyhow::anyhow!("line {}: {err}", line_index + 1))?;
+        match values[..] {
+            [x, y, z] => positions.push(glam::vec3(x, y, z)),
+            [x, y, z, r, g, b] => {
+                positions.push(glam::vec3(x, y, z));
+                colors.push(glam::vec3(r, g, b) / 255.0);
+            }
+            _ => anyhow::bail!(
+                "line {}: expected 3 or 6 values, but found {}",
+                line_index + 1,
+                values.len()
+            ),
+        }
+    }
+    if !colors.is_empty() && colors.len() != positions.len() {
+        anyhow::bail!("Some points have colors and others don't");
+    }
+    Ok((positions, colors))
+}
+
+// plyのformat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+// plyのvertexのpropertyの型と名前
+struct PlyProperty {
+    ty: String,
+    name: String,
+}
+impl PlyProperty {
+    // binaryでのバイト数
+    fn size(&self) -> anyhow::Result<usize> {
+        Ok(match self.ty.as_str() {
+            "char" | "uchar" | "int8" | "uint8" => 1,
+            "short" | "ushort" | "int16" | "uint16" => 2,
+            "int" | "uint" | "float" | "int32" | "uint32" | "float32" => 4,
+            "double" | "float64" => 8,
+            ty => anyhow::bail!("Unsupported ply property type: {ty}"),
+        })
+    }
+
+    // binaryのbytesを読んでf32にする
+    fn read(&self, bytes: &[u8], format: PlyFormat) -> f32 {
+        macro_rules! read {
+            ($t:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                if format == PlyFormat::BinaryBigEndian {
+                    <$t>::from_be_bytes(bytes) as f32
+                } else {
+                    <$t>::from_le_bytes(bytes) as f32
+                }
+            }};
+        }
+        match self.ty.as_str() {
+            "char" | "int8" => read!(i8),
+            "uchar" | "uint8" => read!(u8),
+            "short" | "int16" => read!(i16),
+            "ushort" | "uint16" => read!(u16),
+            "int" | "int32" => read!(i32),
+            "uint" | "uint32" => read!(u32),
+            "float" | "float32" => read!(f32),
+            _ => read!(f64),
+        }
+    }
+
+    // 色のpropertyの値を[0, 1]にする。整数の色は0から255とみなす
+    fn normalize_color(&self, value: f32) -> f32 {
+        if self.ty.starts_with('f') || self.ty == "double" {
+            value
+        } else {
+            value / 255.0
+        }
+    }
+}
+
+// plyのbytesから位置と色を読む
+fn load_ply(bytes: &[u8]) -> anyhow::Result<(Vec<glam::Vec3>, Vec<glam::Vec3>)> {
+    // headerを読む
+    let header_end = bytes
+        .windows(b"end_header".len())
+        .position(|window| window == b"end_header")
+        .ok_or_else(|| anyhow::anyhow!("The ply file has no end_header"))?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map(|position| header_end + position + 1)
+        .unwrap_or(bytes.len());
+    let header = std::str::from_utf8(&bytes[..header_end])?;
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        anyhow::bail!("Not a ply file");
+    }
+
+    let mut format = None;
+    let mut vertex_count = None;
+    let mut vertex_properties = vec![];
+    // vertexより前にあるelement。binaryではvertexまで読み飛ばせないのでエラーにする
+    let mut element_before_vertex = None;
+    let mut in_vertex = false;
+    for line in lines {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["format", "ascii", ..] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", ..] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", "binary_big_endian", ..] => format = Some(PlyFormat::BinaryBigEndian),
+            ["element", "vertex", count] => {
+                vertex_count = Some(count.parse::<usize>()?);
+                in_vertex = true;
+            }
+            ["element", name, ..] => {
+                if vertex_count.is_none() && element_before_vertex.is_none() {
+                    element_before_vertex = Some(name.to_string());
+                }
+                in_vertex = false;
+            }
+            ["property", "list", ..] if in_vertex => {
+                anyhow::bail!("List properties of the vertex element are not supported")
+            }
+            ["property", ty, name] if in_vertex => vertex_properties.push(PlyProperty {
+                ty: ty.to_string(),
+                name: name.to_string(),
+            }),
+            _ => (),
+        }
+    }
+    let format = format.ok_or_else(|| anyhow::anyhow!("The ply file has no format"))?;
+    let vertex_count =
+        vertex_count.ok_or_else(|| anyhow::anyhow!("The ply file has no vertex element"))?;
+    if let Some(name) = element_before_vertex {
+        anyhow::bail!("The element {name:?} before the vertex element is not supported");
+    }
+
+    let find = |name: &str| vertex_properties.iter().position(|p| p.name == name);
+    let (Some(x), Some(y), Some(z)) = (find("x"), find("y"), find("z")) else {
+        anyhow::bail!("The vertex element has no x, y, z properties");
+    };
+    let color = match (find("red"), find("green"), find("blue")) {
+        (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+        _ => None,
+    };
+
+    // vertexごとのpropertyの値をf32で読む
+    let mut values = Vec::with_capacity(vertex_count);
+    let body = &bytes[body_start..];
+    if format == PlyFormat::Ascii {
+        let text = std::str::from_utf8(body)?;
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        for _ in 0..vertex_count {
+            let line = lines
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("The ply file has fewer vertices than declared"))?;
+            let vertex = line
+                .split_whitespace()
+                .take(vertex_properties.len())
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()?;
+            if vertex.len() != vertex_properties.len() {
+                anyhow::bail!("A vertex of the ply file has too few values");
+            }
+            values.push(vertex);
+        }
+    } else {
+        let sizes = vertex_properties
+            .iter()
+            .map(|property| property.size())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let stride = sizes.iter().sum::<usize>();
+        if body.len() < stride * vertex_count {
+            anyhow::bail!("The ply file has fewer vertices than declared");
+        }
+        for vertex_bytes in body.chunks_exact(stride).take(vertex_count) {
+            let mut offset = 0;
+            let vertex = vertex_properties
+                .iter()
+                .zip(&sizes)
+                .map(|(property, &size)| {
+                    let value = property.read(&vertex_bytes[offset..offset + size], format);
+                    offset += size;
+                    value
+                })
+                .collect::<Vec<_>>();
+            values.push(vertex);
+        }
+    }
+
+    let positions = values
+        .iter()
+        .map(|vertex| glam::vec3(vertex[x], vertex[y], vertex[z]))
+        .collect();
+    let colors = match color {
+        Some(indices) => values
+            .iter()
+            .map(|vertex| {
+                let [r, g, b] = indices.map(|i| vertex_properties[i].normalize_color(vertex[i]));
+                glam::vec3(r, g, b)
+            })
+            .collect(),
+        None => vec![],
+    };
+    Ok((positions, colors))
+}
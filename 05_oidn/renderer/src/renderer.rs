@@ -1,10 +1,40 @@
 use ash::vk;
 use bytemuck;
-use oidn::{OidnBuffer, OidnDevice, OidnFilter};
+use oidn::{OidnBuffer, OidnDevice, OidnError, OidnFilter};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
 use crate::NextImage;
 
+// render_untilで進捗を通知するサンプル数の間隔
+const RENDER_UNTIL_PROGRESS_INTERVAL: u32 = 8;
+
+// load_sceneで使えるdevice localメモリの割合のデフォルト値
+const DEFAULT_MEMORY_BUDGET_FRACTION: f32 = 0.8;
+
+// target_frame_msを指定したときに1回のrenderでtraceするサンプル数の上限
+const MAX_SAMPLES_PER_FRAME: u32 = 16;
+// サンプル数を増やすのは、1サンプル増やしてもtarget_frame_msのこの割合に収まる場合だけにする。
+// 減らす閾値との間に幅を持たせて、サンプル数がフレームごとに振動しないようにする。
+const SAMPLES_PER_FRAME_INCREASE_RATIO: f32 = 0.75;
+
+// RenderDocなどのキャプチャで表示するpassのlabelの色
+const TRACE_LABEL_COLOR: [f32; 4] = [1.0, 0.5, 0.2, 1.0];
+const RESOLVE_LABEL_COLOR: [f32; 4] = [0.3, 0.8, 0.3, 1.0];
+const DENOISE_LABEL_COLOR: [f32; 4] = [0.3, 0.5, 1.0, 1.0];
+const OUTPUT_LABEL_COLOR: [f32; 4] = [0.8, 0.4, 0.9, 1.0];
+
+// ray tracing pipelineのPushConstantsを読むshader stage。
+// pipeline layoutのrangeとcmd_push_constantsの両方でこれを使う。
+const RAY_TRACING_PUSH_CONSTANT_STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(
+    vk::ShaderStageFlags::RAYGEN_KHR.as_raw()
+        | vk::ShaderStageFlags::ANY_HIT_KHR.as_raw()
+        | vk::ShaderStageFlags::CLOSEST_HIT_KHR.as_raw()
+        | vk::ShaderStageFlags::MISS_KHR.as_raw()
+        | vk::ShaderStageFlags::INTERSECTION_KHR.as_raw(),
+);
+
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct PushConstants {
@@ -18,24 +48,38 @@ struct PushConstants {
     max_recursion_depth: u32,
     instance_params_index: u32,
     materials_index: u32,
-    padding_0: [u32; 1],
+    random_seed: u32,
     sun_color: glam::Vec3,
     sun_strength: f32,
     sun_direction: glam::Vec2,
     sun_angle: f32,
-    sun_enabled: u32,
+    // レンズの歪みの係数k1を下位16bit、k2を上位16bitに詰めたhalf float
+    lens_distortion: u32,
     sky_width: u32,
     sky_height: u32,
     sky_rotation: f32,
     sky_strength: f32,
     sky_enabled: u32,
-    padding_1: [u32; 3],
+    lights_index: u32,
+    instance_light_indices_index: u32,
+    light_count: u32,
     sky_buffer_address: u64,
     sky_cdf_row_buffer_address: u64,
     sky_pdf_row_buffer_address: u64,
     sky_cdf_column_buffer_address: u64,
     sky_pdf_column_buffer_address: u64,
-    padding_2: [u32; 2],
+    camera_projection: u32,
+    camera_orthographic_height: f32,
+    background_color: glam::Vec3,
+    // 0より大きければレイの始点をずらさずにtminをこの値にする
+    ray_epsilon: f32,
+    debug_image_index: u32,
+    // debug outputとflagとambient occlusionの半径。bitの割り当てはdebug_flags関数を参照
+    debug_flags: u32,
+    // render regionのoffsetのxを下位16bit、yを上位16bitに詰めたもの
+    render_region_offset: u32,
+    // instance id、depth、positionの順に連続したindexに並んだAOVのimageの先頭のindex
+    aov_image_index: u32,
 }
 
 #[repr(C)]
@@ -44,26 +88,39 @@ struct ResolvePushConstants {
     input_index: u32,
     output_index: u32,
     sample_count: u32,
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct BeforeDenoisePushConstants {
-    color_image_index: u32,
+    region_x: u32,
+    region_y: u32,
+    region_width: u32,
+    region_height: u32,
     albedo_image_index: u32,
+    // 0でなければresolveした色の輝度のhistogramをこのbufferに加算する
+    luminance_histogram_buffer_address: u64,
+    // 0でなければresolveした色とalbedo、normalをoidnの入力のbufferに書き込む
+    oidn_color_buffer_address: u64,
+    oidn_albedo_buffer_address: u64,
+    oidn_normal_buffer_address: u64,
     normal_image_index: u32,
     padding: [u32; 1],
-    color_buffer_address: u64,
-    albedo_buffer_address: u64,
-    normal_buffer_address: u64,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct AfterDenoisePushConstants {
+struct ATrousPushConstants {
     output_image_index: u32,
-    padding: [u32; 1],
-    output_buffer_address: u64,
+    resolved_image_index: u32,
+    denoise_strength: f32,
+    // a_trous_input_image_indexの画像にこのstep幅でÀ-Trousのfilterを掛ける
+    a_trous_step_width: u32,
+    a_trous_input_image_index: u32,
+    albedo_image_index: u32,
+    normal_image_index: u32,
+    depth_image_index: u32,
+    sigma_color: f32,
+    sigma_albedo: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    // À-Trousの最後のpassのみdenoise strengthでresolve済み画像とブレンドする
+    is_last_pass: u32,
 }
 
 #[repr(C)]
@@ -72,10 +129,247 @@ struct FinalPushConstants {
     input_index: u32,
     output_index: u32,
     l_white: f32,
-    aperture: f32,
-    shutter_speed: f32,
-    iso: f32,
+    exposure: f32,
     enable_tone_mapping: u32,
+    // 0: linear、1: sRGB、2: gamma、3: HDRのscRGB、4: HDRのPQ
+    display_transfer: u32,
+    display_bounce_count: u32,
+    sample_count: u32,
+    max_recursion_depth: u32,
+    region_x: u32,
+    region_y: u32,
+    region_width: u32,
+    region_height: u32,
+    display_instance_id: u32,
+    display_depth: u32,
+    display_position: u32,
+    aov_far_plane: f32,
+    display_ambient_occlusion: u32,
+    // 0: linear sRGB、1: ACEScg
+    working_color_space: u32,
+    // display_transferがgammaの場合のgamma
+    display_gamma: f32,
+    // 0でなければinput_indexのresolve済み画像の代わりにoidnの出力のbufferを
+    // denoise_strengthでresolve済み画像とブレンドして表示する
+    oidn_output_buffer_address: u64,
+    denoise_strength: f32,
+    display_traversal_heatmap: u32,
+    // ホワイトバランスの色順応行列。左上の3x3のみを使う
+    white_balance: glam::Mat4,
+    // pixelごとのintersection shaderとany hit shaderの呼び出し回数のbuffer
+    traversal_heatmap_buffer_address: u64,
+    traversal_heatmap_scale: f32,
+    padding: [u32; 1],
+}
+
+// output imageのformat。HDRの出力では1を超える値やPQでエンコードした値を保持するためにfloatにする
+fn output_image_format(hdr_output: crate::HdrOutput) -> vk::Format {
+    match hdr_output {
+        crate::HdrOutput::Sdr => vk::Format::R8G8B8A8_UNORM,
+        crate::HdrOutput::ScRgb | crate::HdrOutput::Hdr10Pq => vk::Format::R16G16B16A16_SFLOAT,
+    }
+}
+
+// ray tracingのshaderにdebug imageへ書き込ませる内容を決める。
+// 0: 書き込まない、1: bounce count、2: geometric normal、3: ambient occlusion、
+// 4: traversal heatmap
+// debug_flagsの下位3bitに収まる値にすること。
+fn debug_output(display_image: crate::DisplayImage) -> u32 {
+    match display_image {
+        crate::DisplayImage::BounceCount => 1,
+        crate::DisplayImage::GeometricNormal => 2,
+        crate::DisplayImage::AmbientOcclusion => 3,
+        crate::DisplayImage::TraversalHeatmap => 4,
+        _ => 0,
+    }
+}
+
+// ray tracingのshaderのintegratorの寄与を切り分けるdebug用のflag。
+// push_constants.glslのDEBUG_FLAG_*と値を揃えること。
+const DEBUG_FLAG_DIRECT_LIGHTING_ONLY: u32 = 0x100;
+const DEBUG_FLAG_INDIRECT_ONLY: u32 = 0x200;
+const DEBUG_FLAG_DISABLE_NEE: u32 = 0x400;
+const DEBUG_FLAG_COLLECT_STATS: u32 = 0x800;
+// skyの代わりに一様な背景色を使う
+const FLAG_USE_BACKGROUND_COLOR: u32 = 0x1000;
+// 太陽を光源として使う
+const FLAG_SUN_ENABLED: u32 = 0x2000;
+// white furnace testのために発光面を無視する
+const DEBUG_FLAG_WHITE_FURNACE: u32 = 0x4000;
+// 作業色空間をACEScgにする
+const FLAG_WORKING_SPACE_ACESCG: u32 = 0x8000;
+
+// light tracerの2回のtraceのどちらかを表すflag。
+// 1回目は発光面からのパスをフィルムのbufferにsplatし、
+// 2回目はpixelごとにフィルムの値を蓄積画像に加える。
+const FLAG_LIGHT_TRACE_SPLAT: u32 = 0x40;
+const FLAG_LIGHT_TRACE_GATHER: u32 = 0x80;
+// パスごとに波長をサンプリングして分散を計算する
+const FLAG_SPECTRAL: u32 = 0x20;
+// Parameters::cull_modeをshaderのray flagsにするためのflag
+const FLAG_CULL_BACK: u32 = 0x08;
+const FLAG_CULL_FRONT: u32 = 0x10;
+
+// light tracerがsplatするフィルムのbufferのstorage bufferのindex。
+// common.glslのLIGHT_TRACE_FILM_BUFFER_INDEXと値を揃えること。
+const LIGHT_TRACE_FILM_BUFFER_INDEX: u32 = 5;
+
+// pixelごとのintersection shaderとany hit shaderの呼び出し回数を数えるbufferのindex。
+// traversal_heatmap.glslのTRAVERSAL_HEATMAP_BUFFER_INDEXと値を揃えること。
+const TRAVERSAL_HEATMAP_BUFFER_INDEX: u32 = 6;
+
+// レイの統計を数えるbufferのstorage bufferのindex。
+// sceneのbufferが0から3を使うので、その後ろに固定で置く。
+// common.glslのRAY_STATS_BUFFER_INDEXと値を揃えること。
+const RAY_STATS_BUFFER_INDEX: u32 = 4;
+
+// debug outputとintegratorのdebug用のflagをまとめてpush constantsのdebug_flagsにする。
+// push constantsに空きがないので、debug用でないflagもここに詰める。
+// push_constants.glslと同じく、bitの割り当ては次のとおり。
+//   bit 0-2   (0x0007): debug_output
+//   bit 3-4   (0x0018): FLAG_CULL_BACK、FLAG_CULL_FRONT
+//   bit 5     (0x0020): FLAG_SPECTRAL
+//   bit 6-7   (0x00c0): FLAG_LIGHT_TRACE_SPLAT、FLAG_LIGHT_TRACE_GATHER
+//   bit 8-11  (0x0f00): DEBUG_FLAG_DIRECT_LIGHTING_ONLY、DEBUG_FLAG_INDIRECT_ONLY、
+//                       DEBUG_FLAG_DISABLE_NEE、DEBUG_FLAG_COLLECT_STATS
+//   bit 12-15 (0xf000): FLAG_USE_BACKGROUND_COLOR、FLAG_SUN_ENABLED、
+//                       DEBUG_FLAG_WHITE_FURNACE、FLAG_WORKING_SPACE_ACESCG
+//   bit 16-31          : half floatのambient occlusionの半径
+fn debug_flags(parameters: &crate::Parameters) -> u32 {
+    let mut flags = debug_output(parameters.display_image);
+    if parameters.direct_lighting_only {
+        flags |= DEBUG_FLAG_DIRECT_LIGHTING_ONLY;
+    }
+    if parameters.indirect_only {
+        flags |= DEBUG_FLAG_INDIRECT_ONLY;
+    }
+    if parameters.disable_nee {
+        flags |= DEBUG_FLAG_DISABLE_NEE;
+    }
+    if parameters.collect_stats {
+        flags |= DEBUG_FLAG_COLLECT_STATS;
+    }
+    if parameters.white_furnace {
+        // white furnace testでは背景を放射輝度1の一様な環境光にして、太陽と発光面を使わない
+        flags |= DEBUG_FLAG_WHITE_FURNACE | FLAG_USE_BACKGROUND_COLOR;
+    } else {
+        if !parameters.background_is_environment {
+            flags |= FLAG_USE_BACKGROUND_COLOR;
+        }
+        if parameters.sun_enabled == 1 {
+            flags |= FLAG_SUN_ENABLED;
+        }
+    }
+    if parameters.working_color_space == crate::WorkingColorSpace::AcesCg {
+        flags |= FLAG_WORKING_SPACE_ACESCG;
+    }
+    if parameters.spectral {
+        flags |= FLAG_SPECTRAL;
+    }
+    match parameters.cull_mode {
+        crate::CullMode::None => {}
+        crate::CullMode::Back => flags |= FLAG_CULL_BACK,
+        crate::CullMode::Front => flags |= FLAG_CULL_FRONT,
+    }
+    // ambient occlusionの半径はhalf floatにして上位16bitに詰める
+    let ao_radius = exr::prelude::f16::from_f32(parameters.ao_radius.max(0.0)).to_bits() as u32;
+    flags | (ao_radius << 16)
+}
+
+// レンズの歪みの係数k1とk2をhalf floatにして下位16bitと上位16bitに詰める
+fn lens_distortion(parameters: &crate::Parameters) -> u32 {
+    let k1 = exr::prelude::f16::from_f32(parameters.lens_distortion).to_bits() as u32;
+    let k2 = exr::prelude::f16::from_f32(parameters.lens_distortion_k2).to_bits() as u32;
+    k1 | (k2 << 16)
+}
+
+// new_with_output_imageで渡された、outputの結果をコピーする外部のimage
+struct ExternalOutputImage {
+    image: ashtray::ImageHandle,
+    width: u32,
+    height: u32,
+}
+
+// oidnのfilterと共有buffer
+struct OidnObjects {
+    filter: OidnFilter,
+    // albedoとnormalをそれぞれin-placeでdenoiseするprefilter
+    albedo_prefilter: OidnFilter,
+    normal_prefilter: OidnFilter,
+    _color_buffer: OidnBuffer,
+    _albedo_buffer: OidnBuffer,
+    _normal_buffer: OidnBuffer,
+    _output_buffer: OidnBuffer,
+}
+impl OidnObjects {
+    fn new(
+        device: &OidnDevice,
+        width: u32,
+        height: u32,
+        color_buffer: &ashtray::utils::SharedBuffer,
+        albedo_buffer: &ashtray::utils::SharedBuffer,
+        normal_buffer: &ashtray::utils::SharedBuffer,
+        output_buffer: &ashtray::utils::SharedBuffer,
+    ) -> Result<Self, OidnError> {
+        let color_buffer = device.new_buffer(color_buffer)?;
+        let albedo_buffer = device.new_buffer(albedo_buffer)?;
+        let normal_buffer = device.new_buffer(normal_buffer)?;
+        let output_buffer = device.new_buffer(output_buffer)?;
+        let mut filter = device.new_filter("RT")?;
+        filter.hdr(true);
+        filter.srgb(false);
+        filter.resize(width, height);
+        filter.color(&color_buffer);
+        filter.albedo(&albedo_buffer);
+        filter.normal(&normal_buffer);
+        filter.output(&output_buffer);
+        filter.commit()?;
+
+        // prefilterは使うときにexecuteでcommitされる
+        let mut albedo_prefilter = device.new_filter("RT")?;
+        albedo_prefilter.resize(width, height);
+        albedo_prefilter.albedo(&albedo_buffer);
+        albedo_prefilter.output(&albedo_buffer);
+        let mut normal_prefilter = device.new_filter("RT")?;
+        normal_prefilter.resize(width, height);
+        normal_prefilter.normal(&normal_buffer);
+        normal_prefilter.output(&normal_buffer);
+
+        Ok(Self {
+            filter,
+            albedo_prefilter,
+            normal_prefilter,
+            _color_buffer: color_buffer,
+            _albedo_buffer: albedo_buffer,
+            _normal_buffer: normal_buffer,
+            _output_buffer: output_buffer,
+        })
+    }
+
+    // denoiseを実行する。
+    // prefilter_auxがtrueの場合は先にalbedoとnormalをprefilterでdenoiseしてから、
+    // それらがノイズのないものとしてclean_auxを有効にしたfilterでcolorをdenoiseする。
+    fn execute(&self, prefilter_aux: bool) -> Result<(), OidnError> {
+        if prefilter_aux {
+            self.albedo_prefilter.execute()?;
+            self.normal_prefilter.execute()?;
+        }
+        self.filter.clean_aux(prefilter_aux);
+        self.filter.execute()
+    }
+}
+
+// Renderer::new_with_output_imageに渡す、Renderer::newと同じ引数をまとめたもの
+pub struct RendererDesc {
+    pub width: u32,
+    pub height: u32,
+    pub instance: ashtray::InstanceHandle,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: ashtray::DeviceHandle,
+    pub queue_handles: ashtray::utils::QueueHandles,
+    pub graphics_command_pool: ashtray::CommandPoolHandle,
+    pub allocator: ashtray::AllocatorHandle,
+    pub pipeline_cache: ashtray::PipelineCacheHandle,
 }
 
 pub struct Renderer {
@@ -89,33 +383,47 @@ pub struct Renderer {
     compute_command_pool: ashtray::CommandPoolHandle,
     transfer_command_buffer: ashtray::CommandBufferHandle,
     allocator: ashtray::AllocatorHandle,
+    pipeline_cache: ashtray::PipelineCacheHandle,
 
+    sampler_cache: ashtray::utils::SamplerCache,
+    memory_budget_fraction: f32,
     sampler: ashtray::SamplerHandle,
     accumulate_image: ashtray::utils::ImageHandles,
     base_color_image: ashtray::utils::ImageHandles,
     normal_image: ashtray::utils::ImageHandles,
     resolved_image: ashtray::utils::ImageHandles,
     denoised_image: ashtray::utils::ImageHandles,
+    // À-Trousのpassの間でdenoised imageと交互に読み書きする中間画像
+    a_trous_image: ashtray::utils::ImageHandles,
     output_images: [ashtray::utils::ImageHandles; 2],
+    bounce_count_image: ashtray::utils::ImageHandles,
+    geometric_normal_image: ashtray::utils::ImageHandles,
+    instance_id_image: ashtray::utils::ImageHandles,
+    depth_image: ashtray::utils::ImageHandles,
+    position_image: ashtray::utils::ImageHandles,
 
     color_buffer: ashtray::utils::SharedBuffer,
     albedo_buffer: ashtray::utils::SharedBuffer,
     normal_buffer: ashtray::utils::SharedBuffer,
     output_buffer: ashtray::utils::SharedBuffer,
-
-    oidn_device: OidnDevice,
-    oidn_filter: OidnFilter,
-    oidn_color_buffer: OidnBuffer,
-    oidn_albedo_buffer: OidnBuffer,
-    oidn_normal_buffer: OidnBuffer,
-    oidn_output_buffer: OidnBuffer,
-
-    before_denoise_compute_pipeline_layout: ashtray::PipelineLayoutHandle,
-    before_denoise_compute_pipeline: ashtray::ComputePipelineHandle,
-    after_denoise_compute_pipeline_layout: ashtray::PipelineLayoutHandle,
-    after_denoise_compute_pipeline: ashtray::ComputePipelineHandle,
-    denoise_command_buffer: ashtray::CommandBufferHandle,
-    denoise_fence: ashtray::FenceHandle,
+    // resolved imageの輝度のhistogramを書き込むbuffer
+    luminance_histogram_buffer: ashtray::utils::SharedBuffer,
+    // traceしたレイの数とhitしたレイの数を数えるbuffer
+    ray_stats_buffer: ashtray::utils::SharedBuffer,
+    // light tracerがsplatするpixelごとのRGBのfloatのbuffer
+    light_trace_film_buffer: ashtray::utils::SharedBuffer,
+    // pixelごとのintersection shaderとany hit shaderの呼び出し回数のbuffer
+    traversal_heatmap_buffer: ashtray::utils::SharedBuffer,
+    // 各passのGPUの処理時間を計測するtimestamp query
+    gpu_timer_pool: ashtray::utils::GpuTimerPool,
+
+    // oidnの初期化に失敗した場合はNoneになり、denoiseせずにresolveした画像をそのまま使う
+    oidn_device: Option<OidnDevice>,
+    oidn_objects: Option<OidnObjects>,
+
+    a_trous_compute_pipeline_layout: ashtray::PipelineLayoutHandle,
+    a_trous_compute_pipeline: ashtray::ComputePipelineHandle,
+    a_trous_command_buffer: ashtray::CommandBufferHandle,
 
     descriptor_sets: ashtray::utils::BindlessDescriptorSets,
 
@@ -125,8 +433,18 @@ pub struct Renderer {
     resolved_image_index: u32,
     denoised_image_index: u32,
     output_image_indices: [u32; 2],
+    bounce_count_image_index: u32,
+    geometric_normal_image_index: u32,
+    instance_id_image_index: u32,
+    depth_image_index: u32,
+    position_image_index: u32,
+    a_trous_image_index: u32,
 
     scene_objects: Option<crate::scene::SceneObjects>,
+    // 現在のTLASを作ったときのanimationの時刻。sceneを読み込んだ直後はNone
+    tlas_animation_time: Option<f32>,
+    // 現在のmotion instanceのTLASを作ったときのshutter_speed。sceneを読み込んだ直後はNone
+    tlas_shutter_speed: Option<f32>,
 
     ray_tracing_pipeline: Option<ashtray::RayTracingPipelineHandle>,
     ray_tracing_pipeline_layout: Option<ashtray::PipelineLayoutHandle>,
@@ -135,28 +453,72 @@ pub struct Renderer {
     shader_binding_table: Option<ashtray::utils::ShaderBindingTable>,
     instance_params_buffer_index: Option<u32>,
     materials_buffer_index: Option<u32>,
+    lights_buffer_index: Option<u32>,
+    instance_light_indices_buffer_index: Option<u32>,
     render_command_buffer: ashtray::CommandBufferHandle,
-    render_fence: ashtray::FenceHandle,
 
     resolve_compute_pipeline_layout: ashtray::PipelineLayoutHandle,
     resolve_compute_pipeline: ashtray::ComputePipelineHandle,
     resolve_command_buffer: ashtray::CommandBufferHandle,
-    resolve_fence: ashtray::FenceHandle,
+    luminance_histogram_command_buffer: ashtray::CommandBufferHandle,
+
+    // skinを持つmodelの頂点を変形するcompute pipeline。render_command_bufferに積む
+    skinning_compute_pipeline_layout: ashtray::PipelineLayoutHandle,
+    skinning_compute_pipeline: ashtray::ComputePipelineHandle,
 
     output_compute_pipeline_layout: ashtray::PipelineLayoutHandle,
     output_compute_pipeline: ashtray::ComputePipelineHandle,
     output_command_buffers: [ashtray::CommandBufferHandle; 2],
     output_fences: [ashtray::FenceHandle; 2],
+    // Someの場合はoutput imageに加えてこのimageにも最終結果を書き込む
+    external_output_image: Option<ExternalOutputImage>,
+
+    // trace、resolve、denoise、outputのsubmitを直列化するtimeline semaphore。
+    // 各passは直前にsubmitしたpassの値を待ってから次の値をsignalする。
+    // CPUは必要になるまで待たないので、oidnの実行中にGPUは次のサンプルのtraceを進められる。
+    timeline_semaphore: ashtray::SemaphoreHandle,
+    timeline_value: u64,
+    // 各command bufferを最後にsubmitしたときにsignalするtimelineの値。
+    // command bufferを使い回す前にこの値を待つ。
+    render_timeline_value: u64,
+    resolve_timeline_value: u64,
+    a_trous_timeline_value: u64,
+    // 最後にsubmitしたoutputの値。oidnの出力のbufferを書き換える前にこの値を待つ
+    output_timeline_value: u64,
 
     current_image_index: usize,
 
     sample_count: u32,
+    // 1回のrenderでtraceするサンプル数
+    samples_per_frame: u32,
     rendering_start_time: Instant,
     rendering_time: Duration,
+    // max_render_secondsの時間が経過して蓄積を止めたかどうか
+    render_time_limit_reached: bool,
+    // pauseした時刻。Someの間はrenderで新しいサンプルをtraceしない
+    paused_at: Option<Instant>,
 
     need_resolve: bool,
     need_denoise: bool,
+    // resolveまでsubmitして、oidnの実行を次のrenderまで遅延しているかどうか
+    pending_denoise: bool,
+    // denoised imageかoidnの出力のbufferが現在の蓄積に対するdenoise結果かどうか
+    denoised_valid: bool,
 }
+// ray tracing pipelineで使うshader module
+struct RayTracingShaderModules {
+    raygen: ashtray::ShaderModuleHandle,
+    material_closest_hit: ashtray::ShaderModuleHandle,
+    material_anyhit: ashtray::ShaderModuleHandle,
+    material_miss: ashtray::ShaderModuleHandle,
+    shadow_closest_hit: ashtray::ShaderModuleHandle,
+    shadow_anyhit_alpha_blend: ashtray::ShaderModuleHandle,
+    shadow_anyhit_alpha_mask: ashtray::ShaderModuleHandle,
+    shadow_miss: ashtray::ShaderModuleHandle,
+    sphere_closest_hit: ashtray::ShaderModuleHandle,
+    sphere_intersection: ashtray::ShaderModuleHandle,
+}
+
 impl Renderer {
     pub fn new(
         width: u32,
@@ -167,6 +529,7 @@ impl Renderer {
         queue_handles: ashtray::utils::QueueHandles,
         graphics_command_pool: ashtray::CommandPoolHandle,
         allocator: ashtray::AllocatorHandle,
+        pipeline_cache: ashtray::PipelineCacheHandle,
     ) -> Self {
         let transfer_command_pool =
             ashtray::utils::create_transfer_command_pool(&device, &queue_handles);
@@ -179,9 +542,12 @@ impl Renderer {
                 .unwrap();
 
         // samplerの作成
-        let sampler = ashtray::utils::create_sampler(&device);
+        // 同じ設定のsamplerはsceneのテクスチャも含めてキャッシュから共有する
+        let sampler_cache = ashtray::utils::SamplerCache::new(&device);
+        let sampler = sampler_cache.get(&ashtray::utils::SamplerDesc::nearest_clamp());
 
         // レンダリングに必要なimageの作成
+        // 蓄積用のimageは誤差を避けるためfloat32、それ以外は帯域削減のためfloat16にする
         let accumulate_image = ashtray::utils::create_storage_image(
             &device,
             &queue_handles,
@@ -189,6 +555,7 @@ impl Renderer {
             &transfer_command_buffer,
             width,
             height,
+            vk::Format::R32G32B32A32_SFLOAT,
         );
         let base_color_image = ashtray::utils::create_storage_image(
             &device,
@@ -197,6 +564,7 @@ impl Renderer {
             &transfer_command_buffer,
             width,
             height,
+            vk::Format::R16G16B16A16_SFLOAT,
         );
         let normal_image = ashtray::utils::create_storage_image(
             &device,
@@ -205,6 +573,7 @@ impl Renderer {
             &transfer_command_buffer,
             width,
             height,
+            vk::Format::R16G16B16A16_SFLOAT,
         );
         let resolved_image = ashtray::utils::create_storage_image(
             &device,
@@ -213,6 +582,7 @@ impl Renderer {
             &transfer_command_buffer,
             width,
             height,
+            vk::Format::R16G16B16A16_SFLOAT,
         );
         let denoised_image = ashtray::utils::create_storage_image(
             &device,
@@ -221,6 +591,61 @@ impl Renderer {
             &transfer_command_buffer,
             width,
             height,
+            vk::Format::R16G16B16A16_SFLOAT,
+        );
+        let a_trous_image = ashtray::utils::create_storage_image(
+            &device,
+            &queue_handles,
+            &allocator,
+            &transfer_command_buffer,
+            width,
+            height,
+            vk::Format::R16G16B16A16_SFLOAT,
+        );
+        let bounce_count_image = ashtray::utils::create_storage_image(
+            &device,
+            &queue_handles,
+            &allocator,
+            &transfer_command_buffer,
+            width,
+            height,
+            vk::Format::R32G32B32A32_SFLOAT,
+        );
+        let geometric_normal_image = ashtray::utils::create_storage_image(
+            &device,
+            &queue_handles,
+            &allocator,
+            &transfer_command_buffer,
+            width,
+            height,
+            vk::Format::R16G16B16A16_SFLOAT,
+        );
+        let instance_id_image = ashtray::utils::create_storage_image(
+            &device,
+            &queue_handles,
+            &allocator,
+            &transfer_command_buffer,
+            width,
+            height,
+            vk::Format::R32_UINT,
+        );
+        let depth_image = ashtray::utils::create_storage_image(
+            &device,
+            &queue_handles,
+            &allocator,
+            &transfer_command_buffer,
+            width,
+            height,
+            vk::Format::R32_SFLOAT,
+        );
+        let position_image = ashtray::utils::create_storage_image(
+            &device,
+            &queue_handles,
+            &allocator,
+            &transfer_command_buffer,
+            width,
+            height,
+            vk::Format::R32G32B32A32_SFLOAT,
         );
         let output_images = [
             ashtray::utils::create_shader_readonly_image(
@@ -231,7 +656,9 @@ impl Renderer {
                 width,
                 height,
                 vk::Format::R8G8B8A8_UNORM,
-                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
             ),
             ashtray::utils::create_shader_readonly_image(
                 &device,
@@ -241,7 +668,9 @@ impl Renderer {
                 width,
                 height,
                 vk::Format::R8G8B8A8_UNORM,
-                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
             ),
         ];
 
@@ -275,20 +704,70 @@ impl Renderer {
                 | vk::BufferUsageFlags::STORAGE_BUFFER,
         );
 
+        // 輝度のhistogram用bufferの確保
+        let luminance_histogram_buffer = ashtray::utils::SharedBuffer::new(
+            &device,
+            (crate::exposure::LUMINANCE_HISTOGRAM_BIN_COUNT * std::mem::size_of::<u32>()) as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::STORAGE_BUFFER,
+        );
+
+        // レイの統計用bufferの確保
+        let ray_stats_buffer = ashtray::utils::SharedBuffer::new(
+            &device,
+            2 * std::mem::size_of::<u32>() as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::STORAGE_BUFFER,
+        );
+        let light_trace_film_buffer = ashtray::utils::SharedBuffer::new(
+            &device,
+            width as u64 * height as u64 * 3 * std::mem::size_of::<f32>() as u64,
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::STORAGE_BUFFER,
+        );
+        let traversal_heatmap_buffer = ashtray::utils::SharedBuffer::new(
+            &device,
+            width as u64 * height as u64 * std::mem::size_of::<u32>() as u64,
+            vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        );
+
+        // GPUの処理時間の計測用のquery poolの作成
+        let gpu_timer_pool = ashtray::utils::GpuTimerPool::new(&device, 8);
+
         // oidnの初期化
-        let oidn_device = OidnDevice::new();
-        let oidn_color_buffer = oidn_device.new_buffer(&color_buffer);
-        let oidn_albedo_buffer = oidn_device.new_buffer(&albedo_buffer);
-        let oidn_normal_buffer = oidn_device.new_buffer(&normal_buffer);
-        let oidn_output_buffer = oidn_device.new_buffer(&output_buffer);
-        let mut oidn_filter = oidn_device.new_filter("RT");
-        oidn_filter.hdr(true);
-        oidn_filter.srgb(false);
-        oidn_filter.resize(width, height);
-        oidn_filter.color(&oidn_color_buffer);
-        oidn_filter.albedo(&oidn_albedo_buffer);
-        oidn_filter.normal(&oidn_normal_buffer);
-        oidn_filter.output(&oidn_output_buffer);
+        // Vulkanと同じGPUでoidnのdeviceが作れればGPU上でdenoiseし、
+        // 作れなければデフォルトのdeviceにフォールバックする
+        let device_uuid = {
+            let mut physical_device_id_properties = vk::PhysicalDeviceIDProperties::builder();
+            let mut physical_device_properties = vk::PhysicalDeviceProperties2::builder()
+                .push_next(&mut physical_device_id_properties);
+            instance
+                .get_physical_device_properties2(physical_device, &mut physical_device_properties);
+            physical_device_id_properties.device_uuid
+        };
+        let oidn_device = match OidnDevice::new_on_gpu(&device_uuid) {
+            Ok(oidn_device) if oidn_device.supports_shared_buffer() => Ok(oidn_device),
+            _ => OidnDevice::new(),
+        }
+        .map_err(|e| eprintln!("[WARN]OIDN is unavailable, denoise is disabled: {}", e))
+        .ok();
+        let oidn_objects = oidn_device.as_ref().and_then(|oidn_device| {
+            OidnObjects::new(
+                oidn_device,
+                width,
+                height,
+                &color_buffer,
+                &albedo_buffer,
+                &normal_buffer,
+                &output_buffer,
+            )
+            .map_err(|e| eprintln!("[WARN]OIDN is unavailable, denoise is disabled: {}", e))
+            .ok()
+        });
 
         // render用command bufferを作成
         let render_command_buffer = {
@@ -301,9 +780,6 @@ impl Renderer {
             command_buffers.into_iter().next().unwrap()
         };
 
-        // render用fenceの作成
-        let render_fence = ashtray::utils::create_signaled_fence(&device);
-
         // bindlessなdescriptor setsを作成
         let descriptor_sets = ashtray::utils::BindlessDescriptorSets::create(&device);
         let accumulate_image_index = 0;
@@ -333,104 +809,149 @@ impl Renderer {
         descriptor_sets
             .storage_image
             .update(&output_images[1], output_image_indices[1]);
+        let bounce_count_image_index = 7;
+        descriptor_sets
+            .storage_image
+            .update(&bounce_count_image, bounce_count_image_index);
+        let geometric_normal_image_index = 8;
+        descriptor_sets
+            .storage_image
+            .update(&geometric_normal_image, geometric_normal_image_index);
+        let instance_id_image_index = 9;
+        descriptor_sets
+            .storage_image
+            .update(&instance_id_image, instance_id_image_index);
+        // depthとpositionはinstance idに続くindexに置く
+        let depth_image_index = 10;
+        descriptor_sets
+            .storage_image
+            .update(&depth_image, depth_image_index);
+        let position_image_index = 11;
+        descriptor_sets
+            .storage_image
+            .update(&position_image, position_image_index);
+        let a_trous_image_index = 12;
+        descriptor_sets
+            .storage_image
+            .update(&a_trous_image, a_trous_image_index);
+        descriptor_sets
+            .storage_buffer
+            .update(&ray_stats_buffer.buffer, RAY_STATS_BUFFER_INDEX);
+        descriptor_sets.storage_buffer.update(
+            &light_trace_film_buffer.buffer,
+            LIGHT_TRACE_FILM_BUFFER_INDEX,
+        );
+        descriptor_sets.storage_buffer.update(
+            &traversal_heatmap_buffer.buffer,
+            TRAVERSAL_HEATMAP_BUFFER_INDEX,
+        );
 
         // resolveのcompute pipelineを作成
         let resolve_compute_pipeline_layout = device.create_pipeline_layout(
             &vk::PipelineLayoutCreateInfo::builder()
                 .set_layouts(&[*descriptor_sets.storage_image.layout])
-                .push_constant_ranges(&[vk::PushConstantRange {
-                    stage_flags: vk::ShaderStageFlags::COMPUTE,
-                    offset: 0,
-                    size: std::mem::size_of::<ResolvePushConstants>() as u32,
-                }]),
+                .push_constant_ranges(&[
+                    ashtray::utils::push_constant_range::<ResolvePushConstants>(
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                    ),
+                ]),
         );
         let resolve_compute_shader_module = ashtray::utils::create_shader_module(
             &device,
             &include_bytes!("./shaders/spv/resolve.comp.spv")[..],
         );
-        let resolve_compute_pipeline = ashtray::utils::create_compute_pipeline(
-            &device,
-            &resolve_compute_pipeline_layout,
-            &resolve_compute_shader_module,
-        );
         let resolve_command_pool =
             ashtray::utils::create_compute_command_pool(&device, &queue_handles);
-        let resolve_command_buffer =
-            ashtray::utils::allocate_command_buffers(&device, &resolve_command_pool, 1)
-                .into_iter()
-                .next()
+        let [resolve_command_buffer, luminance_histogram_command_buffer]: [ashtray::CommandBufferHandle; 2] =
+            ashtray::utils::allocate_command_buffers(&device, &resolve_command_pool, 2)
+                .try_into()
                 .unwrap();
-        let resolve_fence = ashtray::utils::create_signaled_fence(&device);
 
-        // denosiseのcompute pipelineを作成
-        let before_denoise_compute_pipeline_layout = device.create_pipeline_layout(
-            &vk::PipelineLayoutCreateInfo::builder()
-                .set_layouts(&[
-                    *descriptor_sets.storage_image.layout,
-                    *descriptor_sets.storage_buffer.layout,
-                ])
-                .push_constant_ranges(&[vk::PushConstantRange {
-                    stage_flags: vk::ShaderStageFlags::COMPUTE,
-                    offset: 0,
-                    size: std::mem::size_of::<BeforeDenoisePushConstants>() as u32,
-                }]),
-        );
-        let before_denoise_compute_shader_module = ashtray::utils::create_shader_module(
-            &device,
-            &include_bytes!("./shaders/spv/before_denoise.comp.spv")[..],
-        );
-        let before_denoise_compute_pipeline = ashtray::utils::create_compute_pipeline(
-            &device,
-            &before_denoise_compute_pipeline_layout,
-            &before_denoise_compute_shader_module,
-        );
-        let after_denoise_compute_pipeline_layout = device.create_pipeline_layout(
+        // À-Trousのcompute pipelineを作成
+        let a_trous_compute_pipeline_layout = device.create_pipeline_layout(
             &vk::PipelineLayoutCreateInfo::builder()
-                .set_layouts(&[
-                    *descriptor_sets.storage_image.layout,
-                    *descriptor_sets.storage_buffer.layout,
-                ])
-                .push_constant_ranges(&[vk::PushConstantRange {
-                    stage_flags: vk::ShaderStageFlags::COMPUTE,
-                    offset: 0,
-                    size: std::mem::size_of::<AfterDenoisePushConstants>() as u32,
-                }]),
-        );
-        let after_denoise_compute_shader_module = ashtray::utils::create_shader_module(
-            &device,
-            &include_bytes!("./shaders/spv/after_denoise.comp.spv")[..],
+                .set_layouts(&[*descriptor_sets.storage_image.layout])
+                .push_constant_ranges(&[
+                    ashtray::utils::push_constant_range::<ATrousPushConstants>(
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                    ),
+                ]),
         );
-        let after_denoise_compute_pipeline = ashtray::utils::create_compute_pipeline(
+        let a_trous_compute_shader_module = ashtray::utils::create_shader_module(
             &device,
-            &after_denoise_compute_pipeline_layout,
-            &after_denoise_compute_shader_module,
+            &include_bytes!("./shaders/spv/a_trous.comp.spv")[..],
         );
-        let denoise_command_buffer =
+        let a_trous_command_buffer =
             ashtray::utils::allocate_command_buffers(&device, &compute_command_pool, 1)
                 .into_iter()
                 .next()
                 .unwrap();
-        let denoise_fence = ashtray::utils::create_fence(&device);
 
         // outputのcompute pipelineを作成
         let output_compute_pipeline_layout = device.create_pipeline_layout(
             &vk::PipelineLayoutCreateInfo::builder()
                 .set_layouts(&[*descriptor_sets.storage_image.layout])
-                .push_constant_ranges(&[vk::PushConstantRange {
-                    stage_flags: vk::ShaderStageFlags::COMPUTE,
-                    offset: 0,
-                    size: std::mem::size_of::<FinalPushConstants>() as u32,
-                }]),
+                .push_constant_ranges(&[
+                    ashtray::utils::push_constant_range::<FinalPushConstants>(
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                    ),
+                ]),
         );
         let output_compute_shader_module = ashtray::utils::create_shader_module(
             &device,
             &include_bytes!("./shaders/spv/output.comp.spv")[..],
         );
-        let output_compute_pipeline = ashtray::utils::create_compute_pipeline(
+
+        // skinningのcompute pipelineを作成
+        let skinning_compute_pipeline_layout = device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&[
+                ashtray::utils::push_constant_range::<crate::scene::SkinningPushConstants>(
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                ),
+            ]),
+        );
+        let skinning_compute_shader_module = ashtray::utils::create_shader_module(
             &device,
-            &output_compute_pipeline_layout,
-            &output_compute_shader_module,
+            &include_bytes!("./shaders/spv/skinning.comp.spv")[..],
         );
+
+        // compute pipelineをまとめて作成
+        let mut compute_pipelines = ashtray::utils::create_compute_pipelines_batch(
+            &device,
+            &pipeline_cache,
+            &[
+                (
+                    &resolve_compute_pipeline_layout,
+                    &resolve_compute_shader_module,
+                    "main",
+                ),
+                (
+                    &a_trous_compute_pipeline_layout,
+                    &a_trous_compute_shader_module,
+                    "main",
+                ),
+                (
+                    &output_compute_pipeline_layout,
+                    &output_compute_shader_module,
+                    "main",
+                ),
+                (
+                    &skinning_compute_pipeline_layout,
+                    &skinning_compute_shader_module,
+                    "main",
+                ),
+            ],
+        )
+        .into_iter();
+        let resolve_compute_pipeline = compute_pipelines.next().unwrap();
+        let a_trous_compute_pipeline = compute_pipelines.next().unwrap();
+        let output_compute_pipeline = compute_pipelines.next().unwrap();
+        let skinning_compute_pipeline = compute_pipelines.next().unwrap();
+
         let output_command_pool =
             ashtray::utils::create_compute_command_pool(&device, &queue_handles);
         let output_command_buffers: [ashtray::CommandBufferHandle; 2] =
@@ -442,6 +963,9 @@ impl Renderer {
             ashtray::utils::create_signaled_fence(&device),
         ];
 
+        // passのsubmitを直列化するtimeline semaphoreを作成
+        let timeline_semaphore = ashtray::utils::create_timeline_semaphore(&device, 0);
+
         Self {
             params: crate::Parameters::default(),
 
@@ -453,33 +977,40 @@ impl Renderer {
             compute_command_pool,
             transfer_command_buffer,
             allocator,
+            pipeline_cache,
 
+            sampler_cache,
+            memory_budget_fraction: DEFAULT_MEMORY_BUDGET_FRACTION,
             sampler,
             accumulate_image,
             base_color_image,
             normal_image,
             resolved_image,
             denoised_image,
+            a_trous_image,
             output_images,
+            bounce_count_image,
+            geometric_normal_image,
+            instance_id_image,
+            depth_image,
+            position_image,
 
             color_buffer,
             albedo_buffer,
             normal_buffer,
             output_buffer,
+            luminance_histogram_buffer,
+            ray_stats_buffer,
+            light_trace_film_buffer,
+            traversal_heatmap_buffer,
+            gpu_timer_pool,
 
             oidn_device,
-            oidn_color_buffer,
-            oidn_albedo_buffer,
-            oidn_normal_buffer,
-            oidn_output_buffer,
-            oidn_filter,
-
-            before_denoise_compute_pipeline_layout,
-            before_denoise_compute_pipeline,
-            after_denoise_compute_pipeline_layout,
-            after_denoise_compute_pipeline,
-            denoise_command_buffer,
-            denoise_fence,
+            oidn_objects,
+
+            a_trous_compute_pipeline_layout,
+            a_trous_compute_pipeline,
+            a_trous_command_buffer,
 
             descriptor_sets,
 
@@ -489,8 +1020,16 @@ impl Renderer {
             resolved_image_index,
             denoised_image_index,
             output_image_indices,
+            bounce_count_image_index,
+            geometric_normal_image_index,
+            instance_id_image_index,
+            depth_image_index,
+            position_image_index,
+            a_trous_image_index,
 
             scene_objects: None,
+            tlas_animation_time: None,
+            tlas_shutter_speed: None,
 
             ray_tracing_pipeline: None,
             ray_tracing_pipeline_layout: None,
@@ -498,31 +1037,126 @@ impl Renderer {
             shader_binding_table: None,
             instance_params_buffer_index: None,
             materials_buffer_index: None,
+            lights_buffer_index: None,
+            instance_light_indices_buffer_index: None,
             render_command_buffer,
-            render_fence,
 
             resolve_compute_pipeline_layout,
             resolve_compute_pipeline,
             resolve_command_buffer,
-            resolve_fence,
+            luminance_histogram_command_buffer,
+
+            skinning_compute_pipeline_layout,
+            skinning_compute_pipeline,
 
             output_compute_pipeline_layout,
             output_compute_pipeline,
             output_command_buffers,
             output_fences,
+            external_output_image: None,
+
+            timeline_semaphore,
+            timeline_value: 0,
+            render_timeline_value: 0,
+            resolve_timeline_value: 0,
+            a_trous_timeline_value: 0,
+            output_timeline_value: 0,
 
             current_image_index: 0,
 
             sample_count: 0,
+            samples_per_frame: 1,
             rendering_start_time: Instant::now(),
             rendering_time: Duration::from_secs(0),
+            render_time_limit_reached: false,
+            paused_at: None,
 
             need_resolve: false,
             need_denoise: false,
+            pending_denoise: false,
+            denoised_valid: false,
+        }
+    }
+
+    // Renderer::newと同様にRendererを作成し、最終結果を渡されたoutput_imageにもコピーするようにする。
+    // OpenXRのswapchainや動画のエンコーダなど外部のAPIとimageを共有して、
+    // CPUを経由せずに結果を受け渡すのに使う。
+    // output_imageは主にashtray::ImageHandle::from_external_memoryでimportしたものを想定している。
+    //
+    // output_imageはdescのwidth x heightの2D imageで、usageにTRANSFER_DSTを含み、
+    // formatがBLIT_DSTに対応している必要がある。outputの結果はblitでformatを変換してコピーする。
+    // output_imageはGENERALのlayoutに遷移させてそのままにするので、外部のAPIもGENERALとして扱う。
+    // Parametersのwidthとheightがoutput_imageと異なる間はoutput_imageには書き込まない。
+    // 書き込みの完了はwait_outputで待つことができる。
+    pub fn new_with_output_image(desc: RendererDesc, output_image: ashtray::ImageHandle) -> Self {
+        let width = desc.width;
+        let height = desc.height;
+        let mut renderer = Self::new(
+            desc.width,
+            desc.height,
+            desc.instance,
+            desc.physical_device,
+            desc.device,
+            desc.queue_handles,
+            desc.graphics_command_pool,
+            desc.allocator,
+            desc.pipeline_cache,
+        );
+
+        // outputのコピーはcompute queueで行うので、GENERALのlayoutへの遷移もcompute queueで行う
+        {
+            let command_buffer = ashtray::utils::allocate_command_buffers(
+                &renderer.device,
+                &renderer.compute_command_pool,
+                1,
+            )
+            .into_iter()
+            .next()
+            .unwrap();
+            let fence = ashtray::utils::create_fence(&renderer.device);
+            ashtray::utils::begin_onetime_command_buffer(&command_buffer);
+            ashtray::utils::cmd_image_barriers(
+                &command_buffer,
+                vk::PipelineStageFlags2::TOP_OF_PIPE,
+                vk::AccessFlags2::NONE,
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                vk::AccessFlags2::NONE,
+                vk::ImageLayout::GENERAL,
+                &output_image,
+            );
+            command_buffer.end_command_buffer();
+            renderer.device.queue_submit(
+                renderer.queue_handles.compute.queue,
+                std::slice::from_ref(
+                    &vk::SubmitInfo::builder()
+                        .command_buffers(&[*command_buffer])
+                        .wait_dst_stage_mask(&[])
+                        .wait_semaphores(&[]),
+                ),
+                Some(fence.clone()),
+            );
+            renderer.device.wait_fences(&[fence], u64::MAX);
         }
+
+        renderer.external_output_image = Some(ExternalOutputImage {
+            image: output_image,
+            width,
+            height,
+        });
+
+        renderer
     }
 
-    pub fn load_scene(&mut self, scene: &crate::Scene) {
+    // sceneを読み込む。
+    // 必要なGPUのメモリの見積もりがmemory budgetを超える場合は、
+    // GPUのメモリを確保せずにSceneTooLargeErrorを返し、前のsceneをそのまま使い続ける。
+    pub fn load_scene(&mut self, scene: &crate::Scene) -> Result<(), crate::SceneTooLargeError> {
+        // 実行中のtraceが古いsceneを参照しているので終わるのを待つ
+        self.wait_timeline(self.timeline_value);
+
+        let budget = (self.allocator.report().device_local_total as f64
+            * self.memory_budget_fraction as f64) as u64;
         let scene_objects = crate::scene::load_scene(
             &self.device,
             &self.queue_handles,
@@ -530,8 +1164,10 @@ impl Renderer {
             &self.transfer_command_pool,
             &self.allocator,
             &self.descriptor_sets,
+            &self.sampler_cache,
             scene,
-        );
+            budget,
+        )?;
 
         let instance_params_buffer_index = 0;
         self.descriptor_sets.storage_buffer.update(
@@ -543,6 +1179,15 @@ impl Renderer {
             &scene_objects.tlas.materials_buffer.buffer,
             materials_buffer_index,
         );
+        let lights_buffer_index = 2;
+        self.descriptor_sets
+            .storage_buffer
+            .update(&scene_objects.lights_buffer.buffer, lights_buffer_index);
+        let instance_light_indices_buffer_index = 3;
+        self.descriptor_sets.storage_buffer.update(
+            &scene_objects.instance_light_indices_buffer.buffer,
+            instance_light_indices_buffer_index,
+        );
 
         // acceleration structureのdescriptor setの作成
         let acceleration_structure_descriptor_set =
@@ -552,121 +1197,259 @@ impl Renderer {
             );
 
         // ray tracing pipelineの作成
-        let raygen_shader_module = ashtray::utils::create_shader_module(
-            &self.device,
-            include_bytes!("./shaders/spv/raygen.rgen.spv"),
-        );
-        let material_closest_hit_shader_module = ashtray::utils::create_shader_module(
-            &self.device,
-            include_bytes!("./shaders/spv/material/closesthit.rchit.spv"),
-        );
-        let material_anyhit_shader_module = ashtray::utils::create_shader_module(
-            &self.device,
-            include_bytes!("./shaders/spv/material/anyhit.rahit.spv"),
-        );
-        let material_miss_shader_module = ashtray::utils::create_shader_module(
-            &self.device,
-            include_bytes!("./shaders/spv/material/miss.rmiss.spv"),
-        );
-        let shadow_closest_hit_shader_module = ashtray::utils::create_shader_module(
-            &self.device,
-            include_bytes!("./shaders/spv/shadow/closesthit.rchit.spv"),
-        );
-        let shadow_anyhit_alpha_blend_shader_module = ashtray::utils::create_shader_module(
-            &self.device,
-            include_bytes!("./shaders/spv/shadow/anyhit_alpha_blend.rahit.spv"),
-        );
-        let shadow_anyhit_alpha_mask_shader_module = ashtray::utils::create_shader_module(
-            &self.device,
-            include_bytes!("./shaders/spv/shadow/anyhit_alpha_mask.rahit.spv"),
-        );
-        let shadow_miss_shader_module = ashtray::utils::create_shader_module(
-            &self.device,
-            include_bytes!("./shaders/spv/shadow/miss.rmiss.spv"),
-        );
-        let (ray_tracing_pipeline, pipeline_layout, shader_binding_table) =
-            ashtray::utils::create_ray_tracing_pipelines(
-                &self.instance,
-                self.physical_device,
+        // motion instanceのTLASではレイごとの時刻でtraceするraygenを使う
+        let motion = scene_objects.tlas.motion;
+        let shader_modules = RayTracingShaderModules {
+            raygen: ashtray::utils::create_shader_module(
                 &self.device,
-                &self.allocator,
-                &[raygen_shader_module],
-                &[material_miss_shader_module, shadow_miss_shader_module],
-                &[
-                    // material opaque
-                    ashtray::utils::HitShaderModules {
-                        closest_hit: Some(material_closest_hit_shader_module.clone()),
-                        any_hit: None,
-                        intersection: None,
-                    },
-                    // material alpha mask
-                    ashtray::utils::HitShaderModules {
-                        closest_hit: Some(material_closest_hit_shader_module.clone()),
-                        any_hit: Some(material_anyhit_shader_module),
-                        intersection: None,
-                    },
-                    // material alpha blend
-                    ashtray::utils::HitShaderModules {
-                        closest_hit: Some(material_closest_hit_shader_module),
-                        any_hit: None,
-                        intersection: None,
-                    },
-                    // shadow opaque
-                    ashtray::utils::HitShaderModules {
-                        closest_hit: Some(shadow_closest_hit_shader_module.clone()),
-                        any_hit: None,
-                        intersection: None,
-                    },
-                    // shadow alpha mask
-                    ashtray::utils::HitShaderModules {
-                        closest_hit: Some(shadow_closest_hit_shader_module.clone()),
-                        any_hit: Some(shadow_anyhit_alpha_mask_shader_module),
-                        intersection: None,
-                    },
-                    // shadow alpha blend
-                    ashtray::utils::HitShaderModules {
-                        closest_hit: Some(shadow_closest_hit_shader_module),
-                        any_hit: Some(shadow_anyhit_alpha_blend_shader_module),
-                        intersection: None,
-                    },
-                ],
-                &[
-                    *self.descriptor_sets.uniform_buffer.layout.clone(),
-                    *self.descriptor_sets.combined_image_sampler.layout.clone(),
-                    *self.descriptor_sets.storage_buffer.layout.clone(),
-                    *self.descriptor_sets.storage_image.layout.clone(),
-                    *acceleration_structure_descriptor_set.layout.clone(),
-                ],
-                &[vk::PushConstantRange::builder()
-                    .stage_flags(
-                        vk::ShaderStageFlags::RAYGEN_KHR
-                            | vk::ShaderStageFlags::ANY_HIT_KHR
-                            | vk::ShaderStageFlags::CLOSEST_HIT_KHR
-                            | vk::ShaderStageFlags::MISS_KHR,
-                    )
-                    .offset(0)
-                    .size(std::mem::size_of::<PushConstants>() as u32)
-                    .build()],
+                if motion {
+                    include_bytes!("./shaders/spv/raygen_motion.rgen.spv").as_slice()
+                } else {
+                    include_bytes!("./shaders/spv/raygen.rgen.spv").as_slice()
+                },
+            ),
+            material_closest_hit: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/material/closesthit.rchit.spv"),
+            ),
+            material_anyhit: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/material/anyhit.rahit.spv"),
+            ),
+            material_miss: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/material/miss.rmiss.spv"),
+            ),
+            shadow_closest_hit: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/shadow/closesthit.rchit.spv"),
+            ),
+            shadow_anyhit_alpha_blend: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/shadow/anyhit_alpha_blend.rahit.spv"),
+            ),
+            shadow_anyhit_alpha_mask: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/shadow/anyhit_alpha_mask.rahit.spv"),
+            ),
+            shadow_miss: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/shadow/miss.rmiss.spv"),
+            ),
+            sphere_closest_hit: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/sphere/closesthit.rchit.spv"),
+            ),
+            sphere_intersection: ashtray::utils::create_shader_module(
+                &self.device,
+                include_bytes!("./shaders/spv/sphere/intersection.rint.spv"),
+            ),
+        };
+        let (ray_tracing_pipeline, pipeline_layout, shader_binding_table) = self
+            .create_ray_tracing_pipeline(
+                shader_modules,
+                &acceleration_structure_descriptor_set.layout,
+                motion,
             );
 
         self.scene_objects = Some(scene_objects);
+        self.tlas_animation_time = None;
+        self.tlas_shutter_speed = None;
         self.ray_tracing_pipeline = Some(ray_tracing_pipeline);
         self.ray_tracing_pipeline_layout = Some(pipeline_layout);
         self.acceleration_structure_descriptor_set = Some(acceleration_structure_descriptor_set);
         self.shader_binding_table = Some(shader_binding_table);
         self.instance_params_buffer_index = Some(instance_params_buffer_index);
         self.materials_buffer_index = Some(materials_buffer_index);
+        self.lights_buffer_index = Some(lights_buffer_index);
+        self.instance_light_indices_buffer_index = Some(instance_light_indices_buffer_index);
+
+        // 前のsceneで蓄積したサンプルを破棄する
+        self.reset_accumulation();
+        Ok(())
+    }
+
+    // load_sceneで使えるdevice localメモリの割合を設定する。デフォルトは0.8。
+    // 読み込み前から確保されているメモリも含めてこの割合を超えるsceneは読み込まない。
+    pub fn set_memory_budget_fraction(&mut self, fraction: f32) {
+        self.memory_budget_fraction = fraction.clamp(0.0, 1.0);
+    }
+
+    // shader moduleからray tracing pipelineとshader binding tableを作成する。
+    // motionがtrueの場合はmotion instanceのTLASをtraceできるpipelineにする
+    fn create_ray_tracing_pipeline(
+        &self,
+        shader_modules: RayTracingShaderModules,
+        acceleration_structure_descriptor_set_layout: &ashtray::DescriptorSetLayoutHandle,
+        motion: bool,
+    ) -> (
+        ashtray::RayTracingPipelineHandle,
+        ashtray::PipelineLayoutHandle,
+        ashtray::utils::ShaderBindingTable,
+    ) {
+        ashtray::utils::create_ray_tracing_pipelines(
+            &self.instance,
+            self.physical_device,
+            &self.device,
+            &self.allocator,
+            &self.pipeline_cache,
+            &[shader_modules.raygen],
+            &[shader_modules.material_miss, shader_modules.shadow_miss],
+            &[
+                // material opaque
+                ashtray::utils::HitShaderModules {
+                    closest_hit: Some(shader_modules.material_closest_hit.clone()),
+                    any_hit: None,
+                    intersection: None,
+                },
+                // material alpha mask
+                ashtray::utils::HitShaderModules {
+                    closest_hit: Some(shader_modules.material_closest_hit.clone()),
+                    any_hit: Some(shader_modules.material_anyhit),
+                    intersection: None,
+                },
+                // material alpha blend
+                ashtray::utils::HitShaderModules {
+                    closest_hit: Some(shader_modules.material_closest_hit),
+                    any_hit: None,
+                    intersection: None,
+                },
+                // material point cloud sphere
+                ashtray::utils::HitShaderModules {
+                    closest_hit: Some(shader_modules.sphere_closest_hit),
+                    any_hit: None,
+                    intersection: Some(shader_modules.sphere_intersection.clone()),
+                },
+                // shadow opaque
+                ashtray::utils::HitShaderModules {
+                    closest_hit: Some(shader_modules.shadow_closest_hit.clone()),
+                    any_hit: None,
+                    intersection: None,
+                },
+                // shadow alpha mask
+                ashtray::utils::HitShaderModules {
+                    closest_hit: Some(shader_modules.shadow_closest_hit.clone()),
+                    any_hit: Some(shader_modules.shadow_anyhit_alpha_mask),
+                    intersection: None,
+                },
+                // shadow alpha blend
+                ashtray::utils::HitShaderModules {
+                    closest_hit: Some(shader_modules.shadow_closest_hit.clone()),
+                    any_hit: Some(shader_modules.shadow_anyhit_alpha_blend),
+                    intersection: None,
+                },
+                // shadow point cloud sphere
+                ashtray::utils::HitShaderModules {
+                    closest_hit: Some(shader_modules.shadow_closest_hit),
+                    any_hit: None,
+                    intersection: Some(shader_modules.sphere_intersection),
+                },
+            ],
+            &[
+                *self.descriptor_sets.uniform_buffer.layout.clone(),
+                *self.descriptor_sets.combined_image_sampler.layout.clone(),
+                *self.descriptor_sets.storage_buffer.layout.clone(),
+                *self.descriptor_sets.storage_image.layout.clone(),
+                **acceleration_structure_descriptor_set_layout,
+            ],
+            &[ashtray::utils::push_constant_range::<PushConstants>(
+                RAY_TRACING_PUSH_CONSTANT_STAGES,
+                0,
+            )],
+            if motion {
+                vk::PipelineCreateFlags::RAY_TRACING_ALLOW_MOTION_NV
+            } else {
+                vk::PipelineCreateFlags::empty()
+            },
+        )
+    }
+
+    // render regionを画像の範囲に切り詰めて(x, y, width, height)で返す。
+    // render regionが指定されていない場合は画像全体を返す。
+    fn render_region(&self) -> (u32, u32, u32, u32) {
+        let (width, height) = (self.params.width, self.params.height);
+        match self.params.render_region {
+            Some((x, y, w, h)) => {
+                let x = x.min(width);
+                let y = y.min(height);
+                (x, y, w.min(width - x), h.min(height - y))
+            }
+            None => (0, 0, width, height),
+        }
+    }
+
+    // ray tracingとresolveで使うrender region。
+    // 出力時に上下反転されるので、出力画像の座標でのrender regionのyを反転する。
+    fn flipped_render_region(&self) -> (u32, u32, u32, u32) {
+        let (x, y, width, height) = self.render_region();
+        (x, self.params.height - y - height, width, height)
+    }
+
+    // 直前にsubmitしたpassの完了を待ってから実行するようにcommand bufferをsubmitし、
+    // 完了時にsignalされるtimelineの値を返す。
+    // traceはgraphics queue、resolveとdenoiseとoutputはcompute queueにsubmitするので、
+    // timeline semaphoreはgraphicsとcomputeのqueue familyをまたいでpassを直列化する。
+    fn submit_on_timeline(
+        &mut self,
+        queue: vk::Queue,
+        command_buffer: &ashtray::CommandBufferHandle,
+        fence: Option<ashtray::FenceHandle>,
+    ) -> u64 {
+        let wait_value = self.timeline_value;
+        self.timeline_value += 1;
+        let signal_value = self.timeline_value;
+
+        let wait_semaphore_info = vk::SemaphoreSubmitInfo::builder()
+            .semaphore(*self.timeline_semaphore)
+            .value(wait_value)
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS);
+        let signal_semaphore_info = vk::SemaphoreSubmitInfo::builder()
+            .semaphore(*self.timeline_semaphore)
+            .value(signal_value)
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS);
+        let command_buffer_info =
+            vk::CommandBufferSubmitInfo::builder().command_buffer(**command_buffer);
+        let submit_info = vk::SubmitInfo2::builder()
+            .wait_semaphore_infos(std::slice::from_ref(&wait_semaphore_info))
+            .command_buffer_infos(std::slice::from_ref(&command_buffer_info))
+            .signal_semaphore_infos(std::slice::from_ref(&signal_semaphore_info))
+            .build();
+        self.device.queue_submit2(queue, &[submit_info], fence);
+
+        signal_value
+    }
+
+    // timelineがvalueに達するまでCPUで待つ
+    fn wait_timeline(&self, value: u64) {
+        self.device.wait_semaphores(
+            std::slice::from_ref(&self.timeline_semaphore),
+            std::slice::from_ref(&value),
+            u64::MAX,
+        );
     }
 
     fn set_parameters(&mut self, parameters: crate::Parameters) {
+        // 正距円筒図法は2:1の画像を前提にしているので、パラメータが変わったときに確認する
+        if self.params.requires_accumulation_reset(&parameters)
+            && parameters.camera_projection == crate::CameraProjection::Equirectangular
+            && parameters.width != parameters.height * 2
+        {
+            eprintln!(
+                "[WARN] Equirectangular projection expects a 2:1 image, but the size is {}x{}",
+                parameters.width, parameters.height
+            );
+        }
         if self.params.width != parameters.width || self.params.height != parameters.height {
             // width/heightが変わっていたらstorage imageをリサイズして作り直す。
             self.params = parameters;
             self.sample_count = 0;
             self.rendering_start_time = Instant::now();
             self.rendering_time = Duration::from_secs(0);
+            self.render_time_limit_reached = false;
 
             self.device.wait_idle();
+            self.pending_denoise = false;
+            self.denoised_valid = false;
 
             // imageの再生性
             self.accumulate_image = ashtray::utils::create_storage_image(
@@ -676,6 +1459,7 @@ impl Renderer {
                 &self.transfer_command_buffer,
                 self.params.width,
                 self.params.height,
+                vk::Format::R32G32B32A32_SFLOAT,
             );
             self.base_color_image = ashtray::utils::create_storage_image(
                 &self.device,
@@ -684,6 +1468,7 @@ impl Renderer {
                 &self.transfer_command_buffer,
                 self.params.width,
                 self.params.height,
+                vk::Format::R16G16B16A16_SFLOAT,
             );
             self.normal_image = ashtray::utils::create_storage_image(
                 &self.device,
@@ -692,6 +1477,7 @@ impl Renderer {
                 &self.transfer_command_buffer,
                 self.params.width,
                 self.params.height,
+                vk::Format::R16G16B16A16_SFLOAT,
             );
             self.resolved_image = ashtray::utils::create_storage_image(
                 &self.device,
@@ -700,6 +1486,7 @@ impl Renderer {
                 &self.transfer_command_buffer,
                 self.params.width,
                 self.params.height,
+                vk::Format::R16G16B16A16_SFLOAT,
             );
             self.denoised_image = ashtray::utils::create_storage_image(
                 &self.device,
@@ -708,29 +1495,63 @@ impl Renderer {
                 &self.transfer_command_buffer,
                 self.params.width,
                 self.params.height,
+                vk::Format::R16G16B16A16_SFLOAT,
             );
-            self.output_images = [
-                ashtray::utils::create_shader_readonly_image(
-                    &self.device,
-                    &self.queue_handles,
-                    &self.allocator,
-                    &self.transfer_command_buffer,
-                    self.params.width,
-                    self.params.height,
-                    vk::Format::R8G8B8A8_UNORM,
-                    vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
-                ),
-                ashtray::utils::create_shader_readonly_image(
-                    &self.device,
-                    &self.queue_handles,
-                    &self.allocator,
-                    &self.transfer_command_buffer,
-                    self.params.width,
-                    self.params.height,
-                    vk::Format::R8G8B8A8_UNORM,
-                    vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
-                ),
-            ];
+            self.a_trous_image = ashtray::utils::create_storage_image(
+                &self.device,
+                &self.queue_handles,
+                &self.allocator,
+                &self.transfer_command_buffer,
+                self.params.width,
+                self.params.height,
+                vk::Format::R16G16B16A16_SFLOAT,
+            );
+            self.bounce_count_image = ashtray::utils::create_storage_image(
+                &self.device,
+                &self.queue_handles,
+                &self.allocator,
+                &self.transfer_command_buffer,
+                self.params.width,
+                self.params.height,
+                vk::Format::R32G32B32A32_SFLOAT,
+            );
+            self.geometric_normal_image = ashtray::utils::create_storage_image(
+                &self.device,
+                &self.queue_handles,
+                &self.allocator,
+                &self.transfer_command_buffer,
+                self.params.width,
+                self.params.height,
+                vk::Format::R16G16B16A16_SFLOAT,
+            );
+            self.instance_id_image = ashtray::utils::create_storage_image(
+                &self.device,
+                &self.queue_handles,
+                &self.allocator,
+                &self.transfer_command_buffer,
+                self.params.width,
+                self.params.height,
+                vk::Format::R32_UINT,
+            );
+            self.depth_image = ashtray::utils::create_storage_image(
+                &self.device,
+                &self.queue_handles,
+                &self.allocator,
+                &self.transfer_command_buffer,
+                self.params.width,
+                self.params.height,
+                vk::Format::R32_SFLOAT,
+            );
+            self.position_image = ashtray::utils::create_storage_image(
+                &self.device,
+                &self.queue_handles,
+                &self.allocator,
+                &self.transfer_command_buffer,
+                self.params.width,
+                self.params.height,
+                vk::Format::R32G32B32A32_SFLOAT,
+            );
+            self.recreate_output_images();
 
             // accumulate bufferのクリア
             let command_buffer = self.render_command_buffer.clone();
@@ -795,16 +1616,19 @@ impl Renderer {
             );
 
             // oidnのfilterのりサイズ
-            self.oidn_color_buffer = self.oidn_device.new_buffer(&self.color_buffer);
-            self.oidn_albedo_buffer = self.oidn_device.new_buffer(&self.albedo_buffer);
-            self.oidn_normal_buffer = self.oidn_device.new_buffer(&self.normal_buffer);
-            self.oidn_output_buffer = self.oidn_device.new_buffer(&self.output_buffer);
-            self.oidn_filter
-                .resize(self.params.width, self.params.height);
-            self.oidn_filter.color(&self.oidn_color_buffer);
-            self.oidn_filter.albedo(&self.oidn_albedo_buffer);
-            self.oidn_filter.normal(&self.oidn_normal_buffer);
-            self.oidn_filter.output(&self.oidn_output_buffer);
+            self.oidn_objects = self.oidn_device.as_ref().and_then(|oidn_device| {
+                OidnObjects::new(
+                    oidn_device,
+                    self.params.width,
+                    self.params.height,
+                    &self.color_buffer,
+                    &self.albedo_buffer,
+                    &self.normal_buffer,
+                    &self.output_buffer,
+                )
+                .map_err(|e| eprintln!("[WARN]OIDN is unavailable, denoise is disabled: {}", e))
+                .ok()
+            });
 
             // descriptor setの更新
             let accumulate_image_index = 0;
@@ -827,57 +1651,183 @@ impl Renderer {
             self.descriptor_sets
                 .storage_image
                 .update(&self.denoised_image, denoised_image_index);
-            let output_image_indices = [5, 6];
+            let bounce_count_image_index = 7;
             self.descriptor_sets
                 .storage_image
-                .update(&self.output_images[0], output_image_indices[0]);
+                .update(&self.bounce_count_image, bounce_count_image_index);
+            let geometric_normal_image_index = 8;
             self.descriptor_sets
                 .storage_image
-                .update(&self.output_images[1], output_image_indices[1]);
-        } else if self.params != parameters {
-            // そうでなくてdirtyなら蓄積をリセットするコマンドのみを発行する。
-            self.params = parameters;
-            self.sample_count = 0;
-            self.rendering_start_time = Instant::now();
-            self.rendering_time = Duration::from_secs(0);
+                .update(&self.geometric_normal_image, geometric_normal_image_index);
+            let instance_id_image_index = 9;
+            self.descriptor_sets
+                .storage_image
+                .update(&self.instance_id_image, instance_id_image_index);
+            let depth_image_index = 10;
+            self.descriptor_sets
+                .storage_image
+                .update(&self.depth_image, depth_image_index);
+            let position_image_index = 11;
+            self.descriptor_sets
+                .storage_image
+                .update(&self.position_image, position_image_index);
+            let a_trous_image_index = 12;
+            self.descriptor_sets
+                .storage_image
+                .update(&self.a_trous_image, a_trous_image_index);
 
-            let command_buffer = self.render_command_buffer.clone();
-            command_buffer.reset_command_buffer(vk::CommandBufferResetFlags::RELEASE_RESOURCES);
-            ashtray::utils::begin_onetime_command_buffer(&command_buffer);
-            command_buffer.cmd_clear_color_image(
-                &self.accumulate_image.image,
-                vk::ImageLayout::GENERAL,
-                &vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 1.0],
-                },
-                &[vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                }],
+            self.light_trace_film_buffer = ashtray::utils::SharedBuffer::new(
+                &self.device,
+                self.params.width as u64
+                    * self.params.height as u64
+                    * 3
+                    * std::mem::size_of::<f32>() as u64,
+                vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::STORAGE_BUFFER,
             );
-            command_buffer.end_command_buffer();
-            let buffers_to_submit = [*command_buffer];
-            let submit_info = vk::SubmitInfo::builder()
-                .command_buffers(&buffers_to_submit)
-                .build();
-            let fence = ashtray::utils::create_fence(&self.device);
-            self.device.queue_submit(
-                self.queue_handles.graphics.queue,
-                &[submit_info],
-                Some(fence.clone()),
+            self.descriptor_sets.storage_buffer.update(
+                &self.light_trace_film_buffer.buffer,
+                LIGHT_TRACE_FILM_BUFFER_INDEX,
             );
-            self.device.wait_fences(&[fence], u64::MAX);
+
+            self.traversal_heatmap_buffer = ashtray::utils::SharedBuffer::new(
+                &self.device,
+                self.params.width as u64
+                    * self.params.height as u64
+                    * std::mem::size_of::<u32>() as u64,
+                vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            );
+            self.descriptor_sets.storage_buffer.update(
+                &self.traversal_heatmap_buffer.buffer,
+                TRAVERSAL_HEATMAP_BUFFER_INDEX,
+            );
+        } else if self.params.requires_accumulation_reset(&parameters)
+            || debug_output(self.params.display_image) != debug_output(parameters.display_image)
+        {
+            // そうでなくてdirtyなら蓄積をリセットするコマンドのみを発行する。
+            // bounce countとgeometric normalはその表示中にしか書き込まないので、
+            // 切り替えたときも蓄積し直す。
+            self.params = parameters;
+            self.reset_accumulation();
         } else {
-            // display imageのみの更新
+            // 露出やdisplay imageやdenoiseのパラメータなど、resolve以降の処理のみの更新。
+            // 蓄積はそのままで、output imageはrenderの度に書き込むので次のrenderで反映される
+            // HDRの出力の切り替えでoutput imageのformatが変わる場合は作り直す
+            if output_image_format(self.params.hdr_output)
+                != output_image_format(parameters.hdr_output)
+            {
+                self.device.wait_idle();
+                self.params.hdr_output = parameters.hdr_output;
+                self.recreate_output_images();
+            }
+            // denoiserが変わっていたら、別のdenoiserの結果や未処理のdenoiseは破棄する
+            if self.params.denoiser != parameters.denoiser {
+                self.pending_denoise = false;
+                self.denoised_valid = false;
+            }
+            // denoiseのパラメータが変わっていたらdenoise結果を作り直す
+            if (self.params.denoiser != parameters.denoiser
+                || self.params.denoise_strength != parameters.denoise_strength
+                || self.params.prefilter_aux != parameters.prefilter_aux
+                || self.params.a_trous_iterations != parameters.a_trous_iterations
+                || self.params.a_trous_sigma_color != parameters.a_trous_sigma_color
+                || self.params.a_trous_sigma_albedo != parameters.a_trous_sigma_albedo
+                || self.params.a_trous_sigma_normal != parameters.a_trous_sigma_normal
+                || self.params.a_trous_sigma_depth != parameters.a_trous_sigma_depth)
+                && (parameters.denoise_every_sample || self.accumulation_limit().is_some())
+            {
+                self.need_denoise = true;
+            }
             self.params = parameters;
         }
     }
 
-    fn ray_trace(&mut self) {
+    // paramsのサイズとhdr_outputのformatでoutput imageを作り直して、descriptor setを更新する。
+    // 使用中のoutput imageが無いことを呼び出し側で保証する。
+    fn recreate_output_images(&mut self) {
+        let format = output_image_format(self.params.hdr_output);
+        self.output_images = [0, 1].map(|_| {
+            ashtray::utils::create_shader_readonly_image(
+                &self.device,
+                &self.queue_handles,
+                &self.allocator,
+                &self.transfer_command_buffer,
+                self.params.width,
+                self.params.height,
+                format,
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+            )
+        });
+        for (image, &index) in self.output_images.iter().zip(&self.output_image_indices) {
+            self.descriptor_sets.storage_image.update(image, index);
+        }
+    }
+
+    // 蓄積したサンプルを破棄してサンプリングをやり直す。
+    // Parametersの変更はset_parametersで自動的に検出されるが、
+    // それ以外の理由で蓄積をやり直したい場合は明示的にこれを呼び出す。
+    pub fn reset_accumulation(&mut self) {
+        self.sample_count = 0;
+        self.rendering_start_time = Instant::now();
+        self.rendering_time = Duration::from_secs(0);
+        self.render_time_limit_reached = false;
+
+        // 古いサンプルに対する未処理のdenoiseは破棄して、クリアした画像をresolveし直す
+        self.need_resolve = true;
+        self.need_denoise = false;
+        self.pending_denoise = false;
+        self.denoised_valid = false;
+
+        // 実行中のpassが蓄積画像を使っているので終わるのを待つ
+        self.wait_timeline(self.timeline_value);
+
+        let command_buffer = self.render_command_buffer.clone();
+        command_buffer.reset_command_buffer(vk::CommandBufferResetFlags::RELEASE_RESOURCES);
+        ashtray::utils::begin_onetime_command_buffer(&command_buffer);
+        command_buffer.cmd_clear_color_image(
+            &self.accumulate_image.image,
+            vk::ImageLayout::GENERAL,
+            &vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
+            &[vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            }],
+        );
+        command_buffer.end_command_buffer();
+        let buffers_to_submit = [*command_buffer];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&buffers_to_submit)
+            .build();
+        let fence = ashtray::utils::create_fence(&self.device);
+        self.device.queue_submit(
+            self.queue_handles.graphics.queue,
+            &[submit_info],
+            Some(fence.clone()),
+        );
+        self.device.wait_fences(&[fence], u64::MAX);
+    }
+
+    // サンプル数か経過時間の上限に達して蓄積を止めている場合は、その理由を返す。
+    pub fn accumulation_limit(&self) -> Option<crate::AccumulationLimit> {
         if self.sample_count >= self.params.max_sample_count {
+            Some(crate::AccumulationLimit::SampleCount)
+        } else if self.render_time_limit_reached {
+            Some(crate::AccumulationLimit::RenderTime)
+        } else {
+            None
+        }
+    }
+
+    fn ray_trace(&mut self) {
+        if self.accumulation_limit().is_some() {
             return;
         }
 
@@ -885,18 +1835,123 @@ impl Renderer {
             return;
         }
 
+        // 前のサンプルのtraceが終わるのを待ってからTLASとcommand bufferを使い回す。
+        // rendering_timeは前のサンプルのtraceが終わった時点までの時間になる。
+        self.wait_timeline(self.render_timeline_value);
+        if self.sample_count > 0 {
+            self.rendering_time = self.rendering_start_time.elapsed();
+        }
+
+        // 前のサンプルまででmax_render_secondsを超えていたら、このサンプルはtraceせずに蓄積を止める。
+        // 最後のサンプルのresolveではまだ上限に達していなかったので、
+        // resolveし直して上限に達したときのdenoiseを行う。
+        if let Some(max_render_seconds) = self.params.max_render_seconds {
+            if self.sample_count > 0 && self.rendering_time.as_secs_f32() >= max_render_seconds {
+                self.render_time_limit_reached = true;
+                self.need_resolve = true;
+                return;
+            }
+        }
+
         let shader_binding_table = self.shader_binding_table.as_ref().unwrap();
         let ray_tracing_pipeline = self.ray_tracing_pipeline.as_ref().unwrap();
         let ray_tracing_pipeline_layout = self.ray_tracing_pipeline_layout.as_ref().unwrap();
         let descriptor_sets = self.acceleration_structure_descriptor_set.as_ref().unwrap();
         let instance_params_index = self.instance_params_buffer_index.unwrap();
         let materials_index = self.materials_buffer_index.unwrap();
-        let scene = self.scene_objects.as_ref().unwrap();
+        let lights_index = self.lights_buffer_index.unwrap();
+        let instance_light_indices_index = self.instance_light_indices_buffer_index.unwrap();
+        let (region_x, region_y, region_width, region_height) = self.flipped_render_region();
 
         // command bufferの開始
         let command_buffer = self.render_command_buffer.clone();
         command_buffer.reset_command_buffer(vk::CommandBufferResetFlags::RELEASE_RESOURCES);
         ashtray::utils::begin_onetime_command_buffer(&command_buffer);
+        let timer = command_buffer.timer_scope(&self.gpu_timer_pool, "ray_trace");
+        let label = command_buffer.debug_label("ray_trace", TRACE_LABEL_COLOR);
+
+        // TLASのinstanceを書き換えて同じbufferにビルドし直してからtraceする。
+        // motion instanceのTLASではレイごとの時刻でinstanceが補間されるので、
+        // シャッターの開いている時間が変わったときだけビルドし直す。
+        // motion instanceを使えない場合は、サンプルごとにシャッターが開いている間の時刻で
+        // ビルドし直す。時刻はsample indexのradical inverseで[0, shutter_speed)に層化する。
+        // glbのanimationの時刻が変わった場合もそのposeでビルドし直す。
+        // shutter_speedやanimation_timeが変わるとset_parametersで蓄積がリセットされるので、
+        // 前の時刻のposeのサンプルが混ざることはない。
+        let scene = self.scene_objects.as_mut().unwrap();
+        let has_motion = scene.has_motion();
+        let per_sample_motion = has_motion && !scene.tlas.motion;
+        let shutter_changed =
+            scene.tlas.motion && self.tlas_shutter_speed != Some(self.params.shutter_speed);
+        let animation_changed =
+            scene.has_animation() && self.tlas_animation_time != Some(self.params.animation_time);
+        if per_sample_motion || shutter_changed || animation_changed {
+            let time = if per_sample_motion {
+                (self.sample_count.reverse_bits() as f64 / 4294967296.0) as f32
+                    * self.params.shutter_speed
+            } else {
+                0.0
+            };
+            if animation_changed {
+                scene.cmd_update_skins(
+                    &command_buffer,
+                    &self.skinning_compute_pipeline,
+                    &self.skinning_compute_pipeline_layout,
+                    self.params.animation_time,
+                );
+            }
+            scene.cmd_update_tlas(
+                &command_buffer,
+                time,
+                self.params.shutter_speed,
+                self.params.animation_time,
+            );
+            self.tlas_shutter_speed = Some(self.params.shutter_speed);
+            self.tlas_animation_time = Some(self.params.animation_time);
+        }
+        let scene = self.scene_objects.as_ref().unwrap();
+
+        // 統計を収集する場合はサンプルごとにレイの数を0から数え直す
+        if self.params.collect_stats {
+            command_buffer.cmd_fill_buffer(&self.ray_stats_buffer.buffer, 0, vk::WHOLE_SIZE, 0);
+            command_buffer.cmd_pipeline_barrier2(
+                &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                    &vk::MemoryBarrier2::builder()
+                        .src_stage_mask(vk::PipelineStageFlags2::CLEAR)
+                        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+                        .dst_access_mask(
+                            vk::AccessFlags2::SHADER_STORAGE_READ
+                                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                        ),
+                )),
+            );
+        }
+
+        // traversal heatmapはサンプルをまたいで呼び出し回数を足していくので、
+        // 蓄積をやり直すときだけ0から数え直す
+        if self.params.display_image == crate::DisplayImage::TraversalHeatmap
+            && self.sample_count == 0
+        {
+            command_buffer.cmd_fill_buffer(
+                &self.traversal_heatmap_buffer.buffer,
+                0,
+                vk::WHOLE_SIZE,
+                0,
+            );
+            command_buffer.cmd_pipeline_barrier2(
+                &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                    &vk::MemoryBarrier2::builder()
+                        .src_stage_mask(vk::PipelineStageFlags2::CLEAR)
+                        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+                        .dst_access_mask(
+                            vk::AccessFlags2::SHADER_STORAGE_READ
+                                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                        ),
+                )),
+            );
+        }
 
         // sbt entryの用意
         let raygen_shader_sbt_entry = vk::StridedDeviceAddressRegionKHR::builder()
@@ -930,84 +1985,165 @@ impl Renderer {
             &[],
         );
 
-        command_buffer.cmd_push_constants(
-            ray_tracing_pipeline_layout,
-            vk::ShaderStageFlags::RAYGEN_KHR
-                | vk::ShaderStageFlags::ANY_HIT_KHR
-                | vk::ShaderStageFlags::CLOSEST_HIT_KHR
-                | vk::ShaderStageFlags::MISS_KHR,
-            0,
-            &[PushConstants {
-                accumulate_image_index: self.accumulate_image_index,
-                base_color_image_index: self.base_color_image_index,
-                normal_image_index: self.normal_image_index,
-                camera_rotate: glam::Mat4::from_euler(
-                    glam::EulerRot::YXZ,
-                    self.params.rotate_y.to_radians(),
-                    self.params.rotate_x.to_radians(),
-                    self.params.rotate_z.to_radians(),
-                ),
-                camera_translate: glam::Vec3::new(
-                    self.params.position_x,
-                    self.params.position_y,
-                    self.params.position_z,
-                ),
-                camera_fov: self.params.fov.to_radians(),
-                sample_index: self.sample_count as u32,
-                max_recursion_depth: self.params.max_recursion_depth,
-                instance_params_index,
-                materials_index,
-                sun_direction: glam::vec2(
-                    self.params.sun_direction.x.to_radians(),
-                    self.params.sun_direction.y.to_radians(),
-                ),
-                sun_angle: self.params.sun_angle.to_radians(),
-                sun_strength: self.params.sun_strength,
-                sun_color: self.params.sun_color,
-                sun_enabled: self.params.sun_enabled,
-                sky_width: scene.sky_texture_width,
-                sky_height: scene.sky_texture_height,
-                sky_rotation: self.params.sky_rotation.to_radians(),
-                sky_strength: self.params.sky_strength,
-                sky_enabled: self.params.sky_enabled,
-                sky_buffer_address: scene.sky_texture_buffer.device_address,
-                sky_cdf_row_buffer_address: scene.sky_texture_cdf_row_buffer.device_address,
-                sky_pdf_row_buffer_address: scene.sky_texture_pdf_row_buffer.device_address,
-                sky_cdf_column_buffer_address: scene.sky_texture_cdf_column_buffer.device_address,
-                sky_pdf_column_buffer_address: scene.sky_texture_pdf_column_buffer.device_address,
-                padding_0: [0; 1],
-                padding_1: [0; 3],
-                padding_2: [0; 2],
-            }],
-        );
+        let push_constants = PushConstants {
+            accumulate_image_index: self.accumulate_image_index,
+            base_color_image_index: self.base_color_image_index,
+            normal_image_index: self.normal_image_index,
+            camera_rotate: glam::Mat4::from_euler(
+                glam::EulerRot::YXZ,
+                self.params.rotate_y.to_radians(),
+                self.params.rotate_x.to_radians(),
+                self.params.rotate_z.to_radians(),
+            ),
+            camera_translate: glam::Vec3::new(
+                self.params.position_x,
+                self.params.position_y,
+                self.params.position_z,
+            ),
+            camera_fov: match self.params.camera_projection {
+                crate::CameraProjection::Perspective { fov } => fov.to_radians(),
+                crate::CameraProjection::Orthographic { .. } => 0.0,
+                crate::CameraProjection::Equirectangular => 0.0,
+            },
+            sample_index: self.sample_count as u32,
+            max_recursion_depth: self.params.max_recursion_depth,
+            instance_params_index,
+            materials_index,
+            random_seed: self
+                .params
+                .seed
+                .map(|seed| (seed ^ (seed >> 32)) as u32)
+                .unwrap_or(0),
+            sun_direction: glam::vec2(
+                self.params.sun_direction.x.to_radians(),
+                self.params.sun_direction.y.to_radians(),
+            ),
+            sun_angle: self.params.sun_angle.to_radians(),
+            sun_strength: self.params.sun_strength,
+            // 太陽の色と背景色はlinear sRGBで指定するので作業色空間に変換する
+            sun_color: self
+                .params
+                .working_color_space
+                .from_linear_srgb(self.params.sun_color),
+            lens_distortion: lens_distortion(&self.params),
+            sky_width: scene.sky_texture_width,
+            sky_height: scene.sky_texture_height,
+            sky_rotation: self.params.sky_rotation.to_radians(),
+            sky_strength: self.params.sky_strength,
+            sky_enabled: if self.params.background_is_environment && !self.params.white_furnace {
+                self.params.sky_enabled
+            } else {
+                0
+            },
+            lights_index,
+            instance_light_indices_index,
+            light_count: scene.light_count,
+            sky_buffer_address: scene.sky_texture_buffer.device_address,
+            sky_cdf_row_buffer_address: scene.sky_texture_cdf_row_buffer.device_address,
+            sky_pdf_row_buffer_address: scene.sky_texture_pdf_row_buffer.device_address,
+            sky_cdf_column_buffer_address: scene.sky_texture_cdf_column_buffer.device_address,
+            sky_pdf_column_buffer_address: scene.sky_texture_pdf_column_buffer.device_address,
+            camera_projection: match self.params.camera_projection {
+                crate::CameraProjection::Perspective { .. } => 0,
+                crate::CameraProjection::Orthographic { .. } => 1,
+                crate::CameraProjection::Equirectangular => 2,
+            },
+            background_color: if self.params.white_furnace {
+                glam::Vec3::ONE
+            } else {
+                self.params
+                    .working_color_space
+                    .from_linear_srgb(self.params.background_color)
+            },
+            ray_epsilon: self.params.ray_epsilon.max(0.0),
+            camera_orthographic_height: match self.params.camera_projection {
+                crate::CameraProjection::Perspective { .. } => 0.0,
+                crate::CameraProjection::Orthographic { height } => height,
+                crate::CameraProjection::Equirectangular => 0.0,
+            },
+            debug_image_index: match self.params.display_image {
+                crate::DisplayImage::GeometricNormal => self.geometric_normal_image_index,
+                _ => self.bounce_count_image_index,
+            },
+            debug_flags: debug_flags(&self.params),
+            render_region_offset: region_x | region_y << 16,
+            aov_image_index: self.instance_id_image_index,
+        };
+        let trace_rays = |debug_flags: u32| {
+            command_buffer.cmd_push_constants(
+                ray_tracing_pipeline_layout,
+                RAY_TRACING_PUSH_CONSTANT_STAGES,
+                0,
+                &PushConstants {
+                    debug_flags,
+                    ..push_constants
+                },
+            );
+            command_buffer.cmd_trace_rays(
+                &raygen_shader_sbt_entry,
+                &miss_shader_sbt_entry,
+                &hit_shader_sbt_entry,
+                &vk::StridedDeviceAddressRegionKHR::default(),
+                region_width,
+                region_height,
+                1,
+            );
+        };
 
         // ray tracingの実行
-        command_buffer.cmd_trace_rays(
-            &raygen_shader_sbt_entry,
-            &miss_shader_sbt_entry,
-            &hit_shader_sbt_entry,
-            &vk::StridedDeviceAddressRegionKHR::default(),
-            self.params.width,
-            self.params.height,
-            1,
-        );
+        match self.params.integrator {
+            crate::Integrator::PathTracer => trace_rays(push_constants.debug_flags),
+            crate::Integrator::LightTracer => {
+                // フィルムを0にしてから、発光面からのパスをsplatし、
+                // すべてのsplatが終わってからpixelごとに蓄積画像に加える
+                command_buffer.cmd_fill_buffer(
+                    &self.light_trace_film_buffer.buffer,
+                    0,
+                    vk::WHOLE_SIZE,
+                    0,
+                );
+                command_buffer.cmd_pipeline_barrier2(
+                    &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                        &vk::MemoryBarrier2::builder()
+                            .src_stage_mask(vk::PipelineStageFlags2::CLEAR)
+                            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                            .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+                            .dst_access_mask(
+                                vk::AccessFlags2::SHADER_STORAGE_READ
+                                    | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                            ),
+                    )),
+                );
+                // splatは発光面からのパスごとにlaunchしてpixelに対応しないので、
+                // traversal heatmapには数えない
+                let splat_debug_flags = if debug_output(self.params.display_image)
+                    == debug_output(crate::DisplayImage::TraversalHeatmap)
+                {
+                    push_constants.debug_flags & !0x7
+                } else {
+                    push_constants.debug_flags
+                };
+                trace_rays(splat_debug_flags | FLAG_LIGHT_TRACE_SPLAT);
+                command_buffer.cmd_pipeline_barrier2(
+                    &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                        &vk::MemoryBarrier2::builder()
+                            .src_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+                            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                            .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+                            .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ),
+                    )),
+                );
+                trace_rays(push_constants.debug_flags | FLAG_LIGHT_TRACE_GATHER);
+            }
+        }
 
+        drop(label);
+        drop(timer);
         command_buffer.end_command_buffer();
-        let buffers_to_submit = [*command_buffer];
-        let submit_info = vk::SubmitInfo::builder()
-            .command_buffers(&buffers_to_submit)
-            .build();
-        self.device.reset_fences(&[self.render_fence.clone()]);
-        self.device.queue_submit(
-            self.queue_handles.graphics.queue,
-            &[submit_info],
-            Some(self.render_fence.clone()),
-        );
-        self.device
-            .wait_fences(&[self.render_fence.clone()], u64::MAX);
+        self.render_timeline_value =
+            self.submit_on_timeline(self.queue_handles.graphics.queue, &command_buffer, None);
 
         self.sample_count += 1;
-        self.rendering_time = self.rendering_start_time.elapsed();
 
         self.need_resolve = true;
     }
@@ -1019,47 +2155,66 @@ impl Renderer {
         }
 
         let command_buffer = self.resolve_command_buffer.clone();
+        let (region_x, region_y, region_width, region_height) = self.flipped_render_region();
 
+        self.wait_timeline(self.resolve_timeline_value);
         command_buffer.reset_command_buffer(vk::CommandBufferResetFlags::RELEASE_RESOURCES);
         ashtray::utils::begin_onetime_command_buffer(&command_buffer);
+        let timer = command_buffer.timer_scope(&self.gpu_timer_pool, "resolve");
+        let label = command_buffer.debug_label("resolve", RESOLVE_LABEL_COLOR);
 
         command_buffer.cmd_bind_compute_pipeline(&self.resolve_compute_pipeline);
         command_buffer.cmd_bind_descriptor_sets(
             vk::PipelineBindPoint::COMPUTE,
             &self.resolve_compute_pipeline_layout,
             0,
-            &[self.descriptor_sets.storage_image.set.clone()],
+            std::slice::from_ref(&self.descriptor_sets.storage_image.set),
             &[],
         );
+        // oidnを使える場合は、resolveと同時にoidnの入力のbufferにも書き込んでおく。
+        // denoiserを切り替えたときにrender regionの外が古いままにならないように、
+        // oidnのdenoiserを選んでいない間も書き込む
+        let (oidn_color_buffer_address, oidn_albedo_buffer_address, oidn_normal_buffer_address) =
+            if self.oidn_objects.is_some() {
+                (
+                    self.color_buffer.device_address,
+                    self.albedo_buffer.device_address,
+                    self.normal_buffer.device_address,
+                )
+            } else {
+                (0, 0, 0)
+            };
         command_buffer.cmd_push_constants(
             &self.resolve_compute_pipeline_layout,
             vk::ShaderStageFlags::COMPUTE,
             0,
-            &[ResolvePushConstants {
+            &ResolvePushConstants {
                 sample_count: self.sample_count,
                 input_index: self.accumulate_image_index,
                 output_index: self.resolved_image_index,
-            }],
+                region_x,
+                region_y,
+                region_width,
+                region_height,
+                albedo_image_index: self.base_color_image_index,
+                luminance_histogram_buffer_address: 0,
+                oidn_color_buffer_address,
+                oidn_albedo_buffer_address,
+                oidn_normal_buffer_address,
+                normal_image_index: self.normal_image_index,
+                padding: [0; 1],
+            },
         );
-        command_buffer.cmd_dispatch((self.params.width + 7) / 8, (self.params.height + 7) / 8, 1);
+        command_buffer.cmd_dispatch(region_width.div_ceil(8), region_height.div_ceil(8), 1);
+        drop(label);
+        drop(timer);
         command_buffer.end_command_buffer();
 
-        self.device.reset_fences(&[self.resolve_fence.clone()]);
-        self.device.queue_submit(
-            self.queue_handles.compute.queue,
-            std::slice::from_ref(
-                &vk::SubmitInfo::builder()
-                    .command_buffers(&[*command_buffer])
-                    .wait_dst_stage_mask(&[vk::PipelineStageFlags::TRANSFER])
-                    .wait_semaphores(&[]),
-            ),
-            Some(self.resolve_fence.clone()),
-        );
-        self.device
-            .wait_fences(&[self.resolve_fence.clone()], u64::MAX);
+        self.resolve_timeline_value =
+            self.submit_on_timeline(self.queue_handles.compute.queue, &command_buffer, None);
 
         self.need_resolve = false;
-        if self.params.denoise_every_sample || self.sample_count == self.params.max_sample_count {
+        if self.params.denoise_every_sample || self.accumulation_limit().is_some() {
             self.need_denoise = true;
         }
     }
@@ -1068,97 +2223,134 @@ impl Renderer {
         if !self.need_denoise {
             return;
         }
+        match self.params.denoiser {
+            crate::Denoiser::None => {
+                self.need_denoise = false;
+                return;
+            }
+            crate::Denoiser::ATrous => {
+                self.a_trous_denoise();
+                return;
+            }
+            crate::Denoiser::Oidn => (),
+        }
+        if self.oidn_objects.is_none() {
+            self.need_denoise = false;
+            return;
+        }
 
-        // oidn用のbufferに蓄積画像をコピー
-        let command_buffer = self.denoise_command_buffer.clone();
-        command_buffer.reset_command_buffer(vk::CommandBufferResetFlags::RELEASE_RESOURCES);
-        ashtray::utils::begin_onetime_command_buffer(&command_buffer);
-        command_buffer.cmd_bind_compute_pipeline(&self.before_denoise_compute_pipeline);
-        command_buffer.cmd_bind_descriptor_sets(
-            vk::PipelineBindPoint::COMPUTE,
-            &self.before_denoise_compute_pipeline_layout,
-            0,
-            &[
-                self.descriptor_sets.storage_image.set.clone(),
-                self.descriptor_sets.storage_buffer.set.clone(),
-            ],
-            &[],
-        );
-        command_buffer.cmd_push_constants(
-            &self.before_denoise_compute_pipeline_layout,
-            vk::ShaderStageFlags::COMPUTE,
-            0,
-            &[BeforeDenoisePushConstants {
-                color_image_index: self.resolved_image_index,
-                albedo_image_index: self.base_color_image_index,
-                normal_image_index: self.normal_image_index,
-                color_buffer_address: self.color_buffer.device_address,
-                albedo_buffer_address: self.albedo_buffer.device_address,
-                normal_buffer_address: self.normal_buffer.device_address,
-                padding: [0; 1],
-            }],
-        );
-        command_buffer.cmd_dispatch((self.params.width + 7) / 8, (self.params.height + 7) / 8, 1);
-        command_buffer.end_command_buffer();
-        self.device.reset_fences(&[self.denoise_fence.clone()]);
-        self.device.queue_submit(
-            self.queue_handles.compute.queue,
-            std::slice::from_ref(
-                &vk::SubmitInfo::builder()
-                    .command_buffers(&[*command_buffer])
-                    .wait_dst_stage_mask(&[vk::PipelineStageFlags::COMPUTE_SHADER])
-                    .wait_semaphores(&[]),
-            ),
-            Some(self.denoise_fence.clone()),
-        );
-        self.device
-            .wait_fences(&[self.denoise_fence.clone()], u64::MAX);
+        // oidnの入力のbufferはresolveで書き込み済みなので、
+        // oidnの実行は次のrenderでtraceをsubmitした後に行う
+        self.need_denoise = false;
+        self.pending_denoise = true;
+    }
+
+    // resolveが終わるのを待ってoidnでdenoiseし、結果をoidnの出力のbufferに書き込む。
+    // 出力のbufferはoutputのpassがresolve済み画像とブレンドしながら直接読む。
+    // 次のサンプルのtraceをsubmitした後に呼ぶことで、CPUでのoidnの実行とGPUでのtraceを並行させる。
+    fn finish_denoise(&mut self) {
+        if !self.pending_denoise {
+            return;
+        }
+        self.pending_denoise = false;
+
+        // oidnの入力のbufferへの書き込みと、前の出力のbufferを読むoutputが終わるのを待つ
+        self.wait_timeline(self.resolve_timeline_value.max(self.output_timeline_value));
 
         // oidnでdenoise
-        self.oidn_filter.execute();
+        // 失敗した場合はoidnを無効にしてresolveした画像をそのまま使う
+        if let Err(e) = self
+            .oidn_objects
+            .as_ref()
+            .unwrap()
+            .execute(self.params.prefilter_aux)
+        {
+            eprintln!("[WARN]OIDN denoise failed, denoise is disabled: {}", e);
+            self.oidn_objects = None;
+            self.denoised_valid = false;
+            return;
+        }
 
-        // oidnの結果をoutput imageにコピー
-        let command_buffer = self.denoise_command_buffer.clone();
+        self.denoised_valid = true;
+    }
+
+    // resolved imageにÀ-Trousのfilterをa_trous_iterations回掛けて、結果をdenoised imageに書き込む。
+    // 各passはdenoised imageとÀ-Trous用の中間画像を交互に読み書きし、
+    // 最後のpassがdenoised imageに書き込むように最初の書き込み先を決める。
+    // oidnと違ってGPUだけで完結するので、遅延せずにそのままsubmitする。
+    fn a_trous_denoise(&mut self) {
+        let iterations = self.params.a_trous_iterations.clamp(1, 10);
+
+        let command_buffer = self.a_trous_command_buffer.clone();
+        self.wait_timeline(self.a_trous_timeline_value);
         command_buffer.reset_command_buffer(vk::CommandBufferResetFlags::RELEASE_RESOURCES);
         ashtray::utils::begin_onetime_command_buffer(&command_buffer);
-        command_buffer.cmd_bind_compute_pipeline(&self.after_denoise_compute_pipeline);
+        let timer = command_buffer.timer_scope(&self.gpu_timer_pool, "a_trous");
+        let label = command_buffer.debug_label("a_trous", DENOISE_LABEL_COLOR);
+        command_buffer.cmd_bind_compute_pipeline(&self.a_trous_compute_pipeline);
         command_buffer.cmd_bind_descriptor_sets(
             vk::PipelineBindPoint::COMPUTE,
-            &self.after_denoise_compute_pipeline_layout,
+            &self.a_trous_compute_pipeline_layout,
             0,
-            &[
-                self.descriptor_sets.storage_image.set.clone(),
-                self.descriptor_sets.storage_buffer.set.clone(),
-            ],
+            std::slice::from_ref(&self.descriptor_sets.storage_image.set),
             &[],
         );
-        command_buffer.cmd_push_constants(
-            &self.after_denoise_compute_pipeline_layout,
-            vk::ShaderStageFlags::COMPUTE,
-            0,
-            &[AfterDenoisePushConstants {
-                output_image_index: self.denoised_image_index,
-                output_buffer_address: self.output_buffer.device_address,
-                padding: [0; 1],
-            }],
-        );
-        command_buffer.cmd_dispatch((self.params.width + 7) / 8, (self.params.height + 7) / 8, 1);
+
+        let mut input_image_index = self.resolved_image_index;
+        for i in 0..iterations {
+            let output_image_index = if (iterations - 1 - i).is_multiple_of(2) {
+                self.denoised_image_index
+            } else {
+                self.a_trous_image_index
+            };
+            command_buffer.cmd_push_constants(
+                &self.a_trous_compute_pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &ATrousPushConstants {
+                    output_image_index,
+                    resolved_image_index: self.resolved_image_index,
+                    denoise_strength: self.params.denoise_strength.clamp(0.0, 1.0),
+                    a_trous_step_width: 1 << i,
+                    a_trous_input_image_index: input_image_index,
+                    albedo_image_index: self.base_color_image_index,
+                    normal_image_index: self.normal_image_index,
+                    depth_image_index: self.depth_image_index,
+                    sigma_color: self.params.a_trous_sigma_color * 0.5f32.powi(i as i32),
+                    sigma_albedo: self.params.a_trous_sigma_albedo,
+                    sigma_normal: self.params.a_trous_sigma_normal,
+                    sigma_depth: self.params.a_trous_sigma_depth,
+                    is_last_pass: if i == iterations - 1 { 1 } else { 0 },
+                },
+            );
+            command_buffer.cmd_dispatch(
+                self.params.width.div_ceil(8),
+                self.params.height.div_ceil(8),
+                1,
+            );
+            // 次のpassが今のpassの書き込んだ画像を読む
+            command_buffer.cmd_pipeline_barrier2(
+                &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                    &vk::MemoryBarrier2::builder()
+                        .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                        .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                        .dst_access_mask(
+                            vk::AccessFlags2::SHADER_STORAGE_READ
+                                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                        ),
+                )),
+            );
+            input_image_index = output_image_index;
+        }
+        drop(label);
+        drop(timer);
         command_buffer.end_command_buffer();
-        self.device.reset_fences(&[self.denoise_fence.clone()]);
-        self.device.queue_submit(
-            self.queue_handles.compute.queue,
-            std::slice::from_ref(
-                &vk::SubmitInfo::builder()
-                    .command_buffers(&[*command_buffer])
-                    .wait_dst_stage_mask(&[])
-                    .wait_semaphores(&[]),
-            ),
-            Some(self.denoise_fence.clone()),
-        );
-        self.device
-            .wait_fences(&[self.denoise_fence.clone()], u64::MAX);
+        self.a_trous_timeline_value =
+            self.submit_on_timeline(self.queue_handles.compute.queue, &command_buffer, None);
 
         self.need_denoise = false;
+        self.denoised_valid = true;
     }
 
     // output textureに結果を焼き込む
@@ -1167,16 +2359,32 @@ impl Renderer {
             crate::DisplayImage::BaseColor => self.base_color_image_index,
             crate::DisplayImage::Normal => self.normal_image_index,
             crate::DisplayImage::Resolved => self.resolved_image_index,
+            crate::DisplayImage::BounceCount => self.bounce_count_image_index,
+            crate::DisplayImage::AmbientOcclusion => self.bounce_count_image_index,
+            // 呼び出し回数はbufferから読むので、画像のサイズを取るためだけに使う
+            crate::DisplayImage::TraversalHeatmap => self.bounce_count_image_index,
+            crate::DisplayImage::GeometricNormal => self.geometric_normal_image_index,
+            crate::DisplayImage::InstanceId => self.instance_id_image_index,
+            crate::DisplayImage::Depth => self.depth_image_index,
+            crate::DisplayImage::Position => self.position_image_index,
             crate::DisplayImage::Final => {
-                if self.params.denoise_every_sample
-                    || self.sample_count == self.params.max_sample_count
-                {
+                if self.params.denoiser == crate::Denoiser::ATrous && self.denoised_valid {
                     self.denoised_image_index
                 } else {
                     self.resolved_image_index
                 }
             }
         };
+        // oidnの結果はimageにコピーせずに、outputのpassで出力のbufferから直接読む
+        let oidn_output_buffer_address = if self.params.display_image == crate::DisplayImage::Final
+            && self.params.denoiser == crate::Denoiser::Oidn
+            && self.oidn_objects.is_some()
+            && self.denoised_valid
+        {
+            self.output_buffer.device_address
+        } else {
+            0
+        };
         let enable_tone_mapping = if self.params.display_image == crate::DisplayImage::Final
             || self.params.display_image == crate::DisplayImage::Resolved
         {
@@ -1184,16 +2392,35 @@ impl Renderer {
         } else {
             0
         };
+        // 出力先がsRGBの場合は伝達関数をハードウェアに任せる。
+        // HDRの出力ではトーンマッピングとSDRの伝達関数の代わりにswapchainのcolor spaceに合わせてエンコードする
+        let (display_transfer, display_gamma) = match self.params.hdr_output {
+            crate::HdrOutput::ScRgb => (3, 1.0),
+            crate::HdrOutput::Hdr10Pq => (4, 1.0),
+            crate::HdrOutput::Sdr => {
+                if enable_tone_mapping == 0 || self.params.output_is_srgb {
+                    (0, 1.0)
+                } else {
+                    match self.params.display_transfer {
+                        crate::DisplayTransfer::Linear => (0, 1.0),
+                        crate::DisplayTransfer::Srgb => (1, 1.0),
+                        crate::DisplayTransfer::Gamma(gamma) => (2, gamma.max(0.01)),
+                    }
+                }
+            }
+        };
         let image_handles = &self.output_images[self.current_image_index];
-        let fences = [self.output_fences[self.current_image_index].clone()];
+        let (region_x, region_y, region_width, region_height) = self.render_region();
+        let fences = std::slice::from_ref(&self.output_fences[self.current_image_index]);
         let command_buffer = self.output_command_buffers[self.current_image_index].clone();
 
-        self.device.wait_fences(&fences, u64::MAX);
-        self.device.reset_fences(&fences);
+        self.device.wait_fences(fences, u64::MAX);
+        self.device.reset_fences(fences);
 
         command_buffer.reset_command_buffer(vk::CommandBufferResetFlags::RELEASE_RESOURCES);
 
         ashtray::utils::begin_onetime_command_buffer(&command_buffer);
+        let label = command_buffer.debug_label("output", OUTPUT_LABEL_COLOR);
 
         ashtray::utils::cmd_image_barriers(
             &command_buffer,
@@ -1211,24 +2438,168 @@ impl Renderer {
             vk::PipelineBindPoint::COMPUTE,
             &self.output_compute_pipeline_layout,
             0,
-            &[self.descriptor_sets.storage_image.set.clone()],
+            std::slice::from_ref(&self.descriptor_sets.storage_image.set),
             &[],
         );
+        let push_constants = FinalPushConstants {
+            input_index: input_image_index,
+            output_index: self.output_image_indices[self.current_image_index],
+            l_white: self.params.l_white,
+            exposure: crate::exposure::exposure_multiplier(crate::exposure::ev100_from_physical(
+                self.params.aperture,
+                self.params.shutter_speed,
+                self.params.iso,
+            )),
+            enable_tone_mapping,
+            display_transfer,
+            display_bounce_count: if self.params.display_image == crate::DisplayImage::BounceCount {
+                1
+            } else {
+                0
+            },
+            sample_count: self.sample_count,
+            max_recursion_depth: self.params.max_recursion_depth,
+            region_x,
+            region_y,
+            region_width,
+            region_height,
+            display_instance_id: if self.params.display_image == crate::DisplayImage::InstanceId {
+                1
+            } else {
+                0
+            },
+            display_depth: if self.params.display_image == crate::DisplayImage::Depth {
+                1
+            } else {
+                0
+            },
+            display_position: if self.params.display_image == crate::DisplayImage::Position {
+                1
+            } else {
+                0
+            },
+            aov_far_plane: self.params.aov_far_plane.max(f32::EPSILON),
+            display_ambient_occlusion: if self.params.display_image
+                == crate::DisplayImage::AmbientOcclusion
+            {
+                1
+            } else {
+                0
+            },
+            working_color_space: match self.params.working_color_space {
+                crate::WorkingColorSpace::LinearSrgb => 0,
+                crate::WorkingColorSpace::AcesCg => 1,
+            },
+            display_gamma,
+            oidn_output_buffer_address,
+            denoise_strength: self.params.denoise_strength.clamp(0.0, 1.0),
+            display_traversal_heatmap: if self.params.display_image
+                == crate::DisplayImage::TraversalHeatmap
+            {
+                1
+            } else {
+                0
+            },
+            // ホワイトバランスの行列はlinear sRGBで計算しているので作業色空間の行列にする
+            white_balance: glam::Mat4::from_mat3(
+                self.params.working_color_space.matrix_from_linear_srgb(
+                    crate::white_balance::white_balance_matrix(
+                        self.params.white_balance_temp,
+                        self.params.tint,
+                    ),
+                ),
+            ),
+            traversal_heatmap_buffer_address: self.traversal_heatmap_buffer.device_address,
+            traversal_heatmap_scale: self.params.traversal_heatmap_scale.max(f32::EPSILON),
+            padding: [0; 1],
+        };
         command_buffer.cmd_push_constants(
             &self.output_compute_pipeline_layout,
             vk::ShaderStageFlags::COMPUTE,
             0,
-            &[FinalPushConstants {
-                input_index: input_image_index,
-                output_index: self.output_image_indices[self.current_image_index],
-                l_white: self.params.l_white,
-                aperture: self.params.aperture,
-                shutter_speed: self.params.shutter_speed,
-                iso: self.params.iso,
-                enable_tone_mapping,
-            }],
+            &push_constants,
         );
-        command_buffer.cmd_dispatch((self.params.width + 7) / 8, (self.params.height + 7) / 8, 1);
+        command_buffer.cmd_dispatch(region_width.div_ceil(8), region_height.div_ceil(8), 1);
+
+        // 外部のimageにもoutput imageの結果をコピーする。
+        // layoutはGENERALのままにして、外部のAPIとの間の同期はwait_outputで行う
+        if let Some(external_output_image) = self
+            .external_output_image
+            .as_ref()
+            .filter(|image| image.width == self.params.width && image.height == self.params.height)
+        {
+            ashtray::utils::cmd_image_barriers(
+                &command_buffer,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_READ,
+                vk::ImageLayout::GENERAL,
+                &image_handles.image,
+            );
+            ashtray::utils::cmd_image_barriers(
+                &command_buffer,
+                vk::PipelineStageFlags2::TOP_OF_PIPE,
+                vk::AccessFlags2::NONE,
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_WRITE,
+                vk::ImageLayout::GENERAL,
+                &external_output_image.image,
+            );
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build();
+            let offsets = [
+                vk::Offset3D {
+                    x: region_x as i32,
+                    y: region_y as i32,
+                    z: 0,
+                },
+                vk::Offset3D {
+                    x: (region_x + region_width) as i32,
+                    y: (region_y + region_height) as i32,
+                    z: 1,
+                },
+            ];
+            command_buffer.cmd_blit_image(
+                &image_handles.image,
+                vk::ImageLayout::GENERAL,
+                &external_output_image.image,
+                vk::ImageLayout::GENERAL,
+                &[vk::ImageBlit::builder()
+                    .src_subresource(subresource)
+                    .src_offsets(offsets)
+                    .dst_subresource(subresource)
+                    .dst_offsets(offsets)
+                    .build()],
+                vk::Filter::NEAREST,
+            );
+            ashtray::utils::cmd_image_barriers(
+                &command_buffer,
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_WRITE,
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                vk::AccessFlags2::NONE,
+                vk::ImageLayout::GENERAL,
+                &external_output_image.image,
+            );
+            ashtray::utils::cmd_image_barriers(
+                &command_buffer,
+                vk::PipelineStageFlags2::BLIT,
+                vk::AccessFlags2::TRANSFER_READ,
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::NONE,
+                vk::ImageLayout::GENERAL,
+                &image_handles.image,
+            );
+        }
 
         ashtray::utils::cmd_image_barriers(
             &command_buffer,
@@ -1241,20 +2612,16 @@ impl Renderer {
             &image_handles.image,
         );
 
+        drop(label);
         command_buffer.end_command_buffer();
 
-        self.device.queue_submit(
+        let image_view = image_handles.image_view.clone();
+        self.output_timeline_value = self.submit_on_timeline(
             self.queue_handles.compute.queue,
-            std::slice::from_ref(
-                &vk::SubmitInfo::builder()
-                    .command_buffers(&[*command_buffer])
-                    .wait_dst_stage_mask(&[vk::PipelineStageFlags::TRANSFER])
-                    .wait_semaphores(&[]),
-            ),
+            &command_buffer,
             Some(self.output_fences[self.current_image_index].clone()),
         );
 
-        let image_view = image_handles.image_view.clone();
         let sampler = self.sampler.clone();
         let sample_count = self.sample_count;
 
@@ -1269,10 +2636,914 @@ impl Renderer {
     }
 
     pub fn render(&mut self, parameters: crate::Parameters) -> NextImage {
+        // pause中はパラメータも反映せずに最後のoutput imageをそのまま返す。
+        // パラメータの変更はresumeした後のrenderで反映される。
+        if self.is_paused() {
+            return self.current_image();
+        }
         self.set_parameters(parameters);
-        self.ray_trace();
+        self.update_samples_per_frame();
+        for _ in 0..self.samples_per_frame {
+            self.ray_trace();
+        }
+        // 前のrenderで遅延したdenoiseを、今submitしたtraceと並行して実行する
+        self.finish_denoise();
         self.resolve();
         self.denoise();
         self.output_image()
     }
+
+    // 蓄積を一時停止する。
+    // pause中のrenderは新しいサンプルのtraceやresolve、denoiseを行わず、
+    // 最後に書き込んだoutput imageを返す。sample_countも進まない。
+    pub fn pause(&mut self) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(Instant::now());
+        }
+    }
+
+    // pauseした蓄積を再開する。
+    // 蓄積はそのまま続きから再開し、pauseしていた時間はrendering_timeに含めない。
+    pub fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.rendering_start_time += paused_at.elapsed();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    // 1回のrenderでtraceするサンプル数を返す。
+    pub fn samples_per_frame(&self) -> u32 {
+        self.samples_per_frame
+    }
+
+    // target_frame_msに収まるように、1回のrenderでtraceするサンプル数を調整する。
+    // GPUのtimer queryで測った最新のray_traceの1サンプルの処理時間から見積もる。
+    // target_frame_msを超えたらすぐに収まるサンプル数まで減らし、
+    // 増やすのは余裕がある場合に1つずつにする。
+    fn update_samples_per_frame(&mut self) {
+        let target_frame_ms = self.params.target_frame_ms;
+        if target_frame_ms <= 0.0 {
+            self.samples_per_frame = 1;
+            return;
+        }
+        let Some(sample_ms) = self
+            .gpu_timer_pool
+            .durations()
+            .get("ray_trace")
+            .map(|duration| duration.as_secs_f32() * 1000.0)
+        else {
+            return;
+        };
+        if sample_ms <= 0.0 {
+            return;
+        }
+        let frame_ms = sample_ms * self.samples_per_frame as f32;
+        if frame_ms > target_frame_ms {
+            self.samples_per_frame = (target_frame_ms / sample_ms) as u32;
+        } else if frame_ms + sample_ms <= target_frame_ms * SAMPLES_PER_FRAME_INCREASE_RATIO {
+            self.samples_per_frame += 1;
+        }
+        self.samples_per_frame = self.samples_per_frame.clamp(1, MAX_SAMPLES_PER_FRAME);
+    }
+
+    // 直前のrenderで設定したパラメータのまま、target_samplesに達するまでサンプリングを繰り返す。
+    // on_progressは一定サンプル数ごとに現在のサンプル数と経過時間を受け取り、
+    // falseを返すとその時点で打ち切る。
+    // cancelはサンプルごとに確認し、trueになっていればその時点で打ち切る。
+    // Ctrl-Cのハンドラなど別スレッドから止められるように、Arc<AtomicBool>を共有して渡すことを想定している。
+    // 打ち切った場合もそれまでのサンプルでresolveとdenoiseを行い、
+    // 途中までの画像と到達したサンプル数を返す。
+    // 返す前にGPUの処理が全て終わるのを待つ。
+    pub fn render_until(
+        &mut self,
+        target_samples: u32,
+        cancel: &AtomicBool,
+        mut on_progress: impl FnMut(u32, Duration) -> bool,
+    ) -> NextImage {
+        let target_samples = target_samples.min(self.params.max_sample_count);
+        // resolveがoidnの入力のbufferを書き換える前に、遅延しているdenoiseを済ませる
+        self.finish_denoise();
+        while self.ray_tracing_pipeline.is_some()
+            && self.sample_count < target_samples
+            && self.accumulation_limit().is_none()
+        {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            self.ray_trace();
+            if self
+                .sample_count
+                .is_multiple_of(RENDER_UNTIL_PROGRESS_INTERVAL)
+                || self.sample_count == target_samples
+            {
+                self.resolve();
+                if !on_progress(self.sample_count, self.rendering_time) {
+                    break;
+                }
+            }
+        }
+        // 最後のtraceが終わるまでの時間をrendering_timeにする
+        self.wait_timeline(self.render_timeline_value);
+        if self.sample_count > 0 {
+            self.rendering_time = self.rendering_start_time.elapsed();
+        }
+        self.finish_denoise();
+        self.resolve();
+        self.denoise();
+        self.finish_denoise();
+        let next_image = self.output_image();
+        // 呼び出し側がすぐにRendererを破棄しても使用中のリソースを解放しないように、
+        // outputまでのGPUの処理を待つ
+        self.wait_timeline(self.timeline_value);
+        next_image
+    }
+
+    // sceneを読み込んでparametersでサンプリングを行い、蓄積した画像と計測結果を返す。
+    // サンプラーやMISの変更を同じサンプル数か同じ時間で比較するためのもので、denoiseや出力は行わない。
+    // referenceを与えた場合はBenchmarkResult::imageと同じ形式の画像として誤差を計算する。
+    // parametersのmax_sample_countはmodeに合わせて上書きし、max_render_secondsは使わない。
+    pub fn benchmark(
+        &mut self,
+        scene: &crate::Scene,
+        parameters: crate::Parameters,
+        mode: crate::BenchmarkMode,
+        reference: Option<&[f32]>,
+    ) -> anyhow::Result<crate::BenchmarkResult> {
+        self.load_scene(scene)?;
+        self.set_parameters(crate::Parameters {
+            max_sample_count: match mode {
+                crate::BenchmarkMode::EqualSamples(sample_count) => sample_count,
+                crate::BenchmarkMode::EqualTime(_) => u32::MAX,
+            },
+            max_render_seconds: None,
+            ..parameters
+        });
+        self.reset_accumulation();
+
+        let start_time = Instant::now();
+        while self.ray_tracing_pipeline.is_some() {
+            let done = match mode {
+                crate::BenchmarkMode::EqualSamples(sample_count) => {
+                    self.sample_count >= sample_count
+                }
+                crate::BenchmarkMode::EqualTime(duration) => start_time.elapsed() >= duration,
+            };
+            if done {
+                break;
+            }
+            self.ray_trace();
+        }
+        self.wait_timeline(self.render_timeline_value);
+        let elapsed = start_time.elapsed();
+
+        // 蓄積画像をサンプル数で割ってRGBにする
+        let sample_count = self.sample_count.max(1) as f32;
+        let image = self
+            .read_back_aov(&self.accumulate_image, 4)?
+            .chunks_exact(4)
+            .flat_map(|p| p[..3].iter().map(|&c| f32::from_bits(c) / sample_count))
+            .collect::<Vec<_>>();
+
+        let error = match reference {
+            Some(reference) => {
+                anyhow::ensure!(
+                    reference.len() == image.len(),
+                    "Reference has {} values but the image has {}",
+                    reference.len(),
+                    image.len()
+                );
+                let mse = image
+                    .iter()
+                    .zip(reference)
+                    .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+                    .sum::<f64>()
+                    / image.len().max(1) as f64;
+                Some(crate::BenchmarkError {
+                    mse,
+                    rmse: mse.sqrt(),
+                })
+            }
+            None => None,
+        };
+
+        Ok(crate::BenchmarkResult {
+            image,
+            sample_count: self.sample_count,
+            elapsed,
+            error,
+        })
+    }
+
+    // SPIR-Vのファイルを読み直してpipelineを作り直し、蓄積をリセットする。
+    // 読み込みに失敗したshaderのpipelineは古いものをそのまま使う。
+    #[cfg(feature = "shader-hot-reload")]
+    pub fn reload_shaders(&mut self) {
+        fn load_shader_module(
+            device: &ashtray::DeviceHandle,
+            name: &str,
+        ) -> Option<ashtray::ShaderModuleHandle> {
+            let path = format!("{}/src/shaders/spv/{}", env!("CARGO_MANIFEST_DIR"), name);
+            match ashtray::utils::create_shader_module_from_file(device, &path) {
+                Ok(shader_module) => Some(shader_module),
+                Err(err) => {
+                    eprintln!("[WARN] Failed to reload shader {}: {}", path, err);
+                    None
+                }
+            }
+        }
+
+        self.device.wait_idle();
+
+        // compute pipelineの再作成
+        if let Some(shader_module) = load_shader_module(&self.device, "resolve.comp.spv") {
+            self.resolve_compute_pipeline = ashtray::utils::create_compute_pipeline(
+                &self.device,
+                &self.pipeline_cache,
+                &self.resolve_compute_pipeline_layout,
+                &shader_module,
+            );
+        }
+        if let Some(shader_module) = load_shader_module(&self.device, "a_trous.comp.spv") {
+            self.a_trous_compute_pipeline = ashtray::utils::create_compute_pipeline(
+                &self.device,
+                &self.pipeline_cache,
+                &self.a_trous_compute_pipeline_layout,
+                &shader_module,
+            );
+        }
+        if let Some(shader_module) = load_shader_module(&self.device, "output.comp.spv") {
+            self.output_compute_pipeline = ashtray::utils::create_compute_pipeline(
+                &self.device,
+                &self.pipeline_cache,
+                &self.output_compute_pipeline_layout,
+                &shader_module,
+            );
+        }
+        if let Some(shader_module) = load_shader_module(&self.device, "skinning.comp.spv") {
+            self.skinning_compute_pipeline = ashtray::utils::create_compute_pipeline(
+                &self.device,
+                &self.pipeline_cache,
+                &self.skinning_compute_pipeline_layout,
+                &shader_module,
+            );
+        }
+
+        // ray tracing pipelineの再作成
+        // すべてのshaderが読み込めたときのみ作り直す
+        if let Some(acceleration_structure_descriptor_set) =
+            self.acceleration_structure_descriptor_set.as_ref()
+        {
+            let motion = self
+                .scene_objects
+                .as_ref()
+                .is_some_and(|scene| scene.tlas.motion);
+            let shader_modules = (|| {
+                Some(RayTracingShaderModules {
+                    raygen: load_shader_module(
+                        &self.device,
+                        if motion {
+                            "raygen_motion.rgen.spv"
+                        } else {
+                            "raygen.rgen.spv"
+                        },
+                    )?,
+                    material_closest_hit: load_shader_module(
+                        &self.device,
+                        "material/closesthit.rchit.spv",
+                    )?,
+                    material_anyhit: load_shader_module(&self.device, "material/anyhit.rahit.spv")?,
+                    material_miss: load_shader_module(&self.device, "material/miss.rmiss.spv")?,
+                    shadow_closest_hit: load_shader_module(
+                        &self.device,
+                        "shadow/closesthit.rchit.spv",
+                    )?,
+                    shadow_anyhit_alpha_blend: load_shader_module(
+                        &self.device,
+                        "shadow/anyhit_alpha_blend.rahit.spv",
+                    )?,
+                    shadow_anyhit_alpha_mask: load_shader_module(
+                        &self.device,
+                        "shadow/anyhit_alpha_mask.rahit.spv",
+                    )?,
+                    shadow_miss: load_shader_module(&self.device, "shadow/miss.rmiss.spv")?,
+                    sphere_closest_hit: load_shader_module(
+                        &self.device,
+                        "sphere/closesthit.rchit.spv",
+                    )?,
+                    sphere_intersection: load_shader_module(
+                        &self.device,
+                        "sphere/intersection.rint.spv",
+                    )?,
+                })
+            })();
+            if let Some(shader_modules) = shader_modules {
+                let (ray_tracing_pipeline, pipeline_layout, shader_binding_table) = self
+                    .create_ray_tracing_pipeline(
+                        shader_modules,
+                        &acceleration_structure_descriptor_set.layout.clone(),
+                        motion,
+                    );
+                self.ray_tracing_pipeline = Some(ray_tracing_pipeline);
+                self.ray_tracing_pipeline_layout = Some(pipeline_layout);
+                self.shader_binding_table = Some(shader_binding_table);
+            }
+        }
+
+        self.reset_accumulation();
+    }
+
+    // 最後にrenderやrender_untilで書き込んだoutput imageのNextImageを返す。
+    // 再描画をせずに現在の結果を取得したい場合に使う。
+    pub fn current_image(&self) -> NextImage {
+        let last_image_index = (self.current_image_index + 1) % 2;
+        NextImage {
+            image_view: self.output_images[last_image_index].image_view.clone(),
+            sampler: self.sampler.clone(),
+            sample_count: self.sample_count,
+            rendering_time: self.rendering_time,
+        }
+    }
+
+    // 最後にsubmitしたoutputの書き込みが完了するまで待つ。
+    // new_with_output_imageで渡したimageを外部のAPIで読み込む前に呼び出す。
+    pub fn wait_output(&self) {
+        let last_image_index = (self.current_image_index + 1) % 2;
+        self.device.wait_fences(
+            std::slice::from_ref(&self.output_fences[last_image_index]),
+            u64::MAX,
+        );
+    }
+
+    // 最後に書き込んだoutput imageのImageViewとSamplerを返す。
+    // egui_ash::ImageRegistryなどに登録してeguiのtextureとして表示するのに使う。
+    pub fn egui_texture(&self) -> (ashtray::ImageViewHandle, ashtray::SamplerHandle) {
+        let next_image = self.current_image();
+        (next_image.image_view, next_image.sampler)
+    }
+
+    // 最後に書き込んだoutput imageをGPUから読み戻して画像ファイルとして保存する。
+    // 画像のフォーマットはpathの拡張子から決定する。
+    // トーンマッピング後の8bitの画像なので、HDRのまま保存する場合はscreenshot_hdrを使う。
+    pub fn save_image(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let last_image_index = (self.current_image_index + 1) % 2;
+        let image_handles = &self.output_images[last_image_index];
+        self.device.wait_fences(
+            std::slice::from_ref(&self.output_fences[last_image_index]),
+            u64::MAX,
+        );
+
+        // output imageはcompute queueで書き込んでいるので、読み戻しもcompute queueで行う
+        image_handles.image.save_to_file(
+            &self.device,
+            &self.queue_handles.compute,
+            &self.allocator,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            path,
+        )
+    }
+
+    // 最後に書き込んだoutput imageをGPUから読み戻して、RGBA8のpixelの列として返す。
+    // pixelは出力画像の左上から行ごとに並ぶ。
+    pub fn read_output_image(&self) -> anyhow::Result<Vec<u8>> {
+        if self.params.hdr_output != crate::HdrOutput::Sdr {
+            anyhow::bail!("The output image is not 8-bit while hdr_output is enabled");
+        }
+        let last_image_index = (self.current_image_index + 1) % 2;
+        self.device.wait_fences(
+            std::slice::from_ref(&self.output_fences[last_image_index]),
+            u64::MAX,
+        );
+
+        // output imageはR8G8B8A8_UNORMなので1pixel 4byte
+        self.read_back_image(
+            &self.queue_handles.compute,
+            &self.output_images[last_image_index].image,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            4,
+        )
+    }
+
+    // 最後に書き込んだoutput imageをGPUから読み戻して、JPEGにエンコードしたバイト列を返す。
+    // リモートのviewerにsocket越しに送るライブプレビュー用で、数フレームごとに呼べる程度に軽い。
+    // output imageはトーンマッピングと伝達関数をかけた後の8bitの画像なので、ブラウザでそのまま表示できる。
+    // output_is_srgbの場合は伝達関数がかかっていないので、CPUでsRGBの伝達関数をかける。
+    // qualityは1から100で、大きいほどサイズが大きく高画質になる。alphaは捨てる。
+    pub fn encode_preview_jpeg(&self, quality: u8) -> anyhow::Result<Vec<u8>> {
+        let rgba = self.read_output_image()?;
+        let srgb_table = self.params.output_is_srgb.then(|| {
+            (0..=255)
+                .map(|value| {
+                    let linear = value as f32 / 255.0;
+                    let encoded = if linear <= 0.0031308 {
+                        linear * 12.92
+                    } else {
+                        1.055 * linear.powf(1.0 / 2.4) - 0.055
+                    };
+                    (encoded * 255.0).round() as u8
+                })
+                .collect::<Vec<_>>()
+        });
+        let rgb = rgba
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let rgb = [pixel[0], pixel[1], pixel[2]];
+                match &srgb_table {
+                    Some(table) => rgb.map(|value| table[value as usize]),
+                    None => rgb,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut jpeg = vec![];
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, quality.clamp(1, 100))
+            .encode(
+                &rgb,
+                self.params.width,
+                self.params.height,
+                image::ColorType::Rgb8,
+            )?;
+        Ok(jpeg)
+    }
+
+    // 蓄積したサンプルの平均の放射輝度をresolved imageからGPUから読み戻して返す。
+    // 露出補正やトーンマッピングをかける前の値で、pixelは出力画像の左上から行ごとに並ぶ。
+    // white furnace testで白い物体が背景と同じ1になっているかを確認するのに使う。
+    pub fn read_accumulate(&self) -> anyhow::Result<Vec<glam::Vec3>> {
+        let width = self.params.width as usize;
+        let height = self.params.height as usize;
+
+        // 実行中のresolveが終わるのを待つ
+        self.wait_timeline(self.timeline_value);
+
+        // resolved imageはR16G16B16A16_SFLOATなので1pixel 8byte。resolveはcompute queueで行う
+        let data = self.read_back_image(
+            &self.queue_handles.compute,
+            &self.resolved_image.image,
+            vk::ImageLayout::GENERAL,
+            8,
+        )?;
+        let pixels = data
+            .chunks_exact(8)
+            .map(|bytes| {
+                let channel = |i: usize| {
+                    exr::prelude::f16::from_bits(u16::from_le_bytes([bytes[i], bytes[i + 1]]))
+                        .to_f32()
+                };
+                glam::vec3(channel(0), channel(2), channel(4))
+            })
+            .collect::<Vec<_>>();
+        // resolved imageは上下が反転しているので、行の順番を反転する
+        Ok(pixels
+            .chunks_exact(width)
+            .take(height)
+            .rev()
+            .flatten()
+            .copied()
+            .collect())
+    }
+
+    // resolved imageをGPUから読み戻して、OpenEXRとしてエンコードしたバイト列を返す。
+    // 露出補正やトーンマッピングをかける前のリニアな放射輝度をそのまま書き込み、
+    // 露出のパラメータはメタデータとして埋め込む。
+    // resolved imageのalphaは常に1なので、premultipliedとして扱っても値は変わらない。
+    // チャンネルの精度はParameters::hdr_precisionで選択する。
+    pub fn screenshot_hdr(&self) -> anyhow::Result<Vec<u8>> {
+        use exr::prelude::*;
+
+        let width = self.params.width as usize;
+        let height = self.params.height as usize;
+
+        // 実行中のresolveが終わるのを待つ
+        self.wait_timeline(self.timeline_value);
+
+        // resolved imageはR16G16B16A16_SFLOATなので1pixel 8byte。resolveはcompute queueで行う
+        let data = self.read_back_image(
+            &self.queue_handles.compute,
+            &self.resolved_image.image,
+            vk::ImageLayout::GENERAL,
+            8,
+        )?;
+        let pixels = data
+            .chunks_exact(2)
+            .map(|bytes| f16::from_bits(u16::from_le_bytes([bytes[0], bytes[1]])))
+            .collect::<Vec<_>>();
+        // resolved imageは上下が反転しているので、output imageと同様に反転して読む
+        let pixel = |position: Vec2<usize>| {
+            let index = ((height - 1 - position.y()) * width + position.x()) * 4;
+            (
+                pixels[index],
+                pixels[index + 1],
+                pixels[index + 2],
+                pixels[index + 3],
+            )
+        };
+
+        let mut attributes = LayerAttributes::named("");
+        attributes.software_name = Some(Text::from("toy-raytracing"));
+        attributes.exposure = Some(self.params.shutter_speed);
+        attributes.aperture = Some(self.params.aperture);
+        attributes.iso_speed = Some(self.params.iso);
+        attributes.other.insert(
+            Text::from("exposureMultiplier"),
+            AttributeValue::F32(crate::exposure::exposure_multiplier(
+                crate::exposure::ev100_from_physical(
+                    self.params.aperture,
+                    self.params.shutter_speed,
+                    self.params.iso,
+                ),
+            )),
+        );
+
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        match self.params.hdr_precision {
+            crate::HdrPrecision::Half => {
+                let layer = Layer::new(
+                    (width, height),
+                    attributes,
+                    Encoding::FAST_LOSSLESS,
+                    SpecificChannels::rgba(pixel),
+                );
+                Image::from_layer(layer).write().to_buffered(&mut bytes)?;
+            }
+            crate::HdrPrecision::Full => {
+                let layer = Layer::new(
+                    (width, height),
+                    attributes,
+                    Encoding::FAST_LOSSLESS,
+                    SpecificChannels::rgba(|position| {
+                        let (r, g, b, a) = pixel(position);
+                        (r.to_f32(), g.to_f32(), b.to_f32(), a.to_f32())
+                    }),
+                );
+                Image::from_layer(layer).write().to_buffered(&mut bytes)?;
+            }
+        }
+
+        Ok(bytes.into_inner())
+    }
+
+    // 読み込んだsceneのglbのanimationの長さ(秒)を返す。
+    // animationがない場合やsceneを読み込んでいない場合は0を返す。
+    pub fn animation_duration(&self) -> f32 {
+        self.scene_objects
+            .as_ref()
+            .map_or(0.0, |scene| scene.animation_duration())
+    }
+
+    // 読み込んだsceneでビルドしたBLASの数と、glbをまたいで同じmeshのBLASを共有した数を返す。
+    // sceneを読み込んでいない場合はどちらも0を返す。
+    pub fn blas_stats(&self) -> crate::BlasStats {
+        self.scene_objects
+            .as_ref()
+            .map_or(crate::BlasStats::default(), |scene| scene.blas_stats)
+    }
+
+    // 各passのGPUの処理時間をpassの名前ごとに返す。
+    // 名前はray_trace、resolve、a_trousで、
+    // まだ一度も実行していないpassは含まれない。
+    // GPUの処理が終わっていないpassは前回計測できた処理時間を返す。
+    pub fn gpu_timings(&self) -> BTreeMap<&'static str, Duration> {
+        self.gpu_timer_pool.durations()
+    }
+
+    // 直前にtraceしたサンプルでtraceしたレイの数とhitしたレイの数を読み戻す。
+    // カメラレイやバウンスのレイに加えて、NEEやambient occlusionのshadow rayも数える。
+    // shadow rayは遮蔽物に当たった場合をhitとする。
+    // Parameters::collect_statsがtrueの間にtraceしたサンプルのみ数えるので、
+    // falseの場合や、まだ一度もtraceしていない場合はすべて0になる。
+    // 実行中のtraceが終わるのを待つので、毎フレーム呼ぶとCPUとGPUの並行性が下がる。
+    pub fn ray_stats(&self) -> anyhow::Result<crate::RayStats> {
+        let buffer_size = self.ray_stats_buffer.size;
+        let readback_buffer = ashtray::utils::create_host_buffer(
+            &self.device,
+            &self.allocator,
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+        );
+
+        // 実行中のtraceが終わるのを待つ
+        self.wait_timeline(self.render_timeline_value);
+
+        // ray statsのbufferはray tracingと同じgraphics queueで書き込む
+        self.submit_readback(&self.queue_handles.graphics, |command_buffer| {
+            command_buffer.cmd_pipeline_barrier2(
+                &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                    &vk::MemoryBarrier2::builder()
+                        .src_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+                        .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+                        .dst_access_mask(vk::AccessFlags2::TRANSFER_READ),
+                )),
+            );
+            command_buffer.cmd_copy_buffer(
+                &self.ray_stats_buffer.buffer,
+                &readback_buffer.buffer,
+                &[vk::BufferCopy::builder().size(buffer_size).build()],
+            );
+        });
+
+        let data = readback_buffer
+            .mapped_slice()
+            .ok_or_else(|| anyhow::anyhow!("Readback buffer is not host visible"))?;
+        let traced = u32::from_le_bytes(data[0..4].try_into().unwrap());
+        let hits = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        Ok(crate::RayStats {
+            traced,
+            hits,
+            misses: traced.saturating_sub(hits),
+        })
+    }
+
+    // resolved imageの輝度のhistogramを計算して読み戻す。
+    // 現在の蓄積をresolveし直すcompute passでhistogramを数えるので、
+    // render regionに関係なく画像全体のpixelが対象になる。
+    // binの数と範囲はexposure::LUMINANCE_HISTOGRAM_*で、
+    // exposure::auto_exposure_ev100などで自動露出に使うことを想定している。
+    pub fn read_luminance_histogram(&self) -> anyhow::Result<Vec<u32>> {
+        let buffer_size = self.luminance_histogram_buffer.size;
+        let readback_buffer = ashtray::utils::create_host_buffer(
+            &self.device,
+            &self.allocator,
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+        );
+
+        // 実行中のpassが終わるのを待つ
+        self.wait_timeline(self.timeline_value);
+
+        let command_buffer = self.luminance_histogram_command_buffer.clone();
+        command_buffer.reset_command_buffer(vk::CommandBufferResetFlags::RELEASE_RESOURCES);
+        ashtray::utils::begin_onetime_command_buffer(&command_buffer);
+
+        command_buffer.cmd_fill_buffer(
+            &self.luminance_histogram_buffer.buffer,
+            0,
+            vk::WHOLE_SIZE,
+            0,
+        );
+        command_buffer.cmd_pipeline_barrier2(
+            &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                &vk::MemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::CLEAR)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                    .dst_access_mask(
+                        vk::AccessFlags2::SHADER_STORAGE_READ
+                            | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    ),
+            )),
+        );
+
+        command_buffer.cmd_bind_compute_pipeline(&self.resolve_compute_pipeline);
+        command_buffer.cmd_bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            &self.resolve_compute_pipeline_layout,
+            0,
+            std::slice::from_ref(&self.descriptor_sets.storage_image.set),
+            &[],
+        );
+        command_buffer.cmd_push_constants(
+            &self.resolve_compute_pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &ResolvePushConstants {
+                sample_count: self.sample_count,
+                input_index: self.accumulate_image_index,
+                output_index: self.resolved_image_index,
+                region_x: 0,
+                region_y: 0,
+                region_width: self.params.width,
+                region_height: self.params.height,
+                albedo_image_index: 0,
+                luminance_histogram_buffer_address: self.luminance_histogram_buffer.device_address,
+                oidn_color_buffer_address: 0,
+                oidn_albedo_buffer_address: 0,
+                oidn_normal_buffer_address: 0,
+                normal_image_index: 0,
+                padding: [0; 1],
+            },
+        );
+        command_buffer.cmd_dispatch(
+            self.params.width.div_ceil(8),
+            self.params.height.div_ceil(8),
+            1,
+        );
+
+        command_buffer.cmd_pipeline_barrier2(
+            &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                &vk::MemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                    .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_READ),
+            )),
+        );
+        command_buffer.cmd_copy_buffer(
+            &self.luminance_histogram_buffer.buffer,
+            &readback_buffer.buffer,
+            &[vk::BufferCopy::builder().size(buffer_size).build()],
+        );
+        command_buffer.end_command_buffer();
+
+        let fence = ashtray::utils::create_fence(&self.device);
+        self.device.queue_submit(
+            self.queue_handles.compute.queue,
+            std::slice::from_ref(
+                &vk::SubmitInfo::builder()
+                    .command_buffers(&[*command_buffer])
+                    .wait_dst_stage_mask(&[])
+                    .wait_semaphores(&[]),
+            ),
+            Some(fence.clone()),
+        );
+        self.device.wait_fences(&[fence], u64::MAX);
+
+        let data = readback_buffer
+            .mapped_slice()
+            .ok_or_else(|| anyhow::anyhow!("Readback buffer is not host visible"))?;
+        Ok(data
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect())
+    }
+
+    // 最初のサンプルでカメラレイがhitしたinstanceのindexをpixelごとに読み戻す。
+    // indexはsceneのinstanceの順番で、何にもhitしなかったpixelはu32::MAXになる。
+    // pixelはoutput imageと同じく左上から行優先で並ぶ。
+    // 2回目以降のbounceでhitしたinstanceは含まない。
+    pub fn read_instance_ids(&self) -> anyhow::Result<Vec<u32>> {
+        self.read_back_aov(&self.instance_id_image, 1)
+    }
+
+    // 最初のサンプルでカメラレイがhitした位置のdepthをpixelごとに読み戻す。
+    // depthはNDCではなく、カメラの位置(平行投影ではカメラの平面)から
+    // カメラの前方向(-Z)に沿って測ったview spaceでの線形な距離。
+    // 何にもhitしなかったpixelはf32::INFINITYになる。並び順はread_instance_idsと同じ。
+    pub fn read_depth(&self) -> anyhow::Result<Vec<f32>> {
+        Ok(self
+            .read_back_aov(&self.depth_image, 1)?
+            .into_iter()
+            .map(f32::from_bits)
+            .collect())
+    }
+
+    // 最初のサンプルでカメラレイがhitした位置のworld spaceでの座標をpixelごとに読み戻す。
+    // 何にもhitしなかったpixelはNoneになる。並び順はread_instance_idsと同じ。
+    pub fn read_positions(&self) -> anyhow::Result<Vec<Option<glam::Vec3>>> {
+        Ok(self
+            .read_back_aov(&self.position_image, 4)?
+            .chunks_exact(4)
+            .map(|p| {
+                // wはhitした場合に1、hitしなかった場合に0
+                (f32::from_bits(p[3]) != 0.0).then(|| {
+                    glam::Vec3::new(
+                        f32::from_bits(p[0]),
+                        f32::from_bits(p[1]),
+                        f32::from_bits(p[2]),
+                    )
+                })
+            })
+            .collect())
+    }
+
+    // 各チャンネル32bitのAOVのimageを読み戻して、チャンネルごとの値のbitを返す。
+    // ray tracingで書き込むimageは上下が反転しているので、行の順番を反転して
+    // output imageと同じく左上から行優先で並べる。
+    fn read_back_aov(
+        &self,
+        image: &ashtray::utils::ImageHandles,
+        channels: usize,
+    ) -> anyhow::Result<Vec<u32>> {
+        let width = self.params.width as usize;
+
+        // 実行中のtraceが終わるのを待つ
+        self.wait_timeline(self.timeline_value);
+
+        // AOVはray tracingと同じgraphics queueで書き込む
+        let data = self.read_back_image(
+            &self.queue_handles.graphics,
+            &image.image,
+            vk::ImageLayout::GENERAL,
+            4 * channels as u64,
+        )?;
+        let values = data
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        Ok(values
+            .chunks_exact(width * channels)
+            .rev()
+            .flatten()
+            .copied()
+            .collect())
+    }
+
+    // layoutにあるimageの内容をhost bufferにコピーしてCPUに読み戻す。
+    // 読み戻した後はimageを元のlayoutに戻す。
+    // queueには最後にimageに書き込んだqueueを渡す。
+    fn read_back_image(
+        &self,
+        queue: &ashtray::utils::QueueHandle,
+        image: &ashtray::ImageHandle,
+        layout: vk::ImageLayout,
+        bytes_per_pixel: u64,
+    ) -> anyhow::Result<Vec<u8>> {
+        // imageの内容をhost bufferにコピーする
+        let buffer_size = self.params.width as u64 * self.params.height as u64 * bytes_per_pixel;
+        let readback_buffer = ashtray::utils::create_host_buffer(
+            &self.device,
+            &self.allocator,
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+        );
+
+        self.submit_readback(queue, |command_buffer| {
+            ashtray::utils::cmd_image_barriers(
+                command_buffer,
+                vk::PipelineStageFlags2::COMPUTE_SHADER
+                    | vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR,
+                vk::AccessFlags2::SHADER_WRITE,
+                layout,
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_READ,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+            );
+            command_buffer.cmd_copy_image_to_buffer(
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                &readback_buffer.buffer,
+                &[vk::BufferImageCopy::builder()
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .mip_level(0)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .image_extent(vk::Extent3D {
+                        width: self.params.width,
+                        height: self.params.height,
+                        depth: 1,
+                    })
+                    .build()],
+            );
+            ashtray::utils::cmd_image_barriers(
+                command_buffer,
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_READ,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags2::COMPUTE_SHADER
+                    | vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR,
+                vk::AccessFlags2::SHADER_READ,
+                layout,
+                image,
+            );
+        });
+
+        let data = readback_buffer
+            .mapped_slice()
+            .ok_or_else(|| anyhow::anyhow!("Readback buffer is not host visible"))?;
+        Ok(data.to_vec())
+    }
+
+    // queueのfamilyのcommand bufferにrecordでコマンドを積み、queueにsubmitして完了を待つ。
+    // 読み戻すimageやbufferはEXCLUSIVEなので、queue family ownershipを移さずに済むように
+    // 最後に書き込んだqueueでコピーする。
+    fn submit_readback(
+        &self,
+        queue: &ashtray::utils::QueueHandle,
+        record: impl FnOnce(&ashtray::CommandBufferHandle),
+    ) {
+        let command_pool = self.device.create_command_pool(
+            &vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(queue.family_index),
+        );
+        let command_buffer =
+            ashtray::utils::allocate_command_buffers(&self.device, &command_pool, 1)
+                .into_iter()
+                .next()
+                .unwrap();
+        ashtray::utils::begin_onetime_command_buffer(&command_buffer);
+        record(&command_buffer);
+        command_buffer.end_command_buffer();
+
+        let fence = ashtray::utils::create_fence(&self.device);
+        self.device.queue_submit(
+            queue.queue,
+            std::slice::from_ref(
+                &vk::SubmitInfo::builder()
+                    .command_buffers(&[*command_buffer])
+                    .wait_dst_stage_mask(&[])
+                    .wait_semaphores(&[]),
+            ),
+            Some(fence.clone()),
+        );
+        self.device.wait_fences(&[fence], u64::MAX);
+    }
 }
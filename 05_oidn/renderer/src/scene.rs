@@ -1,19 +1,370 @@
 use ash::vk;
 use image::Pixel;
 
+/// glbのmeshの法線の扱い方。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ShadingMode {
+    /// glbの頂点の法線を補間して使う。
+    #[default]
+    Smooth,
+    /// 三角形の面の法線を使い、面ごとに平らにシェーディングする。
+    Flat,
+    /// glbの頂点の法線が無いか長さが0や非有限の値を含むmeshは、
+    /// 隣接する三角形の法線から計算し直した頂点の法線を使う。
+    Auto,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Glb {
     pub path: String,
+    /// glbに含まれるmeshの法線の扱い方。
+    #[serde(default)]
+    pub shading: ShadingMode,
+}
+
+/// カメラから直接見えるかどうかを表すray maskのビット。
+pub const RAY_MASK_CAMERA: u8 = 0x01;
+/// 影を落とすかどうか(shadow rayに当たるかどうか)を表すray maskのビット。
+pub const RAY_MASK_SHADOW: u8 = 0x02;
+/// 反射や屈折などの2回目以降のバウンスのレイに当たるかどうかを表すray maskのビット。
+pub const RAY_MASK_INDIRECT: u8 = 0x04;
+
+fn default_ray_mask() -> u8 {
+    0xFF
 }
 
+fn default_light_link() -> u32 {
+    u32::MAX
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Instance {
     pub transform: glam::Mat4,
+    /// シャッターが開いてから1秒後のtransform。
+    /// Someの場合はシャッターが開いている間transformからtransform_endへ補間して動かし、
+    /// モーションブラーをかける。
+    #[serde(default)]
+    pub transform_end: Option<glam::Mat4>,
     pub glb_index: usize,
+    /// TLASのinstanceのmaskに書き込むray mask。
+    /// シェーダーはカメラレイにRAY_MASK_CAMERA、shadow rayにRAY_MASK_SHADOW、
+    /// それ以外のバウンスのレイにRAY_MASK_INDIRECTをcull maskとして使うので、
+    /// ビットを落とすことでinstanceをそれぞれのレイから隠すことができる。
+    /// 例えばRAY_MASK_CAMERAだけ落とすとカメラからは見えないが照明には寄与するlightになり、
+    /// RAY_MASK_SHADOWだけ落とすと影を落とさないinstanceになる。
+    #[serde(default = "default_ray_mask")]
+    pub ray_mask: u8,
+    /// light linkingでこのinstanceが属するgroupのビット。
+    /// 発光面のNEEとBSDFのサンプリングで当たった発光面は、
+    /// そのlightのlight_link_maskとこのgroupのANDが0でない場合のみこのinstanceを照らす。
+    /// 太陽とskyはlight linkingの対象外で、常にすべてのinstanceを照らす。
+    #[serde(default = "default_light_link")]
+    pub light_link_group: u32,
+    /// このinstanceが発光面の場合に照らすgroupのビット。
+    /// 例えばこのinstanceだけを1にして主役のinstanceのlight_link_groupを1にすると、
+    /// 主役のinstanceだけを照らすrim lightになる。
+    /// カメラから直接見える発光はlight linkingの影響を受けない。
+    #[serde(default = "default_light_link")]
+    pub light_link_mask: u32,
+}
+
+/// Scene::validateで見つかったsceneの問題
+#[derive(Debug, Clone)]
+pub enum SceneError {
+    /// instanceのglb_indexがglb_listの範囲外
+    GlbIndexOutOfRange {
+        instance_index: usize,
+        glb_index: usize,
+        glb_count: usize,
+    },
+    /// glbのファイルが存在しないか読み込めない
+    UnreadableGlb {
+        glb_index: usize,
+        path: String,
+        reason: String,
+    },
+    /// skyのtextureのファイルが存在しないか読み込めない
+    UnreadableSkyTexture { path: String, reason: String },
+}
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GlbIndexOutOfRange {
+                instance_index,
+                glb_index,
+                glb_count,
+            } => write!(
+                f,
+                "instances[{instance_index}]: glb_index {glb_index} is out of range \
+                 (glb_list has {glb_count} entries)"
+            ),
+            Self::UnreadableGlb {
+                glb_index,
+                path,
+                reason,
+            } => write!(f, "glb_list[{glb_index}]: cannot read {path:?}: {reason}"),
+            Self::UnreadableSkyTexture { path, reason } => {
+                write!(f, "sky_texture_path: cannot read {path:?}: {reason}")
+            }
+        }
+    }
+}
+impl std::error::Error for SceneError {}
+
+/// Renderer::load_sceneで、sceneの読み込みに必要なGPUのメモリの見積もりが
+/// memory budgetを超えた場合のエラー。サイズはすべてバイト。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SceneTooLargeError {
+    /// sceneの読み込みに必要なメモリの見積もり
+    pub required: u64,
+    /// 読み込み前に既に確保されているdevice localメモリ
+    pub allocated: u64,
+    /// device localメモリのサイズにmemory budgetの割合を掛けたサイズ
+    pub budget: u64,
+    /// device localメモリのサイズ
+    pub total: u64,
+}
+impl std::fmt::Display for SceneTooLargeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        write!(
+            f,
+            "the scene needs about {:.1} MiB of GPU memory, but only {:.1} MiB of the \
+             {:.1} MiB budget is free ({:.1} MiB device local memory in total)",
+            self.required as f64 / MIB,
+            self.budget.saturating_sub(self.allocated) as f64 / MIB,
+            self.budget as f64 / MIB,
+            self.total as f64 / MIB,
+        )
+    }
+}
+impl std::error::Error for SceneTooLargeError {}
+
+/// glbに含まれるKHR_lights_punctualのlightを、instanceのtransformでworld spaceに置いたもの。
+///
+/// rendererの放射輝度はexposureのEV100の式と同じくcd/m^2の測光量として扱っているので、
+/// glTFの単位をそのまま使う。
+/// Directionalのintensityはlux(lm/m^2)で、光に垂直な面の放射照度としてsun_strengthと同じ意味になる。
+/// PointとSpotのintensityはcandela(lm/sr)で、放射強度として距離の2乗で減衰させて使う。
+/// colorはlinearな色で、放射照度や放射強度はintensityとcolorの積になる。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PunctualLight {
+    Directional {
+        /// 光が進む方向。太陽の方向とは逆向きになる。
+        direction: glam::Vec3,
+        color: glam::Vec3,
+        /// lux
+        intensity: f32,
+    },
+    Point {
+        position: glam::Vec3,
+        color: glam::Vec3,
+        /// candela
+        intensity: f32,
+        /// 光が届く距離。Noneの場合は無限遠まで届く。
+        range: Option<f32>,
+    },
+    Spot {
+        position: glam::Vec3,
+        /// 円錐の中心の光が進む方向。
+        direction: glam::Vec3,
+        color: glam::Vec3,
+        /// 円錐のinner_cone_angleの内側のcandela
+        intensity: f32,
+        /// 光が届く距離。Noneの場合は無限遠まで届く。
+        range: Option<f32>,
+        /// 円錐の中心から減衰し始める角度(radian)
+        inner_cone_angle: f32,
+        /// 円錐の中心から0まで減衰する角度(radian)
+        outer_cone_angle: f32,
+    },
 }
 
+// ファイルが存在して読み込めるかをチェックする
+fn check_readable(path: &str) -> Result<(), String> {
+    std::fs::File::open(path)
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Scene {
     pub sky_texture_path: String,
     pub glb_list: Vec<Glb>,
     pub instances: Vec<Instance>,
+    /// 三角形のmeshとは別に、球の集合として描画する点群。
+    #[serde(default)]
+    pub point_clouds: Vec<crate::PointCloud>,
+}
+impl Scene {
+    /// RON形式で書かれたsceneのファイルを読み込む。
+    /// sceneに含まれるglbやskyのtextureのpathはそのまま使われるので、
+    /// カレントディレクトリからの相対パスか絶対パスで書く必要がある。
+    pub fn load_from_file(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let scene = ron::from_str(&text)?;
+        Ok(scene)
+    }
+
+    /// sceneを読み込む前に、instanceのindexが範囲内か、
+    /// 参照しているファイルが存在して読み込めるかをチェックする。
+    /// 見つかった問題はすべてまとめて返す。
+    pub fn validate(&self) -> Result<(), Vec<SceneError>> {
+        let mut errors = vec![];
+
+        for (instance_index, instance) in self.instances.iter().enumerate() {
+            if instance.glb_index >= self.glb_list.len() {
+                errors.push(SceneError::GlbIndexOutOfRange {
+                    instance_index,
+                    glb_index: instance.glb_index,
+                    glb_count: self.glb_list.len(),
+                });
+            }
+        }
+
+        for (glb_index, glb) in self.glb_list.iter().enumerate() {
+            if let Err(reason) = check_readable(&glb.path) {
+                errors.push(SceneError::UnreadableGlb {
+                    glb_index,
+                    path: glb.path.clone(),
+                    reason,
+                });
+            }
+        }
+
+        if let Err(reason) = check_readable(&self.sky_texture_path) {
+            errors.push(SceneError::UnreadableSkyTexture {
+                path: self.sky_texture_path.clone(),
+                reason,
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// sceneのすべてのinstanceと点群を囲むworld spaceのAABBを(min, max)で返す。
+    /// 各glbの頂点のAABBをinstanceのtransformで変換した8頂点を囲むAABBをマージする。
+    /// transform_endがある場合はその位置のAABBもマージする。
+    /// 点群は球ごとのAABBをマージする。
+    /// glbを読み込んで頂点を走査するので、sceneのロードと同程度の時間がかかる。
+    /// instanceが1つもない場合は(Vec3::ZERO, Vec3::ZERO)を返す。
+    pub fn world_bounds(&self) -> anyhow::Result<(glam::Vec3, glam::Vec3)> {
+        // glbごとのlocal spaceのAABB
+        let mut glb_bounds = vec![];
+        for glb in &self.glb_list {
+            let glb_scenes = glb::load(&glb.path)
+                .map_err(|err| anyhow::anyhow!("Failed to load {}: {err}", glb.path))?;
+            let bounds = glb_scenes
+                .iter()
+                .flat_map(|glb_scene| &glb_scene.models)
+                .flat_map(|model| model.vertices())
+                .fold(None, |bounds, vertex| {
+                    merge_bounds(bounds, (vertex.position, vertex.position))
+                });
+            glb_bounds.push(bounds);
+        }
+
+        let mut world_bounds = None;
+        for instance in &self.instances {
+            let Some((min, max)) = glb_bounds.get(instance.glb_index).copied().flatten() else {
+                continue;
+            };
+            let transforms = std::iter::once(instance.transform).chain(instance.transform_end);
+            for transform in transforms {
+                for i in 0..8 {
+                    let corner = glam::Vec3::select(
+                        glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+                        max,
+                        min,
+                    );
+                    let corner = transform.transform_point3(corner);
+                    world_bounds = merge_bounds(world_bounds, (corner, corner));
+                }
+            }
+        }
+        for point_cloud in &self.point_clouds {
+            let radius = glam::Vec3::splat(point_cloud.radius);
+            for position in &point_cloud.positions {
+                world_bounds = merge_bounds(world_bounds, (*position - radius, *position + radius));
+            }
+        }
+
+        Ok(world_bounds.unwrap_or((glam::Vec3::ZERO, glam::Vec3::ZERO)))
+    }
+
+    /// sceneのinstanceが参照するglbに含まれるKHR_lights_punctualのlightを、
+    /// glbのnodeの階層のtransformとinstanceのtransformを合成してworld spaceで返す。
+    /// lightはinstanceの順に、instanceごとにglbのnodeをたどった順で並ぶ。
+    /// Directionalは太陽として使えるので、Parameters::set_sun_from_lightで太陽のパラメータにできる。
+    /// PointとSpotは現在のrendererでは描画しない。
+    pub fn punctual_lights(&self) -> anyhow::Result<Vec<PunctualLight>> {
+        // glbごとのlocal spaceのlight
+        let mut glb_lights = vec![];
+        for glb in &self.glb_list {
+            let glb_scenes = glb::load(&glb.path)
+                .map_err(|err| anyhow::anyhow!("Failed to load {}: {err}", glb.path))?;
+            let lights = glb_scenes
+                .into_iter()
+                .flat_map(|glb_scene| glb_scene.lights)
+                .collect::<Vec<_>>();
+            glb_lights.push(lights);
+        }
+
+        let mut lights = vec![];
+        for instance in &self.instances {
+            let Some(instance_lights) = glb_lights.get(instance.glb_index) else {
+                continue;
+            };
+            for light in instance_lights {
+                let transform = instance.transform * light.transform();
+                let position = transform.transform_point3(glam::Vec3::ZERO);
+                let direction = transform
+                    .transform_vector3(glam::Vec3::NEG_Z)
+                    .normalize_or_zero();
+                lights.push(match light.kind() {
+                    glb::LightKind::Directional => PunctualLight::Directional {
+                        direction,
+                        color: light.color(),
+                        intensity: light.intensity(),
+                    },
+                    glb::LightKind::Point => PunctualLight::Point {
+                        position,
+                        color: light.color(),
+                        intensity: light.intensity(),
+                        range: light.range(),
+                    },
+                    glb::LightKind::Spot {
+                        inner_cone_angle,
+                        outer_cone_angle,
+                    } => PunctualLight::Spot {
+                        position,
+                        direction,
+                        color: light.color(),
+                        intensity: light.intensity(),
+                        range: light.range(),
+                        inner_cone_angle,
+                        outer_cone_angle,
+                    },
+                });
+            }
+        }
+        Ok(lights)
+    }
+}
+
+// 2つのAABBをマージする。NoneのAABBは空として扱う
+fn merge_bounds(
+    bounds: Option<(glam::Vec3, glam::Vec3)>,
+    (min, max): (glam::Vec3, glam::Vec3),
+) -> Option<(glam::Vec3, glam::Vec3)> {
+    match bounds {
+        Some((bounds_min, bounds_max)) => Some((bounds_min.min(min), bounds_max.max(max))),
+        None => Some((min, max)),
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -40,13 +391,159 @@ pub(crate) struct Material {
     normal_texture_index: i32,
     alpha_cutoff: f32,
     ty: u32,
+    double_sided: u32,
+    // KHR_texture_transformのUVの2x3のアフィン行列(行優先)
+    base_color_uv_transform: [f32; 6],
+    metallic_roughness_uv_transform: [f32; 6],
+    normal_uv_transform: [f32; 6],
+    emissive_uv_transform: [f32; 6],
+    // KHR_materials_transmissionとKHR_materials_ior
+    transmission_factor: f32,
+    transmission_texture_index: i32,
+    transmission_uv_transform: [f32; 6],
+    ior: f32,
+    // KHR_materials_clearcoat
+    clearcoat_factor: f32,
+    clearcoat_roughness_factor: f32,
+    // KHR_materials_specular
+    specular_factor: f32,
+    specular_color_factor: [f32; 3],
+    // KHR_materials_iridescence。thicknessはnm
+    iridescence_factor: f32,
+    iridescence_ior: f32,
+    iridescence_thickness: f32,
+    // 1ならShadingMode::Flatで面の法線を使う
+    flat_shading: u32,
+    // KHR_materials_dispersion。20 / アッベ数
+    dispersion: f32,
+}
+impl Material {
+    // Parameters::cull_modeでカリングしないmaterialかどうか。
+    // 両面のmaterialは裏面も表として描画し、透過するmaterialは内側から外側へ抜けるレイが
+    // 裏面にhitする必要があるので、どちらもカリングしない。
+    fn cull_disabled(&self) -> bool {
+        self.double_sided == 1 || self.transmission_factor > 0.0
+    }
+}
+
+// 単位行列のUVの2x3のアフィン行列
+const IDENTITY_UV_TRANSFORM: [f32; 6] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+
+// 点群の球のinstanceのsbt offset。
+// materialのtyの0から2の後ろの、球のintersection shaderを持つhit groupを指す。
+// raygen.rgenのhit groupの並びと値を揃えること。
+const POINT_CLOUD_SBT_OFFSET: u32 = 3;
+
+// 点群の球に使う白の拡散反射のmaterial。
+// 球ごとの色はclosest hit shaderでbase_color_factorに掛ける。
+fn point_cloud_material() -> Material {
+    Material {
+        base_color_factor: [1.0, 1.0, 1.0, 1.0],
+        base_color_texture_index: -1,
+        emissive_factor: [0.0, 0.0, 0.0],
+        emissive_texture_index: -1,
+        metallic_factor: 0.0,
+        metallic_texture_index: -1,
+        roughness_factor: 1.0,
+        roughness_texture_index: -1,
+        normal_factor: 1.0,
+        normal_texture_index: -1,
+        alpha_cutoff: 0.5,
+        ty: 0,
+        double_sided: 0,
+        base_color_uv_transform: IDENTITY_UV_TRANSFORM,
+        metallic_roughness_uv_transform: IDENTITY_UV_TRANSFORM,
+        normal_uv_transform: IDENTITY_UV_TRANSFORM,
+        emissive_uv_transform: IDENTITY_UV_TRANSFORM,
+        transmission_factor: 0.0,
+        transmission_texture_index: -1,
+        transmission_uv_transform: IDENTITY_UV_TRANSFORM,
+        ior: 1.5,
+        clearcoat_factor: 0.0,
+        clearcoat_roughness_factor: 0.0,
+        specular_factor: 1.0,
+        specular_color_factor: [1.0, 1.0, 1.0],
+        iridescence_factor: 0.0,
+        iridescence_ior: 1.3,
+        iridescence_thickness: 0.0,
+        flat_shading: 0,
+        dispersion: 0.0,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct EmissiveLight {
+    instance_index: u32,
+    triangle_count: u32,
+    area: f32,
+    select_pdf: f32,
+    alias_probability: f32,
+    alias_index: u32,
+    triangle_cdf_address: u64,
+    // このlightが照らすinstanceのlight_link_groupのビット
+    light_link_mask: u32,
+    padding: u32,
+}
+
+// skinを持つglbのmodelを、compute shaderでanimationのposeに変形するためのbufferとBLAS
+pub(crate) struct SkinnedModel {
+    model: glb::Model,
+    // 変形に使うanimationのindex
+    animation_index: usize,
+    // 変形した頂点をvertex bufferに書き込んでrefitするBLAS
+    blas: ashtray::utils::UpdatableBlasObjects,
+    // rest poseの頂点
+    rest_vertices_buffer: ashtray::utils::BufferObjects,
+    // 頂点ごとのjointのindexとweight
+    vertex_joints_buffer: ashtray::utils::BufferObjects,
+    vertex_weights_buffer: ashtray::utils::BufferObjects,
+    // jointごとの変形の行列。poseが変わるたびにHostから書き換える
+    joint_matrices_buffer: ashtray::utils::BufferObjects,
+}
+
+// skinning.compのpush constants
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct SkinningPushConstants {
+    rest_vertices_address: u64,
+    skinned_vertices_address: u64,
+    vertex_joints_address: u64,
+    vertex_weights_address: u64,
+    joint_matrices_address: u64,
+    vertex_count: u32,
+    joint_count: u32,
+}
+
+// skinで変形する発光instanceがある場合に、poseが変わるたびに
+// lightの面積と選択確率を計算し直すための情報
+pub(crate) struct SkinnedLights {
+    lights: Vec<EmissiveLight>,
+    // lightごとの発光の輝度。放射束は輝度と面積の積になる
+    emissive_luminances: Vec<f64>,
+    triangle_cdf: Vec<f32>,
+    triangle_cdf_offsets: Vec<usize>,
+    lights_staging_buffer: ashtray::utils::BufferObjects,
+    triangle_cdf_staging_buffer: ashtray::utils::BufferObjects,
 }
 
 pub(crate) struct SceneObjects {
     pub(crate) _sampler: ashtray::SamplerHandle,
     pub(crate) _images: Vec<ashtray::utils::ImageHandles>,
     pub(crate) _blas_list: Vec<ashtray::utils::BlasObjects>,
+    pub(crate) blas_stats: crate::BlasStats,
     pub(crate) tlas: ashtray::utils::TlasObjects,
+    pub(crate) instances: Vec<(ashtray::utils::BlasObjects, glam::Mat4, u32, u32, u8, u32)>,
+    pub(crate) instance_transform_ends: Vec<Option<glam::Mat4>>,
+    // glbのsceneごとのanimation
+    pub(crate) animations: Vec<glb::Animation>,
+    // instancesごとに、動かすanimationのindexとそのanimationの中のmodelのindex
+    pub(crate) instance_animations: Vec<Option<(usize, usize)>>,
+    // skinを持つglbのmodel。同じglbのinstanceは変形した頂点とBLASを共有する
+    pub(crate) skinned_models: Vec<SkinnedModel>,
+    // instancesごとに、animationのposeに合わせてskinで変形するskinned_modelsのindex
+    pub(crate) instance_skins: Vec<Option<usize>>,
+    pub(crate) materials: Vec<Material>,
     pub(crate) sky_texture_width: u32,
     pub(crate) sky_texture_height: u32,
     pub(crate) sky_texture_buffer: ashtray::utils::BufferObjects,
@@ -54,7 +551,704 @@ pub(crate) struct SceneObjects {
     pub(crate) sky_texture_pdf_row_buffer: ashtray::utils::BufferObjects,
     pub(crate) sky_texture_cdf_column_buffer: ashtray::utils::BufferObjects,
     pub(crate) sky_texture_pdf_column_buffer: ashtray::utils::BufferObjects,
+    pub(crate) light_count: u32,
+    pub(crate) lights_buffer: ashtray::utils::BufferObjects,
+    pub(crate) light_triangle_cdf_buffer: ashtray::utils::BufferObjects,
+    pub(crate) skinned_lights: Option<SkinnedLights>,
+    pub(crate) instance_light_indices_buffer: ashtray::utils::BufferObjects,
+}
+
+impl SceneObjects {
+    /// transform_endを持つinstanceがあるかどうか。
+    pub(crate) fn has_motion(&self) -> bool {
+        self.instance_transform_ends.iter().any(|t| t.is_some())
+    }
+
+    /// glbのanimationで動くinstanceがあるかどうか。
+    pub(crate) fn has_animation(&self) -> bool {
+        self.instance_animations.iter().any(|a| a.is_some())
+    }
+
+    /// glbのanimationの長さ(秒)の最大値。
+    pub(crate) fn animation_duration(&self) -> f32 {
+        self.animations
+            .iter()
+            .map(|animation| animation.duration())
+            .fold(0.0, f32::max)
+    }
+
+    /// glbのanimationをanimation_time秒の時点のposeにして、TLASを同じbufferに
+    /// ビルドし直すコマンドをcommand_bufferに積む。
+    /// skinを持つmodelのBLASはcmd_update_skinsで先に変形しておく。
+    ///
+    /// motion instanceのTLASでは、シャッターが開いた時点からshutter_speed秒後までを
+    /// レイごとのtime 0.0から1.0に割り当てる。
+    /// そうでないTLASでは、シャッターが開いてからtime秒後のtransformにする。
+    pub(crate) fn cmd_update_tlas(
+        &mut self,
+        command_buffer: &ashtray::CommandBufferHandle,
+        time: f32,
+        shutter_speed: f32,
+        animation_time: f32,
+    ) {
+        let animation_transforms = self
+            .animations
+            .iter()
+            .map(|animation| {
+                if animation.is_animated() {
+                    animation.sample(animation_time)
+                } else {
+                    vec![]
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut instances = Vec::with_capacity(self.instances.len());
+        let mut transform_ends = Vec::with_capacity(self.instances.len());
+        for (
+            (
+                (
+                    (blas, transform, material_index, sbt_offset, ray_mask, light_link_group),
+                    transform_end,
+                ),
+                animation,
+            ),
+            skinned,
+        ) in self
+            .instances
+            .iter()
+            .zip(&self.instance_transform_ends)
+            .zip(&self.instance_animations)
+            .zip(&self.instance_skins)
+        {
+            let (transform, transform_end) = match transform_end {
+                Some(transform_end) if self.tlas.motion => (
+                    *transform,
+                    Some(interpolate_transform(
+                        *transform,
+                        *transform_end,
+                        shutter_speed,
+                    )),
+                ),
+                Some(transform_end) => (
+                    interpolate_transform(*transform, *transform_end, time),
+                    None,
+                ),
+                None => (*transform, None),
+            };
+            // skinで変形した頂点は既にworld spaceのposeになっているのでそのまま使う。
+            // それ以外のglbの頂点はrest poseで焼き込まれているので、
+            // rest poseからの差分を掛ける
+            let animation_transform = match (skinned, animation) {
+                (None, Some((animation_index, model_index))) => {
+                    animation_transforms[*animation_index][*model_index]
+                }
+                _ => glam::Mat4::IDENTITY,
+            };
+            instances.push((
+                blas.clone(),
+                transform * animation_transform,
+                *material_index,
+                *sbt_offset,
+                *ray_mask,
+                *light_link_group,
+            ));
+            transform_ends.push(transform_end.map(|t| t * animation_transform));
+        }
+
+        ashtray::utils::cmd_update_tlas(
+            command_buffer,
+            &mut self.tlas,
+            &instances,
+            &transform_ends,
+            &self.materials,
+            Material::cull_disabled,
+        );
+    }
+
+    /// glbのanimationをanimation_time秒の時点のposeにして、skinを持つmodelの頂点を
+    /// compute shaderで変形し、そのBLASをrefitするコマンドをcommand_bufferに積む。
+    /// skinで変形する発光instanceは、そのposeでの面積でlightの選択確率も計算し直す。
+    /// 積んだコマンドの後でcmd_update_tlasでTLASをビルドし直す必要がある。
+    ///
+    /// joint行列とlightはHostから書き込むので、前にこのsceneを更新したり
+    /// traceに使ったりしたcommand bufferの完了を待ってから呼ぶ必要がある。
+    pub(crate) fn cmd_update_skins(
+        &mut self,
+        command_buffer: &ashtray::CommandBufferHandle,
+        skinning_pipeline: &ashtray::ComputePipelineHandle,
+        skinning_pipeline_layout: &ashtray::PipelineLayoutHandle,
+        animation_time: f32,
+    ) {
+        if self.skinned_models.is_empty() {
+            return;
+        }
+
+        command_buffer.cmd_bind_compute_pipeline(skinning_pipeline);
+        let mut joint_matrices_list = vec![];
+        for skinned in &mut self.skinned_models {
+            let skin = skinned.model.skin().unwrap();
+            let joint_matrices =
+                self.animations[skinned.animation_index].joint_matrices(animation_time, skin);
+            // rest poseの頂点にはmodelのtransformが適用済みなので、それを戻してから変形する
+            let inverse_transform = skinned.model.transform().inverse();
+            skinned.joint_matrices_buffer.write(
+                &joint_matrices
+                    .iter()
+                    .map(|joint_matrix| *joint_matrix * inverse_transform)
+                    .collect::<Vec<_>>(),
+            );
+            let vertex_count = skinned.model.vertices().len() as u32;
+            command_buffer.cmd_push_constants(
+                skinning_pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &SkinningPushConstants {
+                    rest_vertices_address: skinned.rest_vertices_buffer.device_address,
+                    skinned_vertices_address: skinned.blas.blas.vertex_buffer.device_address,
+                    vertex_joints_address: skinned.vertex_joints_buffer.device_address,
+                    vertex_weights_address: skinned.vertex_weights_buffer.device_address,
+                    joint_matrices_address: skinned.joint_matrices_buffer.device_address,
+                    vertex_count,
+                    joint_count: joint_matrices.len() as u32,
+                },
+            );
+            command_buffer.cmd_dispatch(vertex_count.div_ceil(64), 1, 1);
+            joint_matrices_list.push(joint_matrices);
+        }
+
+        // 変形した頂点をBLASのrefitとhit shaderから読めるようにしてからrefitする
+        command_buffer.cmd_pipeline_barrier2(
+            &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                &vk::MemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                    .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                    .dst_stage_mask(
+                        vk::PipelineStageFlags2::ACCELERATION_STRUCTURE_BUILD_KHR
+                            | vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR,
+                    )
+                    .dst_access_mask(
+                        vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_STORAGE_READ,
+                    ),
+            )),
+        );
+        for skinned in &self.skinned_models {
+            ashtray::utils::cmd_update_blas(command_buffer, &skinned.blas);
+        }
+
+        // skinで変形する発光instanceはworld spaceの面積が変わるので、
+        // 三角形のCDFとlightの放射束、alias tableを作り直してGPUのbufferに書き込む
+        let Some(skinned_lights) = &mut self.skinned_lights else {
+            return;
+        };
+        let mut light_powers = vec![];
+        for (i, light) in skinned_lights.lights.iter_mut().enumerate() {
+            let instance_index = light.instance_index as usize;
+            if let Some(skinned_index) = self.instance_skins[instance_index] {
+                let skinned = &self.skinned_models[skinned_index];
+                let positions = skinned
+                    .model
+                    .skinned_vertices(&joint_matrices_list[skinned_index])
+                    .iter()
+                    .map(|v| v.position)
+                    .collect::<Vec<_>>();
+                let triangle_areas = triangle_areas(
+                    &positions,
+                    skinned.model.indices().unwrap(),
+                    self.instances[instance_index].1,
+                );
+                let (cdf, area) = triangle_cdf(&triangle_areas);
+                let offset = skinned_lights.triangle_cdf_offsets[i];
+                skinned_lights.triangle_cdf[offset..offset + cdf.len()].copy_from_slice(&cdf);
+                light.area = area as f32;
+            }
+            light_powers.push(skinned_lights.emissive_luminances[i] * light.area as f64);
+        }
+        update_light_selection(&mut skinned_lights.lights, &light_powers);
+
+        skinned_lights
+            .lights_staging_buffer
+            .write(&skinned_lights.lights);
+        skinned_lights
+            .triangle_cdf_staging_buffer
+            .write(&skinned_lights.triangle_cdf);
+        command_buffer.cmd_copy_buffer(
+            &skinned_lights.lights_staging_buffer.buffer,
+            &self.lights_buffer.buffer,
+            &[vk::BufferCopy::builder()
+                .size(self.lights_buffer.size())
+                .build()],
+        );
+        command_buffer.cmd_copy_buffer(
+            &skinned_lights.triangle_cdf_staging_buffer.buffer,
+            &self.light_triangle_cdf_buffer.buffer,
+            &[vk::BufferCopy::builder()
+                .size(self.light_triangle_cdf_buffer.size())
+                .build()],
+        );
+        command_buffer.cmd_pipeline_barrier2(
+            &vk::DependencyInfoKHR::builder().memory_barriers(std::slice::from_ref(
+                &vk::MemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR)
+                    .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ),
+            )),
+        );
+    }
+}
+
+// positionsの頂点をtransformでworld spaceにした三角形の面積を返す。
+fn triangle_areas(positions: &[glam::Vec3], indices: &[u32], transform: glam::Mat4) -> Vec<f64> {
+    indices
+        .chunks(3)
+        .map(|index| {
+            let p0 = transform.transform_point3(positions[index[0] as usize]);
+            let p1 = transform.transform_point3(positions[index[1] as usize]);
+            let p2 = transform.transform_point3(positions[index[2] as usize]);
+            (p1 - p0).cross(p2 - p0).length() as f64 * 0.5
+        })
+        .collect()
+}
+
+// 三角形を面積に比例した確率で選択するためのCDFを作成する。
+// 返り値は先頭の0を含む三角形の数+1個のCDFと、面積の合計。
+fn triangle_cdf(triangle_areas: &[f64]) -> (Vec<f32>, f64) {
+    let area = triangle_areas.iter().sum::<f64>();
+    let mut cdf_sum = 0.0;
+    let mut cdf = vec![0.0f32];
+    for triangle_area in triangle_areas {
+        cdf_sum += triangle_area;
+        cdf.push((cdf_sum / area) as f32);
+    }
+    (cdf, area)
+}
+
+// lightを放射束light_powersに比例した確率で選択するためのpdfとalias tableを設定する。
+fn update_light_selection(lights: &mut [EmissiveLight], light_powers: &[f64]) {
+    let light_power_sum = light_powers.iter().sum::<f64>();
+    let alias_table = build_alias_table(light_powers);
+    for (i, light) in lights.iter_mut().enumerate() {
+        light.select_pdf = if light_power_sum > 0.0 {
+            (light_powers[i] / light_power_sum) as f32
+        } else {
+            1.0 / light_powers.len() as f32
+        };
+        light.alias_probability = alias_table[i].0 as f32;
+        light.alias_index = alias_table[i].1;
+    }
+}
+
+// transformをscale、rotation、translationに分解してそれぞれ補間する。
+fn interpolate_transform(start: glam::Mat4, end: glam::Mat4, t: f32) -> glam::Mat4 {
+    let (start_scale, start_rotation, start_translation) = start.to_scale_rotation_translation();
+    let (end_scale, end_rotation, end_translation) = end.to_scale_rotation_translation();
+    glam::Mat4::from_scale_rotation_translation(
+        start_scale.lerp(end_scale, t),
+        start_rotation.slerp(end_rotation, t),
+        start_translation.lerp(end_translation, t),
+    )
+}
+
+// weightsに比例した確率で選択するためのalias tableを作成する。
+// 返り値は各要素の(そのまま選ばれる確率, aliasのindex)。
+fn build_alias_table(weights: &[f64]) -> Vec<(f64, u32)> {
+    let n = weights.len();
+    let sum = weights.iter().sum::<f64>();
+    if sum <= 0.0 || !sum.is_finite() {
+        // 重みが使えない場合は一様に選択する
+        return (0..n).map(|i| (1.0, i as u32)).collect();
+    }
+
+    let mut scaled = weights
+        .iter()
+        .map(|w| w / sum * n as f64)
+        .collect::<Vec<_>>();
+    let mut table = (0..n).map(|i| (1.0, i as u32)).collect::<Vec<_>>();
+    let mut small = vec![];
+    let mut large = vec![];
+    for (i, &w) in scaled.iter().enumerate() {
+        if w < 1.0 {
+            small.push(i);
+        } else {
+            large.push(i);
+        }
+    }
+    while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+        table[s] = (scaled[s], l as u32);
+        scaled[l] -= 1.0 - scaled[s];
+        if scaled[l] < 1.0 {
+            large.pop();
+            small.push(l);
+        }
+    }
+    table
+}
+
+// glbのmodelの頂点をBLASのvertex bufferの頂点に変換する。
+// skinを持つmodelではrest poseの頂点になり、skinning.compで変形する。
+fn model_vertices(model: &glb::Model, shading: ShadingMode) -> Vec<Vertex> {
+    let indices = model.indices().unwrap();
+    let mut vertices = model
+        .vertices()
+        .iter()
+        .map(|v| Vertex {
+            position: [v.position.x, v.position.y, v.position.z],
+            normal: [v.normal.x, v.normal.y, v.normal.z],
+            tangent: [0.0, 0.0, 0.0],
+            tex_coords: [v.tex_coords.x, v.tex_coords.y],
+        })
+        .collect::<Vec<_>>();
+    // 法線が無いか壊れているmeshは隣接する三角形から法線を計算し直す。
+    // skinで変形する頂点はrest poseの頂点から計算した法線を変形する
+    if shading == ShadingMode::Auto && !model.has_valid_normals() {
+        for (vertex, normal) in vertices.iter_mut().zip(model.smooth_normals()) {
+            vertex.normal = normal.to_array();
+        }
+    }
+    // UVからtangentの計算
+    for index in indices.chunks(3) {
+        let idx0 = index[0] as usize;
+        let idx1 = index[1] as usize;
+        let idx2 = index[2] as usize;
+        let dv1 = glam::Vec3::from_array(vertices[idx1].position)
+            - glam::Vec3::from_array(vertices[idx0].position);
+        let dv2 = glam::Vec3::from_array(vertices[idx2].position)
+            - glam::Vec3::from_array(vertices[idx0].position);
+        let duv1 = glam::Vec2::from_array(vertices[idx1].tex_coords)
+            - glam::Vec2::from_array(vertices[idx0].tex_coords);
+        let duv2 = glam::Vec2::from_array(vertices[idx2].tex_coords)
+            - glam::Vec2::from_array(vertices[idx0].tex_coords);
+        let r = 1.0 / (duv1.x * duv2.y - duv1.y * duv2.x);
+        let tangent = (dv1 * duv2.y - dv2 * duv1.y) * r;
+
+        vertices[idx0].tangent = tangent.to_array();
+        vertices[idx1].tangent = tangent.to_array();
+        vertices[idx2].tangent = tangent.to_array();
+    }
+    vertices
+}
+
+// BLASのindex bufferに格納するu16のindex。
+// u16のindexはシェーダーが4バイト単位で読むので偶数個になるようにパディングする。
+// u32のindexの場合はNoneを返し、model.indices()をそのまま格納する。
+fn blas_indices16(model: &glb::Model) -> Option<Vec<u16>> {
+    (model.index_format() == glb::IndexFormat::U16).then(|| {
+        let mut indices16 = model
+            .indices()
+            .unwrap()
+            .iter()
+            .map(|&i| i as u16)
+            .collect::<Vec<_>>();
+        if !indices16.len().is_multiple_of(2) {
+            indices16.push(0);
+        }
+        indices16
+    })
+}
+
+// modelの頂点verticesのBLASをビルドする。
+// 毎フレーム作り直すBLASはコンパクションしても元が取れないので、
+// FastTraceの場合のみコンパクションする。
+fn build_model_blas(
+    device: &ashtray::DeviceHandle,
+    queue_handles: &ashtray::utils::QueueHandles,
+    compute_command_pool: &ashtray::CommandPoolHandle,
+    allocator: &ashtray::AllocatorHandle,
+    model: &glb::Model,
+    vertices: &[Vertex],
+    preference: ashtray::utils::BuildPreference,
+) -> ashtray::utils::BlasObjects {
+    let format = blas_geometry_format(model);
+    let transparent = model.material().alpha_mode != glb::AlphaMode::Opaque;
+    let compact = preference == ashtray::utils::BuildPreference::FastTrace;
+    match blas_indices16(model) {
+        Some(indices16) => ashtray::utils::cerate_blas(
+            device,
+            queue_handles,
+            compute_command_pool,
+            allocator,
+            vertices,
+            &indices16,
+            format,
+            transparent,
+            preference,
+            compact,
+        ),
+        None => ashtray::utils::cerate_blas(
+            device,
+            queue_handles,
+            compute_command_pool,
+            allocator,
+            vertices,
+            model.indices().unwrap(),
+            format,
+            transparent,
+            preference,
+            compact,
+        ),
+    }
+}
+
+// skinを持つmodelのrefitできるBLASと、skinning.compで変形するためのbufferを作る。
+// BLASのvertex bufferはrest poseの頂点verticesで初期化し、
+// cmd_update_skinsで変形した頂点に書き換えてrefitする。
+#[allow(clippy::too_many_arguments)]
+fn create_skinned_model(
+    device: &ashtray::DeviceHandle,
+    queue_handles: &ashtray::utils::QueueHandles,
+    compute_command_pool: &ashtray::CommandPoolHandle,
+    transfer_command_pool: &ashtray::CommandPoolHandle,
+    allocator: &ashtray::AllocatorHandle,
+    model: &glb::Model,
+    vertices: &[Vertex],
+    animation_index: usize,
+) -> SkinnedModel {
+    let skin = model.skin().unwrap();
+    let format = blas_geometry_format(model);
+    let transparent = model.material().alpha_mode != glb::AlphaMode::Opaque;
+    let blas = match blas_indices16(model) {
+        Some(indices16) => ashtray::utils::create_updatable_blas(
+            device,
+            queue_handles,
+            compute_command_pool,
+            transfer_command_pool,
+            allocator,
+            vertices,
+            &indices16,
+            format,
+            transparent,
+        ),
+        None => ashtray::utils::create_updatable_blas(
+            device,
+            queue_handles,
+            compute_command_pool,
+            transfer_command_pool,
+            allocator,
+            vertices,
+            model.indices().unwrap(),
+            format,
+            transparent,
+        ),
+    };
+
+    let usage = vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+    let rest_vertices_buffer = ashtray::utils::create_device_local_buffer_with_data(
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
+        vertices,
+        usage,
+    );
+    // シェーダーではu16を読めないのでjointのindexはu32に広げて格納する
+    let vertex_joints = skin
+        .vertex_joints()
+        .iter()
+        .map(|joints| joints.map(|joint| joint as u32))
+        .collect::<Vec<_>>();
+    let vertex_joints_buffer = ashtray::utils::create_device_local_buffer_with_data(
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
+        &vertex_joints,
+        usage,
+    );
+    let vertex_weights_buffer = ashtray::utils::create_device_local_buffer_with_data(
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
+        skin.vertex_weights(),
+        usage,
+    );
+    // 空のbufferは作れないのでjointが無い場合も1つ分確保しておく
+    let joint_matrices_buffer = ashtray::utils::create_host_buffer(
+        device,
+        allocator,
+        (skin.joints().len().max(1) * std::mem::size_of::<glam::Mat4>()) as u64,
+        usage,
+    );
+
+    SkinnedModel {
+        model: model.clone(),
+        animation_index,
+        blas,
+        rest_vertices_buffer,
+        vertex_joints_buffer,
+        vertex_weights_buffer,
+        joint_matrices_buffer,
+    }
+}
+
+// BLASを共有するかどうかを決めるmeshの内容。
+// floatもバイト列で比較するので、-0.0と0.0のようにビットが違う値は別のmeshとして扱う。
+#[derive(PartialEq, Eq, Hash)]
+struct BlasCacheKey {
+    vertices: Vec<u8>,
+    indices: Vec<u8>,
+    index_format: glb::IndexFormat,
+    transparent: bool,
+}
+
+// modelのindexをBLASに格納する際のformat
+fn blas_geometry_format(model: &glb::Model) -> ashtray::utils::BlasGeometryFormat {
+    match model.index_format() {
+        glb::IndexFormat::U16 => ashtray::utils::BlasGeometryFormat {
+            index_type: vk::IndexType::UINT16,
+            ..Default::default()
+        },
+        glb::IndexFormat::U32 => ashtray::utils::BlasGeometryFormat::default(),
+    }
+}
+
+// materialのtextureをload_sceneでGPUにuploadした場合のサイズ。
+// textureはすべてRGBA8でmipmapを持たない。
+fn material_texture_size(material: &glb::model::Material) -> u64 {
+    fn size(width: u32, height: u32) -> u64 {
+        width as u64 * height as u64 * 4
+    }
+    let pbr = &material.pbr;
+    [
+        pbr.base_color_texture
+            .as_ref()
+            .map(|t| size(t.width(), t.height())),
+        pbr.metallic_texture
+            .as_ref()
+            .map(|t| size(t.width(), t.height())),
+        pbr.roughness_texture
+            .as_ref()
+            .map(|t| size(t.width(), t.height())),
+        material
+            .normal
+            .as_ref()
+            .map(|n| size(n.texture.width(), n.texture.height())),
+        material
+            .emissive
+            .texture
+            .as_ref()
+            .map(|t| size(t.width(), t.height())),
+        material
+            .transmission
+            .texture
+            .as_ref()
+            .map(|t| size(t.width(), t.height())),
+    ]
+    .into_iter()
+    .flatten()
+    .sum()
+}
+
+// sceneの読み込みで確保するGPUのメモリのサイズを見積もる。
+// vertex buffer、index buffer、texture、点群のAABBと色のbuffer、BLASとTLAS、
+// 最大のscratch buffer、skyのbufferを数え、materialやlightなどの小さいbufferは数えない。
+// BLASはコンパクション前のサイズで数えるので実際の使用量よりも大きめになる。
+fn estimate_scene_memory(
+    device: &ashtray::DeviceHandle,
+    scene: &Scene,
+    glb_scenes_list: &[Vec<glb::Scene>],
+    sky_texture: &image::DynamicImage,
+) -> u64 {
+    let vertex_size = std::mem::size_of::<Vertex>();
+    let mut required = 0;
+    let mut max_scratch_size = 0;
+    let mut model_counts = vec![];
+    for glb_scenes in glb_scenes_list {
+        let models = glb_scenes.iter().flat_map(|glb_scene| &glb_scene.models);
+        model_counts.push(models.clone().count());
+        // skinで変形するmodelはrest poseの頂点とjointのindex、weightのbufferも確保する
+        for glb_scene in glb_scenes {
+            if !glb_scene.animation.is_animated() {
+                continue;
+            }
+            for model in glb_scene
+                .models
+                .iter()
+                .filter(|model| model.skin().is_some())
+            {
+                let vertex_count = model.vertices().len();
+                required += (vertex_count
+                    * (vertex_size
+                        + std::mem::size_of::<[u32; 4]>()
+                        + std::mem::size_of::<[f32; 4]>())) as u64;
+            }
+        }
+        for model in models {
+            let material = model.material();
+            let format = blas_geometry_format(model);
+            let vertex_count = model.vertices().len();
+            let index_count = model.indices().map_or(0, |indices| indices.len());
+            let build_sizes = ashtray::utils::get_blas_build_sizes(
+                device,
+                vertex_count,
+                vertex_size,
+                index_count,
+                format,
+                material.alpha_mode != glb::AlphaMode::Opaque,
+                ashtray::utils::BuildPreference::FastTrace,
+                true,
+            );
+            required += (vertex_count * vertex_size + index_count * format.index_size()) as u64
+                + build_sizes.acceleration_structure_size
+                + material_texture_size(&material);
+            max_scratch_size = max_scratch_size.max(build_sizes.build_scratch_size);
+        }
+    }
+
+    // 点群はAABBと色のbufferとAABBのBLASを作る
+    for point_cloud in &scene.point_clouds {
+        let sphere_count = point_cloud.positions.len();
+        let build_sizes = ashtray::utils::get_aabb_blas_build_sizes(
+            device,
+            sphere_count,
+            ashtray::utils::BuildPreference::FastTrace,
+            true,
+        );
+        required += (sphere_count
+            * (std::mem::size_of::<vk::AabbPositionsKHR>() + std::mem::size_of::<glam::Vec3>()))
+            as u64
+            + build_sizes.acceleration_structure_size;
+        max_scratch_size = max_scratch_size.max(build_sizes.build_scratch_size);
+    }
+
+    let instance_count = scene
+        .instances
+        .iter()
+        .map(|instance| model_counts.get(instance.glb_index).copied().unwrap_or(0))
+        .sum::<usize>()
+        + scene.point_clouds.len();
+    let motion = ashtray::utils::is_ray_tracing_motion_blur_enabled(device)
+        && scene
+            .instances
+            .iter()
+            .any(|instance| instance.transform_end.is_some());
+    let build_sizes = ashtray::utils::get_tlas_build_sizes(
+        device,
+        instance_count,
+        ashtray::utils::BuildPreference::FastTrace,
+        motion,
+    );
+    required += build_sizes.acceleration_structure_size
+        + (instance_count * ashtray::utils::get_tlas_instance_size(motion)) as u64;
+    max_scratch_size = max_scratch_size.max(build_sizes.build_scratch_size);
+
+    // skyはRGB32Fの画素と、行ごとのCDFとPDF、列のCDFとPDFのbufferを作る
+    let sky_width = sky_texture.width() as u64;
+    let sky_height = sky_texture.height() as u64;
+    let sky_size = (sky_width * sky_height * 3
+        + (sky_width + 1) * sky_height
+        + sky_width * sky_height
+        + (sky_height + 1)
+        + sky_height)
+        * std::mem::size_of::<f32>() as u64;
+
+    required + max_scratch_size + sky_size
 }
+
+// budgetは読み込み前から確保されているメモリも含めたdevice localメモリの上限。
+// 必要なメモリの見積もりがbudgetを超える場合はGPUのメモリを確保せずにエラーを返す。
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn load_scene(
     device: &ashtray::DeviceHandle,
     queue_handles: &ashtray::utils::QueueHandles,
@@ -62,56 +1256,62 @@ pub(crate) fn load_scene(
     transfer_command_pool: &ashtray::CommandPoolHandle,
     allocator: &ashtray::AllocatorHandle,
     descriptor_sets: &ashtray::utils::BindlessDescriptorSets,
+    sampler_cache: &ashtray::utils::SamplerCache,
     scene: &Scene,
-) -> SceneObjects {
-    let sampler = ashtray::utils::create_sampler_image(device);
+    budget: u64,
+) -> Result<SceneObjects, SceneTooLargeError> {
+    let glb_scenes_list = scene
+        .glb_list
+        .iter()
+        .map(|glb| glb::load(&glb.path).expect("Failed to load glb file"))
+        .collect::<Vec<_>>();
+    let sky_texture = image::open(&scene.sky_texture_path).unwrap();
+
+    let report = allocator.report();
+    let required = estimate_scene_memory(device, scene, &glb_scenes_list, &sky_texture);
+    if report.device_local_allocated + required > budget {
+        return Err(SceneTooLargeError {
+            required,
+            allocated: report.device_local_allocated,
+            budget,
+            total: report.device_local_total,
+        });
+    }
+
+    let sampler = sampler_cache.get(&ashtray::utils::SamplerDesc::linear_repeat());
     let mut images = vec![];
     let mut blas_lists = vec![];
+    let mut blas_cache =
+        std::collections::HashMap::<BlasCacheKey, ashtray::utils::BlasObjects>::new();
+    let mut blas_stats = crate::BlasStats::default();
+    let mut mesh_lists = vec![];
     let mut materials = vec![];
     let mut materials_offset_indices = vec![];
     let mut instances = vec![];
+    let mut animations = vec![];
+    let mut model_animation_lists = vec![];
+    let mut model_skin_lists = vec![];
+    let mut skinned_models = vec![];
 
-    for glb in &scene.glb_list {
-        let glb_scenes = glb::load(&glb.path).expect("Failed to load glb file");
-
+    for (glb, glb_scenes) in scene.glb_list.iter().zip(glb_scenes_list) {
         let mut glb_blas_list = vec![];
+        let mut glb_mesh_list = vec![];
+        let mut glb_model_animation_list = vec![];
+        let mut glb_model_skin_list = vec![];
         materials_offset_indices.push(materials.len());
 
         for glb_scene in glb_scenes {
-            for model in &glb_scene.models {
-                let vertices = model.vertices();
+            let animation_index = animations.len();
+            let is_animated = glb_scene.animation.is_animated();
+            animations.push(glb_scene.animation.clone());
+
+            for (model_index, model) in glb_scene.models.iter().enumerate() {
+                glb_model_animation_list
+                    .push(is_animated.then_some((animation_index, model_index)));
                 let indices = model.indices().unwrap();
                 let material = model.material();
 
-                let mut vertices = vertices
-                    .iter()
-                    .map(|v| Vertex {
-                        position: [v.position.x, v.position.y, v.position.z],
-                        normal: [v.normal.x, v.normal.y, v.normal.z],
-                        tangent: [0.0, 0.0, 0.0],
-                        tex_coords: [v.tex_coords.x, v.tex_coords.y],
-                    })
-                    .collect::<Vec<_>>();
-                // UVからtangentの計算
-                for index in indices.chunks(3) {
-                    let idx0 = index[0] as usize;
-                    let idx1 = index[1] as usize;
-                    let idx2 = index[2] as usize;
-                    let dv1 = glam::Vec3::from_array(vertices[idx1].position)
-                        - glam::Vec3::from_array(vertices[idx0].position);
-                    let dv2 = glam::Vec3::from_array(vertices[idx2].position)
-                        - glam::Vec3::from_array(vertices[idx0].position);
-                    let duv1 = glam::Vec2::from_array(vertices[idx1].tex_coords)
-                        - glam::Vec2::from_array(vertices[idx0].tex_coords);
-                    let duv2 = glam::Vec2::from_array(vertices[idx2].tex_coords)
-                        - glam::Vec2::from_array(vertices[idx0].tex_coords);
-                    let r = 1.0 / (duv1.x * duv2.y - duv1.y * duv2.x);
-                    let tangent = (dv1 * duv2.y - dv2 * duv1.y) * r;
-
-                    vertices[idx0].tangent = tangent.to_array();
-                    vertices[idx1].tangent = tangent.to_array();
-                    vertices[idx2].tangent = tangent.to_array();
-                }
+                let vertices = model_vertices(model, glb.shading);
 
                 let base_color_factor = material.pbr.base_color_factor;
                 let base_color_texture_index =
@@ -275,6 +1475,40 @@ pub(crate) fn load_scene(
                     -1
                 };
 
+                let transmission_factor = material.transmission.factor;
+                let transmission_texture_index =
+                    if let Some(texture) = &material.transmission.texture {
+                        let data = texture
+                            .enumerate_pixels()
+                            .flat_map(|(_x, _y, p)| p.to_rgba().0)
+                            .collect::<Vec<_>>();
+                        let image = ashtray::utils::create_shader_readonly_image_with_data(
+                            device,
+                            queue_handles,
+                            allocator,
+                            transfer_command_pool,
+                            texture.width(),
+                            texture.height(),
+                            &data,
+                            vk::Format::R8G8B8A8_UNORM,
+                            vk::ImageUsageFlags::SAMPLED,
+                        );
+                        let image_index = images.len();
+
+                        descriptor_sets.combined_image_sampler.update(
+                            &image,
+                            &sampler,
+                            image_index as u32,
+                        );
+
+                        images.push(image);
+                        image_index as i32
+                    } else {
+                        -1
+                    };
+
+                // common.glslのMATERIAL_TYPE_*と値を揃えること。
+                // BLENDはany hitでalphaの確率でhitを受け入れる確率的な透過になる。
                 let ty = match material.alpha_mode {
                     glb::AlphaMode::Opaque => 0,
                     glb::AlphaMode::Mask => 1,
@@ -300,24 +1534,131 @@ pub(crate) fn load_scene(
                     emissive_texture_index,
                     alpha_cutoff: material.alpha_cutoff,
                     ty,
+                    double_sided: material.double_sided as u32,
+                    base_color_uv_transform: material
+                        .pbr
+                        .base_color_uv_transform
+                        .unwrap_or(IDENTITY_UV_TRANSFORM),
+                    metallic_roughness_uv_transform: material
+                        .pbr
+                        .metallic_roughness_uv_transform
+                        .unwrap_or(IDENTITY_UV_TRANSFORM),
+                    normal_uv_transform: material
+                        .normal
+                        .as_ref()
+                        .and_then(|normal| normal.uv_transform)
+                        .unwrap_or(IDENTITY_UV_TRANSFORM),
+                    emissive_uv_transform: material
+                        .emissive
+                        .uv_transform
+                        .unwrap_or(IDENTITY_UV_TRANSFORM),
+                    transmission_factor,
+                    transmission_texture_index,
+                    transmission_uv_transform: material
+                        .transmission
+                        .uv_transform
+                        .unwrap_or(IDENTITY_UV_TRANSFORM),
+                    ior: material.ior,
+                    clearcoat_factor: material.clearcoat.factor,
+                    clearcoat_roughness_factor: material.clearcoat.roughness_factor,
+                    specular_factor: material.specular.factor,
+                    specular_color_factor: material.specular.color_factor.to_array(),
+                    iridescence_factor: material.iridescence.factor,
+                    iridescence_ior: material.iridescence.ior,
+                    iridescence_thickness: material.iridescence.thickness(),
+                    flat_shading: (glb.shading == ShadingMode::Flat) as u32,
+                    dispersion: material.dispersion,
                 };
                 materials.push(material);
 
-                let blas = ashtray::utils::cerate_blas(
-                    device,
-                    queue_handles,
-                    compute_command_pool,
-                    allocator,
-                    &vertices,
-                    &indices,
-                    transparent_flag,
-                );
+                // indexはaccessorの型のままBLASのindex bufferに格納する。
+                let indices16 = blas_indices16(model);
+
+                // vertexとindexのバイト列が完全に一致するmeshは、
+                // glbをまたいでも同じBLASを共有してビルドを省く。
+                let key = BlasCacheKey {
+                    vertices: vertices
+                        .iter()
+                        .flat_map(|v| {
+                            v.position
+                                .iter()
+                                .chain(&v.normal)
+                                .chain(&v.tangent)
+                                .chain(&v.tex_coords)
+                        })
+                        .flat_map(|value| value.to_bits().to_le_bytes())
+                        .collect(),
+                    indices: match &indices16 {
+                        Some(indices16) => bytemuck::cast_slice(indices16).to_vec(),
+                        None => bytemuck::cast_slice(indices).to_vec(),
+                    },
+                    index_format: model.index_format(),
+                    transparent: transparent_flag,
+                };
+                // animationが無い場合はskinで変形してもrest poseのままなので変形しない。
+                // skinで変形するmodelはBLASを共有せず、refitできるBLASを作る
+                let skinned = is_animated && model.skin().is_some();
+                glb_model_skin_list.push(skinned.then_some(skinned_models.len()));
+                let blas = if skinned {
+                    let skinned_model = create_skinned_model(
+                        device,
+                        queue_handles,
+                        compute_command_pool,
+                        transfer_command_pool,
+                        allocator,
+                        model,
+                        &vertices,
+                        animation_index,
+                    );
+                    blas_stats.built += 1;
+                    let blas = skinned_model.blas.blas.clone();
+                    skinned_models.push(skinned_model);
+                    blas
+                } else if let Some(blas) = blas_cache.get(&key) {
+                    blas_stats.deduplicated += 1;
+                    blas.clone()
+                } else {
+                    let blas = build_model_blas(
+                        device,
+                        queue_handles,
+                        compute_command_pool,
+                        allocator,
+                        model,
+                        &vertices,
+                        ashtray::utils::BuildPreference::FastTrace,
+                    );
+                    blas_stats.built += 1;
+                    blas_cache.insert(key, blas.clone());
+                    blas
+                };
                 glb_blas_list.push(blas);
+
+                let positions = vertices
+                    .iter()
+                    .map(|v| glam::Vec3::from_array(v.position))
+                    .collect::<Vec<_>>();
+                glb_mesh_list.push((positions, indices.clone()));
             }
         }
         blas_lists.push(glb_blas_list);
+        mesh_lists.push(glb_mesh_list);
+        model_animation_lists.push(glb_model_animation_list);
+        model_skin_lists.push(glb_model_skin_list);
     }
 
+    let mut lights = vec![];
+    let mut light_powers = vec![];
+    let mut light_emissive_luminances = vec![];
+    let mut light_triangle_cdf = vec![];
+    let mut light_triangle_cdf_offsets = vec![];
+    let mut instance_light_indices = vec![];
+    let mut instance_transform_ends = vec![];
+    let mut instance_animations = vec![];
+    let mut instance_skins = vec![];
+
+    // TLASのinstanceはscene.instancesの順、その中はglbのmodelの順に並べる。
+    // HashMapなどの順序の定まらないコレクションを経由しないので、
+    // 同じsceneからは常に同じ順序のTLASがビルドされる。
     for instance in &scene.instances {
         let transform = instance.transform;
         let glb_index = instance.glb_index;
@@ -330,9 +1671,158 @@ pub(crate) fn load_scene(
             let material = materials[material_index].clone();
             let sbt_offset = material.ty as u32;
 
-            instances.push((blas, transform, material_index as u32, sbt_offset));
+            // 発光するinstanceはworld spaceの面積と発光の強さから放射束を求めてlightとして登録する
+            let emissive_luminance = luminance(glam::Vec3::from_array(material.emissive_factor));
+            if emissive_luminance > 0.0 {
+                let (positions, indices) = &mesh_lists[glb_index][i];
+                let triangle_areas = triangle_areas(positions, indices, transform);
+
+                // 三角形を面積に比例した確率で選択するためのCDFを作成する
+                let (triangle_cdf, area) = triangle_cdf(&triangle_areas);
+                light_triangle_cdf_offsets.push(light_triangle_cdf.len());
+                light_triangle_cdf.extend(triangle_cdf);
+
+                instance_light_indices.push(lights.len() as u32);
+                lights.push(EmissiveLight {
+                    instance_index: instances.len() as u32,
+                    triangle_count: (indices.len() / 3) as u32,
+                    area: area as f32,
+                    select_pdf: 0.0,
+                    alias_probability: 1.0,
+                    alias_index: 0,
+                    triangle_cdf_address: 0,
+                    light_link_mask: instance.light_link_mask,
+                    padding: 0,
+                });
+                light_powers.push(emissive_luminance * area);
+                light_emissive_luminances.push(emissive_luminance);
+            } else {
+                instance_light_indices.push(u32::MAX);
+            }
+
+            instances.push((
+                blas,
+                transform,
+                material_index as u32,
+                sbt_offset,
+                instance.ray_mask,
+                instance.light_link_group,
+            ));
+            instance_transform_ends.push(instance.transform_end);
+            instance_animations.push(model_animation_lists[glb_index][i]);
+            instance_skins.push(model_skin_lists[glb_index][i]);
+        }
+    }
+
+    // 点群は点群ごとに1つのAABBのBLASにして、world spaceの位置のままinstanceにする。
+    // 点群のinstanceはscene.instancesのinstanceの後ろに並ぶ
+    for point_cloud in &scene.point_clouds {
+        if point_cloud.positions.is_empty() {
+            continue;
         }
+        let blas = ashtray::utils::create_aabb_blas(
+            device,
+            queue_handles,
+            compute_command_pool,
+            allocator,
+            &point_cloud.aabbs(),
+            &point_cloud.primitive_colors(),
+            ashtray::utils::BuildPreference::FastTrace,
+            true,
+        );
+        blas_stats.built += 1;
+
+        let material_index = materials.len();
+        materials.push(point_cloud_material());
+
+        instance_light_indices.push(u32::MAX);
+        instances.push((
+            blas,
+            glam::Mat4::IDENTITY,
+            material_index as u32,
+            POINT_CLOUD_SBT_OFFSET,
+            0xFF,
+            u32::MAX,
+        ));
+        instance_transform_ends.push(None);
+        instance_animations.push(None);
+        instance_skins.push(None);
+    }
+
+    if light_triangle_cdf.is_empty() {
+        light_triangle_cdf.push(0.0);
     }
+    let light_triangle_cdf_buffer = ashtray::utils::create_device_local_buffer_with_data(
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
+        &light_triangle_cdf,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+    );
+
+    // lightを放射束に比例した確率で選択するためのalias tableを作成する
+    update_light_selection(&mut lights, &light_powers);
+    for (i, light) in lights.iter_mut().enumerate() {
+        light.triangle_cdf_address = light_triangle_cdf_buffer.device_address
+            + (light_triangle_cdf_offsets[i] * std::mem::size_of::<f32>()) as u64;
+    }
+    let light_count = lights.len() as u32;
+    // skinで変形する発光instanceがあれば、poseが変わるたびに作り直すlightとCDFを保持しておく
+    let skinned_lights = lights
+        .iter()
+        .any(|light| instance_skins[light.instance_index as usize].is_some())
+        .then(|| SkinnedLights {
+            lights_staging_buffer: ashtray::utils::create_host_buffer_with_data(
+                device,
+                allocator,
+                &lights,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            ),
+            triangle_cdf_staging_buffer: ashtray::utils::create_host_buffer_with_data(
+                device,
+                allocator,
+                &light_triangle_cdf,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            ),
+            lights: lights.clone(),
+            emissive_luminances: light_emissive_luminances,
+            triangle_cdf: light_triangle_cdf.clone(),
+            triangle_cdf_offsets: light_triangle_cdf_offsets,
+        });
+    // 空のbufferは作れないのでlightが無い場合はダミーを入れておく
+    if lights.is_empty() {
+        lights.push(EmissiveLight {
+            instance_index: 0,
+            triangle_count: 0,
+            area: 0.0,
+            select_pdf: 0.0,
+            alias_probability: 1.0,
+            alias_index: 0,
+            triangle_cdf_address: 0,
+            light_link_mask: 0,
+            padding: 0,
+        });
+    }
+    if instance_light_indices.is_empty() {
+        instance_light_indices.push(u32::MAX);
+    }
+    let lights_buffer = ashtray::utils::create_device_local_buffer_with_data(
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
+        &lights,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+    );
+    let instance_light_indices_buffer = ashtray::utils::create_device_local_buffer_with_data(
+        device,
+        queue_handles,
+        transfer_command_pool,
+        allocator,
+        &instance_light_indices,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+    );
 
     let blas_list = blas_lists
         .iter()
@@ -340,6 +1830,11 @@ pub(crate) fn load_scene(
         .map(|b| b.clone())
         .collect::<Vec<_>>();
 
+    // animationやmotion instanceを使えない場合のmotion blurではTLASを毎サンプルビルドし直すが、
+    // FastBuildでビルドの時間が減る分とトレースが遅くなる分をまだ計測していないので、
+    // 静的なsceneと同じFastTraceでビルドする
+    let has_motion = instance_transform_ends.iter().any(|t| t.is_some());
+    let motion = has_motion && ashtray::utils::is_ray_tracing_motion_blur_enabled(device);
     let tlas = ashtray::utils::create_tlas(
         device,
         queue_handles,
@@ -347,10 +1842,12 @@ pub(crate) fn load_scene(
         transfer_command_pool,
         allocator,
         &instances,
+        motion.then_some(instance_transform_ends.as_slice()),
         &materials,
+        ashtray::utils::BuildPreference::FastTrace,
+        Material::cull_disabled,
     );
 
-    let sky_texture = image::open(&scene.sky_texture_path).unwrap();
     let sky_texture_width = sky_texture.width();
     let sky_texture_height = sky_texture.height();
     let sky_data = sky_texture
@@ -477,11 +1974,19 @@ pub(crate) fn load_scene(
         vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
     );
 
-    SceneObjects {
+    Ok(SceneObjects {
         _sampler: sampler,
         _images: images,
         _blas_list: blas_list,
+        blas_stats,
         tlas,
+        instances,
+        instance_transform_ends,
+        animations,
+        instance_animations,
+        skinned_models,
+        instance_skins,
+        materials,
         sky_texture_width,
         sky_texture_height,
         sky_texture_buffer,
@@ -489,5 +1994,99 @@ pub(crate) fn load_scene(
         sky_texture_pdf_row_buffer,
         sky_texture_cdf_column_buffer,
         sky_texture_pdf_column_buffer,
+        light_count,
+        lights_buffer,
+        light_triangle_cdf_buffer,
+        skinned_lights,
+        instance_light_indices_buffer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SKY_TEXTURE_PATH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../assets/sky/scythian_tombs_2_1k.exr"
+    );
+
+    fn instance(glb_index: usize) -> Instance {
+        Instance {
+            transform: glam::Mat4::IDENTITY,
+            transform_end: None,
+            glb_index,
+            ray_mask: default_ray_mask(),
+            light_link_group: default_light_link(),
+            light_link_mask: default_light_link(),
+        }
+    }
+
+    // validateはファイルを開けるかだけを見るので、中身は空でよい
+    fn existing_glb_path() -> String {
+        let path = std::env::temp_dir().join("scene_validate_existing.glb");
+        std::fs::write(&path, []).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn validate_valid_scene() {
+        let scene = Scene {
+            sky_texture_path: SKY_TEXTURE_PATH.to_string(),
+            glb_list: vec![Glb {
+                path: existing_glb_path(),
+                shading: ShadingMode::default(),
+            }],
+            instances: vec![instance(0)],
+            point_clouds: vec![],
+        };
+        assert!(scene.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_reports_all_errors_of_broken_scene() {
+        let missing_glb = std::env::temp_dir()
+            .join("scene_validate_missing.glb")
+            .to_string_lossy()
+            .into_owned();
+        let missing_sky = std::env::temp_dir()
+            .join("scene_validate_missing.exr")
+            .to_string_lossy()
+            .into_owned();
+        let scene = Scene {
+            sky_texture_path: missing_sky.clone(),
+            glb_list: vec![
+                Glb {
+                    path: existing_glb_path(),
+                    shading: ShadingMode::default(),
+                },
+                Glb {
+                    path: missing_glb.clone(),
+                    shading: ShadingMode::default(),
+                },
+            ],
+            instances: vec![instance(0), instance(2)],
+            point_clouds: vec![],
+        };
+
+        // 最初の問題で止まらず、3種類の問題がすべてまとめて返る
+        let errors = scene.validate().unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(
+            errors[0],
+            SceneError::GlbIndexOutOfRange {
+                instance_index: 1,
+                glb_index: 2,
+                glb_count: 2,
+            }
+        ));
+        assert!(matches!(
+            &errors[1],
+            SceneError::UnreadableGlb { glb_index: 1, path, .. } if *path == missing_glb
+        ));
+        assert!(matches!(
+            &errors[2],
+            SceneError::UnreadableSkyTexture { path, .. } if *path == missing_sky
+        ));
     }
 }
@@ -2,14 +2,19 @@
 #define _BXDF_BXDF_COMMON_GLSL_
 
 #include "../payload.glsl"
+#include "../color_space.glsl"
 
 // 非導電体のF0
 const float MIN_DIELECTRICS_F0 = 0.04;
 
 // metallicに応じてspecularのF0の値を計算する。
 // metallicワークフローを参照のこと。
-vec3 baseColorToSpecularF0(vec3 baseColor, float metallic) {
-  vec3 specularF0 = mix(vec3(MIN_DIELECTRICS_F0), baseColor, metallic);
+// 非導電体のF0はKHR_materials_specularのspecularとspecularColorで変調する。
+vec3 baseColorToSpecularF0(vec3 baseColor, float metallic, float specular,
+                           vec3 specularColor) {
+  vec3 dielectricF0 =
+      min(vec3(MIN_DIELECTRICS_F0) * specularColor, vec3(1.0)) * specular;
+  vec3 specularF0 = mix(dielectricF0, baseColor, metallic);
   return specularF0;
 }
 
@@ -41,8 +46,29 @@ struct MaterialData {
   vec3 shadingNormal;  // world space
   vec3 geometryNormal; // world space
   float alpha;
+  float transmission;
+  float eta; // 入射側の屈折率 / 透過側の屈折率
+  float clearcoat;
+  float clearcoatRoughness;
+  float specular;
+  vec3 specularColor;
+  float iridescence;
+  float iridescenceIor;
+  float iridescenceThickness; // nm
 };
 
+// base colorのテクスチャとfactorからalphaを取得する。
+// any hitでalpha maskとalpha blendの判定に使う。
+float getBaseColorAlpha(Material material, vec2 texCoord) {
+  if (material.baseColorTextureIndex == -1) {
+    return material.baseColorFactor.a;
+  } else {
+    texCoord = applyUvTransform(material.baseColorUvTransform, texCoord);
+    vec4 pixel = texture(images[material.baseColorTextureIndex], texCoord);
+    return material.baseColorFactor.a * pixel.a;
+  }
+}
+
 // テクスチャとhit情報のPrdからマテリアルのデータを取得する。
 MaterialData getMaterialData(Prd prd, Material material, vec3 viewDirection) {
   vec3 baseColor;
@@ -51,39 +77,62 @@ MaterialData getMaterialData(Prd prd, Material material, vec3 viewDirection) {
     baseColor = material.baseColorFactor.rgb;
     alpha = material.baseColorFactor.a;
   } else {
-    vec4 pixel =
-        texture(images[material.baseColorTextureIndex], prd.hitTexCoord);
+    vec2 texCoord =
+        applyUvTransform(material.baseColorUvTransform, prd.hitTexCoord);
+    vec4 pixel = texture(images[material.baseColorTextureIndex], texCoord);
     baseColor = material.baseColorFactor.rgb * pixel.rgb;
     alpha = material.baseColorFactor.a * pixel.a;
   }
+  // BLENDはany hitでalphaの確率でhitを受け入れてcoverageを表現しているので、
+  // 受け入れられたhitの面は不透明として扱う
+  if (material.ty == MATERIAL_TYPE_BLEND) {
+    alpha = 1.0;
+  }
 
   vec3 emissive;
   if (material.emissiveTextureIndex == -1) {
     emissive = material.emissiveFactor;
   } else {
-    emissive =
-        material.emissiveFactor *
-        texture(images[material.emissiveTextureIndex], prd.hitTexCoord).rgb;
+    vec2 texCoord =
+        applyUvTransform(material.emissiveUvTransform, prd.hitTexCoord);
+    emissive = material.emissiveFactor *
+               texture(images[material.emissiveTextureIndex], texCoord).rgb;
   }
 
   float metallic;
   if (material.metallicTextureIndex == -1) {
     metallic = material.metallicFactor;
   } else {
-    metallic =
-        material.metallicFactor *
-        texture(images[material.metallicTextureIndex], prd.hitTexCoord).r;
+    vec2 texCoord = applyUvTransform(material.metallicRoughnessUvTransform,
+                                     prd.hitTexCoord);
+    metallic = material.metallicFactor *
+               texture(images[material.metallicTextureIndex], texCoord).r;
   }
 
   float roughness;
   if (material.roughnessTextureIndex == -1) {
     roughness = material.roughnessFactor;
   } else {
-    roughness =
-        material.roughnessFactor *
-        texture(images[material.roughnessTextureIndex], prd.hitTexCoord).r;
+    vec2 texCoord = applyUvTransform(material.metallicRoughnessUvTransform,
+                                     prd.hitTexCoord);
+    roughness = material.roughnessFactor *
+                texture(images[material.roughnessTextureIndex], texCoord).r;
   }
 
+  float transmission;
+  if (material.transmissionTextureIndex == -1) {
+    transmission = material.transmissionFactor;
+  } else {
+    vec2 texCoord =
+        applyUvTransform(material.transmissionUvTransform, prd.hitTexCoord);
+    transmission =
+        material.transmissionFactor *
+        texture(images[material.transmissionTextureIndex], texCoord).r;
+  }
+
+  // 裏面からhitした場合は物体の内側から外側へ抜けるので屈折率の比が逆になる
+  float eta = prd.hitInside == 1 ? material.ior : 1.0 / material.ior;
+
   vec3 geometryNormal;
   vec3 shadingNormal;
   if (material.normalTextureIndex == -1) {
@@ -110,8 +159,10 @@ MaterialData getMaterialData(Prd prd, Material material, vec3 viewDirection) {
     tangent = cross(bitangent, shadingNormal);
     mat3 tbn = mat3(tangent, bitangent, shadingNormal);
 
+    vec2 texCoord =
+        applyUvTransform(material.normalUvTransform, prd.hitTexCoord);
     vec3 normalFromTexture =
-        texture(images[material.normalTextureIndex], prd.hitTexCoord).rgb;
+        texture(images[material.normalTextureIndex], texCoord).rgb;
     normalFromTexture = normalize(normalFromTexture * 2.0 - 1.0);
     normalFromTexture = normalize(tbn * normalFromTexture);
 
@@ -120,13 +171,23 @@ MaterialData getMaterialData(Prd prd, Material material, vec3 viewDirection) {
   }
 
   MaterialData data;
-  data.baseColor = baseColor;
+  // glTFの色はlinear sRGBなので作業色空間に変換する
+  data.baseColor = toWorkingSpace(baseColor);
   data.alpha = alpha;
   data.metallic = metallic;
   data.roughness = roughness;
-  data.emissive = emissive;
+  data.emissive = toWorkingSpace(emissive);
   data.shadingNormal = shadingNormal;
   data.geometryNormal = geometryNormal;
+  data.transmission = transmission;
+  data.eta = eta;
+  data.clearcoat = material.clearcoatFactor;
+  data.clearcoatRoughness = material.clearcoatRoughnessFactor;
+  data.specular = material.specularFactor;
+  data.specularColor = toWorkingSpace(material.specularColorFactor);
+  data.iridescence = material.iridescenceFactor;
+  data.iridescenceIor = material.iridescenceIor;
+  data.iridescenceThickness = material.iridescenceThickness;
   return data;
 }
 
@@ -137,6 +198,11 @@ struct BrdfData {
 
   float alpha;
 
+  // KHR_materials_iridescenceの薄膜干渉
+  float iridescence;
+  float iridescenceIor;
+  float iridescenceThickness; // nm
+
   vec3 V; // view direction in local space for shading normal
 
   mat3 tbn; // local space to world space for shading normal
@@ -155,11 +221,16 @@ BrdfData getBrdfData(MaterialData material, vec3 viewDirection) {
 
   BrdfData data;
   data.specularF0 =
-      baseColorToSpecularF0(material.baseColor, material.metallic);
+      baseColorToSpecularF0(material.baseColor, material.metallic,
+                            material.specular, material.specularColor);
   data.diffuseReflectance =
       baseColorToDiffuseReflectance(material.baseColor, material.metallic);
   data.alpha = material.roughness * material.roughness;
 
+  data.iridescence = material.iridescence;
+  data.iridescenceIor = material.iridescenceIor;
+  data.iridescenceThickness = material.iridescenceThickness;
+
   data.V = normalize(inverse(tbn) * viewDirection);
 
   data.tbn = tbn;
@@ -0,0 +1,28 @@
+#ifndef _BXDF_CLEARCOAT_GLSL_
+#define _BXDF_CLEARCOAT_GLSL_
+
+#include "bxdf_common.glsl"
+
+// KHR_materials_clearcoatのクリアコート層の屈折率1.5に対応するF0
+// ((1.5 - 1.0) / (1.5 + 1.0))^2
+const float CLEARCOAT_F0 = 0.04;
+
+// クリアコート層のGGX反射の計算に使うBrdfDataを作る。
+// 法線と視線方向はベースの層と共有し、F0とroughnessだけを差し替える。
+// 薄膜干渉はベースの層の上の膜なのでクリアコート層には掛けない。
+BrdfData getClearcoatBrdfData(MaterialData material, BrdfData brdf) {
+  BrdfData data = brdf;
+  data.specularF0 = vec3(CLEARCOAT_F0);
+  data.iridescence = 0.0;
+  data.alpha = material.clearcoatRoughness * material.clearcoatRoughness;
+  return data;
+}
+
+// クリアコート層で反射される割合を計算する。
+// ベースの層はクリアコート層を透過した残りの 1 - clearcoat * Fc で減衰させる。
+float clearcoatFresnel(MaterialData material, BrdfData brdf) {
+  float NoV = clamp(brdf.V.z, 0.0, 1.0);
+  return material.clearcoat * Fresnel(vec3(CLEARCOAT_F0), NoV).x;
+}
+
+#endif
@@ -0,0 +1,31 @@
+#ifndef _BXDF_DIELECTRIC_GLSL_
+#define _BXDF_DIELECTRIC_GLSL_
+
+#include "bxdf_common.glsl"
+
+// 誘電体の境界での非偏光のフレネル反射率を計算する。
+// etaは入射側の屈折率 / 透過側の屈折率。全反射の場合は1.0を返す。
+float fresnelDielectric(float cosThetaI, float eta) {
+  float sin2ThetaT = eta * eta * (1.0 - cosThetaI * cosThetaI);
+  if (sin2ThetaT >= 1.0) {
+    return 1.0;
+  }
+  float cosThetaT = sqrt(1.0 - sin2ThetaT);
+  float rs = (eta * cosThetaI - cosThetaT) / (eta * cosThetaI + cosThetaT);
+  float rp = (cosThetaI - eta * cosThetaT) / (cosThetaI + eta * cosThetaT);
+  return 0.5 * (rs * rs + rp * rp);
+}
+
+// 平滑な誘電体を屈折して透過したときの減衰を計算する。
+// KHR_materials_transmissionに従ってbaseColorを透過色として使う。
+// 物体に入るときにだけ吸収させて、出るときには減衰させないことで
+// 物体を1回通り抜けるごとにbaseColorだけ吸収されるようにする。
+vec3 evalDielectricBtdf(MaterialData material, bool entering) {
+  if (entering) {
+    return material.baseColor;
+  } else {
+    return vec3(1.0);
+  }
+}
+
+#endif
@@ -3,6 +3,7 @@
 #extension GL_EXT_debug_printf : enable
 
 #include "bxdf_common.glsl"
+#include "iridescence.glsl"
 
 // Source: Understanding the Masking-Shadowing Function
 // in Microfacet-Based BRDFs
@@ -127,6 +128,13 @@ vec3 evalGGXBrdf(BrdfData brdf, MaterialData material, vec3 L) {
   float NoL = max(dot(N, L), 0.00001);
 
   vec3 F = Fresnel(brdf.specularF0, HoV);
+  // KHR_materials_iridescenceの薄膜干渉をfactorの割合で混ぜる
+  if (brdf.iridescence > 0.0) {
+    vec3 iridescenceF = evalIridescence(1.0, brdf.iridescenceIor, HoV,
+                                        brdf.iridescenceThickness,
+                                        brdf.specularF0);
+    F = mix(F, iridescenceF, brdf.iridescence);
+  }
   float G2 = Smith_G2_GGX(brdf.alpha, NoV, NoL);
   float D = D_GGX(brdf.alpha, H);
 
@@ -0,0 +1,118 @@
+#ifndef _BXDF_IRIDESCENCE_GLSL_
+#define _BXDF_IRIDESCENCE_GLSL_
+
+#include "bxdf_common.glsl"
+
+// KHR_materials_iridescenceの薄膜干渉のフレネル項。
+// glTFの仕様の参照実装と同じく、Belcour and Barla,
+// "A Practical Extension to Microfacet Theory for the Modeling of Varying
+// Iridescence"の方法で、薄膜の上下の界面で反射した光の干渉を
+// XYZの等色関数のフーリエ空間で評価してlinear sRGBにする。
+
+// XYZからlinear sRGBへの変換行列
+const mat3 XYZ_TO_REC709 =
+    mat3(3.2404542, -0.9692660, 0.0556434, -1.5371385, 1.8760108, -0.2040259,
+         -0.4985314, 0.0415560, 1.0572252);
+
+// 屈折率からF0への変換
+vec3 iridescenceIorToF0(vec3 transmittedIor, float incidentIor) {
+  vec3 r = (transmittedIor - vec3(incidentIor)) /
+           (transmittedIor + vec3(incidentIor));
+  return r * r;
+}
+
+float iridescenceIorToF0(float transmittedIor, float incidentIor) {
+  float r = (transmittedIor - incidentIor) / (transmittedIor + incidentIor);
+  return r * r;
+}
+
+// 空気との界面のF0から屈折率への変換
+vec3 iridescenceF0ToIor(vec3 f0) {
+  vec3 sqrtF0 = sqrt(f0);
+  return (vec3(1.0) + sqrtF0) / (vec3(1.0) - sqrtF0);
+}
+
+vec3 iridescenceSchlick(vec3 f0, float cosTheta) {
+  return f0 + (vec3(1.0) - f0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+float iridescenceSchlick(float f0, float cosTheta) {
+  return f0 + (1.0 - f0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// 光路差OPD(nm)と位相のずれshiftに対するXYZの感度をフーリエ空間で評価し、
+// linear sRGBにして返す
+vec3 evalIridescenceSensitivity(float opd, vec3 shift) {
+  float phase = 2.0 * PI * opd * 1.0e-9;
+  vec3 val = vec3(5.4856e-13, 4.4201e-13, 5.2481e-13);
+  vec3 pos = vec3(1.6810e+06, 1.7953e+06, 2.2084e+06);
+  vec3 var = vec3(4.3278e+09, 9.3046e+09, 6.6121e+09);
+
+  vec3 xyz = val * sqrt(2.0 * PI * var) * cos(pos * phase + shift) *
+             exp(-phase * phase * var);
+  xyz.x += 9.7470e-14 * sqrt(2.0 * PI * 4.5282e+09) *
+           cos(2.2399e+06 * phase + shift.x) * exp(-4.5282e+09 * phase * phase);
+  xyz /= 1.0685e-7;
+
+  return XYZ_TO_REC709 * xyz;
+}
+
+// 屈折率outsideIorの媒質から、屈折率filmIorで厚さthickness(nm)の薄膜を通して
+// F0がbaseF0の面に入射したときのフレネル反射率を計算する。
+// cosTheta1は入射側でのハーフベクトルと視線方向の内積。
+vec3 evalIridescence(float outsideIor, float filmIor, float cosTheta1,
+                     float thickness, vec3 baseF0) {
+  // 膜が薄くなるほど膜の屈折率を周囲の屈折率に近づけて、厚さ0で効果がなくなるようにする
+  float iridescenceIor =
+      mix(outsideIor, filmIor, smoothstep(0.0, 0.03, thickness));
+  // 膜の中での角度をスネルの法則で求める
+  float sinTheta2Sq = (outsideIor / iridescenceIor) *
+                      (outsideIor / iridescenceIor) *
+                      (1.0 - cosTheta1 * cosTheta1);
+  float cosTheta2Sq = 1.0 - sinTheta2Sq;
+  // 全反射
+  if (cosTheta2Sq < 0.0) {
+    return vec3(1.0);
+  }
+  float cosTheta2 = sqrt(cosTheta2Sq);
+
+  // 1つ目の界面(周囲と膜)
+  float r0 = iridescenceIorToF0(iridescenceIor, outsideIor);
+  float r12 = iridescenceSchlick(r0, cosTheta1);
+  float t121 = 1.0 - r12;
+  float phi12 = iridescenceIor < outsideIor ? PI : 0.0;
+  float phi21 = PI - phi12;
+
+  // 2つ目の界面(膜とベースの面)。F0が1のときは屈折率が無限大になるので避ける
+  vec3 baseIor = iridescenceF0ToIor(clamp(baseF0, 0.0, 0.9999));
+  vec3 r1 = iridescenceIorToF0(baseIor, iridescenceIor);
+  vec3 r23 = iridescenceSchlick(r1, cosTheta2);
+  vec3 phi23 = vec3(baseIor.x < iridescenceIor ? PI : 0.0,
+                    baseIor.y < iridescenceIor ? PI : 0.0,
+                    baseIor.z < iridescenceIor ? PI : 0.0);
+
+  // 位相差
+  float opd = 2.0 * iridescenceIor * thickness * cosTheta2;
+  vec3 phi = vec3(phi21) + phi23;
+
+  vec3 r123 = clamp(r12 * r23, 1e-5, 0.9999);
+  vec3 sqrtR123 = sqrt(r123);
+  vec3 rs = t121 * t121 * r23 / (vec3(1.0) - r123);
+
+  // m = 0の項
+  vec3 c0 = r12 + rs;
+  vec3 result = c0;
+
+  // m > 0の項
+  vec3 cm = rs - t121;
+  for (int m = 1; m <= 2; ++m) {
+    cm *= sqrtR123;
+    vec3 sm = 2.0 * evalIridescenceSensitivity(float(m) * opd, float(m) * phi);
+    result += cm * sm;
+  }
+
+  // 色域外の負の値は0にする
+  return max(result, vec3(0.0));
+}
+
+#endif
@@ -3,29 +3,58 @@
 
 #include "../common.glsl"
 #include "../distribute_1d.glsl"
+#include "clearcoat.glsl"
+#include "dielectric.glsl"
 #include "ggx.glsl"
 #include "lambert.glsl"
 #include "transparent.glsl"
 
-// NEEで利用するためにviewDirectionとoutDirectionを与えたときのBSDFの減衰を計算する
-vec3 evalStandardBsdfNEE(Prd prd, Material material, vec3 viewDirection,
-                         vec3 outDirection) {
-  MaterialData materialData = getMaterialData(prd, material, viewDirection);
-  BrdfData brdfData = getBrdfData(materialData, viewDirection);
-
-  vec3 L = normalize(inverse(brdfData.tbn) * outDirection);
+// 各lobeを選択する重みを計算する。
+// 0: specular, 1: diffuse, 2: transparent, 3: transmission, 4: clearcoat
+// transmissionはdiffuseを置き換える平滑な誘電体の透過で、metallicな部分は透過しない。
+// clearcoatはベースの層の上に乗るGGX反射で、
+// ベースの層はクリアコート層のフレネル反射の分だけ減衰させてエネルギーを保存する。
+float[5] getStandardLobeWeights(MaterialData materialData, BrdfData brdfData) {
+  float transmission =
+      materialData.transmission * (1.0 - materialData.metallic);
 
   float weightSpecular = 1.0;
   weightSpecular *= materialData.alpha;
+  weightSpecular *= 1.0 - transmission;
   float NoV = clamp(brdfData.V.z, 0.0, 1.0);
   float weightDiffuse = 1.0 - luminance(Fresnel(brdfData.specularF0, NoV));
   weightDiffuse *= 1.0 - materialData.metallic;
   weightDiffuse = clamp(weightDiffuse, 0.0, 1.0);
   weightDiffuse *= materialData.alpha;
+  weightDiffuse *= 1.0 - materialData.transmission;
   float weightTransparent = 1.0 - materialData.alpha;
+  float weightTransmission = materialData.alpha * transmission;
+  float weightClearcoat = materialData.alpha * materialData.clearcoat;
+
+  float baseAttenuation = 1.0 - clearcoatFresnel(materialData, brdfData);
+  weightSpecular *= baseAttenuation;
+  weightDiffuse *= baseAttenuation;
+  weightTransmission *= baseAttenuation;
+
+  return float[5](weightSpecular, weightDiffuse, weightTransparent,
+                  weightTransmission, weightClearcoat);
+}
+
+// NEEで利用するためにviewDirectionとoutDirectionを与えたときのBSDFの減衰を計算する
+vec3 evalStandardBsdfNEE(Prd prd, Material material, vec3 viewDirection,
+                         vec3 outDirection) {
+  MaterialData materialData = getMaterialData(prd, material, viewDirection);
+  BrdfData brdfData = getBrdfData(materialData, viewDirection);
+
+  vec3 L = normalize(inverse(brdfData.tbn) * outDirection);
+
+  float[5] weights = getStandardLobeWeights(materialData, brdfData);
+  float weightSpecular = weights[0];
+  float weightDiffuse = weights[1];
+  float weightClearcoat = weights[4];
 
   // NEEではperfect
-  // specular面はNEEでサンプリングしないので透過と完全鏡面は無視する
+  // specular面はNEEでサンプリングしないので透過と完全鏡面と誘電体の透過は無視する
   if (dot(outDirection, materialData.shadingNormal) > 0.0) {
     // 反射の場合
     vec3 bsdf = vec3(0.0);
@@ -41,6 +70,13 @@ vec3 evalStandardBsdfNEE(Prd prd, Material material, vec3 viewDirection,
       bsdf += weightSpecular * specularBrdf;
     }
 
+    // clearcoat
+    if (materialData.clearcoatRoughness != 0.0) {
+      BrdfData clearcoatBrdfData = getClearcoatBrdfData(materialData, brdfData);
+      vec3 clearcoatBrdf = evalGGXBrdf(clearcoatBrdfData, materialData, L);
+      bsdf += weightClearcoat * clearcoatBrdf;
+    }
+
     return bsdf;
   } else {
     return vec3(0.0);
@@ -67,21 +103,21 @@ float evalStandardPdf(Prd prd, Material material, vec3 viewDirection,
   BrdfData brdfData = getBrdfData(materialData, viewDirection);
 
   vec3 L = normalize(inverse(brdfData.tbn) * outDirection);
-  vec3 N = vec3(0.0, 0.0, 1.0);
 
-  float weightSpecular = 1.0;
-  weightSpecular *= materialData.alpha;
-  float NoV = clamp(dot(N, brdfData.V), 0.0, 1.0);
-  float weightDiffuse = 1.0 - luminance(Fresnel(brdfData.specularF0, NoV));
-  weightDiffuse *= 1.0 - materialData.metallic;
-  weightDiffuse = clamp(weightDiffuse, 0.0, 1.0);
-  weightDiffuse *= materialData.alpha;
-  float weightTransparent = 1.0 - materialData.alpha;
-  float[3] func = float[3](weightSpecular, weightDiffuse, weightTransparent);
+  float[5] func = getStandardLobeWeights(materialData, brdfData);
+
+  // 誘電体の透過はフレネル反射率で反射と屈折を選ぶ
+  float cosThetaI =
+      clamp(dot(materialData.shadingNormal, viewDirection), 0.0, 1.0);
+  float fresnel = fresnelDielectric(cosThetaI, materialData.eta);
+  vec3 refracted =
+      refract(-viewDirection, materialData.shadingNormal, materialData.eta);
 
   float specularPdf = 0.0;
   float diffusePdf = 0.0;
   float transparentPdf = 0.0;
+  float transmissionPdf = 0.0;
+  float clearcoatPdf = 0.0;
   if (dot(outDirection, prd.hitGeometryNormal) > 0.0) {
     // 反射の場合
     if (materialData.roughness == 0.0) {
@@ -95,14 +131,38 @@ float evalStandardPdf(Prd prd, Material material, vec3 viewDirection,
       specularPdf = evalGGXPdf(brdfData, materialData, L);
     }
     diffusePdf = evalLambertPdf(brdfData, materialData, L);
-  } else if (dot(-viewDirection, outDirection) > 0.9999) {
-    // 透過の場合
-    transparentPdf = 1.0;
+    // 誘電体の反射
+    if (dot(reflect(-viewDirection, materialData.shadingNormal),
+            outDirection) > 0.9999) {
+      transmissionPdf = fresnel;
+    }
+    // クリアコート層の反射
+    if (materialData.clearcoatRoughness == 0.0) {
+      if (dot(reflect(-viewDirection, materialData.shadingNormal),
+              outDirection) > 0.9999) {
+        clearcoatPdf = 1.0;
+      }
+    } else {
+      BrdfData clearcoatBrdfData = getClearcoatBrdfData(materialData, brdfData);
+      clearcoatPdf = evalGGXPdf(clearcoatBrdfData, materialData, L);
+    }
+  } else {
+    if (dot(-viewDirection, outDirection) > 0.9999) {
+      // 透過の場合
+      transparentPdf = 1.0;
+    }
+    if (refracted != vec3(0.0) &&
+        dot(normalize(refracted), outDirection) > 0.9999) {
+      // 誘電体の屈折の場合
+      transmissionPdf = 1.0 - fresnel;
+    }
   }
 
   return getPdfDistribute1D(func, 0) * specularPdf +
          getPdfDistribute1D(func, 1) * diffusePdf +
-         getPdfDistribute1D(func, 2) * transparentPdf;
+         getPdfDistribute1D(func, 2) * transparentPdf +
+         getPdfDistribute1D(func, 3) * transmissionPdf +
+         getPdfDistribute1D(func, 4) * clearcoatPdf;
 }
 
 // sampleStandardBsdfの返り値
@@ -126,15 +186,12 @@ sampleStandardBsdf(float[3] u, Prd prd, Material material, vec3 viewDirection) {
 
   result.emissive = materialData.emissive;
 
-  float weightSpecular = 1.0;
-  weightSpecular *= materialData.alpha;
-  float NoV = clamp(brdfData.V.z, 0.0, 1.0);
-  float weightDiffuse = 1.0 - luminance(Fresnel(brdfData.specularF0, NoV));
-  weightDiffuse *= 1.0 - materialData.metallic;
-  weightDiffuse = clamp(weightDiffuse, 0.0, 1.0);
-  weightDiffuse *= materialData.alpha;
-  float weightTransparent = 1.0 - materialData.alpha;
-  float[3] func = float[3](weightSpecular, weightDiffuse, weightTransparent);
+  float[5] func = getStandardLobeWeights(materialData, brdfData);
+  float weightSpecular = func[0];
+  float weightDiffuse = func[1];
+  float weightTransparent = func[2];
+  float weightTransmission = func[3];
+  float weightClearcoat = func[4];
 
   uint bsdfType;
   float pdfBsdfSelect = samplePdfDistribute1D(u[0], func, bsdfType);
@@ -211,6 +268,76 @@ sampleStandardBsdf(float[3] u, Prd prd, Material material, vec3 viewDirection) {
     // perfect specularなのでcos項は無視できるので1.0とする
     result.cosTheta = 1.0;
   } break;
+  case 3: {
+    // transmission
+    // 平滑な誘電体としてフレネル反射率の確率で反射し、それ以外は屈折する。
+    // 全反射の場合はフレネル反射率が1.0なので必ず反射する。
+    vec3 N = materialData.shadingNormal;
+    float cosThetaI = clamp(dot(N, viewDirection), 0.0, 1.0);
+    float fresnel = fresnelDielectric(cosThetaI, materialData.eta);
+    if (u[1] < fresnel) {
+      result.outDirection = reflect(-viewDirection, N);
+
+      result.pdf = fresnel;
+      result.pdf *= pdfBsdfSelect;
+
+      result.bsdf = weightTransmission * fresnel * vec3(1.0);
+    } else {
+      result.outDirection =
+          normalize(refract(-viewDirection, N, materialData.eta));
+
+      if (dot(result.outDirection, materialData.geometryNormal) > 0.0) {
+        result.traceNext = false;
+        return result;
+      }
+
+      result.pdf = 1.0 - fresnel;
+      result.pdf *= pdfBsdfSelect;
+
+      result.bsdf = weightTransmission * (1.0 - fresnel) *
+                    evalDielectricBtdf(materialData, prd.hitInside == 0);
+    }
+
+    // perfect specularなのでcos項は無視できるので1.0とする
+    result.cosTheta = 1.0;
+  } break;
+  case 4: {
+    // clearcoat
+    BrdfData clearcoatBrdfData = getClearcoatBrdfData(materialData, brdfData);
+    if (materialData.clearcoatRoughness == 0.0) {
+      // 完全鏡面反射
+      result.outDirection = reflect(-viewDirection, materialData.shadingNormal);
+
+      result.pdf = 1.0;
+      result.pdf *= pdfBsdfSelect;
+
+      float NoV = clamp(brdfData.V.z, 0.0, 1.0);
+      result.bsdf = weightClearcoat * Fresnel(vec3(CLEARCOAT_F0), NoV);
+
+      // perfect specularなのでcos項は無視できるので1.0とする
+      result.cosTheta = 1.0;
+    } else {
+      // GGX反射
+      vec2 uu = vec2(u[1], u[2]);
+      vec3 L = sampleGGXDirection(uu, clearcoatBrdfData);
+
+      result.outDirection = normalize(brdfData.tbn * L);
+
+      if (dot(result.outDirection, materialData.geometryNormal) < 0.0) {
+        result.traceNext = false;
+        return result;
+      }
+
+      result.pdf = evalGGXPdf(clearcoatBrdfData, materialData, L);
+      result.pdf *= pdfBsdfSelect;
+
+      result.bsdf =
+          weightClearcoat * evalGGXBrdf(clearcoatBrdfData, materialData, L);
+
+      result.cosTheta =
+          max(dot(result.outDirection, materialData.shadingNormal), 0.0);
+    }
+  } break;
   }
 
   if (luminance(result.bsdf) == 0.0 || isnan(luminance(result.bsdf))) {
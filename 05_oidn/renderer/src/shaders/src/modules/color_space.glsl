@@ -0,0 +1,22 @@
+#ifndef _COLOR_SPACE_GLSL_
+#define _COLOR_SPACE_GLSL_
+
+#include "common.glsl"
+
+// linear sRGB(D65)からACEScg(D60)への変換行列。
+// Bradfordの色順応で白色点を合わせている。color_space.rsの行列と値を揃えること。
+// GLSLのmat3は列優先なので、行列の各列を並べている。
+const mat3 LINEAR_SRGB_TO_ACESCG =
+    mat3(0.6130974, 0.0701937, 0.0206156, 0.3395231, 0.9163539, 0.1095698,
+         0.0473794, 0.0134523, 0.8698151);
+
+// テクスチャやskyなどlinear sRGBで読み込んだ色を作業色空間の色に変換する。
+// 太陽の色と背景色はCPU側で変換済みなのでこれを通さない。
+vec3 toWorkingSpace(vec3 linearSrgb) {
+  if ((pushConstants.debugFlags & FLAG_WORKING_SPACE_ACESCG) != 0) {
+    return LINEAR_SRGB_TO_ACESCG * linearSrgb;
+  }
+  return linearSrgb;
+}
+
+#endif
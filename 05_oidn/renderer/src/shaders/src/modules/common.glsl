@@ -8,10 +8,31 @@
 #extension GL_EXT_buffer_reference2 : require
 #extension GL_EXT_scalar_block_layout : enable
 
+// ray mask
+// instanceのmaskとtraceRayのcull maskのANDが0になるとそのinstanceは無視される。
+// カメラレイ、shadow ray、それ以外のバウンスのレイでビットを分けておくことで、
+// カメラからは見えないlightや影を落とさないinstanceを作ることができる。
+// scene.rsのRAY_MASK_*と値を揃えること。
+#define RAY_MASK_CAMERA 0x01
+#define RAY_MASK_SHADOW 0x02
+#define RAY_MASK_INDIRECT 0x04
+
+// MaterialのtyのglTFのalpha mode
+// scene.rsのMaterialのtyと値を揃えること。
+#define MATERIAL_TYPE_OPAQUE 0
+#define MATERIAL_TYPE_MASK 1
+#define MATERIAL_TYPE_BLEND 2
+
 // define types
 
 #include "push_constants.glsl"
 
+// KHR_texture_transformのUVの2x3のアフィン行列
+struct UvTransform {
+  vec3 row0;
+  vec3 row1;
+};
+
 struct Material {
   vec4 baseColorFactor;
   int baseColorTextureIndex;
@@ -25,15 +46,57 @@ struct Material {
   int normalTextureIndex;
   float alphaCutoff;
   uint ty;
+  uint doubleSided;
+  UvTransform baseColorUvTransform;
+  UvTransform metallicRoughnessUvTransform;
+  UvTransform normalUvTransform;
+  UvTransform emissiveUvTransform;
+  float transmissionFactor;
+  int transmissionTextureIndex;
+  UvTransform transmissionUvTransform;
+  float ior;
+  float clearcoatFactor;
+  float clearcoatRoughnessFactor;
+  float specularFactor;
+  vec3 specularColorFactor;
+  // KHR_materials_iridescence。thicknessはnm
+  float iridescenceFactor;
+  float iridescenceIor;
+  float iridescenceThickness;
+  // 1なら頂点の法線を補間せずに面の法線でシェーディングする
+  uint flatShading;
+  // KHR_materials_dispersion。20 / アッベ数で、0なら分散しない
+  float dispersion;
 };
 
 struct InstanceParam {
   uint64_t indexBuffer;
   uint64_t vertexBuffer;
   mat4 transform;
+  // motion instanceのレイの時刻1.0でのtransform。動かないinstanceではtransformと同じ
+  mat4 transformEnd;
   uint materialIndex;
-  uint padding1;
-  uint64_t padding2;
+  // index bufferの型。0ならuint32、1ならuint16
+  uint indexType;
+  // light linkingでこのinstanceが属するgroupのビット
+  uint lightLinkGroup;
+  uint padding2;
+};
+
+// 発光するinstanceをlightとして選択するための情報。
+// selectPdfはこのlightが選ばれる確率で、aliasProbabilityとaliasIndexはalias table。
+// triangleCdfAddressは三角形を面積に比例して選択するためのCDFのbufferを指す。
+struct EmissiveLight {
+  uint instanceIndex;
+  uint triangleCount;
+  float area;
+  float selectPdf;
+  float aliasProbability;
+  uint aliasIndex;
+  uint64_t triangleCdfAddress;
+  // このlightが照らすinstanceのlightLinkGroupのビット
+  uint lightLinkMask;
+  uint padding;
 };
 
 struct Vertex {
@@ -54,10 +117,29 @@ struct Vertex {
 layout(set = 1, binding = 0) uniform sampler2D images[];
 RegisterStorage(scalar, readonly, Materials, { Material items[]; });
 RegisterStorage(scalar, readonly, InstanceParams, { InstanceParam items[]; });
+RegisterStorage(scalar, readonly, EmissiveLights, { EmissiveLight items[]; });
+RegisterStorage(scalar, readonly, InstanceLightIndices, { uint items[]; });
+// レイの統計を数えるbufferのindex。renderer.rsのRAY_STATS_BUFFER_INDEXと値を揃えること
+#define RAY_STATS_BUFFER_INDEX 4
+RegisterStorage(scalar, coherent, RayStats, {
+  uint traced;
+  uint hits;
+});
+// light tracerがsplatするフィルムのbufferのindex。
+// renderer.rsのLIGHT_TRACE_FILM_BUFFER_INDEXと値を揃えること。
+// pixelごとにRGBのfloatをuintのbitで並べ、atomicCompSwapで加算する
+#define LIGHT_TRACE_FILM_BUFFER_INDEX 5
+RegisterStorage(scalar, coherent, LightTraceFilm, { uint items[]; });
 layout(set = 3, binding = 0,
        rgba32f) uniform readonly image2D storageReadImages[];
 layout(set = 3, binding = 0,
        rgba32f) uniform writeonly image2D storageWriteImages[];
+layout(set = 3, binding = 0,
+       rgba16f) uniform writeonly image2D storageWriteHalfImages[];
+layout(set = 3, binding = 0,
+       r32f) uniform writeonly image2D storageWriteFloatImages[];
+layout(set = 3, binding = 0,
+       r32ui) uniform writeonly uimage2D storageWriteUintImages[];
 layout(set = 4, binding = 0) uniform accelerationStructureEXT topLevelAS;
 
 // buffer reference
@@ -67,29 +149,95 @@ layout(buffer_reference, buffer_reference_align = 4,
   Vertex v[];
 };
 layout(buffer_reference, scalar) buffer readonly Indices { uvec3 i[]; };
+// uint16のindexを2つずつ詰めたindex buffer
+layout(buffer_reference, scalar) buffer readonly Indices16 { uint w[]; };
 layout(buffer_reference, scalar) buffer readonly SkyBuffer { vec3 pixel[]; };
 layout(buffer_reference, scalar) buffer readonly SkyCdfBuffer {
   float value[];
 };
 layout(buffer_reference, scalar) buffer readonly SkyPdfBuffer { float p[]; };
+layout(buffer_reference, scalar) buffer readonly LightTriangleCdfBuffer {
+  float value[];
+};
 
 // utilities
 
 #define PI 3.1415926535897932384626433832795
 
+vec2 applyUvTransform(UvTransform t, vec2 uv) {
+  return vec2(dot(t.row0, vec3(uv, 1.0)), dot(t.row1, vec3(uv, 1.0)));
+}
+
+// instanceのindex bufferからprimitiveの三角形の頂点のindexを読む。
+// uint16のindex bufferはuint単位で読んで上下16bitから取り出す。
+// レイの時刻timeでのinstanceのtransformを返す。
+// motion instanceのTLASと同じく行列の要素ごとに線形に補間する
+mat4 getInstanceTransform(InstanceParam instanceParam, float time) {
+  return instanceParam.transform * (1.0 - time) +
+         instanceParam.transformEnd * time;
+}
+
+uvec3 getTriangleIndices(InstanceParam instanceParam, uint primitiveIndex) {
+  if (instanceParam.indexType == 0) {
+    return Indices(instanceParam.indexBuffer).i[primitiveIndex];
+  }
+  Indices16 indices = Indices16(instanceParam.indexBuffer);
+  uvec3 index;
+  for (uint k = 0; k < 3; k++) {
+    uint i = primitiveIndex * 3 + k;
+    index[k] = (indices.w[i >> 1] >> ((i & 1) * 16)) & 0xffff;
+  }
+  return index;
+}
+
 float luminance(vec3 color) {
   return 0.299 * color.r + 0.587 * color.g + 0.114 * color.b;
 }
 
+// 面上の位置pから法線nの側にレイを飛ばすときの始点を、自己交差しないように少しずらす。
+// Ray Tracing Gemsの"A Fast and Robust Method for Avoiding Self-Intersection"の方法で、
+// 座標をその大きさに応じたulp単位でずらすので、sceneのスケールによらず
+// 浮動小数点の誤差の分だけ面から離れる。
+// 原点付近はulpが小さすぎるので固定の距離でずらす。nが0ならpをそのまま返す
+vec3 offsetRayOrigin(vec3 p, vec3 n) {
+  const float origin = 1.0 / 32.0;
+  const float floatScale = 1.0 / 65536.0;
+  const float intScale = 256.0;
+
+  ivec3 offsetInt = ivec3(intScale * n);
+  vec3 pInt = intBitsToFloat(
+      floatBitsToInt(p) +
+      ivec3(p.x < 0.0 ? -offsetInt.x : offsetInt.x,
+            p.y < 0.0 ? -offsetInt.y : offsetInt.y,
+            p.z < 0.0 ? -offsetInt.z : offsetInt.z));
+  return vec3(abs(p.x) < origin ? p.x + floatScale * n.x : pInt.x,
+              abs(p.y) < origin ? p.y + floatScale * n.y : pInt.y,
+              abs(p.z) < origin ? p.z + floatScale * n.z : pInt.z);
+}
+
+// render region
+
+// render regionの分だけずらした画像全体でのpixel座標
+ivec2 getPixelCoords() {
+  uint offset = pushConstants.renderRegionOffset;
+  return ivec2(gl_LaunchIDEXT.xy) + ivec2(offset & 0xffff, offset >> 16);
+}
+
+// render regionではなく画像全体のサイズ
+ivec2 getImageSize() {
+  return imageSize(storageReadImages[pushConstants.accumulateImageIndex]);
+}
+
 // init random seed
 
 uint seed;
 
 void init_random(uint depth) {
+  uvec2 pixel = uvec2(getPixelCoords());
   seed =
       pushConstants.sampleIndex +
-      (gl_LaunchIDEXT.x + gl_LaunchSizeEXT.x * gl_LaunchIDEXT.y) * 0x12345678u +
-      depth * 0x87654321u;
+      (pixel.x + uint(getImageSize().x) * pixel.y) * 0x12345678u +
+      depth * 0x87654321u + pushConstants.randomSeed * 0x9e3779b9u;
 }
 
 #endif
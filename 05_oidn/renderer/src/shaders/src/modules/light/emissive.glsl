@@ -0,0 +1,174 @@
+#ifndef _LIGHT_EMISSIVE_GLSL_
+#define _LIGHT_EMISSIVE_GLSL_
+
+#include "../common.glsl"
+#include "../color_space.glsl"
+
+// alias tableを使って放射束に比例した確率でlightを選択し、そのindexを返す。
+// lightが1つしか無い場合はalias tableを引かずにそのまま選択する。
+uint selectEmissiveLight(float u) {
+  if (pushConstants.lightCount == 1) {
+    return 0;
+  }
+  float x = u * float(pushConstants.lightCount);
+  uint index = min(uint(x), pushConstants.lightCount - 1);
+  EmissiveLight light =
+      GetResource(EmissiveLights, pushConstants.lightsIndex).items[index];
+  if (x - float(index) < light.aliasProbability) {
+    return index;
+  }
+  return light.aliasIndex;
+}
+
+// レイの時刻timeでのinstanceの三角形の頂点をworld spaceで取得する。
+void getEmissiveTriangle(InstanceParam instanceParam, uint primitiveIndex,
+                         float time, out Vertex v0, out Vertex v1,
+                         out Vertex v2, out vec3 p0, out vec3 p1, out vec3 p2) {
+  Vertices vertices = Vertices(instanceParam.vertexBuffer);
+
+  uvec3 index = getTriangleIndices(instanceParam, primitiveIndex);
+  v0 = vertices.v[index.x];
+  v1 = vertices.v[index.y];
+  v2 = vertices.v[index.z];
+
+  mat4 transform = getInstanceTransform(instanceParam, time);
+  p0 = (transform * vec4(v0.position, 1.0)).xyz;
+  p1 = (transform * vec4(v1.position, 1.0)).xyz;
+  p2 = (transform * vec4(v2.position, 1.0)).xyz;
+}
+
+// sampleEmissiveLightの返り値
+struct SampleEmissiveLightResult {
+  vec3 position;
+  vec3 normal;
+  vec3 emissive;
+  float pdfArea;
+  uint doubleSided;
+  uint lightLinkMask;
+};
+
+// CDFを二分探索してlightの三角形を面積に比例した確率で選択する。
+uint selectEmissiveTriangle(EmissiveLight light, float u) {
+  LightTriangleCdfBuffer cdf =
+      LightTriangleCdfBuffer(light.triangleCdfAddress);
+  uint first = 0;
+  uint len = light.triangleCount + 1;
+  while (len > 0) {
+    uint h = len >> 1;
+    uint middle = first + h;
+    if (cdf.value[middle] <= u) {
+      first = middle + 1;
+      len = len - h - 1;
+    } else {
+      len = h;
+    }
+  }
+  return clamp(first - 1, 0, light.triangleCount - 1);
+}
+
+// lightを選択し、そのlightの三角形を面積に比例して選んで三角形上の点を一様にサンプリングする。
+// 返り値のpositionとnormalはworld spaceで、pdfAreaは面積測度でのpdf。
+// normalは三角形の表面の向きで、片面のmaterialではこちら側にしか発光しない。
+// 動くinstanceはレイの時刻timeでの位置でサンプリングする。
+SampleEmissiveLightResult sampleEmissiveLight(float uLight, float[3] u,
+                                              float time) {
+  SampleEmissiveLightResult result;
+
+  uint lightIndex = selectEmissiveLight(uLight);
+  EmissiveLight light =
+      GetResource(EmissiveLights, pushConstants.lightsIndex).items[lightIndex];
+  InstanceParam instanceParam =
+      GetResource(InstanceParams, pushConstants.instanceParamsIndex)
+          .items[light.instanceIndex];
+  Material material = GetResource(Materials, pushConstants.materialsIndex)
+                          .items[instanceParam.materialIndex];
+
+  uint primitiveIndex = selectEmissiveTriangle(light, u[0]);
+  Vertex v0, v1, v2;
+  vec3 p0, p1, p2;
+  getEmissiveTriangle(instanceParam, primitiveIndex, time, v0, v1, v2, p0, p1,
+                      p2);
+
+  // 三角形上の一様な点をbarycentric座標でサンプリングする
+  float sqrtU = sqrt(u[1]);
+  vec3 barycentricCoords =
+      vec3(1.0 - sqrtU, sqrtU * (1.0 - u[2]), sqrtU * u[2]);
+
+  vec3 crossProduct = cross(p1 - p0, p2 - p0);
+
+  result.position = barycentricCoords.x * p0 + barycentricCoords.y * p1 +
+                    barycentricCoords.z * p2;
+  result.normal = normalize(crossProduct);
+  if (determinant(mat3(getInstanceTransform(instanceParam, time))) < 0.0) {
+    result.normal = -result.normal;
+  }
+  result.doubleSided = material.doubleSided;
+  result.lightLinkMask = light.lightLinkMask;
+
+  if (material.emissiveTextureIndex == -1) {
+    result.emissive = material.emissiveFactor;
+  } else {
+    vec2 texCoord = barycentricCoords.x * v0.texCoord +
+                    barycentricCoords.y * v1.texCoord +
+                    barycentricCoords.z * v2.texCoord;
+    texCoord = applyUvTransform(material.emissiveUvTransform, texCoord);
+    result.emissive =
+        material.emissiveFactor *
+        texture(images[material.emissiveTextureIndex], texCoord).rgb;
+  }
+  result.emissive = toWorkingSpace(result.emissive);
+
+  // 三角形は面積に比例して選ばれるので、面積測度のpdfはlight全体の面積で割ったものになる
+  result.pdfArea = light.selectPdf / light.area;
+
+  return result;
+}
+
+// instanceの表面上の点をsampleEmissiveLightでサンプリングするときの面積測度のpdfを返す。
+// instanceがlightでない場合は0を返す。
+float getEmissiveLightPdfArea(uint instanceIndex) {
+  if (pushConstants.lightCount == 0) {
+    return 0.0;
+  }
+  uint lightIndex =
+      GetResource(InstanceLightIndices, pushConstants.instanceLightIndicesIndex)
+          .items[instanceIndex];
+  if (lightIndex == 0xFFFFFFFFu) {
+    return 0.0;
+  }
+  EmissiveLight light =
+      GetResource(EmissiveLights, pushConstants.lightsIndex).items[lightIndex];
+
+  return light.selectPdf / light.area;
+}
+
+// instanceが発光面の場合に照らすinstanceのlightLinkGroupのビットを返す。
+// instanceがlightでない場合は発光しないので0を返す。
+uint getEmissiveLightLinkMask(uint instanceIndex) {
+  if (pushConstants.lightCount == 0) {
+    return 0;
+  }
+  uint lightIndex =
+      GetResource(InstanceLightIndices, pushConstants.instanceLightIndicesIndex)
+          .items[instanceIndex];
+  if (lightIndex == 0xFFFFFFFFu) {
+    return 0;
+  }
+  return GetResource(EmissiveLights, pushConstants.lightsIndex)
+      .items[lightIndex]
+      .lightLinkMask;
+}
+
+// 面積測度のpdfをshadingする点から見た立体角測度のpdfに変換する。
+float convertAreaPdfToSolidAngle(float pdfArea, vec3 origin, vec3 position,
+                                 vec3 normal) {
+  vec3 d = position - origin;
+  float dist2 = dot(d, d);
+  float cosTheta = abs(dot(normal, normalize(d)));
+  if (cosTheta == 0.0) {
+    return 0.0;
+  }
+  return pdfArea * dist2 / cosTheta;
+}
+
+#endif
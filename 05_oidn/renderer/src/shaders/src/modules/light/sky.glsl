@@ -2,6 +2,7 @@
 #define _LIGHT_SKY_GLSL_
 
 #include "../common.glsl"
+#include "../color_space.glsl"
 
 // Skyの方向のサンプリングに対応したpdfを返す。
 // 引数のnormalとdirectionはworld space。
@@ -66,9 +67,10 @@ vec3 getSkyColor(vec3 direction) {
   vec3 color4 = skyBuffer.pixel[y2 * pushConstants.skyWidth + x2];
 
   return pushConstants.skyStrength *
-         (color1 * (1.0 - weightX) * (1.0 - weightY) +
-          color2 * weightX * (1.0 - weightY) +
-          color3 * (1.0 - weightX) * weightY + color4 * weightX * weightY);
+         toWorkingSpace(color1 * (1.0 - weightX) * (1.0 - weightY) +
+                        color2 * weightX * (1.0 - weightY) +
+                        color3 * (1.0 - weightX) * weightY +
+                        color4 * weightX * weightY);
 }
 
 // skyのテクスチャからdirectionの方向の放射輝度を取得する。
@@ -90,7 +92,7 @@ vec3 getSkyStrength(vec3 direction) {
 
   SkyBuffer skyBuffer = SkyBuffer(pushConstants.skyBufferAddress);
   return pushConstants.skyStrength *
-         skyBuffer.pixel[y * pushConstants.skyWidth + x];
+         toWorkingSpace(skyBuffer.pixel[y * pushConstants.skyWidth + x]);
 }
 
 void sampleSky(float[2] u, out vec3 direction, out float pdf,
@@ -159,7 +161,7 @@ void sampleSky(float[2] u, out vec3 direction, out float pdf,
 
   SkyBuffer skyBuffer = SkyBuffer(pushConstants.skyBufferAddress);
   radiance = pushConstants.skyStrength *
-             skyBuffer.pixel[y * pushConstants.skyWidth + x];
+             toWorkingSpace(skyBuffer.pixel[y * pushConstants.skyWidth + x]);
 }
 
 #endif
@@ -10,18 +10,32 @@ vec3 sunDirection() {
   return vec3(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
 }
 
+// 太陽の円錐の半頂角を返す。
+// sunAngleは太陽の視直径(円錐の頂角)で、実際の太陽は約0.53度(0.0093rad)。
+// 0のときに立体角が0になってpdfと放射輝度が発散しないように下限を設ける。
+float sunHalfAngle() { return max(pushConstants.sunAngle / 2, 1.0e-5); }
+
+// 太陽の半頂角alphaに対する1 - cos(alpha)を返す。
+// alphaが小さいとfloatの桁落ちが大きいので、2 * sin(alpha / 2)^2で計算する。
+float sunOneMinusCosHalfAngle() {
+  float s = sin(sunHalfAngle() / 2);
+  return 2 * s * s;
+}
+
 // 与えられたdirectionが太陽の立体角に含まれるかを判定する。
 // 引数のdirectionはworld space。
+// サンプリングした方向が誤差で外れないように半頂角をわずかに広げて判定する。
 bool isSunDirection(vec3 direction) {
-  return dot(direction, sunDirection()) >=
-         cos(pushConstants.sunAngle / 2 + 0.0001);
+  return dot(direction, sunDirection()) >= cos(sunHalfAngle() * 1.001);
 }
 
 // 頂角がsunAngleの円錐の立体角の中から一様にランダムに方向をサンプリングして方向を返す。
 // 返される方向はworld space。
 // thetaとphiはそれぞれ逆関数法で求めた[0, 1)の範囲の乱数の変換方法。
+// 影を落とす物体から遠いほどshadow rayの方向の広がりが大きくなるので、
+// 接地部分では硬く、離れるほど柔らかい半影ができる。
 vec3 sampleSunDirection(float[2] u) {
-  float theta = acos(1 - u[0] + u[0] * cos(pushConstants.sunAngle / 2));
+  float theta = acos(1 - u[0] * sunOneMinusCosHalfAngle());
   float phi = 2 * PI * u[1];
   vec3 w = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
 
@@ -40,23 +54,25 @@ vec3 sampleSunDirection(float[2] u) {
 
 // 太陽の方向のサンプリングに対応したpdfを返す。
 // 引数のdirectionはworld space。
+// 半頂角alphaの円錐の立体角は2 * PI * (1 - cos(alpha))なので、その逆数になる。
 float getSunPdf(vec3 direction) {
   if (!isSunDirection(direction)) {
     return 0.0;
   }
-  return 1.0 / (2 * PI * (1 - cos(pushConstants.sunAngle / 2)));
+  return 1.0 / (2 * PI * sunOneMinusCosHalfAngle());
 }
 
 // 太陽の垂直放射照度(W/m^2)と色から、太陽の放射輝度(W/m^2/sr)を計算する。
-// 放射輝度にcos(theta)をかけて天球で積分をすると放射照度になる。
-// 太陽の立体角の中で放射輝度が一定であるとすると、放射輝度以外の部分の係数は
-// cos(theta)を0<=theta<sunAngle/2, 0<=phi<2*PIで積分したとなる。
-// これは2 * sin(sunAngle/2) / PIとなるので、
-// 垂直放射照度から放射輝度を求めるには
-// 垂直放射照度を2 * sin(sunAngle/2) / PIで割れば良い。
+// 放射輝度にcos(theta)をかけて太陽の円錐の立体角で積分をすると放射照度になる。
+// 太陽の立体角の中で放射輝度Lが一定であるとすると、半頂角をalphaとして
+// E = L * ∫[0, 2PI]∫[0, alpha] cos(theta) sin(theta) dtheta dphi
+//   = L * PI * sin(alpha)^2
+// となるので、垂直放射照度をPI * sin(alpha)^2で割れば放射輝度が求まる。
+// これにより太陽の放射照度はsunAngleによらずsunStrengthで一定になる。
 vec3 getSunStrength() {
-  return pushConstants.sunStrength * pushConstants.sunColor * PI /
-         (2 * sin(pushConstants.sunAngle / 2));
+  float sinHalfAngle = sin(sunHalfAngle());
+  return pushConstants.sunStrength * pushConstants.sunColor /
+         (PI * sinHalfAngle * sinHalfAngle);
 }
 
 #endif
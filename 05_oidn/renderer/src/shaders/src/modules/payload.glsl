@@ -12,13 +12,19 @@ struct Prd {
   vec3 hitShadingNormal;
   vec3 hitTangent;
   vec2 hitTexCoord;
+  uint hitInstanceIndex;
+  uint hitInstanceCustomIndex;
+  uint hitBackFace;
+  uint hitInside; // 面の裏側からhitしたかどうか。透過の屈折率の向きに使う
   uint depth;
+  uint anyHitSeed; // BLENDのマテリアルのany hitで確率的に透過させるための乱数の状態
 };
 
 // shadow rayの結果を詰め込むPayload
 struct ShadowPrd {
   vec3 transparent;
   uint shadow;
+  uint anyHitSeed; // BLENDのマテリアルのany hitで確率的に透過させるための乱数の状態
 };
 
 #endif
@@ -1,6 +1,43 @@
 #ifndef _PUSH_CONSTANTS_GLSL_
 #define _PUSH_CONSTANTS_GLSL_
 
+// debugFlagsのbitの割り当て。renderer.rsのdebug_flags関数のコメントと値を揃えること。
+//   bit 0-2   (0x0007): debug output
+//   bit 3-4   (0x0018): FLAG_CULL_BACK、FLAG_CULL_FRONT
+//   bit 5     (0x0020): FLAG_SPECTRAL
+//   bit 6-7   (0x00c0): FLAG_LIGHT_TRACE_SPLAT、FLAG_LIGHT_TRACE_GATHER
+//   bit 8-11  (0x0f00): DEBUG_FLAG_DIRECT_LIGHTING_ONLY、DEBUG_FLAG_INDIRECT_ONLY、
+//                       DEBUG_FLAG_DISABLE_NEE、DEBUG_FLAG_COLLECT_STATS
+//   bit 12-15 (0xf000): FLAG_USE_BACKGROUND_COLOR、FLAG_SUN_ENABLED、
+//                       DEBUG_FLAG_WHITE_FURNACE、FLAG_WORKING_SPACE_ACESCG
+//   bit 16-31          : half floatのambient occlusionの半径
+
+// integratorの寄与を切り分けるdebug用のflag
+#define DEBUG_FLAG_DIRECT_LIGHTING_ONLY 0x100
+#define DEBUG_FLAG_INDIRECT_ONLY 0x200
+#define DEBUG_FLAG_DISABLE_NEE 0x400
+// trueの場合はtraceしたレイとhitしたレイの数をRayStatsのbufferに数える
+#define DEBUG_FLAG_COLLECT_STATS 0x800
+// skyの代わりに一様な背景色を使う
+#define FLAG_USE_BACKGROUND_COLOR 0x1000
+// 太陽を光源として使う
+#define FLAG_SUN_ENABLED 0x2000
+// white furnace testのために発光面を無視する。
+// 背景色を1にしてFLAG_USE_BACKGROUND_COLORと一緒に使う
+#define DEBUG_FLAG_WHITE_FURNACE 0x4000
+// 作業色空間をACEScgにする
+#define FLAG_WORKING_SPACE_ACESCG 0x8000
+// light tracerの2回のtraceのどちらかを表す。
+// SPLATでは発光面からのパスをフィルムにsplatし、
+// GATHERではpixelごとにフィルムの値を蓄積画像に加える
+#define FLAG_LIGHT_TRACE_SPLAT 0x40
+#define FLAG_LIGHT_TRACE_GATHER 0x80
+// パスごとに波長をサンプリングして分散を計算する
+#define FLAG_SPECTRAL 0x20
+// Parameters::cull_modeのray flags
+#define FLAG_CULL_BACK 0x08
+#define FLAG_CULL_FRONT 0x10
+
 // レンダラーからpush constants経由で渡されるパラメータ。
 layout(push_constant) uniform PushConstants {
   uint accumulateImageIndex;
@@ -13,24 +50,40 @@ layout(push_constant) uniform PushConstants {
   uint maxRecursionDepth;
   uint instanceParamsIndex;
   uint materialsIndex;
-  uint[1] padding0;
+  uint randomSeed;
   vec3 sunColor;
   float sunStrength;
   vec2 sunDirection;
   float sunAngle;
-  uint sunEnabled;
+  // レンズの歪みの係数k1を下位16bit、k2を上位16bitに詰めたhalf float
+  uint lensDistortion;
   uint skyWidth;
   uint skyHeight;
   float skyRotation;
   float skyStrength;
   uint skyEnabled;
-  uint[3] padding1;
+  uint lightsIndex;
+  uint instanceLightIndicesIndex;
+  uint lightCount;
   uint64_t skyBufferAddress;
   uint64_t skyCdfRowBufferAddress;
   uint64_t skyPdfRowBufferAddress;
   uint64_t skyCdfColumnBufferAddress;
   uint64_t skyPdfColumnBufferAddress;
-  uint[2] padding2;
+  uint cameraProjection; // 0: perspective, 1: orthographic, 2: equirectangular
+  float cameraOrthographicHeight;
+  vec3 backgroundColor;
+  // 0より大きければhit位置からそのまま次のレイを飛ばし、tminをこの値にする。
+  // 0ならoffsetRayOriginで始点をずらしてtminを0にする
+  float rayEpsilon;
+  uint debugImageIndex;
+  // 下位3bitがdebug output
+  // (0: none, 1: bounce count, 2: geometric normal, 3: ambient occlusion,
+  // 4: traversal heatmap)。
+  // 残りのbitの割り当てはファイル先頭のコメントを参照
+  uint debugFlags;
+  uint renderRegionOffset; // x | y << 16
+  uint aovImageIndex; // instance id, depth, positionの順に並んだimageの先頭
 }
 pushConstants;
 
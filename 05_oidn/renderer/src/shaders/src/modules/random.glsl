@@ -16,4 +16,14 @@ uint PCGHash() {
 // 0.0～1.0の範囲の乱数を返す。
 float rnd() { return PCGHash() / float(0xFFFFFFFFU); }
 
+// BLENDのマテリアルのalphaをcoverageとして扱い、alphaの確率でhitを受け入れるかを判定する。
+// any hitではseedが初期化されていないので、payloadに載せた乱数の状態を使って更新する。
+// 多数のサンプルで平均すると、alphaの割合だけhitした結果に収束する。
+bool stochasticAlphaTest(float alpha, inout uint anyHitSeed) {
+  seed = anyHitSeed;
+  float u = rnd();
+  anyHitSeed = seed;
+  return u < alpha;
+}
+
 #endif
@@ -0,0 +1,70 @@
+#ifndef _SPECTRAL_GLSL_
+#define _SPECTRAL_GLSL_
+
+#include "color_space.glsl"
+
+// spectral modeでサンプリングする波長の範囲(nm)
+const float WAVELENGTH_MIN = 380.0;
+const float WAVELENGTH_MAX = 780.0;
+
+// KHR_materials_dispersionのアッベ数を定義するフラウンホーファー線の波長(µm)
+const float WAVELENGTH_D = 0.5876;
+const float WAVELENGTH_F = 0.4861;
+const float WAVELENGTH_C = 0.6563;
+
+// wavelengthToLinearSrgbを[WAVELENGTH_MIN, WAVELENGTH_MAX]で平均した値。
+// 一様にサンプリングした波長の重みの期待値が1になるように割る。
+const vec3 WAVELENGTH_RESPONSE_MEAN = vec3(0.440443, 0.288464, 0.273296);
+
+// [0, 1)の乱数から波長(nm)を一様にサンプリングする
+float sampleWavelength(float u) {
+  return mix(WAVELENGTH_MIN, WAVELENGTH_MAX, u);
+}
+
+// 左右で幅の異なるガウス関数
+float piecewiseGaussian(float x, float mu, float sigma1, float sigma2) {
+  float t = (x - mu) / (x < mu ? sigma1 : sigma2);
+  return exp(-0.5 * t * t);
+}
+
+// 波長(nm)の単色光のlinear sRGBの応答。
+// CIE 1931の等色関数をWymanらのガウス関数の和で近似してXYZからlinear sRGBに変換し、
+// gamut外の負の値は0にする。
+vec3 wavelengthToLinearSrgb(float wavelength) {
+  float x = 1.056 * piecewiseGaussian(wavelength, 599.8, 37.9, 31.0) +
+            0.362 * piecewiseGaussian(wavelength, 442.0, 16.0, 26.7) -
+            0.065 * piecewiseGaussian(wavelength, 501.1, 20.4, 26.2);
+  float y = 0.821 * piecewiseGaussian(wavelength, 568.8, 46.9, 40.5) +
+            0.286 * piecewiseGaussian(wavelength, 530.9, 16.3, 31.1);
+  float z = 1.217 * piecewiseGaussian(wavelength, 437.0, 11.8, 36.0) +
+            0.681 * piecewiseGaussian(wavelength, 459.0, 26.0, 13.8);
+  vec3 rgb = mat3(3.2404542, -0.9692660, 0.0556434, -1.5371385, 1.8760108,
+                  -0.2040259, -0.4985314, 0.0415560, 1.0572252) *
+             vec3(x, y, z);
+  return max(rgb, vec3(0.0));
+}
+
+// サンプリングした波長のパスのthroughputに掛ける作業色空間の重み。
+// 波長を一様にサンプリングした場合の期待値が(1, 1, 1)になるので、
+// 分散しないパスの色は変わらない。
+vec3 spectralWeight(float wavelength) {
+  return toWorkingSpace(wavelengthToLinearSrgb(wavelength)) /
+         toWorkingSpace(WAVELENGTH_RESPONSE_MEAN);
+}
+
+// KHR_materials_dispersionのdispersionから求めたCauchyの式で、
+// 波長(nm)での屈折率を返す。iorはD線での屈折率として扱う。
+float dispersedIor(float ior, float dispersion, float wavelength) {
+  if (dispersion <= 0.0) {
+    return ior;
+  }
+  float abbeNumber = 20.0 / dispersion;
+  float b = (ior - 1.0) /
+            (abbeNumber * (1.0 / (WAVELENGTH_F * WAVELENGTH_F) -
+                           1.0 / (WAVELENGTH_C * WAVELENGTH_C)));
+  float lambda = wavelength * 0.001;
+  return ior + b * (1.0 / (lambda * lambda) -
+                    1.0 / (WAVELENGTH_D * WAVELENGTH_D));
+}
+
+#endif
@@ -0,0 +1,28 @@
+#ifndef _SPHERE_GLSL_
+#define _SPHERE_GLSL_
+
+#include "common.glsl"
+
+// 点群の球ごとのAABB。ashtrayのcreate_aabb_blasがinstanceのvertex bufferに置く。
+// AABBは球に外接するので、中心はAABBの中心、半径はAABBの幅の半分になる
+struct SphereAabb {
+  vec3 minimum;
+  vec3 maximum;
+};
+layout(buffer_reference, scalar) buffer readonly SphereAabbs {
+  SphereAabb items[];
+};
+// 点群の球ごとの色。create_aabb_blasがinstanceのindex bufferに置く
+layout(buffer_reference, scalar) buffer readonly SphereColors {
+  vec3 items[];
+};
+
+// intersection shaderからclosest hit shaderに渡す球のhitの情報
+struct SphereHitAttribute {
+  // world spaceの球の外向きの法線
+  vec3 normal;
+  // SphereColorsのindex
+  uint colorIndex;
+};
+
+#endif
@@ -0,0 +1,24 @@
+#ifndef _TRAVERSAL_HEATMAP_GLSL_
+#define _TRAVERSAL_HEATMAP_GLSL_
+
+#include "common.glsl"
+
+// pixelごとのintersection shaderとany hit shaderの呼び出し回数を数えるbufferのindex。
+// renderer.rsのTRAVERSAL_HEATMAP_BUFFER_INDEXと値を揃えること
+#define TRAVERSAL_HEATMAP_BUFFER_INDEX 6
+RegisterStorage(scalar, coherent, TraversalHeatmap, { uint items[]; });
+
+// traversal heatmapの表示中のみ、このshaderの呼び出しをレイを飛ばしたpixelに数える。
+// ハードウェアのtraversalのコストは取れないので、その代わりの目安にする
+void countTraversalHeatmap() {
+  if ((pushConstants.debugFlags & 0x7) != 4) {
+    return;
+  }
+  ivec2 pixel = getPixelCoords();
+  uint index = uint(pixel.x) + uint(getImageSize().x) * uint(pixel.y);
+  atomicAdd(
+      GetResource(TraversalHeatmap, TRAVERSAL_HEATMAP_BUFFER_INDEX).items[index],
+      1);
+}
+
+#endif
@@ -0,0 +1,69 @@
+//! 色温度(ケルビン)とtintからホワイトバランスの色順応行列を計算するヘルパー関数を定義する。
+//!
+//! 指定した色温度とtintの白色点を基準の白色点(6500K、tint 0)に移す
+//! Bradfordの色順応をlinear sRGB上の3x3行列として求める。
+
+/// ホワイトバランスの基準の色温度。この色温度でtintが0のときは行列が単位行列になる。
+pub const NEUTRAL_TEMPERATURE: f32 = 6500.0;
+
+// tintを1動かしたときにCIE 1960 UCSのuv上で白色点を動かす距離
+const TINT_SCALE: f64 = 0.05;
+
+// linear sRGB(D65)からXYZへの変換行列
+const SRGB_TO_XYZ: glam::DMat3 = glam::DMat3::from_cols_array(&[
+    0.4124564, 0.2126729, 0.0193339, //
+    0.3575761, 0.7151522, 0.1191920, //
+    0.1804375, 0.0721750, 0.9503041,
+]);
+
+// XYZからBradfordの錐体応答空間への変換行列
+const XYZ_TO_BRADFORD: glam::DMat3 = glam::DMat3::from_cols_array(&[
+    0.8951, -0.7502, 0.0389, //
+    0.2664, 1.7135, -0.0685, //
+    -0.1614, 0.0367, 1.0296,
+]);
+
+// 黒体放射の色温度からCIE 1960 UCSのuvを計算する。
+// Krystekの近似式を使う(1000K〜15000Kの範囲で有効)。
+fn planckian_locus_uv(temperature: f64) -> glam::DVec2 {
+    let t = temperature.clamp(1000.0, 15000.0);
+    let t2 = t * t;
+    let u = (0.860117757 + 1.54118254e-4 * t + 1.28641212e-7 * t2)
+        / (1.0 + 8.42420235e-4 * t + 7.08145163e-7 * t2);
+    let v = (0.317398726 + 4.22806245e-5 * t + 4.20481691e-8 * t2)
+        / (1.0 - 2.89741816e-5 * t + 1.61456053e-7 * t2);
+    glam::DVec2::new(u, v)
+}
+
+// 色温度とtintから白色点のXYZ(Y = 1)を計算する。
+// tintは黒体放射軌跡に垂直な方向に白色点を動かし、正の値で緑側に動かす。
+fn white_point_xyz(temperature: f64, tint: f64) -> glam::DVec3 {
+    let uv = planckian_locus_uv(temperature);
+    let tangent = planckian_locus_uv(temperature + 1.0) - planckian_locus_uv(temperature - 1.0);
+    let mut normal = glam::DVec2::new(-tangent.y, tangent.x).normalize_or_zero();
+    if normal.y < 0.0 {
+        normal = -normal;
+    }
+    let uv = uv + normal * tint * TINT_SCALE;
+
+    // CIE 1960 UCSのuvからxyに変換する
+    let denominator = 2.0 * uv.x - 8.0 * uv.y + 4.0;
+    let x = 3.0 * uv.x / denominator;
+    let y = 2.0 * uv.y / denominator;
+    glam::DVec3::new(x / y, 1.0, (1.0 - x - y) / y)
+}
+
+/// 色温度(ケルビン)とtintから、linear sRGBの色に掛けるホワイトバランスの行列を計算する。
+///
+/// 指定した色温度とtintの光源で照らされた白が白に見えるように補正するため、
+/// 色温度を下げると画像は青く、tintを上げると画像はマゼンタ寄りになる。
+/// 6500K、tint 0のときは単位行列になる。
+pub fn white_balance_matrix(temperature: f32, tint: f32) -> glam::Mat3 {
+    // 精度のためにf64で計算する
+    let source = XYZ_TO_BRADFORD * white_point_xyz(temperature as f64, tint as f64);
+    let destination = XYZ_TO_BRADFORD * white_point_xyz(NEUTRAL_TEMPERATURE as f64, 0.0);
+    let scale = glam::DMat3::from_diagonal(destination / source);
+
+    (SRGB_TO_XYZ.inverse() * XYZ_TO_BRADFORD.inverse() * scale * XYZ_TO_BRADFORD * SRGB_TO_XYZ)
+        .as_mat3()
+}
@@ -0,0 +1,190 @@
+// white furnace testのモードで、albedoが1のLambertの球が背景と同じ1に収束することを確かめる。
+// GPUが必要なので`cargo test -p renderer -- --ignored`で明示的に実行する。
+
+use std::sync::atomic::AtomicBool;
+
+use ashtray::{utils, InstanceHandle};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+const SPHERE_SEGMENTS: u32 = 64;
+const SPHERE_RINGS: u32 = 32;
+
+// 半径1の球のmeshを1つだけ持つglTFを作る。
+// materialは白のbase colorでmetallicを0、KHR_materials_specularのspecularFactorを0にして、
+// 鏡面反射の無いLambertの拡散反射だけにする。
+fn lambert_sphere_gltf() -> String {
+    let mut positions = vec![];
+    for ring in 0..=SPHERE_RINGS {
+        let theta = ring as f32 / SPHERE_RINGS as f32 * std::f32::consts::PI;
+        for segment in 0..=SPHERE_SEGMENTS {
+            let phi = segment as f32 / SPHERE_SEGMENTS as f32 * std::f32::consts::TAU;
+            positions.push([
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            ]);
+        }
+    }
+    let mut indices = vec![];
+    for ring in 0..SPHERE_RINGS {
+        for segment in 0..SPHERE_SEGMENTS {
+            let a = ring * (SPHERE_SEGMENTS + 1) + segment;
+            let b = a + SPHERE_SEGMENTS + 1;
+            indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+        }
+    }
+
+    // 単位球なので法線は位置と同じになる
+    let positions_bytes = positions
+        .iter()
+        .flatten()
+        .flat_map(|v| v.to_le_bytes())
+        .collect::<Vec<_>>();
+    let indices_bytes = indices
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect::<Vec<_>>();
+    let buffer = [positions_bytes.as_slice(), &indices_bytes].concat();
+
+    format!(
+        r#"{{
+            "asset": {{ "version": "2.0" }},
+            "extensionsUsed": ["KHR_materials_specular"],
+            "scene": 0,
+            "scenes": [{{ "nodes": [0] }}],
+            "nodes": [{{ "mesh": 0 }}],
+            "meshes": [{{
+                "primitives": [{{
+                    "attributes": {{ "POSITION": 0, "NORMAL": 0 }},
+                    "indices": 1,
+                    "material": 0
+                }}]
+            }}],
+            "materials": [{{
+                "pbrMetallicRoughness": {{
+                    "baseColorFactor": [1, 1, 1, 1],
+                    "metallicFactor": 0,
+                    "roughnessFactor": 1
+                }},
+                "extensions": {{ "KHR_materials_specular": {{ "specularFactor": 0 }} }}
+            }}],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": {vertex_count}, "type": "VEC3",
+                   "min": [-1, -1, -1], "max": [1, 1, 1] }},
+                {{ "bufferView": 1, "componentType": 5125, "count": {index_count}, "type": "SCALAR" }}
+            ],
+            "bufferViews": [
+                {{ "buffer": 0, "byteLength": {positions_len} }},
+                {{ "buffer": 0, "byteOffset": {positions_len}, "byteLength": {indices_len} }}
+            ],
+            "buffers": [{{
+                "byteLength": {buffer_len},
+                "uri": "data:application/octet-stream;base64,{data}"
+            }}]
+        }}"#,
+        vertex_count = positions.len(),
+        index_count = indices.len(),
+        positions_len = positions_bytes.len(),
+        indices_len = indices_bytes.len(),
+        buffer_len = buffer.len(),
+        data = STANDARD.encode(&buffer),
+    )
+}
+
+#[test]
+#[ignore = "requires a Vulkan device with ray tracing support"]
+fn lambert_sphere_in_white_furnace_accumulates_to_one() {
+    let gltf_path = std::env::temp_dir().join("white_furnace_lambert_sphere.gltf");
+    std::fs::write(&gltf_path, lambert_sphere_gltf()).unwrap();
+
+    let scene = renderer::Scene {
+        // white furnaceではskyを使わないが、sceneの読み込みにはtextureが必要
+        sky_texture_path: concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/sky/scythian_tombs_2_1k.exr"
+        )
+        .to_string(),
+        glb_list: vec![renderer::Glb {
+            path: gltf_path.to_string_lossy().into_owned(),
+            shading: renderer::ShadingMode::Smooth,
+        }],
+        instances: vec![renderer::Instance {
+            transform: glam::Mat4::IDENTITY,
+            transform_end: None,
+            glb_index: 0,
+            ray_mask: 0xFF,
+            light_link_group: u32::MAX,
+            light_link_mask: u32::MAX,
+        }],
+        point_clouds: vec![],
+    };
+
+    let instance = InstanceHandle::new_headless();
+    let required_device_extensions = utils::get_required_headless_device_extensions(&[]);
+    let physical_device = utils::select_headless_physical_device(
+        &instance,
+        &required_device_extensions,
+        &Default::default(),
+    );
+    let queue_indices = utils::get_headless_queue_indices(&instance, physical_device);
+    let device = utils::create_device(
+        &instance,
+        physical_device,
+        &queue_indices,
+        &required_device_extensions,
+    );
+    let queue_handles = utils::get_queue_handles(&device, &queue_indices);
+    let command_pool = utils::create_graphics_command_pool(&device, &queue_handles);
+    let allocator = utils::create_allocator(&instance, physical_device, &device);
+    let pipeline_cache = device.create_pipeline_cache(None);
+
+    let mut parameters = renderer::Parameters {
+        width: 64,
+        height: 64,
+        max_sample_count: 256,
+        max_recursion_depth: 8,
+        white_furnace: true,
+        ..Default::default()
+    };
+    parameters.frame_scene((glam::Vec3::splat(-1.0), glam::Vec3::splat(1.0)));
+
+    let mut renderer = renderer::Renderer::new(
+        parameters.width,
+        parameters.height,
+        instance.clone(),
+        physical_device,
+        device.clone(),
+        queue_handles.clone(),
+        command_pool.clone(),
+        allocator.clone(),
+        pipeline_cache,
+    );
+    renderer.load_scene(&scene).unwrap();
+    renderer.render(parameters.clone());
+    renderer.render_until(
+        parameters.max_sample_count,
+        &AtomicBool::new(false),
+        |_, _| true,
+    );
+
+    // 背景も放射輝度1なので、球の輪郭の画素も含めてすべての画素が1になる
+    let pixels = renderer.read_accumulate().unwrap();
+    assert_eq!(pixels.len(), 64 * 64);
+    let mean = pixels.iter().copied().sum::<glam::Vec3>() / pixels.len() as f32;
+    assert!(
+        mean.abs_diff_eq(glam::Vec3::ONE, 0.01),
+        "mean radiance {mean}"
+    );
+    for (i, pixel) in pixels.iter().enumerate() {
+        assert!(
+            pixel.abs_diff_eq(glam::Vec3::ONE, 0.05),
+            "pixel ({}, {}) radiance {pixel}",
+            i % 64,
+            i / 64
+        );
+    }
+
+    drop(renderer);
+    device.wait_idle();
+}
@@ -1,5 +1,5 @@
 use ash::{extensions::khr::Swapchain, vk};
-use ashtray::{utils, InstanceHandle};
+use ashtray::{utils, InstanceHandle, SurfaceHandle};
 use egui_ash::{
     raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle},
     App, AppCreator, AshRenderState, CreationContext, HandleRedraw, RunOption,
@@ -11,6 +11,8 @@ mod pane;
 mod scene_view;
 mod tree_behaviour;
 
+const PIPELINE_CACHE_PATH: &str = "pipeline_cache.bin";
+
 struct Viewer {
     scene_view: scene_view::SceneView,
     tree: egui_tiles::Tree<pane::Pane>,
@@ -40,6 +42,24 @@ impl App for Viewer {
     }
 }
 
+// egui-ashのswapchainのformatを、egui-ashと同じ規則でsurfaceのformatから選ぶ。
+// egui-ashはB8G8R8A8_UNORMとSRGB_NONLINEARの組み合わせを優先し、
+// 無ければsurfaceが返す最初のformatを使う。
+fn egui_ash_swapchain_format(
+    surface: &SurfaceHandle,
+    physical_device: vk::PhysicalDevice,
+) -> vk::Format {
+    let surface_formats = surface.get_physical_device_surface_formats(physical_device);
+    surface_formats
+        .iter()
+        .find(|surface_format| {
+            surface_format.format == vk::Format::B8G8R8A8_UNORM
+                && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        })
+        .unwrap_or(&surface_formats[0])
+        .format
+}
+
 struct ViewerCreator;
 impl AppCreator<Arc<Mutex<Allocator>>> for ViewerCreator {
     type App = Viewer;
@@ -53,9 +73,22 @@ impl AppCreator<Arc<Mutex<Allocator>>> for ViewerCreator {
         );
         let required_device_extensions =
             utils::get_required_device_extensions(&cc.required_device_extensions);
-        let physical_device =
-            utils::select_physical_device(&instance, &surface, &required_device_extensions);
+        // `--gpu <name|index|discrete>`で使うGPUを指定できる
+        let preference = std::env::args()
+            .skip_while(|arg| arg != "--gpu")
+            .nth(1)
+            .map(|gpu| utils::PhysicalDevicePreference::from(gpu.as_str()))
+            .unwrap_or_default();
+        let physical_device = utils::select_physical_device(
+            &instance,
+            &surface,
+            &required_device_extensions,
+            &preference,
+        );
         let queue_indices = utils::get_queue_indices(&instance, &surface, physical_device);
+        // egui-ashが作るswapchainがsRGBのformatなら、伝達関数はハードウェアがかける
+        let output_is_srgb =
+            utils::is_srgb_format(egui_ash_swapchain_format(&surface, physical_device));
         let device = utils::create_device(
             &instance,
             physical_device,
@@ -67,6 +100,10 @@ impl AppCreator<Arc<Mutex<Allocator>>> for ViewerCreator {
         let command_pool = utils::create_graphics_command_pool(&device, &queue_handles);
         let allocator = utils::create_allocator(&instance, physical_device, &device);
 
+        // create pipeline cache
+        let pipeline_cache_data = std::fs::read(PIPELINE_CACHE_PATH).ok();
+        let pipeline_cache = device.create_pipeline_cache(pipeline_cache_data.as_deref());
+
         // create renderer
         let mut renderer = renderer::Renderer::new(
             800,
@@ -77,34 +114,61 @@ impl AppCreator<Arc<Mutex<Allocator>>> for ViewerCreator {
             queue_handles.clone(),
             command_pool.clone(),
             allocator.clone(),
+            pipeline_cache.clone(),
         );
 
+        // save pipeline cache
+        if let Err(err) = std::fs::write(PIPELINE_CACHE_PATH, pipeline_cache.get_data()) {
+            eprintln!("[WARN] Failed to save pipeline cache: {err}");
+        }
+
         // load scene
         let scene = renderer::Scene {
             sky_texture_path: "assets/sky/scythian_tombs_2_1k.exr".into(),
             glb_list: vec![
                 renderer::Glb {
                     path: "assets/glb/SanMiguel/san-miguel.glb".into(),
+                    shading: renderer::ShadingMode::Smooth,
                 },
                 renderer::Glb {
                     path: "assets/glb/light.glb".into(),
+                    shading: renderer::ShadingMode::Smooth,
                 },
             ],
             instances: vec![
                 renderer::Instance {
                     glb_index: 0,
                     transform: glam::Mat4::IDENTITY,
+                    transform_end: None,
+                    ray_mask: 0xFF,
+                    light_link_group: u32::MAX,
+                    light_link_mask: u32::MAX,
                 },
                 renderer::Instance {
                     glb_index: 1,
                     transform: glam::Mat4::from_translation(glam::vec3(14.0, 2.0, 3.5)),
+                    transform_end: None,
+                    ray_mask: 0xFF,
+                    light_link_group: u32::MAX,
+                    light_link_mask: u32::MAX,
                 },
             ],
+            point_clouds: vec![],
         };
-        renderer.load_scene(&scene);
+        if let Err(errors) = scene.validate() {
+            for error in &errors {
+                eprintln!("[ERROR] {error}");
+            }
+            std::process::exit(1);
+        }
+        if let Err(error) = renderer.load_scene(&scene) {
+            eprintln!("[ERROR] {error}");
+            std::process::exit(1);
+        }
 
         // create scene view
-        let scene_view = scene_view::SceneView::new(renderer, cc.image_registry);
+        let scene_view =
+            scene_view::SceneView::new(renderer, scene, cc.image_registry, output_is_srgb);
 
         // create app
         let app = Viewer::new(scene_view);
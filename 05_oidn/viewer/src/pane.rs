@@ -57,6 +57,34 @@ impl Pane {
                                 ui.add(egui::widgets::DragValue::new(&mut state.max_sample_count));
                                 ui.end_row();
 
+                                ui.label("max render seconds: ");
+                                ui.horizontal(|ui| {
+                                    let mut enabled = state.max_render_seconds.is_some();
+                                    ui.add(egui::widgets::Checkbox::without_text(&mut enabled));
+                                    match (enabled, state.max_render_seconds) {
+                                        (true, None) => state.max_render_seconds = Some(10.0),
+                                        (false, Some(_)) => state.max_render_seconds = None,
+                                        _ => {}
+                                    }
+                                    if let Some(seconds) = state.max_render_seconds.as_mut() {
+                                        ui.add(
+                                            egui::widgets::DragValue::new(seconds)
+                                                .speed(0.1)
+                                                .clamp_range(0.1..=3600.0)
+                                                .suffix("s"),
+                                        );
+                                    }
+                                });
+                                ui.end_row();
+
+                                ui.label("target frame ms (0 = off): ");
+                                ui.add(
+                                    egui::widgets::DragValue::new(&mut state.target_frame_ms)
+                                        .speed(0.1)
+                                        .clamp_range(0.0..=1000.0),
+                                );
+                                ui.end_row();
+
                                 ui.label("max recursion depth: ");
                                 ui.add(egui::widgets::DragValue::new(
                                     &mut state.max_recursion_depth,
@@ -64,6 +92,49 @@ impl Pane {
                                 state.max_recursion_depth = state.max_recursion_depth.clamp(1, 64);
                                 ui.end_row();
 
+                                ui.label("integrator: ");
+                                egui::ComboBox::from_id_source("integrator")
+                                    .selected_text(format!("{:?}", state.integrator))
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(
+                                            &mut state.integrator,
+                                            renderer::Integrator::PathTracer,
+                                            "PathTracer",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.integrator,
+                                            renderer::Integrator::LightTracer,
+                                            "LightTracer",
+                                        );
+                                    });
+                                ui.end_row();
+
+                                ui.label("spectral: ");
+                                ui.add(egui::widgets::Checkbox::without_text(&mut state.spectral));
+                                ui.end_row();
+
+                                ui.label("cull mode: ");
+                                egui::ComboBox::from_id_source("cull_mode")
+                                    .selected_text(format!("{:?}", state.cull_mode))
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(
+                                            &mut state.cull_mode,
+                                            renderer::CullMode::None,
+                                            "None",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.cull_mode,
+                                            renderer::CullMode::Back,
+                                            "Back",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.cull_mode,
+                                            renderer::CullMode::Front,
+                                            "Front",
+                                        );
+                                    });
+                                ui.end_row();
+
                                 ui.label("display image: ");
                                 egui::ComboBox::from_id_source("display_image")
                                     .selected_text(format!("{:?}", state.display_image))
@@ -83,11 +154,46 @@ impl Pane {
                                             renderer::DisplayImage::Normal,
                                             "Normal",
                                         );
+                                        ui.selectable_value(
+                                            &mut state.display_image,
+                                            renderer::DisplayImage::GeometricNormal,
+                                            "GeometricNormal",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.display_image,
+                                            renderer::DisplayImage::InstanceId,
+                                            "InstanceId",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.display_image,
+                                            renderer::DisplayImage::Depth,
+                                            "Depth",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.display_image,
+                                            renderer::DisplayImage::Position,
+                                            "Position",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.display_image,
+                                            renderer::DisplayImage::AmbientOcclusion,
+                                            "AmbientOcclusion",
+                                        );
                                         ui.selectable_value(
                                             &mut state.display_image,
                                             renderer::DisplayImage::Resolved,
                                             "Resolved",
                                         );
+                                        ui.selectable_value(
+                                            &mut state.display_image,
+                                            renderer::DisplayImage::BounceCount,
+                                            "BounceCount",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.display_image,
+                                            renderer::DisplayImage::TraversalHeatmap,
+                                            "TraversalHeatmap",
+                                        );
                                     });
                                 ui.end_row();
 
@@ -96,6 +202,229 @@ impl Pane {
                                     &mut state.denoise_every_sample,
                                 ));
                                 ui.end_row();
+
+                                ui.label("denoise strength: ");
+                                ui.add(
+                                    egui::widgets::DragValue::new(&mut state.denoise_strength)
+                                        .speed(0.01)
+                                        .clamp_range(0.0..=1.0),
+                                );
+                                ui.end_row();
+
+                                ui.label("aov far plane: ");
+                                ui.add(
+                                    egui::widgets::DragValue::new(&mut state.aov_far_plane)
+                                        .speed(0.1)
+                                        .clamp_range(0.01..=f32::MAX),
+                                );
+                                ui.end_row();
+
+                                ui.label("ao radius: ");
+                                ui.add(
+                                    egui::widgets::DragValue::new(&mut state.ao_radius)
+                                        .speed(0.01)
+                                        .clamp_range(0.001..=f32::MAX),
+                                );
+                                ui.end_row();
+
+                                ui.label("traversal heatmap scale: ");
+                                ui.add(
+                                    egui::widgets::DragValue::new(
+                                        &mut state.traversal_heatmap_scale,
+                                    )
+                                    .speed(0.1)
+                                    .clamp_range(1.0..=f32::MAX),
+                                );
+                                ui.end_row();
+
+                                ui.label("ray epsilon (0 = auto): ");
+                                ui.add(
+                                    egui::widgets::DragValue::new(&mut state.ray_epsilon)
+                                        .speed(0.0001)
+                                        .clamp_range(0.0..=f32::MAX),
+                                );
+                                ui.end_row();
+
+                                ui.label("prefilter aux: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.prefilter_aux,
+                                ));
+                                ui.end_row();
+
+                                ui.label("denoiser: ");
+                                egui::ComboBox::from_id_source("denoiser")
+                                    .selected_text(format!("{:?}", state.denoiser))
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(
+                                            &mut state.denoiser,
+                                            renderer::Denoiser::None,
+                                            "None",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.denoiser,
+                                            renderer::Denoiser::Oidn,
+                                            "Oidn",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.denoiser,
+                                            renderer::Denoiser::ATrous,
+                                            "ATrous",
+                                        );
+                                    });
+                                ui.end_row();
+
+                                if state.denoiser == renderer::Denoiser::ATrous {
+                                    ui.label("a-trous iterations: ");
+                                    ui.add(
+                                        egui::widgets::DragValue::new(
+                                            &mut state.a_trous_iterations,
+                                        )
+                                        .clamp_range(1..=10),
+                                    );
+                                    ui.end_row();
+
+                                    ui.label("a-trous sigma color: ");
+                                    ui.add(
+                                        egui::widgets::DragValue::new(
+                                            &mut state.a_trous_sigma_color,
+                                        )
+                                        .speed(0.01)
+                                        .clamp_range(0.0..=f32::MAX),
+                                    );
+                                    ui.end_row();
+
+                                    ui.label("a-trous sigma albedo: ");
+                                    ui.add(
+                                        egui::widgets::DragValue::new(
+                                            &mut state.a_trous_sigma_albedo,
+                                        )
+                                        .speed(0.01)
+                                        .clamp_range(0.0..=f32::MAX),
+                                    );
+                                    ui.end_row();
+
+                                    ui.label("a-trous sigma normal: ");
+                                    ui.add(
+                                        egui::widgets::DragValue::new(
+                                            &mut state.a_trous_sigma_normal,
+                                        )
+                                        .speed(0.01)
+                                        .clamp_range(0.0..=f32::MAX),
+                                    );
+                                    ui.end_row();
+
+                                    ui.label("a-trous sigma depth: ");
+                                    ui.add(
+                                        egui::widgets::DragValue::new(
+                                            &mut state.a_trous_sigma_depth,
+                                        )
+                                        .speed(0.01)
+                                        .clamp_range(0.0..=f32::MAX),
+                                    );
+                                    ui.end_row();
+                                }
+
+                                ui.label("display transfer: ");
+                                ui.horizontal(|ui| {
+                                    egui::ComboBox::from_id_source("display_transfer")
+                                        .selected_text(match state.display_transfer {
+                                            renderer::DisplayTransfer::Gamma(_) => "Gamma",
+                                            renderer::DisplayTransfer::Srgb => "Srgb",
+                                            renderer::DisplayTransfer::Linear => "Linear",
+                                        })
+                                        .show_ui(ui, |ui| {
+                                            let is_gamma = matches!(
+                                                state.display_transfer,
+                                                renderer::DisplayTransfer::Gamma(_)
+                                            );
+                                            if ui.selectable_label(is_gamma, "Gamma").clicked()
+                                                && !is_gamma
+                                            {
+                                                state.display_transfer =
+                                                    renderer::DisplayTransfer::Gamma(2.2);
+                                            }
+                                            ui.selectable_value(
+                                                &mut state.display_transfer,
+                                                renderer::DisplayTransfer::Srgb,
+                                                "Srgb",
+                                            );
+                                            ui.selectable_value(
+                                                &mut state.display_transfer,
+                                                renderer::DisplayTransfer::Linear,
+                                                "Linear",
+                                            );
+                                        });
+                                    if let renderer::DisplayTransfer::Gamma(gamma) =
+                                        &mut state.display_transfer
+                                    {
+                                        ui.add(
+                                            egui::widgets::DragValue::new(gamma)
+                                                .speed(0.01)
+                                                .clamp_range(0.1..=10.0),
+                                        );
+                                    }
+                                });
+                                ui.end_row();
+
+                                ui.label("direct lighting only: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.direct_lighting_only,
+                                ));
+                                ui.end_row();
+
+                                ui.label("indirect only: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.indirect_only,
+                                ));
+                                ui.end_row();
+
+                                ui.label("disable NEE: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.disable_nee,
+                                ));
+                                ui.end_row();
+
+                                ui.label("white furnace: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.white_furnace,
+                                ));
+                                ui.end_row();
+
+                                ui.label("collect ray stats: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.collect_stats,
+                                ));
+                                ui.end_row();
+
+                                ui.label("accumulation: ");
+                                ui.horizontal(|ui| {
+                                    if ui.button("reset").clicked() {
+                                        state.reset_accumulation = true;
+                                    }
+                                    ui.toggle_value(&mut state.paused, "pause");
+                                });
+                                ui.end_row();
+
+                                ui.label("preset: ");
+                                ui.horizontal(|ui| {
+                                    ui.text_edit_singleline(&mut state.preset_path);
+                                    if ui.button("load").clicked() {
+                                        state.load_preset = true;
+                                    }
+                                    if ui.button("save").clicked() {
+                                        state.save_preset = true;
+                                    }
+                                });
+                                ui.end_row();
+
+                                #[cfg(feature = "shader-hot-reload")]
+                                {
+                                    ui.label("shaders: ");
+                                    if ui.button("reload").clicked() {
+                                        state.reload_shaders = true;
+                                    }
+                                    ui.end_row();
+                                }
                             });
                     });
 
@@ -127,6 +456,27 @@ impl Pane {
                                     );
                                 });
                                 ui.end_row();
+
+                                ui.label("render region: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.render_region_enabled,
+                                ));
+                                ui.end_row();
+
+                                if state.render_region_enabled {
+                                    ui.label("region (x, y, w, h): ");
+                                    ui.with_layout(
+                                        egui::Layout::left_to_right(egui::Align::TOP),
+                                        |ui| {
+                                            let region = &mut state.render_region;
+                                            ui.add(egui::widgets::DragValue::new(&mut region.0));
+                                            ui.add(egui::widgets::DragValue::new(&mut region.1));
+                                            ui.add(egui::widgets::DragValue::new(&mut region.2));
+                                            ui.add(egui::widgets::DragValue::new(&mut region.3));
+                                        },
+                                    );
+                                    ui.end_row();
+                                }
                             });
                     });
 
@@ -166,9 +516,92 @@ impl Pane {
                                 );
                                 ui.end_row();
 
-                                ui.label("field of view: ");
-                                ui.add(egui::widgets::DragValue::new(&mut state.fov));
-                                state.fov = state.fov.clamp(1.0, 179.0);
+                                ui.label("camera control: ");
+                                ui.with_layout(
+                                    egui::Layout::left_to_right(egui::Align::TOP),
+                                    |ui| {
+                                        ui.radio_value(
+                                            &mut state.camera_controller.mode,
+                                            renderer::CameraMode::Orbit,
+                                            "orbit",
+                                        );
+                                        ui.radio_value(
+                                            &mut state.camera_controller.mode,
+                                            renderer::CameraMode::Fly,
+                                            "fly",
+                                        );
+                                    },
+                                );
+                                ui.end_row();
+
+                                ui.label("frame scene: ");
+                                if ui.button("frame").clicked() {
+                                    state.frame_scene = true;
+                                }
+                                ui.end_row();
+
+                                ui.label("equirectangular: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.equirectangular,
+                                ));
+                                ui.end_row();
+
+                                ui.label("orthographic: ");
+                                ui.add(egui::widgets::Checkbox::without_text(
+                                    &mut state.orthographic,
+                                ));
+                                ui.end_row();
+
+                                // 正距円筒図法では全天球を写すので画角の設定はない
+                                if !state.equirectangular {
+                                    if state.orthographic {
+                                        ui.label("orthographic height: ");
+                                        ui.add(egui::widgets::DragValue::new(
+                                            &mut state.orthographic_height,
+                                        ));
+                                        state.orthographic_height =
+                                            state.orthographic_height.max(0.01);
+                                    } else {
+                                        ui.label("field of view: ");
+                                        ui.add(egui::widgets::DragValue::new(&mut state.fov));
+                                        state.fov = state.fov.clamp(1.0, 179.0);
+                                    }
+                                    ui.end_row();
+
+                                    ui.label("lens distortion k1: ");
+                                    ui.add(
+                                        egui::widgets::DragValue::new(&mut state.lens_distortion)
+                                            .speed(0.01)
+                                            .clamp_range(-1.0..=1.0),
+                                    );
+                                    ui.end_row();
+
+                                    ui.label("lens distortion k2: ");
+                                    ui.add(
+                                        egui::widgets::DragValue::new(
+                                            &mut state.lens_distortion_k2,
+                                        )
+                                        .speed(0.01)
+                                        .clamp_range(-1.0..=1.0),
+                                    );
+                                    ui.end_row();
+                                }
+
+                                ui.label("working color space: ");
+                                egui::ComboBox::from_id_source("working_color_space")
+                                    .selected_text(format!("{:?}", state.working_color_space))
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(
+                                            &mut state.working_color_space,
+                                            renderer::WorkingColorSpace::LinearSrgb,
+                                            "LinearSrgb",
+                                        );
+                                        ui.selectable_value(
+                                            &mut state.working_color_space,
+                                            renderer::WorkingColorSpace::AcesCg,
+                                            "AcesCg",
+                                        );
+                                    });
                                 ui.end_row();
 
                                 ui.label("L_white: ");
@@ -176,6 +609,22 @@ impl Pane {
                                 state.l_white = state.l_white.max(0.01);
                                 ui.end_row();
 
+                                ui.label("white balance (K): ");
+                                ui.add(
+                                    egui::widgets::DragValue::new(&mut state.white_balance_temp)
+                                        .speed(10.0)
+                                        .clamp_range(1000.0..=15000.0),
+                                );
+                                ui.end_row();
+
+                                ui.label("tint: ");
+                                ui.add(
+                                    egui::widgets::DragValue::new(&mut state.tint)
+                                        .speed(0.01)
+                                        .clamp_range(-1.0..=1.0),
+                                );
+                                ui.end_row();
+
                                 ui.label("aperture (f-number): ");
                                 ui.add(egui::widgets::DragValue::new(&mut state.aperture));
                                 state.aperture = state.aperture.clamp(1.4, 64.0);
@@ -190,11 +639,49 @@ impl Pane {
                                 ui.add(egui::widgets::DragValue::new(&mut state.iso));
                                 state.iso = state.iso.max(100.0);
                                 ui.end_row();
+
+                                ui.label("auto exposure: ");
+                                if ui.button("auto").clicked() {
+                                    state.auto_exposure = true;
+                                }
+                                ui.end_row();
                             });
                     });
 
                     ui.separator();
 
+                    // glbにanimationがある場合のみ表示する
+                    if state.animation_duration > 0.0 {
+                        egui::Frame::none().inner_margin(margin).show(ui, |ui| {
+                            ui.heading("Animation");
+                            ui.add_space(8.0);
+                            egui::Grid::new("animation_parameters_grid")
+                                .spacing(egui::vec2(16.0, 8.0))
+                                .show(ui, |ui| {
+                                    ui.label("time: ");
+                                    let duration = state.animation_duration;
+                                    ui.add(egui::widgets::Slider::new(
+                                        &mut state.animation_time,
+                                        0.0..=duration,
+                                    ));
+                                    ui.end_row();
+
+                                    ui.label("play: ");
+                                    let label = if state.animation_playing {
+                                        "pause"
+                                    } else {
+                                        "play"
+                                    };
+                                    if ui.button(label).clicked() {
+                                        state.animation_playing = !state.animation_playing;
+                                    }
+                                    ui.end_row();
+                                });
+                        });
+
+                        ui.separator();
+                    }
+
                     egui::Frame::none().inner_margin(margin).show(ui, |ui| {
                         ui.heading("Lights");
 
@@ -298,6 +785,30 @@ impl Pane {
                                     ui.end_row();
                                 });
                         });
+
+                        egui::Frame::none().inner_margin(margin).show(ui, |ui| {
+                            ui.label(egui::RichText::new("Background").heading().size(14.0));
+                            ui.add_space(4.0);
+                            egui::Grid::new("background_parameters_grid")
+                                .spacing(egui::vec2(16.0, 8.0))
+                                .show(ui, |ui| {
+                                    ui.label("background is environment: ");
+                                    ui.add(egui::widgets::Checkbox::without_text(
+                                        &mut state.background_is_environment,
+                                    ));
+                                    ui.end_row();
+
+                                    ui.add_enabled_ui(!state.background_is_environment, |ui| {
+                                        ui.label("background color: ");
+                                    });
+                                    ui.add_enabled_ui(!state.background_is_environment, |ui| {
+                                        let mut rgb = state.background_color.into();
+                                        ui.color_edit_button_rgb(&mut rgb);
+                                        state.background_color = rgb.into();
+                                    });
+                                    ui.end_row();
+                                });
+                        });
                     });
                 });
             }
@@ -324,6 +835,46 @@ impl Pane {
                                 ui.label("rendering time");
                                 ui.label(format!("{:.3}s", state.rendering_time.as_secs_f64()));
                                 ui.end_row();
+
+                                ui.label("accumulation");
+                                ui.label(match state.accumulation_limit {
+                                    None => "running",
+                                    Some(renderer::AccumulationLimit::SampleCount) => {
+                                        "stopped (sample count)"
+                                    }
+                                    Some(renderer::AccumulationLimit::RenderTime) => {
+                                        "stopped (render time)"
+                                    }
+                                });
+                                ui.end_row();
+
+                                if let Some(ray_stats) = state.ray_stats {
+                                    ui.label("rays traced");
+                                    ui.label(format!("{}", ray_stats.traced));
+                                    ui.end_row();
+
+                                    ui.label("ray hits");
+                                    ui.label(format!("{}", ray_stats.hits));
+                                    ui.end_row();
+
+                                    ui.label("ray misses");
+                                    ui.label(format!("{}", ray_stats.misses));
+                                    ui.end_row();
+                                }
+
+                                ui.label("BLAS built");
+                                ui.label(format!("{}", state.blas_stats.built));
+                                ui.end_row();
+
+                                ui.label("BLAS deduplicated");
+                                ui.label(format!("{}", state.blas_stats.deduplicated));
+                                ui.end_row();
+
+                                for (label, duration) in &state.gpu_timings {
+                                    ui.label(format!("{label} (GPU)"));
+                                    ui.label(format!("{:.3}ms", duration.as_secs_f64() * 1000.0));
+                                    ui.end_row();
+                                }
                             });
                     });
                 });
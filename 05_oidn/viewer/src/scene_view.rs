@@ -7,21 +7,43 @@ pub struct SceneViewState {
     pub width: u32,
     pub height: u32,
     pub max_sample_count: u32,
+    pub max_render_seconds: Option<f32>,
     pub sample_count: u32,
+    pub accumulation_limit: Option<renderer::AccumulationLimit>,
     pub display_image: renderer::DisplayImage,
     pub denoise_every_sample: bool,
+    pub denoise_strength: f32,
+    pub prefilter_aux: bool,
+    pub denoiser: renderer::Denoiser,
+    pub a_trous_iterations: u32,
+    pub a_trous_sigma_color: f32,
+    pub a_trous_sigma_albedo: f32,
+    pub a_trous_sigma_normal: f32,
+    pub a_trous_sigma_depth: f32,
     pub rotate_x: f32,
     pub rotate_y: f32,
     pub rotate_z: f32,
     pub position_x: f32,
     pub position_y: f32,
     pub position_z: f32,
+    pub camera_controller: renderer::CameraController,
+    pub orthographic: bool,
+    pub equirectangular: bool,
     pub fov: f32,
+    pub orthographic_height: f32,
+    pub lens_distortion: f32,
+    pub lens_distortion_k2: f32,
     pub l_white: f32,
     pub aperture: f32,
     pub shutter_speed: f32,
+    pub animation_time: f32,
+    pub animation_duration: f32,
+    pub animation_playing: bool,
     pub iso: f32,
     pub max_recursion_depth: u32,
+    pub integrator: renderer::Integrator,
+    pub spectral: bool,
+    pub cull_mode: renderer::CullMode,
     pub sun_direction: glam::Vec2,
     pub sun_angle: f32,
     pub sun_strength: f32,
@@ -30,10 +52,121 @@ pub struct SceneViewState {
     pub sky_rotation: f32,
     pub sky_strength: f32,
     pub sky_enabled: u32,
+    pub background_is_environment: bool,
+    pub background_color: glam::Vec3,
+    pub output_is_srgb: bool,
+    pub display_transfer: renderer::DisplayTransfer,
+    pub direct_lighting_only: bool,
+    pub indirect_only: bool,
+    pub disable_nee: bool,
+    pub white_furnace: bool,
+    pub working_color_space: renderer::WorkingColorSpace,
+    pub collect_stats: bool,
+    pub ray_stats: Option<renderer::RayStats>,
+    pub blas_stats: renderer::BlasStats,
+    pub gpu_timings: std::collections::BTreeMap<&'static str, std::time::Duration>,
+    pub aov_far_plane: f32,
+    pub ao_radius: f32,
+    pub traversal_heatmap_scale: f32,
+    pub ray_epsilon: f32,
+    pub white_balance_temp: f32,
+    pub tint: f32,
+    pub target_frame_ms: f32,
+    pub render_region_enabled: bool,
+    pub render_region: (u32, u32, u32, u32),
+    pub reset_accumulation: bool,
+    pub paused: bool,
+    pub preset_path: String,
+    pub save_preset: bool,
+    pub load_preset: bool,
+    pub frame_scene: bool,
+    pub auto_exposure: bool,
+    #[cfg(feature = "shader-hot-reload")]
+    pub reload_shaders: bool,
+}
+
+impl SceneViewState {
+    // presetから読み込んだパラメータをUIの状態に反映する。
+    // 画像のサイズはviewのサイズに合わせるので反映しない。
+    // output_is_srgbはswapchainのformatで決まるので反映しない。
+    fn apply_parameters(&mut self, parameters: &renderer::Parameters) {
+        self.max_sample_count = parameters.max_sample_count;
+        self.max_render_seconds = parameters.max_render_seconds;
+        self.display_image = parameters.display_image;
+        self.denoise_every_sample = parameters.denoise_every_sample;
+        self.denoise_strength = parameters.denoise_strength;
+        self.prefilter_aux = parameters.prefilter_aux;
+        self.denoiser = parameters.denoiser;
+        self.a_trous_iterations = parameters.a_trous_iterations;
+        self.a_trous_sigma_color = parameters.a_trous_sigma_color;
+        self.a_trous_sigma_albedo = parameters.a_trous_sigma_albedo;
+        self.a_trous_sigma_normal = parameters.a_trous_sigma_normal;
+        self.a_trous_sigma_depth = parameters.a_trous_sigma_depth;
+        self.rotate_x = parameters.rotate_x;
+        self.rotate_y = parameters.rotate_y;
+        self.rotate_z = parameters.rotate_z;
+        self.position_x = parameters.position_x;
+        self.position_y = parameters.position_y;
+        self.position_z = parameters.position_z;
+        self.orthographic = false;
+        self.equirectangular = false;
+        match parameters.camera_projection {
+            renderer::CameraProjection::Perspective { fov } => self.fov = fov,
+            renderer::CameraProjection::Orthographic { height } => {
+                self.orthographic = true;
+                self.orthographic_height = height;
+            }
+            renderer::CameraProjection::Equirectangular => self.equirectangular = true,
+        }
+        self.lens_distortion = parameters.lens_distortion;
+        self.lens_distortion_k2 = parameters.lens_distortion_k2;
+        self.l_white = parameters.l_white;
+        self.aperture = parameters.aperture;
+        self.shutter_speed = parameters.shutter_speed;
+        self.animation_time = parameters.animation_time;
+        self.iso = parameters.iso;
+        self.max_recursion_depth = parameters.max_recursion_depth;
+        self.integrator = parameters.integrator;
+        self.spectral = parameters.spectral;
+        self.cull_mode = parameters.cull_mode;
+        self.sun_direction = parameters.sun_direction;
+        self.sun_angle = parameters.sun_angle;
+        self.sun_strength = parameters.sun_strength;
+        self.sun_color = parameters.sun_color;
+        self.sun_enabled = parameters.sun_enabled;
+        self.sky_rotation = parameters.sky_rotation;
+        self.sky_strength = parameters.sky_strength;
+        self.sky_enabled = parameters.sky_enabled;
+        self.background_is_environment = parameters.background_is_environment;
+        self.background_color = parameters.background_color;
+        self.display_transfer = parameters.display_transfer;
+        self.direct_lighting_only = parameters.direct_lighting_only;
+        self.indirect_only = parameters.indirect_only;
+        self.disable_nee = parameters.disable_nee;
+        self.white_furnace = parameters.white_furnace;
+        self.working_color_space = parameters.working_color_space;
+        self.collect_stats = parameters.collect_stats;
+        self.aov_far_plane = parameters.aov_far_plane;
+        self.ao_radius = parameters.ao_radius;
+        self.traversal_heatmap_scale = parameters.traversal_heatmap_scale;
+        self.ray_epsilon = parameters.ray_epsilon;
+        self.white_balance_temp = parameters.white_balance_temp;
+        self.tint = parameters.tint;
+        self.target_frame_ms = parameters.target_frame_ms;
+        self.render_region_enabled = parameters.render_region.is_some();
+        if let Some(render_region) = parameters.render_region {
+            self.render_region = render_region;
+        }
+    }
 }
 
 struct SceneViewInner {
     renderer: renderer::Renderer,
+    scene: renderer::Scene,
+    // frame sceneで使うsceneのAABB。最初に使うときに計算する
+    scene_bounds: Option<(glam::Vec3, glam::Vec3)>,
+    // animationを再生するときに前のフレームからの経過時間を測るための時刻
+    last_redraw_time: Option<std::time::Instant>,
 
     image_registry: egui_ash::ImageRegistry,
     scene_image: Option<egui::TextureId>,
@@ -49,10 +182,18 @@ pub struct SceneView {
     inner: Arc<Mutex<SceneViewInner>>,
 }
 impl SceneView {
-    pub fn new(renderer: renderer::Renderer, image_registry: egui_ash::ImageRegistry) -> Self {
+    pub fn new(
+        renderer: renderer::Renderer,
+        scene: renderer::Scene,
+        image_registry: egui_ash::ImageRegistry,
+        output_is_srgb: bool,
+    ) -> Self {
         Self {
             inner: Arc::new(Mutex::new(SceneViewInner {
                 renderer,
+                scene,
+                scene_bounds: None,
+                last_redraw_time: None,
 
                 image_registry,
                 scene_image: None,
@@ -66,29 +207,82 @@ impl SceneView {
                     width: 400,
                     height: 300,
                     max_sample_count: 1024,
+                    max_render_seconds: None,
                     sample_count: 0,
+                    accumulation_limit: None,
                     display_image: renderer::DisplayImage::Final,
                     denoise_every_sample: false,
+                    denoise_strength: 1.0,
+                    prefilter_aux: false,
+                    denoiser: renderer::Denoiser::Oidn,
+                    a_trous_iterations: 5,
+                    a_trous_sigma_color: 1.0,
+                    a_trous_sigma_albedo: 0.1,
+                    a_trous_sigma_normal: 0.2,
+                    a_trous_sigma_depth: 0.1,
                     rotate_x: -15.8,
                     rotate_y: -115.2,
                     rotate_z: 0.0,
                     position_x: 7.83,
                     position_y: 3.06,
                     position_z: 1.14,
+                    camera_controller: renderer::CameraController::default(),
+                    orthographic: false,
+                    equirectangular: false,
                     fov: 70.0,
+                    orthographic_height: 5.0,
+                    lens_distortion: 0.0,
+                    lens_distortion_k2: 0.0,
                     l_white: 1.0,
                     aperture: 4.0,
                     shutter_speed: 2.0 / 100.0,
+                    animation_time: 0.0,
+                    animation_duration: 0.0,
+                    animation_playing: false,
                     iso: 200.0,
                     max_recursion_depth: 32,
+                    integrator: renderer::Integrator::PathTracer,
+                    spectral: false,
+                    cull_mode: renderer::CullMode::None,
                     sun_direction: glam::Vec2::new(186.0, 70.0),
                     sun_angle: 0.53_f32,
-                    sun_strength: 110000.0,
+                    sun_strength: 2500.0,
                     sun_color: glam::Vec3::ONE,
                     sun_enabled: 1,
                     sky_rotation: 0.0,
                     sky_strength: 2400.0,
                     sky_enabled: 1,
+                    background_is_environment: true,
+                    background_color: glam::Vec3::ZERO,
+                    output_is_srgb,
+                    display_transfer: renderer::DisplayTransfer::Srgb,
+                    direct_lighting_only: false,
+                    indirect_only: false,
+                    disable_nee: false,
+                    white_furnace: false,
+                    working_color_space: renderer::WorkingColorSpace::LinearSrgb,
+                    collect_stats: false,
+                    ray_stats: None,
+                    blas_stats: renderer::BlasStats::default(),
+                    gpu_timings: std::collections::BTreeMap::new(),
+                    aov_far_plane: 100.0,
+                    ao_radius: 1.0,
+                    traversal_heatmap_scale: 16.0,
+                    ray_epsilon: 0.0,
+                    white_balance_temp: renderer::white_balance::NEUTRAL_TEMPERATURE,
+                    tint: 0.0,
+                    target_frame_ms: 0.0,
+                    render_region_enabled: false,
+                    render_region: (0, 0, 256, 256),
+                    reset_accumulation: false,
+                    paused: false,
+                    preset_path: "preset.ron".into(),
+                    save_preset: false,
+                    load_preset: false,
+                    frame_scene: false,
+                    auto_exposure: false,
+                    #[cfg(feature = "shader-hot-reload")]
+                    reload_shaders: false,
                 })),
             })),
         }
@@ -98,24 +292,91 @@ impl SceneView {
         let mut inner = self.inner.lock().unwrap();
         let state = inner.state.clone();
         let mut state = state.lock().unwrap();
-        let next_image = inner.renderer.render(renderer::Parameters {
+        #[cfg(feature = "shader-hot-reload")]
+        if std::mem::take(&mut state.reload_shaders) {
+            inner.renderer.reload_shaders();
+        }
+        if std::mem::take(&mut state.reset_accumulation) {
+            inner.renderer.reset_accumulation();
+        }
+        if state.paused != inner.renderer.is_paused() {
+            if state.paused {
+                inner.renderer.pause();
+            } else {
+                inner.renderer.resume();
+            }
+        }
+        if std::mem::take(&mut state.load_preset) {
+            match renderer::Parameters::load_preset(&state.preset_path) {
+                Ok(parameters) => state.apply_parameters(&parameters),
+                Err(err) => eprintln!("[WARN] Failed to load preset: {err}"),
+            }
+        }
+        // 現在の画像の輝度のhistogramから、絞りとシャッタースピードはそのままにISO感度を決める
+        if std::mem::take(&mut state.auto_exposure) {
+            match inner.renderer.read_luminance_histogram() {
+                Ok(histogram) => {
+                    let ev100 = renderer::exposure::auto_exposure_ev100(&histogram);
+                    state.iso = renderer::exposure::iso_from_ev100(
+                        ev100,
+                        state.aperture,
+                        state.shutter_speed,
+                    );
+                }
+                Err(err) => eprintln!("[WARN] Failed to read luminance histogram: {err}"),
+            }
+        }
+        // 再生中は前のフレームからの経過時間だけanimationの時刻を進め、最後まで進んだら最初に戻す
+        state.animation_duration = inner.renderer.animation_duration();
+        let now = std::time::Instant::now();
+        if let Some(last_redraw_time) = inner.last_redraw_time.replace(now) {
+            if state.animation_playing && state.animation_duration > 0.0 {
+                state.animation_time = (state.animation_time
+                    + (now - last_redraw_time).as_secs_f32())
+                    % state.animation_duration;
+            }
+        }
+        let mut parameters = renderer::Parameters {
             width: state.width,
             height: state.height,
             max_sample_count: state.max_sample_count,
+            max_render_seconds: state.max_render_seconds,
             display_image: state.display_image,
             denoise_every_sample: state.denoise_every_sample,
+            denoise_strength: state.denoise_strength,
+            prefilter_aux: state.prefilter_aux,
+            denoiser: state.denoiser,
+            a_trous_iterations: state.a_trous_iterations,
+            a_trous_sigma_color: state.a_trous_sigma_color,
+            a_trous_sigma_albedo: state.a_trous_sigma_albedo,
+            a_trous_sigma_normal: state.a_trous_sigma_normal,
+            a_trous_sigma_depth: state.a_trous_sigma_depth,
             rotate_x: state.rotate_x,
             rotate_y: state.rotate_y,
             rotate_z: state.rotate_z,
             position_x: state.position_x,
             position_y: state.position_y,
             position_z: state.position_z,
-            fov: state.fov,
+            camera_projection: if state.equirectangular {
+                renderer::CameraProjection::Equirectangular
+            } else if state.orthographic {
+                renderer::CameraProjection::Orthographic {
+                    height: state.orthographic_height,
+                }
+            } else {
+                renderer::CameraProjection::Perspective { fov: state.fov }
+            },
+            lens_distortion: state.lens_distortion,
+            lens_distortion_k2: state.lens_distortion_k2,
             l_white: state.l_white,
             aperture: state.aperture,
             shutter_speed: state.shutter_speed,
+            animation_time: state.animation_time,
             iso: state.iso,
             max_recursion_depth: state.max_recursion_depth,
+            integrator: state.integrator,
+            spectral: state.spectral,
+            cull_mode: state.cull_mode,
             sun_direction: state.sun_direction,
             sun_strength: state.sun_strength,
             sun_color: state.sun_color,
@@ -124,7 +385,61 @@ impl SceneView {
             sky_rotation: state.sky_rotation,
             sky_strength: state.sky_strength,
             sky_enabled: state.sky_enabled,
-        });
+            background_is_environment: state.background_is_environment,
+            background_color: state.background_color,
+            output_is_srgb: state.output_is_srgb,
+            // egui-ashのswapchainはSDRなのでHDR出力はしない
+            hdr_output: renderer::HdrOutput::Sdr,
+            display_transfer: state.display_transfer,
+            direct_lighting_only: state.direct_lighting_only,
+            indirect_only: state.indirect_only,
+            disable_nee: state.disable_nee,
+            white_furnace: state.white_furnace,
+            working_color_space: state.working_color_space,
+            collect_stats: state.collect_stats,
+            seed: None,
+            render_region: state.render_region_enabled.then_some(state.render_region),
+            hdr_precision: renderer::HdrPrecision::Half,
+            aov_far_plane: state.aov_far_plane,
+            ao_radius: state.ao_radius,
+            traversal_heatmap_scale: state.traversal_heatmap_scale,
+            ray_epsilon: state.ray_epsilon,
+            white_balance_temp: state.white_balance_temp,
+            tint: state.tint,
+            target_frame_ms: state.target_frame_ms,
+        };
+        if std::mem::take(&mut state.save_preset) {
+            if let Err(err) = parameters.save_preset(&state.preset_path) {
+                eprintln!("[WARN] Failed to save preset: {err}");
+            }
+        }
+        if std::mem::take(&mut state.frame_scene) {
+            if inner.scene_bounds.is_none() {
+                match inner.scene.world_bounds() {
+                    Ok(bounds) => inner.scene_bounds = Some(bounds),
+                    Err(err) => eprintln!("[WARN] Failed to compute scene bounds: {err}"),
+                }
+            }
+            if let Some(bounds) = inner.scene_bounds {
+                parameters.frame_scene(bounds);
+                state.position_x = parameters.position_x;
+                state.position_y = parameters.position_y;
+                state.position_z = parameters.position_z;
+                // sceneの中心をorbitの中心にする
+                let position = glam::vec3(state.position_x, state.position_y, state.position_z);
+                let rotation = glam::vec3(state.rotate_x, state.rotate_y, state.rotate_z);
+                state.camera_controller.set_camera(position, rotation);
+                state
+                    .camera_controller
+                    .set_target((bounds.0 + bounds.1) / 2.0);
+                if let renderer::CameraProjection::Orthographic { height } =
+                    parameters.camera_projection
+                {
+                    state.orthographic_height = height;
+                }
+            }
+        }
+        let next_image = inner.renderer.render(parameters);
         let texture_id = unsafe {
             inner.image_registry.register_user_texture(
                 next_image.image_view.image_view_raw(),
@@ -136,6 +451,20 @@ impl SceneView {
         inner.current_sampler = Some(next_image.sampler);
         state.sample_count = next_image.sample_count;
         state.rendering_time = next_image.rendering_time;
+        state.accumulation_limit = inner.renderer.accumulation_limit();
+        state.ray_stats = if state.collect_stats {
+            match inner.renderer.ray_stats() {
+                Ok(stats) => Some(stats),
+                Err(err) => {
+                    eprintln!("[WARN] Failed to read ray stats: {err}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        state.blas_stats = inner.renderer.blas_stats();
+        state.gpu_timings = inner.renderer.gpu_timings();
 
         if let Some(texture_id) = inner.scene_image.take() {
             inner.image_registry.unregister_user_texture(texture_id);
@@ -217,49 +546,33 @@ impl egui::Widget for &mut SceneView {
         let response = response.with_new_rect(rect);
         let response = response.interact(egui::Sense::drag());
 
-        if response.dragged_by(egui::PointerButton::Primary) {
-            state.rotate_y =
-                (state.rotate_y + response.drag_delta().x / 5.0 + 180.0) % 360.0 - 180.0;
-            state.rotate_x = (state.rotate_x + response.drag_delta().y / 5.0).clamp(-90.0, 90.0);
-        }
-        if response.dragged_by(egui::PointerButton::Middle) {
-            let position = glam::vec3(state.position_x, state.position_y, state.position_z);
-            let direction_x = glam::Mat4::from_euler(
-                glam::EulerRot::YXZ,
-                state.rotate_y.to_radians(),
-                state.rotate_x.to_radians(),
-                state.rotate_z.to_radians(),
-            )
-            .transform_vector3(glam::Vec3::NEG_X);
-            let direction_y = glam::Mat4::from_euler(
-                glam::EulerRot::YXZ,
-                state.rotate_y.to_radians(),
-                state.rotate_x.to_radians(),
-                state.rotate_z.to_radians(),
-            )
-            .transform_vector3(glam::Vec3::Y);
-
-            let position = position
-                + direction_x * response.drag_delta().x / 100.0
-                + direction_y * response.drag_delta().y / 100.0;
-            state.position_x = position.x;
-            state.position_y = position.y;
-            state.position_z = position.z;
-        }
+        // マウスの入力をcamera controllerに渡し、動かしたカメラをstateに書き戻す
         let scroll_delta = ui.input(|i| i.scroll_delta);
-        if scroll_delta.y != 0.0 && response.hovered() {
+        let orbit = response.dragged_by(egui::PointerButton::Primary);
+        let pan = response.dragged_by(egui::PointerButton::Middle);
+        let dolly = scroll_delta.y != 0.0 && response.hovered();
+        if orbit || pan || dolly {
             let position = glam::vec3(state.position_x, state.position_y, state.position_z);
-            let direction = glam::Mat4::from_euler(
-                glam::EulerRot::YXZ,
-                state.rotate_y.to_radians(),
-                state.rotate_x.to_radians(),
-                state.rotate_z.to_radians(),
-            )
-            .transform_vector3(glam::Vec3::NEG_Z);
-            let position = position + direction * scroll_delta.y / 100.0;
+            let rotation = glam::vec3(state.rotate_x, state.rotate_y, state.rotate_z);
+            let controller = &mut state.camera_controller;
+            controller.set_camera(position, rotation);
+            if orbit {
+                controller.orbit(response.drag_delta().x, response.drag_delta().y);
+            }
+            if pan {
+                controller.pan(response.drag_delta().x, response.drag_delta().y);
+            }
+            if dolly {
+                controller.dolly(scroll_delta.y);
+            }
+            let position = controller.position();
+            let rotation = controller.rotation();
             state.position_x = position.x;
             state.position_y = position.y;
             state.position_z = position.z;
+            state.rotate_x = rotation.x;
+            state.rotate_y = rotation.y;
+            state.rotate_z = rotation.z;
         }
 
         // update state